@@ -347,6 +347,14 @@ fn main() {
         debug_log!("Bindings Created");
     }
 
+    // Older sherpa-onnx releases have no version functions in their C API
+    println!("cargo::rustc-check-cfg=cfg(sherpa_version_api)");
+    let c_api =
+        std::fs::read_to_string(sherpa_dst.join("sherpa-onnx/c-api/c-api.h")).unwrap_or_default();
+    if c_api.contains("SherpaOnnxGetVersionStr") {
+        println!("cargo:rustc-cfg=sherpa_version_api");
+    }
+
     // Skip build when docs.rs website built this crate
     // Only build the bindings.rs file.
     if env::var("DOCS_RS") == Ok("1".to_string()) {
@@ -505,6 +513,10 @@ fn main() {
         }
         link_lib(&lib, is_dynamic);
     }
+    println!(
+        "cargo:rustc-env=SHERPA_RS_SYS_LINKING={}",
+        if is_dynamic { "dynamic" } else { "static" }
+    );
 
     // Windows debug
     if cfg!(all(debug_assertions, windows)) {
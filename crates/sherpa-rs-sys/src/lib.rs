@@ -3,3 +3,39 @@
 #![allow(non_snake_case)]
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+use std::ffi::CStr;
+
+/// Version of this crate.
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// How sherpa-onnx was linked, `"static"` or `"dynamic"`. Unset when nothing was linked,
+/// e.g. on docs.rs.
+pub const LINKING: Option<&str> = option_env!("SHERPA_RS_SYS_LINKING");
+
+/// Version strings of the linked sherpa-onnx.
+#[derive(Debug, Clone, Copy)]
+pub struct NativeVersion {
+    pub version: &'static CStr,
+    pub git_sha1: &'static CStr,
+    pub git_date: &'static CStr,
+}
+
+/// Version of the linked sherpa-onnx.
+#[cfg(sherpa_version_api)]
+pub fn native_version() -> Option<NativeVersion> {
+    unsafe {
+        Some(NativeVersion {
+            version: CStr::from_ptr(SherpaOnnxGetVersionStr()),
+            git_sha1: CStr::from_ptr(SherpaOnnxGetGitSha1()),
+            git_date: CStr::from_ptr(SherpaOnnxGetGitDate()),
+        })
+    }
+}
+
+/// Version of the linked sherpa-onnx, always `None` as its C API has no version
+/// functions.
+#[cfg(not(sherpa_version_api))]
+pub fn native_version() -> Option<NativeVersion> {
+    None
+}
@@ -0,0 +1,296 @@
+//! Timings for a transcript that's already known, e.g. audiobooks or karaoke.
+//!
+//! sherpa-onnx's C API has no CTC forced alignment, so [`ForcedAligner`] decodes the
+//! audio with a recognizer that reports token timestamps (SenseVoice, Dolphin and
+//! Paraformer models with timestamps) and lines the transcript up with the recognized
+//! words by edit distance. Words heard differently keep the recognized timing with a
+//! lower score, words the model missed are spread between their neighbours with a
+//! score of 0.
+
+use std::{collections::HashSet, fmt::Write};
+
+use crate::{
+    hotwords::read_symbols,
+    pipeline::{AsrConfig, Recognizer},
+    punctuate::is_cjk,
+    subtitle::srt_time,
+    trace::timed,
+    utils::edit_distance,
+    Error, Result, WordSpan,
+};
+
+/// A unit of the transcript with its time in seconds: a word, or a character of CJK
+/// text. Punctuation stays attached to the unit before it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AlignedToken {
+    pub token: String,
+    pub start: f32,
+    pub end: f32,
+    /// 1.0 if the model recognized the token as written, lower if it heard something
+    /// close, 0.0 if the time was interpolated.
+    pub score: f32,
+}
+
+pub struct ForcedAligner {
+    recognizer: Recognizer,
+    /// Characters the model can output, lowercased.
+    chars: HashSet<char>,
+}
+
+impl ForcedAligner {
+    pub fn new(config: AsrConfig) -> Result<Self> {
+        let tokens = match &config {
+            AsrConfig::Whisper(_) | AsrConfig::Moonshine(_) | AsrConfig::FireRedAsr(_) => {
+                return Err(Error::unsupported(
+                    "forced alignment with whisper, moonshine or fire red asr, they don't report token timestamps",
+                ))
+            }
+            AsrConfig::SenseVoice(c) => c.tokens.clone(),
+            AsrConfig::Paraformer(c) => c.tokens.clone(),
+            AsrConfig::Dolphin(c) => c.tokens.clone(),
+        };
+        let chars = read_symbols(&tokens)?
+            .iter()
+            .flat_map(|symbol| symbol.chars())
+            .flat_map(char::to_lowercase)
+            .collect();
+        Ok(Self {
+            recognizer: Recognizer::new(config)?,
+            chars,
+        })
+    }
+
+    /// Time every word of `text` in `samples`.
+    ///
+    /// Fails naming the first character the model can't output, since the text could
+    /// never match there.
+    pub fn align(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        text: &str,
+    ) -> Result<Vec<AlignedToken>> {
+        let _timed = timed!("align.align", sample_rate, samples = samples.len());
+        if sample_rate == 0 {
+            return Err(Error::invalid_input("sample rate must be non-zero"));
+        }
+        let units = split_units(text);
+        for unit in &units {
+            if let Some(c) = unit.key.chars().find(|c| !self.chars.contains(c)) {
+                return Err(Error::invalid_input(format!(
+                    "{:?} in {:?} isn't in the model's vocabulary",
+                    c, unit.text
+                )));
+            }
+        }
+        if units.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let result = self.recognizer.transcribe(sample_rate, samples);
+        if result.timestamps.is_empty() && !result.tokens.is_empty() {
+            return Err(Error::unsupported(
+                "forced alignment with a model that doesn't report token timestamps",
+            ));
+        }
+        let heard: Vec<WordSpan> = result
+            .words()
+            .into_iter()
+            .flat_map(split_word)
+            .filter(|word| !normalize(&word.word).is_empty())
+            .collect();
+        let duration = samples.len() as f32 / sample_rate as f32;
+        Ok(align_units(&units, &heard, duration))
+    }
+}
+
+/// Words of `tokens`, one per token. CJK characters stay separate, as in
+/// [`OfflineRecognizerResult::words`](crate::OfflineRecognizerResult::words).
+pub fn words(tokens: &[AlignedToken]) -> Vec<WordSpan> {
+    tokens
+        .iter()
+        .map(|token| WordSpan {
+            word: token.token.clone(),
+            start: token.start,
+            end: token.end,
+        })
+        .collect()
+}
+
+/// One SRT cue per token, e.g. for karaoke.
+pub fn to_srt(tokens: &[AlignedToken]) -> String {
+    let mut out = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let _ = write!(
+            out,
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            srt_time(token.start),
+            srt_time(token.end),
+            token.token
+        );
+    }
+    out
+}
+
+/// A unit of the transcript and the form it's compared in.
+struct Unit {
+    text: String,
+    key: String,
+}
+
+/// Lowercase letters and digits, what's compared between transcript and recognition.
+pub(crate) fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn split_units(text: &str) -> Vec<Unit> {
+    let mut pieces: Vec<String> = Vec::new();
+    for chunk in text.split_whitespace() {
+        let mut current = String::new();
+        for c in chunk.chars() {
+            if is_cjk(c) && c.is_alphanumeric() {
+                if !current.is_empty() {
+                    pieces.push(std::mem::take(&mut current));
+                }
+                pieces.push(c.to_string());
+            } else {
+                current.push(c);
+            }
+        }
+        if !current.is_empty() {
+            pieces.push(current);
+        }
+    }
+
+    // Punctuation on its own joins the unit before it, or the one after at the start
+    let mut units: Vec<Unit> = Vec::new();
+    let mut leading = String::new();
+    for piece in pieces {
+        let key = normalize(&piece);
+        match units.last_mut() {
+            Some(last) if key.is_empty() => last.text.push_str(&piece),
+            None if key.is_empty() => leading.push_str(&piece),
+            _ => units.push(Unit {
+                text: std::mem::take(&mut leading) + &piece,
+                key,
+            }),
+        }
+    }
+    units
+}
+
+/// Split recognized CJK words into characters, with the time divided evenly.
+fn split_word(word: WordSpan) -> Vec<WordSpan> {
+    let chars: Vec<char> = word.word.chars().collect();
+    if chars.len() < 2 || !chars.iter().any(|&c| is_cjk(c)) {
+        return vec![word];
+    }
+    let step = (word.end - word.start) / chars.len() as f32;
+    chars
+        .iter()
+        .enumerate()
+        .map(|(i, c)| WordSpan {
+            word: c.to_string(),
+            start: word.start + step * i as f32,
+            end: word.start + step * (i + 1) as f32,
+        })
+        .collect()
+}
+
+/// How alike two keys are, from 0.0 to 1.0.
+pub(crate) fn similarity(a: &str, b: &str) -> f32 {
+    let len = a.chars().count().max(b.chars().count());
+    if len == 0 {
+        return 1.0;
+    }
+    1.0 - edit_distance(a, b) as f32 / len as f32
+}
+
+/// Align `units` to `heard` with a word-level edit distance, then interpolate the
+/// units nothing was heard for.
+fn align_units(units: &[Unit], heard: &[WordSpan], duration: f32) -> Vec<AlignedToken> {
+    let keys: Vec<String> = heard.iter().map(|word| normalize(&word.word)).collect();
+    let (n, m) = (units.len(), heard.len());
+
+    // cost[i][j]: aligning the first i units with the first j heard words
+    let mut cost = vec![vec![0.0f32; m + 1]; n + 1];
+    for (i, row) in cost.iter_mut().enumerate() {
+        row[0] = i as f32;
+    }
+    for (j, cell) in cost[0].iter_mut().enumerate() {
+        *cell = j as f32;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            let substitute = cost[i - 1][j - 1] + 1.0 - similarity(&units[i - 1].key, &keys[j - 1]);
+            cost[i][j] = substitute
+                .min(cost[i - 1][j] + 1.0)
+                .min(cost[i][j - 1] + 1.0);
+        }
+    }
+
+    let mut matched: Vec<Option<(usize, f32)>> = vec![None; n];
+    let (mut i, mut j) = (n, m);
+    while i > 0 && j > 0 {
+        let score = similarity(&units[i - 1].key, &keys[j - 1]);
+        if cost[i][j] == cost[i - 1][j - 1] + 1.0 - score {
+            // A substitution with nothing in common is a deletion and an insertion
+            if score > 0.0 {
+                matched[i - 1] = Some((j - 1, score));
+            }
+            i -= 1;
+            j -= 1;
+        } else if cost[i][j] == cost[i - 1][j] + 1.0 {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+
+    let mut tokens: Vec<AlignedToken> = units
+        .iter()
+        .zip(&matched)
+        .map(|(unit, matched)| {
+            let (start, end, score) = match matched {
+                Some((j, score)) => (heard[*j].start, heard[*j].end, *score),
+                None => (f32::NAN, f32::NAN, 0.0),
+            };
+            AlignedToken {
+                token: unit.text.clone(),
+                start,
+                end,
+                score,
+            }
+        })
+        .collect();
+
+    // Spread each run of unmatched units over the gap between its neighbours
+    let mut i = 0;
+    while i < tokens.len() {
+        if !tokens[i].start.is_nan() {
+            i += 1;
+            continue;
+        }
+        let run_start = i;
+        while i < tokens.len() && tokens[i].start.is_nan() {
+            i += 1;
+        }
+        let from = run_start
+            .checked_sub(1)
+            .map(|prev| tokens[prev].end)
+            .unwrap_or(0.0);
+        let to = tokens.get(i).map(|next| next.start).unwrap_or(duration);
+        let to = to.max(from);
+        let step = (to - from) / (i - run_start) as f32;
+        for (k, token) in tokens[run_start..i].iter_mut().enumerate() {
+            token.start = from + step * k as f32;
+            token.end = from + step * (k + 1) as f32;
+        }
+    }
+    tokens
+}
@@ -1,34 +1,106 @@
-use eyre::{bail, Result};
+use std::{fs, path::Path};
 
 use crate::{
     get_default_provider,
+    handle::NativeHandle,
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
     utils::{cstr_to_string, cstring_from_str},
+    Error, FallbackPolicy, Provider, Result, Threads,
 };
 
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct AudioTagConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub labels: String,
     pub top_k: i32,
+    /// When asking for all tags (`top_k <= 0`), drop the ones below this probability.
+    pub threshold: f32,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::opt_path"))]
     pub ced: Option<String>,
     pub debug: bool,
-    pub num_threads: Option<i32>,
-    pub provider: Option<String>,
+    pub num_threads: Threads,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+}
+
+#[derive(Debug, Clone)]
+pub struct TagResult {
+    pub name: String,
+    pub index: i32,
+    pub prob: f32,
+}
+
+/// A row of the labels CSV shipped with the models.
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub index: i32,
+    /// AudioSet machine id, e.g. `/m/0bt9lr`.
+    pub mid: String,
+    pub display_name: String,
 }
 
 pub struct AudioTag {
-    audio_tag: *const sherpa_rs_sys::SherpaOnnxAudioTagging,
+    audio_tag: NativeHandle<sherpa_rs_sys::SherpaOnnxAudioTagging>,
     config: AudioTagConfig,
+    labels: Vec<Label>,
+    provider: ProviderSelection,
 }
 
 impl AudioTag {
     pub fn new(config: AudioTagConfig) -> Result<Self> {
+        let _timed = timed!("audio_tag.create");
+        debug_event!(?config, "creating audio tagger");
+        let (mut tagger, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(AudioTagConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        tagger.provider = provider;
+        Ok(tagger)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    fn create(config: AudioTagConfig) -> Result<Self> {
         let config_clone = config.clone();
+        let ced_model = config.ced.clone().unwrap_or_default();
+        if config.model.is_empty() && ced_model.is_empty() {
+            return Err(Error::invalid_input(
+                "audio tagging needs a zipformer model or a ced model",
+            ));
+        }
+        for (name, path) in [("model", &config.model), ("ced", &ced_model)] {
+            if !path.is_empty() && !Path::new(path).exists() {
+                return Err(Error::model_not_found(
+                    format!("audio tagging {}", name),
+                    path,
+                ));
+            }
+        }
+        let label_rows = read_labels(&config.labels)?;
 
         let model = cstring_from_str(&config.model);
         let ced = cstring_from_str(&config.ced.unwrap_or_default());
         let labels = cstring_from_str(&config.labels);
-        let provider = cstring_from_str(&config.provider.unwrap_or(get_default_provider()));
+        let provider = config
+            .provider
+            .unwrap_or_else(get_default_provider)
+            .to_cstring()?;
 
         let sherpa_config = sherpa_rs_sys::SherpaOnnxAudioTaggingConfig {
             model: sherpa_rs_sys::SherpaOnnxAudioTaggingModelConfig {
@@ -36,60 +108,121 @@ impl AudioTag {
                     model: model.as_ptr(),
                 },
                 ced: ced.as_ptr(),
-                num_threads: config.num_threads.unwrap_or(1),
+                num_threads: config.num_threads.to_raw(),
                 debug: config.debug.into(),
                 provider: provider.as_ptr(),
             },
             labels: labels.as_ptr(),
             top_k: config.top_k,
         };
-        let audio_tag = unsafe { sherpa_rs_sys::SherpaOnnxCreateAudioTagging(&sherpa_config) };
-
-        if audio_tag.is_null() {
-            bail!("Failed to create audio tagging");
+        let audio_tag = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateAudioTagging(&sherpa_config),
+                sherpa_rs_sys::SherpaOnnxDestroyAudioTagging,
+            )
         }
+        .ok_or_else(|| Error::native_creation_failed("audio tagging"))?;
         Ok(Self {
             audio_tag,
             config: config_clone,
+            labels: label_rows,
+            provider: ProviderSelection::default(),
         })
     }
 
-    pub fn compute(&mut self, samples: Vec<f32>, sample_rate: u32) -> Vec<String> {
-        let mut events = Vec::new();
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    /// The `top_k` most likely tags, or every tag above the configured threshold if
+    /// `top_k <= 0`. Most likely first.
+    pub fn compute_tags(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        top_k: i32,
+    ) -> Result<Vec<TagResult>> {
+        let _timed = timed!("audio_tag.compute", sample_rate);
+        let k = if top_k > 0 {
+            top_k
+        } else {
+            self.labels.len() as i32
+        };
+        let mut tags = Vec::new();
         unsafe {
-            let stream = sherpa_rs_sys::SherpaOnnxAudioTaggingCreateOfflineStream(self.audio_tag);
+            let stream =
+                sherpa_rs_sys::SherpaOnnxAudioTaggingCreateOfflineStream(self.audio_tag.as_ptr());
+            if stream.is_null() {
+                return Err(Error::processing_failed(
+                    "audio tagging",
+                    "failed to create a stream",
+                ));
+            }
             sherpa_rs_sys::SherpaOnnxAcceptWaveformOffline(
                 stream,
                 sample_rate as i32,
                 samples.as_ptr(),
                 samples.len() as i32,
             );
-
-            let results = sherpa_rs_sys::SherpaOnnxAudioTaggingCompute(
-                self.audio_tag,
-                stream,
-                self.config.top_k,
-            );
-
-            for i in 0..self.config.top_k {
-                let event = *results.add(i.try_into().unwrap());
-                let event_name = cstr_to_string((*event).name as _);
-                events.push(event_name);
+            let results =
+                sherpa_rs_sys::SherpaOnnxAudioTaggingCompute(self.audio_tag.as_ptr(), stream, k);
+            sherpa_rs_sys::SherpaOnnxDestroyOfflineStream(stream);
+            if results.is_null() {
+                return Err(Error::processing_failed(
+                    "audio tagging",
+                    "no tags returned",
+                ));
             }
 
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineStream(stream);
+            // The result array is null terminated
+            let mut cursor = results;
+            while !(*cursor).is_null() {
+                let event = &**cursor;
+                tags.push(TagResult {
+                    name: cstr_to_string(event.name as _),
+                    index: event.index,
+                    prob: event.prob,
+                });
+                cursor = cursor.add(1);
+            }
+            sherpa_rs_sys::SherpaOnnxAudioTaggingFreeResults(results);
+        }
+        if top_k <= 0 {
+            tags.retain(|tag| tag.prob >= self.config.threshold);
         }
-        events
+        Ok(tags)
     }
-}
 
-unsafe impl Send for AudioTag {}
-unsafe impl Sync for AudioTag {}
+    pub fn compute(&mut self, samples: Vec<f32>, sample_rate: u32) -> Vec<String> {
+        self.compute_tags(&samples, sample_rate, self.config.top_k)
+            .map(|tags| tags.into_iter().map(|tag| tag.name).collect())
+            .unwrap_or_default()
+    }
+}
 
-impl Drop for AudioTag {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyAudioTagging(self.audio_tag);
-        }
+fn read_labels(path: &str) -> Result<Vec<Label>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|_| Error::model_not_found("audio tagging labels", path))?;
+    let mut labels = Vec::new();
+    for line in contents.lines() {
+        // index,mid,display_name where the name may be quoted and contain commas
+        let mut fields = line.splitn(3, ',');
+        let (Some(index), Some(mid), Some(name)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        // Skips the header
+        let Ok(index) = index.trim().parse() else {
+            continue;
+        };
+        labels.push(Label {
+            index,
+            mid: mid.trim().to_string(),
+            display_name: name.trim().trim_matches('"').to_string(),
+        });
     }
+    Ok(labels)
 }
+
+unsafe impl Send for AudioTag {}
+unsafe impl Sync for AudioTag {}
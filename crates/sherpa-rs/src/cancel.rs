@@ -0,0 +1,39 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{Error, Result};
+
+/// Flag shared between an operation and whoever wants to stop it.
+///
+/// Clones share the same flag. Long-running operations check it between chunks,
+/// sentences or segments and return [`Error::Cancelled`], so cancelling takes effect at
+/// the next boundary rather than immediately.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask operations holding this token, or a clone of it, to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// `Err(Error::Cancelled)` for `component` if cancelled.
+    pub(crate) fn check(&self, component: &str) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Error::cancelled(component));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,316 @@
+//! Live microphone audio as mono `f32` chunks at the rate a model expects.
+//!
+//! [`MicSource`] opens an input device with cpal, mixes it down to mono and resamples it
+//! with [`Resampler`], so the chunks it yields go straight into
+//! [`OnlineStream::accept_waveform`](crate::online::OnlineStream::accept_waveform) or
+//! [`SpeechCollector::push`](crate::vad::SpeechCollector::push).
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc,
+    },
+    time::Duration,
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, InputCallbackInfo, Sample, SampleFormat, SizedSample, StreamConfig, StreamInstant,
+    SupportedStreamConfig,
+};
+
+use crate::{
+    trace::debug_event,
+    utils::{downmix, AudioSource, Resampler},
+    Error, Result,
+};
+
+/// How long [`MicSource`] waits for the device before giving up on it.
+const DEVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct MicConfig {
+    /// Input device, one of [`input_devices`]. The default device if `None`.
+    pub device_name: Option<String>,
+    /// Rate of the chunks, whatever the device runs at.
+    pub sample_rate: u32,
+    /// Length of each chunk.
+    pub chunk_ms: u32,
+    /// Audio held for a slow reader before chunks are dropped and counted as overruns.
+    pub buffer_secs: f32,
+}
+
+impl Default for MicConfig {
+    fn default() -> Self {
+        Self {
+            device_name: None,
+            sample_rate: 16000,
+            chunk_ms: 100,
+            buffer_secs: 5.0,
+        }
+    }
+}
+
+/// Capture problems counted by [`MicSource::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    /// Device buffers dropped because chunks weren't read fast enough.
+    pub overruns: u64,
+    /// Gaps in the audio the device delivered, and stream errors it reported.
+    pub underruns: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    overruns: AtomicU64,
+    underruns: AtomicU64,
+}
+
+/// Names of the input devices of the default host, for [`MicConfig::device_name`].
+pub fn input_devices() -> Result<Vec<String>> {
+    let devices = cpal::default_host()
+        .input_devices()
+        .map_err(|err| Error::processing_failed("capture", err.to_string()))?;
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+/// Microphone input, read as an iterator of mono chunks or as an [`AudioSource`].
+///
+/// Capture starts in [`Self::open`] and stops when the source is dropped. Iteration only
+/// ends if the device goes away.
+pub struct MicSource {
+    /// Capture stops when this is dropped.
+    _stream: cpal::Stream,
+    receiver: Receiver<Vec<f32>>,
+    resampler: Option<Resampler>,
+    /// Resampled audio not returned yet.
+    pending: Vec<f32>,
+    sample_rate: u32,
+    chunk_size: usize,
+    counters: Arc<Counters>,
+}
+
+impl MicSource {
+    pub fn open(config: MicConfig) -> Result<Self> {
+        if config.sample_rate == 0 || config.chunk_ms == 0 {
+            return Err(Error::invalid_input(
+                "capture sample rate and chunk length must be non-zero",
+            ));
+        }
+        let host = cpal::default_host();
+        let device = match &config.device_name {
+            Some(name) => host
+                .input_devices()
+                .map_err(|err| Error::processing_failed("capture", err.to_string()))?
+                .find(|device| device.name().is_ok_and(|n| &n == name))
+                .ok_or_else(|| Error::invalid_input(format!("no input device named {:?}", name)))?,
+            None => host
+                .default_input_device()
+                .ok_or_else(|| Error::unsupported("capture without an input device"))?,
+        };
+
+        let supported = input_config(&device, config.sample_rate)?;
+        let stream_config: StreamConfig = supported.config();
+        let device_rate = stream_config.sample_rate.0;
+        debug_event!(
+            device_rate,
+            device_channels = stream_config.channels,
+            sample_rate = config.sample_rate,
+            "opening microphone"
+        );
+
+        // One message per device callback, each usually 10 ms or less
+        let capacity = ((config.buffer_secs.max(0.1) * 100.0) as usize).max(1);
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let counters = Arc::new(Counters::default());
+        let feed = Feed {
+            sender,
+            channels: stream_config.channels.max(1) as usize,
+            sample_rate: device_rate,
+            counters: counters.clone(),
+            expected: None,
+        };
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build::<f32>(&device, &stream_config, feed),
+            SampleFormat::I16 => build::<i16>(&device, &stream_config, feed),
+            SampleFormat::U16 => build::<u16>(&device, &stream_config, feed),
+            SampleFormat::I32 => build::<i32>(&device, &stream_config, feed),
+            other => {
+                return Err(Error::unsupported(format!(
+                    "capture from a device with {} samples",
+                    other
+                )))
+            }
+        }?;
+        stream
+            .play()
+            .map_err(|err| Error::processing_failed("capture", err.to_string()))?;
+
+        let resampler = if device_rate == config.sample_rate {
+            None
+        } else {
+            Some(Resampler::new(device_rate, config.sample_rate, 1)?)
+        };
+        Ok(Self {
+            _stream: stream,
+            receiver,
+            resampler,
+            pending: Vec::new(),
+            sample_rate: config.sample_rate,
+            chunk_size: (config.sample_rate as usize * config.chunk_ms as usize / 1000).max(1),
+            counters,
+        })
+    }
+
+    /// Rate of the returned chunks, [`MicConfig::sample_rate`].
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Overruns and underruns since the source was opened.
+    pub fn stats(&self) -> CaptureStats {
+        CaptureStats {
+            overruns: self.counters.overruns.load(Ordering::Relaxed),
+            underruns: self.counters.underruns.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Block until at least `min` samples are pending. Fails if the device stops
+    /// delivering audio.
+    fn fill(&mut self, min: usize) -> Result<()> {
+        while self.pending.len() < min {
+            let samples = match self.receiver.recv_timeout(DEVICE_TIMEOUT) {
+                Ok(samples) => samples,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(Error::processing_failed(
+                        "capture",
+                        "the input device stopped delivering audio",
+                    ))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::processing_failed(
+                        "capture",
+                        "the input stream closed",
+                    ))
+                }
+            };
+            match &mut self.resampler {
+                Some(resampler) => self.pending.extend(resampler.process(&samples)),
+                None => self.pending.extend_from_slice(&samples),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for MicSource {
+    type Item = Vec<f32>;
+
+    /// The next chunk of [`MicConfig::chunk_ms`], `None` once the device is gone.
+    fn next(&mut self) -> Option<Vec<f32>> {
+        if let Err(err) = self.fill(self.chunk_size) {
+            tracing::warn!("{}", err);
+            return None;
+        }
+        let rest = self.pending.split_off(self.chunk_size);
+        Some(std::mem::replace(&mut self.pending, rest))
+    }
+}
+
+impl AudioSource for MicSource {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    /// Block until audio is available and return up to `frames` of it.
+    fn read_frames(&mut self, frames: usize) -> Result<Vec<f32>> {
+        if frames == 0 {
+            return Ok(Vec::new());
+        }
+        self.fill(1)?;
+        let rest = self.pending.split_off(frames.min(self.pending.len()));
+        Ok(std::mem::replace(&mut self.pending, rest))
+    }
+}
+
+/// State of the device callback.
+struct Feed {
+    sender: SyncSender<Vec<f32>>,
+    channels: usize,
+    sample_rate: u32,
+    counters: Arc<Counters>,
+    /// When the next buffer should have been captured, to notice gaps.
+    expected: Option<StreamInstant>,
+}
+
+impl Feed {
+    fn push(&mut self, samples: Vec<f32>, info: &InputCallbackInfo) {
+        let frames = samples.len() / self.channels;
+        let duration = Duration::from_secs_f64(frames as f64 / self.sample_rate as f64);
+        let captured = info.timestamp().capture;
+        let late = self
+            .expected
+            .and_then(|expected| captured.duration_since(&expected))
+            .is_some_and(|late| late > duration);
+        if late {
+            self.counters.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+        self.expected = captured.add(duration);
+
+        let mono = if self.channels == 1 {
+            samples
+        } else {
+            downmix(&samples, self.channels)
+        };
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(mono) {
+            self.counters.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Config the device supports for capture, preferring `sample_rate` so nothing has to
+/// be resampled.
+fn input_config(device: &cpal::Device, sample_rate: u32) -> Result<SupportedStreamConfig> {
+    let rate = cpal::SampleRate(sample_rate);
+    let exact = device
+        .supported_input_configs()
+        .map_err(|err| Error::processing_failed("capture", err.to_string()))?
+        .filter(|range| range.min_sample_rate() <= rate && rate <= range.max_sample_rate())
+        .min_by_key(|range| (range.channels(), range.sample_format() != SampleFormat::F32));
+    match exact {
+        Some(range) => Ok(range.with_sample_rate(rate)),
+        None => device
+            .default_input_config()
+            .map_err(|err| Error::processing_failed("capture", err.to_string())),
+    }
+}
+
+fn build<T>(device: &cpal::Device, config: &StreamConfig, mut feed: Feed) -> Result<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let counters = feed.counters.clone();
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], info: &InputCallbackInfo| {
+                let samples = data
+                    .iter()
+                    .map(|&sample| f32::from_sample(sample))
+                    .collect();
+                feed.push(samples, info);
+            },
+            move |err| {
+                counters.underruns.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!("capture stream error: {}", err);
+            },
+            None,
+        )
+        .map_err(|err| Error::processing_failed("capture", err.to_string()))
+}
@@ -0,0 +1,212 @@
+//! Path handling for configs loaded with serde.
+//!
+//! Model paths in deserialized configs get a leading `~` expanded to the home directory.
+//! Relative paths are resolved against the directory set with [`with_base_dir`], usually
+//! the one holding the config file, and left as they are otherwise. Empty paths stay
+//! empty so unset models remain unset.
+//!
+//! ```ignore
+//! let text = std::fs::read_to_string("models/engine.toml")?;
+//! let config: WhisperConfig = with_base_dir("models", || toml::from_str(&text))?;
+//! ```
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Deserializer};
+
+thread_local! {
+    static BASE_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with relative paths in deserialized configs resolved against `base_dir`.
+///
+/// Applies to deserialization on the current thread only. Calls can be nested, the
+/// previous base directory is restored when `f` returns or panics.
+pub fn with_base_dir<T>(base_dir: impl Into<PathBuf>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<PathBuf>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            BASE_DIR.with(|base| *base.borrow_mut() = self.0.take());
+        }
+    }
+
+    let previous = BASE_DIR.with(|base| base.replace(Some(base_dir.into())));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// Expand `~` and resolve `path` against the current base directory, the way path
+/// fields of deserialized configs are.
+pub fn resolve_path(path: &Path) -> PathBuf {
+    if path.as_os_str().is_empty() {
+        return PathBuf::new();
+    }
+    let path = match path.strip_prefix("~") {
+        Ok(rest) => match home_dir() {
+            Some(home) => home.join(rest),
+            None => path.to_path_buf(),
+        },
+        Err(_) => path.to_path_buf(),
+    };
+    if path.is_absolute() {
+        return path;
+    }
+    BASE_DIR.with(|base| match &*base.borrow() {
+        Some(base) => base.join(&path),
+        None => path,
+    })
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+fn resolve_str(path: &str) -> String {
+    resolve_path(Path::new(path)).to_string_lossy().into_owned()
+}
+
+pub(crate) fn path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(resolve_str(&String::deserialize(deserializer)?))
+}
+
+/// For fields taking several comma separated paths, like lexicons.
+pub(crate) fn path_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(String::deserialize(deserializer)?
+        .split(',')
+        .map(resolve_str)
+        .collect::<Vec<_>>()
+        .join(","))
+}
+
+pub(crate) fn opt_path<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.map(|path| resolve_str(&path)))
+}
+
+pub(crate) fn path_buf<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    Ok(resolve_path(&PathBuf::deserialize(deserializer)?))
+}
+
+pub(crate) fn opt_path_buf<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<PathBuf>, D::Error> {
+    Ok(Option::<PathBuf>::deserialize(deserializer)?.map(|path| resolve_path(&path)))
+}
+
+pub(crate) fn path_bufs<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<PathBuf>, D::Error> {
+    Ok(Vec::<PathBuf>::deserialize(deserializer)?
+        .into_iter()
+        .map(|path| resolve_path(&path))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, serde::Serialize, Deserialize)]
+    #[serde(default)]
+    struct Paths {
+        #[serde(deserialize_with = "path")]
+        model: String,
+        #[serde(deserialize_with = "path")]
+        absolute: String,
+        #[serde(deserialize_with = "path")]
+        unset: String,
+        #[serde(deserialize_with = "path_list")]
+        lexicon: String,
+        #[serde(deserialize_with = "opt_path")]
+        vocoder: Option<String>,
+        #[serde(deserialize_with = "path_buf")]
+        data_dir: PathBuf,
+        #[serde(deserialize_with = "path_bufs")]
+        rule_fsts: Vec<PathBuf>,
+    }
+
+    fn written() -> Paths {
+        Paths {
+            model: "model.onnx".into(),
+            absolute: std::env::temp_dir()
+                .join("absolute.onnx")
+                .to_string_lossy()
+                .into_owned(),
+            unset: String::new(),
+            lexicon: "lexicon-us.txt,zh/lexicon.txt".into(),
+            vocoder: Some("vocoder.onnx".into()),
+            data_dir: "espeak-ng-data".into(),
+            rule_fsts: vec!["date.fst".into(), "number.fst".into()],
+        }
+    }
+
+    /// `written()` as read with `base` as the base directory.
+    fn resolved(base: &Path) -> Paths {
+        let join = |path: &str| base.join(path).to_string_lossy().into_owned();
+        Paths {
+            model: join("model.onnx"),
+            lexicon: format!("{},{}", join("lexicon-us.txt"), join("zh/lexicon.txt")),
+            vocoder: Some(join("vocoder.onnx")),
+            data_dir: base.join("espeak-ng-data"),
+            rule_fsts: vec![base.join("date.fst"), base.join("number.fst")],
+            ..written()
+        }
+    }
+
+    #[test]
+    fn toml_round_trip_resolves_relative_paths() {
+        let base = std::env::temp_dir().join("models");
+        let text = toml::to_string(&written()).unwrap();
+        let read: Paths = with_base_dir(&base, || toml::from_str(&text)).unwrap();
+        assert_eq!(read, resolved(&base));
+
+        // Resolved paths are absolute, reading them again changes nothing
+        let text = toml::to_string(&read).unwrap();
+        let again: Paths = with_base_dir("elsewhere", || toml::from_str(&text)).unwrap();
+        assert_eq!(again, read);
+    }
+
+    #[test]
+    fn json_round_trip_resolves_relative_paths() {
+        let base = std::env::temp_dir().join("models");
+        let text = serde_json::to_string(&written()).unwrap();
+        let read: Paths = with_base_dir(&base, || serde_json::from_str(&text)).unwrap();
+        assert_eq!(read, resolved(&base));
+    }
+
+    #[test]
+    fn relative_paths_stay_relative_without_a_base_dir() {
+        let text = serde_json::to_string(&written()).unwrap();
+        let read: Paths = serde_json::from_str(&text).unwrap();
+        assert_eq!(read, written());
+    }
+
+    #[test]
+    fn nested_base_dirs_are_restored() {
+        with_base_dir("outer", || {
+            with_base_dir("inner", || {
+                assert_eq!(resolve_path(Path::new("a")), Path::new("inner").join("a"));
+            });
+            assert_eq!(resolve_path(Path::new("a")), Path::new("outer").join("a"));
+        });
+        assert_eq!(resolve_path(Path::new("a")), Path::new("a"));
+    }
+
+    #[test]
+    fn tilde_expands_to_home() {
+        if let Some(home) = home_dir() {
+            assert_eq!(
+                with_base_dir("models", || resolve_path(Path::new("~/model.onnx"))),
+                home.join("model.onnx")
+            );
+        }
+    }
+}
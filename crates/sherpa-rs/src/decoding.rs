@@ -0,0 +1,71 @@
+use std::{ffi::CString, fmt, str::FromStr};
+
+use crate::{utils::cstring_from_str, Error, Result};
+
+/// `max_active_paths` sherpa-onnx uses when it's left at zero.
+pub const DEFAULT_MAX_ACTIVE_PATHS: i32 = 4;
+
+/// How a transducer picks tokens from the joiner output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DecodingMethod {
+    /// Best token at each step. Fastest, and all CTC and attention models support only
+    /// this.
+    #[default]
+    GreedySearch,
+    /// Keep `max_active_paths` hypotheses. Needed for hotwords, and usually fewer
+    /// deletions.
+    ModifiedBeamSearch,
+}
+
+impl DecodingMethod {
+    /// Name sherpa-onnx expects.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DecodingMethod::GreedySearch => "greedy_search",
+            DecodingMethod::ModifiedBeamSearch => "modified_beam_search",
+        }
+    }
+
+    pub(crate) fn to_cstring(self) -> CString {
+        cstring_from_str(self.as_str())
+    }
+}
+
+impl fmt::Display for DecodingMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DecodingMethod {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "greedy_search" => Ok(DecodingMethod::GreedySearch),
+            "modified_beam_search" => Ok(DecodingMethod::ModifiedBeamSearch),
+            other => Err(Error::invalid_input(format!(
+                "unknown decoding method {:?}, expected greedy_search or modified_beam_search",
+                other
+            ))),
+        }
+    }
+}
+
+/// Warn about beam search settings that greedy search ignores. They don't stop the
+/// model from loading, sherpa-onnx just doesn't use them.
+pub(crate) fn warn_unused_beam_options(
+    component: &str,
+    method: DecodingMethod,
+    max_active_paths: i32,
+) {
+    if method == DecodingMethod::GreedySearch && max_active_paths != DEFAULT_MAX_ACTIVE_PATHS {
+        tracing::warn!(
+            "{}: max_active_paths {} is ignored with greedy_search, use modified_beam_search",
+            component,
+            max_active_paths
+        );
+    }
+}
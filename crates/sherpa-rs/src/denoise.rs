@@ -0,0 +1,189 @@
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{cstring_from_str, read_wav, resample, ChannelMix},
+    Error, FallbackPolicy, Provider, Result, Threads,
+};
+use std::path::Path;
+
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SpeechDenoiserConfig {
+    /// GTCRN model.
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+    pub model: String,
+    pub num_threads: Threads,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub debug: bool,
+    /// Resample input that doesn't match the model rate, and the output back afterwards.
+    /// Otherwise the output is at the model rate.
+    pub auto_resample: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DenoisedAudio {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+impl DenoisedAudio {
+    /// Write the audio as a mono 32-bit float WAV.
+    pub fn write_wav<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for &sample in &self.samples {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+}
+
+/// Lets the audio go straight to a recognizer, e.g.
+/// `recognizer.transcribe(audio.sample_rate, audio.as_ref())`.
+impl AsRef<[f32]> for DenoisedAudio {
+    fn as_ref(&self) -> &[f32] {
+        &self.samples
+    }
+}
+
+#[derive(Debug)]
+pub struct SpeechDenoiser {
+    denoiser: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineSpeechDenoiser>,
+    auto_resample: bool,
+    provider: ProviderSelection,
+}
+
+impl SpeechDenoiser {
+    pub fn new(config: SpeechDenoiserConfig) -> Result<Self> {
+        let _timed = timed!("denoise.create");
+        debug_event!(?config, "creating speech denoiser");
+        let (mut denoiser, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(SpeechDenoiserConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        denoiser.provider = provider;
+        Ok(denoiser)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    fn create(config: SpeechDenoiserConfig) -> Result<Self> {
+        if config.model.is_empty() {
+            return Err(Error::invalid_input("denoiser model path is empty"));
+        }
+        if !Path::new(&config.model).exists() {
+            return Err(Error::model_not_found("denoiser model", &config.model));
+        }
+        let model = cstring_from_str(&config.model);
+        let provider = config
+            .provider
+            .unwrap_or_else(get_default_provider)
+            .to_cstring()?;
+
+        let sherpa_config = sherpa_rs_sys::SherpaOnnxOfflineSpeechDenoiserConfig {
+            model: sherpa_rs_sys::SherpaOnnxOfflineSpeechDenoiserModelConfig {
+                gtcrn: sherpa_rs_sys::SherpaOnnxOfflineSpeechDenoiserGtcrnModelConfig {
+                    model: model.as_ptr(),
+                },
+                num_threads: config.num_threads.to_raw(),
+                debug: config.debug.into(),
+                provider: provider.as_ptr(),
+            },
+        };
+        let denoiser = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflineSpeechDenoiser(&sherpa_config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineSpeechDenoiser,
+            )
+        }
+        .ok_or_else(|| Error::native_creation_failed("speech denoiser"))?;
+        Ok(Self {
+            denoiser,
+            auto_resample: config.auto_resample,
+            provider: ProviderSelection::default(),
+        })
+    }
+
+    /// Sample rate the model runs at.
+    pub fn sample_rate(&self) -> u32 {
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxOfflineSpeechDenoiserGetSampleRate(self.denoiser.as_ptr())
+                as u32
+        }
+    }
+
+    /// Denoise mono audio.
+    pub fn run(&mut self, samples: &[f32], sample_rate: u32) -> Result<DenoisedAudio> {
+        let _timed = timed!("denoise.run", sample_rate, samples = samples.len());
+        if sample_rate == 0 {
+            return Err(Error::invalid_input("sample rate must be positive"));
+        }
+        let model_rate = self.sample_rate();
+        if !self.auto_resample || model_rate == 0 || model_rate == sample_rate {
+            return self.run_native(samples, sample_rate);
+        }
+
+        let resampled = resample(samples, sample_rate, model_rate);
+        let mut audio = self.run_native(&resampled, model_rate)?;
+        if audio.sample_rate != sample_rate {
+            audio.samples = resample(&audio.samples, audio.sample_rate, sample_rate);
+            audio.sample_rate = sample_rate;
+        }
+        Ok(audio)
+    }
+
+    /// Read a WAV file and denoise it. Several channels are mixed down to mono.
+    pub fn run_file<P: AsRef<Path>>(&mut self, path: P) -> Result<DenoisedAudio> {
+        let wav = read_wav(path)?;
+        let samples = wav.to_mono(ChannelMix::Average);
+        self.run(&samples, wav.sample_rate)
+    }
+
+    fn run_native(&mut self, samples: &[f32], sample_rate: u32) -> Result<DenoisedAudio> {
+        unsafe {
+            let result = sherpa_rs_sys::SherpaOnnxOfflineSpeechDenoiserRun(
+                self.denoiser.as_ptr(),
+                samples.as_ptr(),
+                samples.len() as i32,
+                sample_rate as i32,
+            );
+            if result.is_null() {
+                return Err(Error::processing_failed(
+                    "speech denoiser",
+                    "no audio returned",
+                ));
+            }
+            let audio = DenoisedAudio {
+                samples: std::slice::from_raw_parts((*result).samples, (*result).n.max(0) as usize)
+                    .to_vec(),
+                sample_rate: (*result).sample_rate as u32,
+            };
+            sherpa_rs_sys::SherpaOnnxDestroyDenoisedAudio(result);
+            Ok(audio)
+        }
+    }
+}
+
+unsafe impl Send for SpeechDenoiser {}
+unsafe impl Sync for SpeechDenoiser {}
@@ -1,10 +1,17 @@
-use crate::{get_default_provider, utils::cstring_from_str};
-use eyre::{bail, Result};
-use std::{path::Path, ptr::null_mut};
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{cstring_from_str, resample, FfiCallback},
+    CancellationToken, Error, FallbackPolicy, Provider, Result, Threads,
+};
+use std::{borrow::Cow, collections::HashMap, fmt::Write, fs, path::Path, ptr::null_mut};
 
 #[derive(Debug)]
 pub struct Diarize {
-    sd: *const sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarization,
+    sd: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarization>,
+    provider: ProviderSelection,
 }
 
 #[derive(Debug, Clone)]
@@ -14,15 +21,295 @@ pub struct Segment {
     pub speaker: i32,
 }
 
+impl Segment {
+    /// One RTTM `SPEAKER` line, without the newline.
+    pub fn to_rttm_line(&self, uri: &str) -> String {
+        format!(
+            "SPEAKER {} 1 {:.3} {:.3} <NA> <NA> speaker_{} <NA> <NA>",
+            uri,
+            self.start,
+            (self.end - self.start).max(0.0),
+            self.speaker
+        )
+    }
+}
+
+pub fn write_rttm<P: AsRef<Path>>(path: P, uri: &str, segments: &[Segment]) -> Result<()> {
+    let path = path.as_ref();
+    let mut out = String::new();
+    for segment in segments {
+        let _ = writeln!(out, "{}", segment.to_rttm_line(uri));
+    }
+    Ok(fs::write(path, out)?)
+}
+
+/// Read the `SPEAKER` lines of an RTTM file.
+///
+/// Labels like `speaker_3` or `3` keep their number. If any label isn't numbered, all
+/// speakers are numbered in order of first appearance instead.
+pub fn read_rttm<P: AsRef<Path>>(path: P) -> Result<Vec<Segment>> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)?;
+
+    let mut rows = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.first() != Some(&"SPEAKER") {
+            continue;
+        }
+        if fields.len() < 8 {
+            return Err(Error::invalid_input(format!(
+                "{}:{}: expected at least 8 fields",
+                path.display(),
+                i + 1
+            )));
+        }
+        let parse = |field: &str| {
+            field.parse::<f32>().map_err(|_| {
+                Error::invalid_input(format!(
+                    "{}:{}: invalid time {:?}",
+                    path.display(),
+                    i + 1,
+                    field
+                ))
+            })
+        };
+        let start = parse(fields[3])?;
+        let duration = parse(fields[4])?;
+        rows.push((start, start + duration.max(0.0), fields[7]));
+    }
+
+    let numbered = |label: &str| {
+        label
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .parse::<i32>()
+            .ok()
+            .filter(|_| label.ends_with(|c: char| c.is_ascii_digit()))
+    };
+    let all_numbered = rows.iter().all(|(_, _, label)| numbered(label).is_some());
+    let mut ids: HashMap<&str, i32> = HashMap::new();
+    let segments = rows
+        .iter()
+        .map(|&(start, end, label)| {
+            let speaker = if all_numbered {
+                numbered(label).unwrap_or_default()
+            } else {
+                let next = ids.len() as i32;
+                *ids.entry(label).or_insert(next)
+            };
+            Segment {
+                start,
+                end,
+                speaker,
+            }
+        })
+        .collect();
+    Ok(segments)
+}
+
+/// Diarization error, in seconds of scored speech.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiarizationErrorRate {
+    /// `(missed + false_alarm + confusion) / total`, 0 if there is no reference speech.
+    pub der: f32,
+    pub missed: f32,
+    pub false_alarm: f32,
+    pub confusion: f32,
+    /// Reference speech time, counted once per active speaker.
+    pub total: f32,
+}
+
+/// Score `hypothesis` against `reference`, ignoring `collar_secs` around each reference
+/// boundary.
+///
+/// Speakers are matched one to one to maximize their overlap, so labels don't need to
+/// agree.
+pub fn diarization_error_rate(
+    reference: &[Segment],
+    hypothesis: &[Segment],
+    collar_secs: f32,
+) -> DiarizationErrorRate {
+    let collar = collar_secs.max(0.0) as f64;
+    let no_score: Vec<(f64, f64)> = reference
+        .iter()
+        .flat_map(|s| [s.start as f64, s.end as f64])
+        .map(|t| (t - collar, t + collar))
+        .collect();
+
+    let mut points: Vec<f64> = reference
+        .iter()
+        .chain(hypothesis)
+        .flat_map(|s| [s.start as f64, s.end as f64])
+        .chain(no_score.iter().flat_map(|&(a, b)| [a, b]))
+        .collect();
+    points.sort_by(|a, b| a.total_cmp(b));
+    points.dedup();
+
+    let speaker_index = |segments: &[Segment]| {
+        let mut index: HashMap<i32, usize> = HashMap::new();
+        for s in segments {
+            let next = index.len();
+            index.entry(s.speaker).or_insert(next);
+        }
+        index
+    };
+    let ref_index = speaker_index(reference);
+    let hyp_index = speaker_index(hypothesis);
+    let active = |segments: &[Segment], index: &HashMap<i32, usize>, t: f64| {
+        let mut ids: Vec<usize> = segments
+            .iter()
+            .filter(|s| (s.start as f64) <= t && t < s.end as f64)
+            .map(|s| index[&s.speaker])
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    };
+
+    // Elementary intervals between boundaries, with the speakers active in each
+    let mut intervals = Vec::new();
+    let mut overlap = vec![vec![0.0; hyp_index.len()]; ref_index.len()];
+    for pair in points.windows(2) {
+        let (t0, t1) = (pair[0], pair[1]);
+        let mid = (t0 + t1) / 2.0;
+        if no_score
+            .iter()
+            .any(|&(a, b)| a <= mid && mid < b && collar > 0.0)
+        {
+            continue;
+        }
+        let refs = active(reference, &ref_index, mid);
+        let hyps = active(hypothesis, &hyp_index, mid);
+        if refs.is_empty() && hyps.is_empty() {
+            continue;
+        }
+        let dur = t1 - t0;
+        for &r in &refs {
+            for &h in &hyps {
+                overlap[r][h] += dur;
+            }
+        }
+        intervals.push((dur, refs, hyps));
+    }
+
+    let mapping = best_mapping(&overlap);
+    let (mut missed, mut false_alarm, mut confusion, mut total) = (0.0, 0.0, 0.0, 0.0);
+    for (dur, refs, hyps) in intervals {
+        let correct = refs
+            .iter()
+            .filter(|&&r| mapping[r].is_some_and(|h| hyps.contains(&h)))
+            .count();
+        let (n_ref, n_hyp) = (refs.len(), hyps.len());
+        missed += dur * n_ref.saturating_sub(n_hyp) as f64;
+        false_alarm += dur * n_hyp.saturating_sub(n_ref) as f64;
+        confusion += dur * (n_ref.min(n_hyp) - correct) as f64;
+        total += dur * n_ref as f64;
+    }
+
+    let der = if total > 0.0 {
+        (missed + false_alarm + confusion) / total
+    } else {
+        0.0
+    };
+    DiarizationErrorRate {
+        der: der as f32,
+        missed: missed as f32,
+        false_alarm: false_alarm as f32,
+        confusion: confusion as f32,
+        total: total as f32,
+    }
+}
+
+/// One-to-one assignment of reference to hypothesis speakers with the most total
+/// overlap (Hungarian algorithm).
+fn best_mapping(overlap: &[Vec<f64>]) -> Vec<Option<usize>> {
+    let rows = overlap.len();
+    let cols = overlap.first().map_or(0, Vec::len);
+    let n = rows.max(cols);
+    if n == 0 {
+        return vec![None; rows];
+    }
+    let max = overlap.iter().flatten().cloned().fold(0.0, f64::max);
+    let cost = |r: usize, c: usize| {
+        max - overlap
+            .get(r)
+            .and_then(|row| row.get(c))
+            .copied()
+            .unwrap_or(0.0)
+    };
+
+    // Potentials and matching are 1-based, index 0 is the unassigned sentinel
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; n + 1];
+    let mut matched = vec![0usize; n + 1];
+    let mut way = vec![0usize; n + 1];
+    for row in 1..=n {
+        matched[0] = row;
+        let mut col0 = 0;
+        let mut min_v = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[col0] = true;
+            let row0 = matched[col0];
+            let mut delta = f64::INFINITY;
+            let mut col1 = 0;
+            for col in 1..=n {
+                if used[col] {
+                    continue;
+                }
+                let reduced = cost(row0 - 1, col - 1) - u[row0] - v[col];
+                if reduced < min_v[col] {
+                    min_v[col] = reduced;
+                    way[col] = col0;
+                }
+                if min_v[col] < delta {
+                    delta = min_v[col];
+                    col1 = col;
+                }
+            }
+            for col in 0..=n {
+                if used[col] {
+                    u[matched[col]] += delta;
+                    v[col] -= delta;
+                } else {
+                    min_v[col] -= delta;
+                }
+            }
+            col0 = col1;
+            if matched[col0] == 0 {
+                break;
+            }
+        }
+        while col0 != 0 {
+            let col1 = way[col0];
+            matched[col0] = matched[col1];
+            col0 = col1;
+        }
+    }
+
+    let mut mapping = vec![None; rows];
+    for col in 1..=n {
+        let row = matched[col];
+        if row >= 1 && row <= rows && col <= cols && overlap[row - 1][col - 1] > 0.0 {
+            mapping[row - 1] = Some(col - 1);
+        }
+    }
+    mapping
+}
+
 type ProgressCallback = Box<dyn (Fn(i32, i32) -> i32) + Send + 'static>;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct DiarizeConfig {
     pub num_clusters: Option<i32>,
     pub threshold: Option<f32>,
     pub min_duration_on: Option<f32>,
     pub min_duration_off: Option<f32>,
-    pub provider: Option<String>,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub num_threads: Threads,
     pub debug: bool,
 }
 
@@ -34,6 +321,8 @@ impl Default for DiarizeConfig {
             min_duration_on: Some(0.0),
             min_duration_off: Some(0.0),
             provider: None,
+            fallback: FallbackPolicy::default(),
+            num_threads: Threads::ONE,
             debug: false,
         }
     }
@@ -45,10 +334,45 @@ impl Diarize {
         embedding_model: P,
         config: DiarizeConfig,
     ) -> Result<Self> {
-        let provider = config.provider.unwrap_or(get_default_provider());
+        let _timed = timed!("diarize.create");
+        debug_event!(?config, "creating speaker diarization");
+        let (segmentation_model, embedding_model) =
+            (segmentation_model.as_ref(), embedding_model.as_ref());
+        let (mut diarize, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(
+                    segmentation_model,
+                    embedding_model,
+                    DiarizeConfig {
+                        provider: Some(provider),
+                        ..config.clone()
+                    },
+                )
+            })?;
+        diarize.provider = provider;
+        Ok(diarize)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    fn create(
+        segmentation_model: &Path,
+        embedding_model: &Path,
+        config: DiarizeConfig,
+    ) -> Result<Self> {
+        let provider = config.provider.unwrap_or_else(get_default_provider);
 
         let debug = config.debug;
         let debug = if debug { 1 } else { 0 };
+        let num_threads = config.num_threads.to_raw();
 
         let embedding_model = embedding_model.as_ref().to_str().unwrap();
         let segmentation_model = segmentation_model.as_ref().to_str().unwrap();
@@ -59,13 +383,13 @@ impl Diarize {
         };
 
         let embedding_model = cstring_from_str(embedding_model);
-        let provider = cstring_from_str(&provider.clone());
+        let provider = provider.to_cstring()?;
         let segmentation_model = cstring_from_str(segmentation_model);
 
         let config = sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationConfig {
             embedding: sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorConfig {
                 model: embedding_model.as_ptr(),
-                num_threads: 1,
+                num_threads,
                 debug,
                 provider: provider.as_ptr(),
             },
@@ -76,76 +400,206 @@ impl Diarize {
                 pyannote: sherpa_rs_sys::SherpaOnnxOfflineSpeakerSegmentationPyannoteModelConfig {
                     model: segmentation_model.as_ptr(),
                 },
-                num_threads: 1,
+                num_threads,
                 debug,
                 provider: provider.as_ptr(),
             },
         };
 
-        let sd = unsafe { sherpa_rs_sys::SherpaOnnxCreateOfflineSpeakerDiarization(&config) };
+        let sd = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflineSpeakerDiarization(&config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineSpeakerDiarization,
+            )
+        }
+        .ok_or_else(|| Error::native_creation_failed("speaker diarization"))?;
+        Ok(Self {
+            sd,
+            provider: ProviderSelection::default(),
+        })
+    }
 
-        if sd.is_null() {
-            bail!("Failed to initialize offline speaker diarization");
+    /// Sample rate the segmentation model expects.
+    pub fn sample_rate(&self) -> u32 {
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationGetSampleRate(self.sd.as_ptr()) as u32
         }
-        Ok(Self { sd })
+    }
+
+    /// Diarize mono audio, resampling it to the model rate if needed.
+    pub fn process(&mut self, samples: &[f32], sample_rate: u32) -> Result<Vec<Segment>> {
+        let samples = self.resample(samples, sample_rate)?;
+        unsafe { self.run(&samples, None, null_mut()) }
+    }
+
+    /// Like [`Diarize::process`], reporting `(processed_chunks, total_chunks)`.
+    ///
+    /// Return `false` from the callback to cancel. sherpa-onnx can't stop part way, so
+    /// the remaining chunks are still computed, but the callback isn't called again and
+    /// an error is returned instead of the segments. A panic in the callback is handled
+    /// the same way, returning [`Error::CallbackPanicked`].
+    pub fn process_with_progress<F>(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        mut on_progress: F,
+    ) -> Result<Vec<Segment>>
+    where
+        F: FnMut(i32, i32) -> bool,
+    {
+        let samples = self.resample(samples, sample_rate)?;
+        let mut state = ProgressState {
+            callback: &mut on_progress,
+            cancelled: false,
+            guard: FfiCallback::new("speaker diarization progress"),
+        };
+        let segments = unsafe {
+            self.run(
+                &samples,
+                Some(cancellable_progress_wrapper),
+                &mut state as *mut ProgressState as *mut std::ffi::c_void,
+            )
+        };
+        state.guard.finish()?;
+        if state.cancelled {
+            return Err(Error::cancelled("speaker diarization"));
+        }
+        segments
+    }
+
+    /// Like [`Diarize::process`], returning [`Error::Cancelled`] once `token` is
+    /// cancelled. As with [`Diarize::process_with_progress`], the native call still runs
+    /// to the end, only its result is dropped.
+    pub fn process_with_cancel(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        token: &CancellationToken,
+    ) -> Result<Vec<Segment>> {
+        token.check("speaker diarization")?;
+        self.process_with_progress(samples, sample_rate, |_, _| !token.is_cancelled())
     }
 
     pub fn compute(
         &mut self,
-        mut samples: Vec<f32>,
+        samples: Vec<f32>,
         progress_callback: Option<ProgressCallback>,
     ) -> Result<Vec<Segment>> {
-        let samples_ptr = samples.as_mut_ptr();
-        let mut segments = Vec::new();
-        unsafe {
-            let mut callback_box =
-                progress_callback.map(|cb| Box::new(cb) as Box<ProgressCallback>);
-            let callback_ptr = callback_box
+        let mut state = progress_callback.map(|callback| CallbackState {
+            callback,
+            guard: FfiCallback::new("speaker diarization progress"),
+        });
+        let segments = unsafe {
+            let callback_ptr = state
                 .as_mut()
-                .map(|b| b.as_mut() as *mut ProgressCallback as *mut std::ffi::c_void)
+                .map(|state| state as *mut CallbackState as *mut std::ffi::c_void)
                 .unwrap_or(null_mut());
+            let callback = if state.is_some() {
+                Some(progress_callback_wrapper as NativeCallback)
+            } else {
+                None
+            };
+            self.run(&samples, callback, callback_ptr)
+        };
+        if let Some(state) = &mut state {
+            state.guard.finish()?;
+        }
+        let segments = segments?;
+        if segments.is_empty() {
+            return Err(Error::processing_failed(
+                "speaker diarization",
+                "no segments found",
+            ));
+        }
+        Ok(segments)
+    }
 
-            let result = sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationProcessWithCallback(
-                self.sd,
-                samples_ptr,
-                samples.len() as i32,
-                if callback_box.is_some() {
-                    Some(progress_callback_wrapper)
-                } else {
-                    None
-                },
-                callback_ptr,
-            );
+    fn resample<'a>(&self, samples: &'a [f32], sample_rate: u32) -> Result<Cow<'a, [f32]>> {
+        let expected = self.sample_rate();
+        if sample_rate == 0 {
+            return Err(Error::invalid_input("sample rate must be positive"));
+        }
+        if sample_rate == expected {
+            return Ok(Cow::Borrowed(samples));
+        }
+        tracing::debug!(
+            "resampling {} Hz to {} Hz for diarization",
+            sample_rate,
+            expected
+        );
+        Ok(Cow::Owned(resample(samples, sample_rate, expected)))
+    }
 
-            let num_segments =
-                sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationResultGetNumSegments(result);
-            let segments_ptr: *const sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationSegment =
-                sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationResultSortByStartTime(result);
+    unsafe fn run(
+        &mut self,
+        samples: &[f32],
+        callback: Option<NativeCallback>,
+        arg: *mut std::ffi::c_void,
+    ) -> Result<Vec<Segment>> {
+        let _timed = timed!("diarize.process", samples = samples.len());
+        let result = sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationProcessWithCallback(
+            self.sd.as_ptr(),
+            samples.as_ptr(),
+            samples.len() as i32,
+            callback,
+            arg,
+        );
+        if result.is_null() {
+            return Err(Error::processing_failed(
+                "speaker diarization",
+                "no result returned",
+            ));
+        }
 
-            if !segments_ptr.is_null() && num_segments > 0 {
-                let segments_result: &[
-                    sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationSegment
-                ] = std::slice::from_raw_parts(segments_ptr, num_segments as usize);
+        let num_segments =
+            sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationResultGetNumSegments(result);
+        let mut segments = Vec::new();
+        if num_segments > 0 {
+            let segments_ptr =
+                sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationResultSortByStartTime(result);
+            if !segments_ptr.is_null() {
+                let segments_result =
+                    std::slice::from_raw_parts(segments_ptr, num_segments as usize);
+                segments.extend(segments_result.iter().map(|segment| Segment {
+                    start: segment.start,
+                    end: segment.end,
+                    speaker: segment.speaker,
+                }));
+                sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationDestroySegment(segments_ptr);
+            }
+        }
+        sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationDestroyResult(result);
+        Ok(segments)
+    }
+}
 
-                for segment in segments_result {
-                    // Use segment here
+type NativeCallback = unsafe extern "C" fn(i32, i32, *mut std::ffi::c_void) -> i32;
 
-                    segments.push(Segment {
-                        start: segment.start,
-                        end: segment.end,
-                        speaker: segment.speaker,
-                    });
-                }
-            } else {
-                bail!("No segments found or invalid pointer.");
-            }
+struct ProgressState<'a> {
+    callback: &'a mut dyn FnMut(i32, i32) -> bool,
+    cancelled: bool,
+    guard: FfiCallback,
+}
 
-            sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationDestroySegment(segments_ptr);
-            sherpa_rs_sys::SherpaOnnxOfflineSpeakerDiarizationDestroyResult(result);
+struct CallbackState {
+    callback: ProgressCallback,
+    guard: FfiCallback,
+}
 
-            Ok(segments)
-        }
+unsafe extern "C" fn cancellable_progress_wrapper(
+    num_processed_chunk: i32,
+    num_total_chunks: i32,
+    arg: *mut std::ffi::c_void,
+) -> i32 {
+    let state = &mut *(arg as *mut ProgressState);
+    if !state.cancelled {
+        let callback = &mut state.callback;
+        let keep_going = state
+            .guard
+            .call(|| callback(num_processed_chunk, num_total_chunks));
+        state.cancelled = keep_going != Some(true);
     }
+    state.cancelled as i32
 }
 
 unsafe extern "C" fn progress_callback_wrapper(
@@ -153,17 +607,186 @@ unsafe extern "C" fn progress_callback_wrapper(
     num_total_chunks: i32,
     arg: *mut std::ffi::c_void,
 ) -> i32 {
-    let callback = &mut *(arg as *mut ProgressCallback);
-    callback(num_processed_chunk, num_total_chunks)
+    let state = &mut *(arg as *mut CallbackState);
+    let callback = &state.callback;
+    state
+        .guard
+        .call(|| callback(num_processed_chunk, num_total_chunks))
+        .unwrap_or(1)
 }
 
 unsafe impl Send for Diarize {}
 unsafe impl Sync for Diarize {}
 
-impl Drop for Diarize {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineSpeakerDiarization(self.sd);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    fn seg(start: f32, end: f32, speaker: i32) -> Segment {
+        Segment {
+            start,
+            end,
+            speaker,
         }
     }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn der_ignores_label_permutation() {
+        let reference = [seg(0.0, 2.0, 0), seg(2.0, 4.0, 1)];
+        let hypothesis = [seg(0.0, 2.0, 7), seg(2.0, 4.0, 3)];
+        let score = diarization_error_rate(&reference, &hypothesis, 0.0);
+        assert_close(score.der, 0.0);
+        assert_close(score.confusion, 0.0);
+        assert_close(score.total, 4.0);
+    }
+
+    #[test]
+    fn der_counts_missed_and_false_alarm_time() {
+        let score = diarization_error_rate(&[seg(0.0, 4.0, 0)], &[seg(1.0, 5.0, 0)], 0.0);
+        assert_close(score.missed, 1.0);
+        assert_close(score.false_alarm, 1.0);
+        assert_close(score.confusion, 0.0);
+        assert_close(score.total, 4.0);
+        assert_close(score.der, 0.5);
+    }
+
+    #[test]
+    fn der_counts_confusion() {
+        let reference = [seg(0.0, 2.0, 0), seg(2.0, 4.0, 1)];
+        let score = diarization_error_rate(&reference, &[seg(0.0, 4.0, 5)], 0.0);
+        assert_close(score.missed, 0.0);
+        assert_close(score.false_alarm, 0.0);
+        assert_close(score.confusion, 2.0);
+        assert_close(score.der, 0.5);
+    }
+
+    #[test]
+    fn der_counts_overlapping_speakers() {
+        let reference = [seg(0.0, 4.0, 0), seg(2.0, 4.0, 1)];
+        let score = diarization_error_rate(&reference, &[seg(0.0, 4.0, 0)], 0.0);
+        assert_close(score.total, 6.0);
+        assert_close(score.missed, 2.0);
+        assert_close(score.der, 2.0 / 6.0);
+
+        let hypothesis = [seg(0.0, 4.0, 1), seg(2.0, 4.0, 0)];
+        let score = diarization_error_rate(&reference, &hypothesis, 0.0);
+        assert_close(score.der, 0.0);
+    }
+
+    #[test]
+    fn der_collar_excludes_boundaries() {
+        let reference = [seg(0.0, 2.0, 0)];
+        let hypothesis = [seg(0.2, 2.2, 0)];
+
+        let score = diarization_error_rate(&reference, &hypothesis, 0.0);
+        assert_close(score.missed, 0.2);
+        assert_close(score.false_alarm, 0.2);
+        assert_close(score.total, 2.0);
+
+        let score = diarization_error_rate(&reference, &hypothesis, 0.25);
+        assert_close(score.der, 0.0);
+        assert_close(score.total, 1.5);
+    }
+
+    #[test]
+    fn der_without_reference_speech_is_zero() {
+        let score = diarization_error_rate(&[], &[seg(0.0, 1.0, 0)], 0.0);
+        assert_close(score.false_alarm, 1.0);
+        assert_close(score.der, 0.0);
+    }
+
+    #[test]
+    fn best_mapping_beats_greedy() {
+        // Greedy takes 0 -> 0 first and ends with 4, the best assignment totals 5
+        let overlap = vec![
+            vec![3.0, 2.0, 0.0],
+            vec![2.0, 0.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        assert_eq!(best_mapping(&overlap), vec![Some(1), Some(0), Some(2)]);
+    }
+
+    #[test]
+    fn best_mapping_leaves_extra_speakers_unmatched() {
+        assert_eq!(best_mapping(&[vec![1.0], vec![3.0]]), vec![None, Some(0)]);
+        assert_eq!(best_mapping(&[vec![0.0, 2.0, 1.0]]), vec![Some(1)]);
+        assert_eq!(best_mapping(&[vec![0.0]]), vec![None]);
+        assert!(best_mapping(&[]).is_empty());
+    }
+
+    #[test]
+    fn rttm_round_trip() {
+        let dir = ScratchDir::new("rttm-round-trip");
+        let path = dir.join("out.rttm");
+        let segments = [seg(0.0, 1.25, 0), seg(1.5, 3.0, 2), seg(2.75, 4.125, 0)];
+        write_rttm(&path, "meeting", &segments).unwrap();
+
+        let read = read_rttm(&path).unwrap();
+        assert_eq!(read.len(), segments.len());
+        for (read, written) in read.iter().zip(&segments) {
+            assert_close(read.start, written.start);
+            assert_close(read.end, written.end);
+            assert_eq!(read.speaker, written.speaker);
+        }
+    }
+
+    #[test]
+    fn read_rttm_numbers_named_speakers_by_appearance() {
+        let dir = ScratchDir::new("rttm-named");
+        let path = dir.join("named.rttm");
+        fs::write(
+            &path,
+            "SPEAKER a 1 0.0 1.0 <NA> <NA> bob <NA> <NA>\n\
+             ;; comment\n\
+             SPEAKER a 1 1.0 1.0 <NA> <NA> alice <NA> <NA>\n\
+             SPEAKER a 1 2.0 1.0 <NA> <NA> bob <NA> <NA>\n",
+        )
+        .unwrap();
+        let speakers: Vec<i32> = read_rttm(&path)
+            .unwrap()
+            .iter()
+            .map(|s| s.speaker)
+            .collect();
+        assert_eq!(speakers, [0, 1, 0]);
+
+        fs::write(&path, "SPEAKER a 1 soon 1.0 <NA> <NA> bob <NA> <NA>\n").unwrap();
+        let err = read_rttm(&path).unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn panicking_progress_callback_cancels_without_unwinding() {
+        let mut calls = 0;
+        let mut callback = |done: i32, _total: i32| {
+            calls += 1;
+            assert!(done < 2, "progress callback failed");
+            true
+        };
+        let mut state = ProgressState {
+            callback: &mut callback,
+            cancelled: false,
+            guard: FfiCallback::new("speaker diarization progress"),
+        };
+        // Called through a C function pointer, as sherpa-onnx does. A panic unwinding
+        // out of it would abort the test process.
+        let trampoline: NativeCallback = cancellable_progress_wrapper;
+        let arg = &mut state as *mut ProgressState as *mut std::ffi::c_void;
+        assert_eq!(unsafe { trampoline(1, 3, arg) }, 0);
+        assert_eq!(unsafe { trampoline(2, 3, arg) }, 1);
+        assert_eq!(unsafe { trampoline(3, 3, arg) }, 1);
+
+        let err = state.guard.finish().unwrap_err();
+        assert!(matches!(err, Error::CallbackPanicked { .. }), "{:?}", err);
+        assert_eq!(calls, 2);
+    }
 }
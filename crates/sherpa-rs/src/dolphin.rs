@@ -1,23 +1,41 @@
-use crate::{get_default_provider, utils::cstring_from_str};
-use eyre::{bail, Result};
-use std::mem;
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    itn::ItnConfig,
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{cstring_from_str, time_warm_up, warm_up_silence, WARM_UP_SAMPLE_RATE},
+    DecodingMethod, Error, FallbackPolicy, Provider, Result, Threads,
+};
+use std::{mem, path::Path, time::Duration};
 
 #[derive(Debug)]
 pub struct DolphinRecognizer {
-    recognizer: *const sherpa_rs_sys::SherpaOnnxOfflineRecognizer,
+    recognizer: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineRecognizer>,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
 }
 
 pub type DolphinRecognizerResult = super::OfflineRecognizerResult;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct DolphinConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
-    pub decoding_method: String,
+    /// Only [`DecodingMethod::GreedySearch`] is supported by this model.
+    pub decoding_method: DecodingMethod,
 
-    pub provider: Option<String>,
-    pub num_threads: Option<i32>,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub num_threads: Threads,
     pub debug: bool,
+    /// Inverse text normalization and homophone replacement applied to the text.
+    pub itn: ItnConfig,
 }
 
 impl Default for DolphinConfig {
@@ -25,24 +43,106 @@ impl Default for DolphinConfig {
         Self {
             model: String::new(),
             tokens: String::new(),
-            decoding_method: String::from("greedy_search"),
+            decoding_method: DecodingMethod::GreedySearch,
             debug: false,
+            itn: ItnConfig::default(),
             provider: None,
-            num_threads: Some(1),
+            fallback: FallbackPolicy::default(),
+            num_threads: Threads::ONE,
         }
     }
 }
 
+impl DolphinConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, path) in [("model", &self.model), ("tokens", &self.tokens)] {
+            if path.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "dolphin {} path is empty",
+                    name
+                )));
+            }
+            if !Path::new(path).is_file() {
+                return Err(Error::model_not_found(format!("dolphin {}", name), path));
+            }
+        }
+        Ok(())
+    }
+}
+
 impl DolphinRecognizer {
+    /// Like [`Self::new`], with the model and tokens found in `dir`, an unpacked
+    /// sherpa-onnx release.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: DolphinConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Dolphin,
+            precision,
+            &config.provider.clone().unwrap_or_else(get_default_provider),
+        )?;
+        Self::new(DolphinConfig {
+            model: dir.file(Role::Model),
+            tokens: dir.file(Role::Tokens),
+            ..config
+        })
+    }
+
     pub fn new(config: DolphinConfig) -> Result<Self> {
+        let _timed = timed!("dolphin.create");
+        debug_event!(?config, "creating dolphin recognizer");
+        let (mut recognizer, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(DolphinConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        recognizer.provider = provider;
+        Ok(recognizer)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model files.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Transcribe half a second of silence and discard it, so the first real
+    /// [`Self::transcribe`] doesn't pay for ONNX Runtime's one-time setup. Returns how long
+    /// it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("dolphin.warm_up");
+        time_warm_up(|| {
+            self.transcribe(WARM_UP_SAMPLE_RATE, &warm_up_silence(WARM_UP_SAMPLE_RATE));
+            Ok(())
+        })
+    }
+
+    fn create(config: DolphinConfig) -> Result<Self> {
+        let precision = ModelPrecision::of_file(&config.model);
+        config.validate()?;
         let debug = config.debug.into();
-        let provider = config.provider.unwrap_or(get_default_provider());
+        let itn = config.itn.to_raw()?;
+        let provider = config.provider.unwrap_or_else(get_default_provider);
 
-        let provider_ptr = cstring_from_str(&provider);
-        let num_threads = config.num_threads.unwrap_or(2);
+        let provider_ptr = provider.to_cstring()?;
+        let num_threads = config.num_threads.to_raw();
         let model_ptr = cstring_from_str(&config.model);
         let tokens_ptr = cstring_from_str(&config.tokens);
-        let decoding_method_ptr = cstring_from_str(&config.decoding_method);
+        let decoding_method_ptr = config.decoding_method.to_cstring();
 
         let model_config = unsafe {
             sherpa_rs_sys::SherpaOnnxOfflineModelConfig {
@@ -86,32 +186,39 @@ impl DolphinRecognizer {
                 hotwords_score: mem::zeroed::<_>(),
                 lm_config: mem::zeroed::<_>(),
                 max_active_paths: mem::zeroed::<_>(),
-                rule_fars: mem::zeroed::<_>(),
-                rule_fsts: mem::zeroed::<_>(),
+                rule_fars: itn.rule_fars(),
+                rule_fsts: itn.rule_fsts(),
                 blank_penalty: mem::zeroed::<_>(),
-                hr: mem::zeroed::<_>(),
+                hr: itn.homophone_replacer(),
             }
         };
 
-        let recognizer = unsafe { sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&config) };
-
-        if recognizer.is_null() {
-            bail!("Failed to create recognizer");
+        let recognizer = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer,
+            )
         }
+        .ok_or_else(|| Error::native_creation_failed("dolphin recognizer"))?;
 
-        Ok(Self { recognizer })
+        Ok(Self {
+            recognizer,
+            provider: ProviderSelection::default(),
+            precision,
+        })
     }
 
     pub fn transcribe(&mut self, sample_rate: u32, samples: &[f32]) -> DolphinRecognizerResult {
+        let _timed = timed!("dolphin.transcribe", sample_rate, samples = samples.len());
         unsafe {
-            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer);
+            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer.as_ptr());
             sherpa_rs_sys::SherpaOnnxAcceptWaveformOffline(
                 stream,
                 sample_rate as i32,
                 samples.as_ptr(),
                 samples.len().try_into().unwrap(),
             );
-            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer, stream);
+            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer.as_ptr(), stream);
             let result_ptr = sherpa_rs_sys::SherpaOnnxGetOfflineStreamResult(stream);
             let raw_result = result_ptr.read();
             let result = DolphinRecognizerResult::new(&raw_result);
@@ -121,15 +228,17 @@ impl DolphinRecognizer {
             result
         }
     }
+
+    /// Decode several clips of `(samples, sample_rate)` in one native batch call.
+    ///
+    /// Results are in input order, an empty batch returns an empty Vec.
+    pub fn transcribe_batch(
+        &mut self,
+        inputs: &[(&[f32], u32)],
+    ) -> Vec<Result<DolphinRecognizerResult>> {
+        crate::decode_offline_batch(self.recognizer.as_ptr(), inputs)
+    }
 }
 
 unsafe impl Send for DolphinRecognizer {}
 unsafe impl Sync for DolphinRecognizer {}
-
-impl Drop for DolphinRecognizer {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer(self.recognizer);
-        }
-    }
-}
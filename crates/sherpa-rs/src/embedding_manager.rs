@@ -1,9 +1,25 @@
-use crate::utils::{cstr_to_string, cstring_from_str};
-use eyre::{bail, Result};
+use crate::{
+    handle::NativeHandle,
+    utils::{cstr_to_string, cstring_from_str},
+    Error, Result,
+};
+use std::{
+    collections::BTreeMap,
+    ffi::CStr,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
 
-#[derive(Debug, Clone)]
+/// Magic bytes of files written by [`EmbeddingManager::save`].
+const FILE_MAGIC: &[u8; 8] = b"SRSPKR01";
+
+#[derive(Debug)]
 pub struct EmbeddingManager {
-    pub(crate) manager: *const sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManager,
+    manager: NativeHandle<sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManager>,
+    dim: usize,
+    /// Copy of what was registered, so it can be saved.
+    speakers: BTreeMap<String, Vec<Vec<f32>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -13,38 +29,69 @@ pub struct SpeakerMatch {
 }
 
 impl EmbeddingManager {
-    pub fn new(dimension: i32) -> Self {
-        unsafe {
-            let manager = sherpa_rs_sys::SherpaOnnxCreateSpeakerEmbeddingManager(dimension);
-            Self { manager }
+    pub fn new(dimension: i32) -> Result<Self> {
+        if dimension <= 0 {
+            return Err(Error::invalid_input(format!(
+                "embedding dimension must be positive, got {}",
+                dimension
+            )));
+        }
+        let manager = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateSpeakerEmbeddingManager(dimension),
+                sherpa_rs_sys::SherpaOnnxDestroySpeakerEmbeddingManager,
+            )
         }
+        .ok_or_else(|| Error::native_creation_failed("speaker embedding manager"))?;
+        Ok(Self {
+            manager,
+            dim: dimension as usize,
+            speakers: BTreeMap::new(),
+        })
+    }
+
+    /// Embedding dimension.
+    pub fn dim(&self) -> usize {
+        self.dim
     }
 
     pub fn search(&mut self, embedding: &[f32], threshold: f32) -> Option<String> {
+        if embedding.len() != self.dim {
+            return None;
+        }
         unsafe {
             let name = sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManagerSearch(
-                self.manager,
-                embedding.to_owned().as_mut_ptr(),
+                self.manager.as_ptr(),
+                embedding.as_ptr(),
                 threshold,
             );
             if name.is_null() {
                 return None;
             }
-            let name = cstr_to_string(name as _);
-            Some(name)
+            let result = cstr_to_string(name as _);
+            sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManagerFreeSearch(name);
+            Some(result)
         }
     }
 
+    /// Up to `n` speakers scoring at least `threshold`, best first.
+    pub fn search_all(&mut self, embedding: &[f32], threshold: f32, n: usize) -> Vec<SpeakerMatch> {
+        self.get_best_matches(embedding, threshold, n as i32)
+    }
+
     pub fn get_best_matches(
         &mut self,
         embedding: &[f32],
         threshold: f32,
         n: i32,
     ) -> Vec<SpeakerMatch> {
+        if embedding.len() != self.dim || n <= 0 {
+            return Vec::new();
+        }
         unsafe {
             let result_ptr = sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManagerGetBestMatches(
-                self.manager,
-                embedding.to_owned().as_mut_ptr(),
+                self.manager.as_ptr(),
+                embedding.as_ptr(),
                 threshold,
                 n,
             );
@@ -66,30 +113,222 @@ impl EmbeddingManager {
         }
     }
 
-    pub fn add(&mut self, name: String, embedding: &mut [f32]) -> Result<()> {
-        let name_c = cstring_from_str(&name.clone());
+    pub fn add(&mut self, name: String, embedding: &[f32]) -> Result<()> {
+        self.add_multi(name, &[embedding.to_vec()])
+    }
+
+    /// Register a speaker from several embeddings, e.g. one per enrollment clip.
+    pub fn add_multi(&mut self, name: String, embeddings: &[Vec<f32>]) -> Result<()> {
+        if embeddings.is_empty() {
+            return Err(Error::invalid_input(format!(
+                "no embeddings given for {}",
+                name
+            )));
+        }
+        if self.contains(&name) {
+            return Err(Error::invalid_input(format!(
+                "speaker {} is already registered",
+                name
+            )));
+        }
+        if let Some(embedding) = embeddings.iter().find(|e| e.len() != self.dim) {
+            return Err(Error::invalid_input(format!(
+                "embedding for {} has dimension {}, expected {}",
+                name,
+                embedding.len(),
+                self.dim
+            )));
+        }
+
+        let name_c = cstring_from_str(&name);
+        let flattened: Vec<f32> = embeddings.concat();
+        let status = unsafe {
+            sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManagerAddListFlattened(
+                self.manager.as_ptr(),
+                name_c.as_ptr(),
+                flattened.as_ptr(),
+                embeddings.len() as i32,
+            )
+        };
+        if status == 0 {
+            return Err(Error::processing_failed(
+                "embedding manager",
+                format!("failed to register {}", name),
+            ));
+        }
+        self.speakers.insert(name, embeddings.to_vec());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        let name_c = cstring_from_str(name);
+        let status = unsafe {
+            sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManagerRemove(
+                self.manager.as_ptr(),
+                name_c.as_ptr(),
+            )
+        };
+        if status == 0 {
+            return Err(Error::invalid_input(format!(
+                "speaker {} is not registered",
+                name
+            )));
+        }
+        self.speakers.remove(name);
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        let name_c = cstring_from_str(name);
         unsafe {
-            let status = sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManagerAdd(
-                self.manager,
+            sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManagerContains(
+                self.manager.as_ptr(),
                 name_c.as_ptr(),
-                embedding.as_mut_ptr(),
+            ) != 0
+        }
+    }
+
+    pub fn num_speakers(&self) -> usize {
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManagerNumSpeakers(self.manager.as_ptr())
+                as usize
+        }
+    }
+
+    pub fn speaker_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        unsafe {
+            let list = sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManagerGetAllSpeakers(
+                self.manager.as_ptr(),
             );
+            if list.is_null() {
+                return names;
+            }
+            let mut cursor = list;
+            while !(*cursor).is_null() {
+                names.push(CStr::from_ptr(*cursor).to_string_lossy().into_owned());
+                cursor = cursor.add(1);
+            }
+            sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingManagerFreeAllSpeakers(list);
+        }
+        names
+    }
+
+    /// Write every registered speaker and their embeddings to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut buf = Vec::new();
+        buf.extend_from_slice(FILE_MAGIC);
+        buf.extend_from_slice(&(self.dim as u32).to_le_bytes());
+        buf.extend_from_slice(&(self.speakers.len() as u32).to_le_bytes());
+        for (name, embeddings) in &self.speakers {
+            buf.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            buf.extend_from_slice(name.as_bytes());
+            buf.extend_from_slice(&(embeddings.len() as u32).to_le_bytes());
+            for value in embeddings.iter().flatten() {
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        fs::File::create(path).and_then(|mut file| file.write_all(&buf))?;
+        Ok(())
+    }
+
+    /// Read speakers written by [`EmbeddingManager::save`] into a new manager.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        Self::from_bytes(&data).map_err(|err| {
+            Error::invalid_input(format!("invalid speakers file {}: {}", path.display(), err))
+        })
+    }
+
+    fn from_bytes(mut data: &[u8]) -> Result<Self> {
+        fn read_u32(data: &mut &[u8]) -> io::Result<u32> {
+            let mut bytes = [0; 4];
+            data.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        }
+
+        let mut magic = [0; FILE_MAGIC.len()];
+        data.read_exact(&mut magic)?;
+        if &magic != FILE_MAGIC {
+            return Err(Error::invalid_input("not a sherpa-rs speakers file"));
+        }
+        let dim = read_u32(&mut data)? as usize;
+        let count = read_u32(&mut data)?;
 
-            if status.is_negative() {
-                bail!("Failed to register {}", name);
+        let dim = i32::try_from(dim).map_err(|_| {
+            Error::invalid_input(format!("embedding dimension {} is too large", dim))
+        })?;
+        let mut manager = Self::new(dim)?;
+        let dim = dim as usize;
+        for _ in 0..count {
+            let name_len = read_u32(&mut data)? as usize;
+            if name_len > data.len() {
+                return Err(Error::invalid_input("truncated speaker name"));
             }
-            Ok(())
+            let (name, rest) = data.split_at(name_len);
+            let name = String::from_utf8(name.to_vec())
+                .map_err(|_| Error::invalid_input("speaker name is not UTF-8"))?;
+            data = rest;
+
+            let num = read_u32(&mut data)? as usize;
+            let mut embeddings = Vec::with_capacity(num);
+            for _ in 0..num {
+                let mut embedding = Vec::with_capacity(dim);
+                for _ in 0..dim {
+                    embedding.push(f32::from_bits(read_u32(&mut data)?));
+                }
+                embeddings.push(embedding);
+            }
+            manager.add_multi(name, &embeddings)?;
+        }
+        Ok(manager)
+    }
+}
+
+impl Clone for EmbeddingManager {
+    /// A separate native manager with the same speakers registered.
+    ///
+    /// # Panics
+    ///
+    /// If sherpa-onnx fails to allocate the new manager.
+    fn clone(&self) -> Self {
+        let mut clone = Self::new(self.dim as i32)
+            .expect("the dimension was accepted when this manager was created");
+        for (name, embeddings) in &self.speakers {
+            // Already accepted once, so registering them again can't fail
+            let _ = clone.add_multi(name.clone(), embeddings);
         }
+        clone
     }
 }
 
 unsafe impl Send for EmbeddingManager {}
 unsafe impl Sync for EmbeddingManager {}
 
-impl Drop for EmbeddingManager {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroySpeakerEmbeddingManager(self.manager);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    #[test]
+    fn new_rejects_non_positive_dimension() {
+        for dimension in [0, -1] {
+            let err = EmbeddingManager::new(dimension).unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
         }
     }
+
+    #[test]
+    fn load_rejects_other_files() {
+        let dir = ScratchDir::new("embedding-manager-load");
+        let err = EmbeddingManager::load(dir.join("speakers.bin")).unwrap_err();
+        assert!(matches!(err, Error::Io(_)), "{:?}", err);
+
+        let path = dir.join("not-speakers.bin");
+        fs::write(&path, b"RIFF\0\0\0\0WAVEfmt ").unwrap();
+        let err = EmbeddingManager::load(&path).unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+    }
 }
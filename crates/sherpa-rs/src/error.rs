@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+/// Error returned by the fallible APIs of this crate.
+///
+/// It implements [`std::error::Error`], so `?` converts it into an `eyre::Report`,
+/// `anyhow::Error` or `Box<dyn Error>` in application code. Match on the variant rather
+/// than the message, messages may change between releases.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// A model file the config points to doesn't exist or can't be read.
+    #[error("{component} not found: {}", path.display())]
+    ModelNotFound { component: String, path: PathBuf },
+    /// sherpa-onnx returned no handle, usually because the model files don't match the
+    /// config.
+    #[error("Failed to create {component}")]
+    NativeCreationFailed { component: String },
+    /// An argument or config value was rejected before reaching sherpa-onnx.
+    #[error("{reason}")]
+    InvalidInput { reason: String },
+    /// sherpa-onnx failed while processing input.
+    #[error("{component} failed: {reason}")]
+    ProcessingFailed { component: String, reason: String },
+    /// Not available in this build, on this platform or in the linked sherpa-onnx.
+    #[error("{what} is not supported")]
+    Unsupported { what: String },
+    /// A progress callback asked to stop.
+    #[error("{component} was cancelled")]
+    Cancelled { component: String },
+    /// A callback passed to this crate panicked. The panic was caught before it could
+    /// unwind into sherpa-onnx, and the native call was asked to stop.
+    #[error("{component} callback panicked: {message}")]
+    CallbackPanicked { component: String, message: String },
+    /// The language identification model gave no language for the audio.
+    #[error("could not identify the spoken language")]
+    LanguageNotIdentified,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Wav(#[from] hound::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub(crate) fn model_not_found(component: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self::ModelNotFound {
+            component: component.into(),
+            path: path.into(),
+        }
+    }
+
+    pub(crate) fn native_creation_failed(component: impl Into<String>) -> Self {
+        Self::NativeCreationFailed {
+            component: component.into(),
+        }
+    }
+
+    pub(crate) fn invalid_input(reason: impl Into<String>) -> Self {
+        Self::InvalidInput {
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn processing_failed(
+        component: impl Into<String>,
+        reason: impl Into<String>,
+    ) -> Self {
+        Self::ProcessingFailed {
+            component: component.into(),
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn unsupported(what: impl Into<String>) -> Self {
+        Self::Unsupported { what: what.into() }
+    }
+
+    pub(crate) fn cancelled(component: impl Into<String>) -> Self {
+        Self::Cancelled {
+            component: component.into(),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    itn::ItnConfig,
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{cstring_from_str, time_warm_up, warm_up_silence, WARM_UP_SAMPLE_RATE},
+    DecodingMethod, Error, FallbackPolicy, Provider, Result, Threads,
+};
+use std::{mem, path::Path, time::Duration};
+
+#[derive(Debug)]
+pub struct FireRedAsrRecognizer {
+    recognizer: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineRecognizer>,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
+}
+
+pub type FireRedAsrRecognizerResult = super::OfflineRecognizerResult;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct FireRedAsrConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+    pub encoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+    pub decoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+    pub tokens: String,
+    /// Only [`DecodingMethod::GreedySearch`] is supported by this model.
+    pub decoding_method: DecodingMethod,
+
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub num_threads: Threads,
+    pub debug: bool,
+    /// Inverse text normalization and homophone replacement applied to the text.
+    pub itn: ItnConfig,
+}
+
+impl Default for FireRedAsrConfig {
+    fn default() -> Self {
+        Self {
+            encoder: String::new(),
+            decoder: String::new(),
+            tokens: String::new(),
+            decoding_method: DecodingMethod::GreedySearch,
+            debug: false,
+            itn: ItnConfig::default(),
+            provider: None,
+            fallback: FallbackPolicy::default(),
+            num_threads: Threads::ONE,
+        }
+    }
+}
+
+impl FireRedAsrConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, path) in [
+            ("encoder", &self.encoder),
+            ("decoder", &self.decoder),
+            ("tokens", &self.tokens),
+        ] {
+            if path.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "fire red asr {} path is empty",
+                    name
+                )));
+            }
+            if !Path::new(path).is_file() {
+                return Err(Error::model_not_found(
+                    format!("fire red asr {}", name),
+                    path,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FireRedAsrRecognizer {
+    /// Like [`Self::new`], with the encoder, decoder and tokens of the sherpa-onnx
+    /// release unpacked in `dir`.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: FireRedAsrConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::FireRedAsr,
+            precision,
+            &config.provider.clone().unwrap_or_else(get_default_provider),
+        )?;
+        Self::new(FireRedAsrConfig {
+            encoder: dir.file(Role::Encoder),
+            decoder: dir.file(Role::Decoder),
+            tokens: dir.file(Role::Tokens),
+            ..config
+        })
+    }
+
+    pub fn new(config: FireRedAsrConfig) -> Result<Self> {
+        let _timed = timed!("fire_red_asr.create");
+        debug_event!(?config, "creating fire red asr recognizer");
+        let (mut recognizer, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(FireRedAsrConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        recognizer.provider = provider;
+        Ok(recognizer)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model files.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Transcribe half a second of silence and discard it, so the first real
+    /// [`Self::transcribe`] doesn't pay for ONNX Runtime's one-time setup. Returns how long
+    /// it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("fire_red_asr.warm_up");
+        time_warm_up(|| {
+            self.transcribe(WARM_UP_SAMPLE_RATE, &warm_up_silence(WARM_UP_SAMPLE_RATE));
+            Ok(())
+        })
+    }
+
+    fn create(config: FireRedAsrConfig) -> Result<Self> {
+        let precision = ModelPrecision::of_file(&config.encoder);
+        config.validate()?;
+        let debug = config.debug.into();
+        let itn = config.itn.to_raw()?;
+        let provider = config.provider.unwrap_or_else(get_default_provider);
+
+        let provider_ptr = provider.to_cstring()?;
+        let num_threads = config.num_threads.to_raw();
+        let encoder_ptr = cstring_from_str(&config.encoder);
+        let decoder_ptr = cstring_from_str(&config.decoder);
+        let tokens_ptr = cstring_from_str(&config.tokens);
+        let decoding_method_ptr = config.decoding_method.to_cstring();
+
+        let model_config = unsafe {
+            sherpa_rs_sys::SherpaOnnxOfflineModelConfig {
+                debug,
+                num_threads,
+                provider: provider_ptr.as_ptr(),
+                fire_red_asr: sherpa_rs_sys::SherpaOnnxOfflineFireRedAsrModelConfig {
+                    encoder: encoder_ptr.as_ptr(),
+                    decoder: decoder_ptr.as_ptr(),
+                },
+                tokens: tokens_ptr.as_ptr(),
+
+                // Zeros
+                nemo_ctc: mem::zeroed::<_>(),
+                paraformer: mem::zeroed::<_>(),
+                tdnn: mem::zeroed::<_>(),
+                telespeech_ctc: mem::zeroed::<_>(),
+                dolphin: mem::zeroed::<_>(),
+                transducer: mem::zeroed::<_>(),
+                whisper: mem::zeroed::<_>(),
+                sense_voice: mem::zeroed::<_>(),
+                moonshine: mem::zeroed::<_>(),
+                bpe_vocab: mem::zeroed::<_>(),
+                model_type: mem::zeroed::<_>(),
+                modeling_unit: mem::zeroed::<_>(),
+                zipformer_ctc: mem::zeroed::<_>(),
+                canary: mem::zeroed::<_>(),
+                wenet_ctc: mem::zeroed::<_>(),
+                omnilingual: mem::zeroed::<_>(),
+            }
+        };
+
+        let config = unsafe {
+            sherpa_rs_sys::SherpaOnnxOfflineRecognizerConfig {
+                decoding_method: decoding_method_ptr.as_ptr(),
+                model_config,
+                feat_config: sherpa_rs_sys::SherpaOnnxFeatureConfig {
+                    sample_rate: 16000,
+                    feature_dim: 80,
+                },
+                hotwords_file: mem::zeroed::<_>(),
+                hotwords_score: mem::zeroed::<_>(),
+                lm_config: mem::zeroed::<_>(),
+                max_active_paths: mem::zeroed::<_>(),
+                rule_fars: itn.rule_fars(),
+                rule_fsts: itn.rule_fsts(),
+                blank_penalty: mem::zeroed::<_>(),
+                hr: itn.homophone_replacer(),
+            }
+        };
+
+        let recognizer = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer,
+            )
+        }
+        .ok_or_else(|| Error::native_creation_failed("fire red asr recognizer"))?;
+
+        Ok(Self {
+            recognizer,
+            provider: ProviderSelection::default(),
+            precision,
+        })
+    }
+
+    pub fn transcribe(&mut self, sample_rate: u32, samples: &[f32]) -> FireRedAsrRecognizerResult {
+        let _timed = timed!(
+            "fire_red_asr.transcribe",
+            sample_rate,
+            samples = samples.len()
+        );
+        unsafe {
+            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer.as_ptr());
+            sherpa_rs_sys::SherpaOnnxAcceptWaveformOffline(
+                stream,
+                sample_rate as i32,
+                samples.as_ptr(),
+                samples.len().try_into().unwrap(),
+            );
+            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer.as_ptr(), stream);
+            let result_ptr = sherpa_rs_sys::SherpaOnnxGetOfflineStreamResult(stream);
+            let raw_result = result_ptr.read();
+            let result = FireRedAsrRecognizerResult::new(&raw_result);
+            // Free
+            sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizerResult(result_ptr);
+            sherpa_rs_sys::SherpaOnnxDestroyOfflineStream(stream);
+            result
+        }
+    }
+
+    /// Decode several clips of `(samples, sample_rate)` in one native batch call.
+    ///
+    /// Results are in input order, an empty batch returns an empty Vec.
+    pub fn transcribe_batch(
+        &mut self,
+        inputs: &[(&[f32], u32)],
+    ) -> Vec<Result<FireRedAsrRecognizerResult>> {
+        crate::decode_offline_batch(self.recognizer.as_ptr(), inputs)
+    }
+}
+
+unsafe impl Send for FireRedAsrRecognizer {}
+unsafe impl Sync for FireRedAsrRecognizer {}
@@ -0,0 +1,101 @@
+//! Ownership of the objects sherpa-onnx hands out.
+//!
+//! Wrappers keep their native object in a [`NativeHandle`] instead of a bare pointer,
+//! so a failed constructor never frees a null pointer and the object is destroyed
+//! exactly once, when the handle drops.
+
+/// Value a dropped handle is overwritten with in debug builds, to catch use after drop.
+#[cfg(debug_assertions)]
+const POISON: usize = usize::MAX;
+
+/// Non-null pointer to a native object, destroyed with `destroy` on drop.
+pub(crate) struct NativeHandle<T> {
+    ptr: *const T,
+    destroy: unsafe extern "C" fn(*const T),
+}
+
+impl<T> NativeHandle<T> {
+    /// Take ownership of `ptr`, `None` if it's null.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be owned by no one else and `destroy` must be the function that frees
+    /// it.
+    pub(crate) unsafe fn new(
+        ptr: *const T,
+        destroy: unsafe extern "C" fn(*const T),
+    ) -> Option<Self> {
+        (!ptr.is_null()).then(|| Self { ptr, destroy })
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const T {
+        #[cfg(debug_assertions)]
+        assert!(
+            self.ptr as usize != POISON,
+            "native handle used after it was dropped"
+        );
+        debug_assert!(!self.ptr.is_null());
+        self.ptr
+    }
+}
+
+impl<T> Drop for NativeHandle<T> {
+    fn drop(&mut self) {
+        let ptr = self.as_ptr();
+        #[cfg(debug_assertions)]
+        {
+            self.ptr = POISON as *const T;
+        }
+        unsafe { (self.destroy)(ptr) }
+    }
+}
+
+impl<T> std::fmt::Debug for NativeHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("NativeHandle").field(&self.ptr).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Frees a boxed counter reference and bumps the counter, standing in for a
+    /// sherpa-onnx destroy function.
+    unsafe extern "C" fn destroy(ptr: *const &'static AtomicUsize) {
+        let counter = Box::from_raw(ptr as *mut &'static AtomicUsize);
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn handle(counter: &'static AtomicUsize) -> NativeHandle<&'static AtomicUsize> {
+        let ptr = Box::into_raw(Box::new(counter));
+        unsafe { NativeHandle::new(ptr, destroy) }.unwrap()
+    }
+
+    #[test]
+    fn new_rejects_null() {
+        assert!(unsafe { NativeHandle::new(std::ptr::null(), destroy) }.is_none());
+    }
+
+    #[test]
+    fn drop_destroys_once() {
+        static DESTROYED: AtomicUsize = AtomicUsize::new(0);
+        let handle = handle(&DESTROYED);
+        assert!(!handle.as_ptr().is_null());
+        assert_eq!(DESTROYED.load(Ordering::SeqCst), 0);
+        drop(handle);
+        assert_eq!(DESTROYED.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "used after it was dropped")]
+    fn use_after_drop_panics() {
+        static DESTROYED: AtomicUsize = AtomicUsize::new(0);
+        let mut handle = std::mem::ManuallyDrop::new(handle(&DESTROYED));
+        unsafe { std::ptr::drop_in_place(&mut *handle) };
+        assert_eq!(DESTROYED.load(Ordering::SeqCst), 1);
+        handle.as_ptr();
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    collections::HashSet,
+    ffi::CString,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{utils::cstring_from_str, DecodingMethod, Error, Result};
+
+/// What the native side needs to tokenize hotwords.
+pub(crate) struct HotwordsModel<'a> {
+    pub tokens: &'a str,
+    pub modeling_unit: &'a str,
+    pub bpe_vocab: &'a str,
+    pub decoding_method: DecodingMethod,
+}
+
+/// A hotwords file ready to hand to sherpa-onnx.
+///
+/// If it was written from a word list, the temp file is removed on drop. sherpa-onnx reads
+/// the file while creating the recognizer, so this only has to outlive that call.
+pub(crate) struct HotwordsFile {
+    path: Option<PathBuf>,
+    temp: bool,
+}
+
+impl HotwordsFile {
+    pub fn new(file: Option<&Path>, words: &[String], model: &HotwordsModel) -> Result<Self> {
+        if file.is_none() && words.is_empty() {
+            return Ok(Self {
+                path: None,
+                temp: false,
+            });
+        }
+
+        // sherpa-onnx only applies hotwords during modified beam search and ignores them otherwise
+        if model.decoding_method != DecodingMethod::ModifiedBeamSearch {
+            return Err(Error::invalid_input(format!(
+                "hotwords need decoding_method modified_beam_search, got {}",
+                model.decoding_method
+            )));
+        }
+
+        let unit = modeling_unit(model.modeling_unit);
+        if unit.contains("bpe") && model.bpe_vocab.is_empty() {
+            return Err(Error::invalid_input(format!(
+                "hotwords with modeling_unit {:?} need bpe_vocab",
+                unit
+            )));
+        }
+
+        let mut lines = match file {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|_| Error::model_not_found("hotwords file", path))?
+                .lines()
+                .map(str::to_string)
+                .collect(),
+            None => Vec::new(),
+        };
+        for word in words {
+            if word.contains('\n') || word.contains('\r') {
+                return Err(Error::invalid_input(format!(
+                    "hotword {:?} contains a line break",
+                    word
+                )));
+            }
+            lines.push(word.trim().to_string());
+        }
+        lines.retain(|l| !l.trim().is_empty());
+
+        // The native encoder skips hotwords it can't tokenize after logging them. For
+        // character units every character must be a token, so check that up front.
+        // BPE pieces can only be checked by sentencepiece itself.
+        if unit == "cjkchar" {
+            let symbols = read_symbols(model.tokens)?;
+            for line in &lines {
+                let phrase = strip_boost(line);
+                let missing = missing_symbols(phrase, &symbols);
+                if !missing.is_empty() {
+                    return Err(Error::invalid_input(format!(
+                        "hotword {:?} can't be tokenized, {:?} not in {}",
+                        phrase, missing, model.tokens
+                    )));
+                }
+            }
+        }
+
+        if words.is_empty() {
+            return Ok(Self {
+                path: file.map(Path::to_path_buf),
+                temp: false,
+            });
+        }
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "sherpa-rs-hotwords-{}-{}.txt",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, lines.join("\n") + "\n")?;
+        Ok(Self {
+            path: Some(path),
+            temp: true,
+        })
+    }
+
+    /// Path as a C string, empty if there are no hotwords.
+    pub fn to_cstring(&self) -> CString {
+        match &self.path {
+            Some(path) => cstring_from_str(&path.to_string_lossy()),
+            None => CString::default(),
+        }
+    }
+}
+
+impl Drop for HotwordsFile {
+    fn drop(&mut self) {
+        if let (true, Some(path)) = (self.temp, &self.path) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// sherpa-onnx treats an empty modeling unit as `cjkchar`.
+pub(crate) fn modeling_unit(unit: &str) -> &str {
+    if unit.is_empty() {
+        "cjkchar"
+    } else {
+        unit
+    }
+}
+
+/// Characters of `phrase` that aren't tokens, for character modeling units.
+pub(crate) fn missing_symbols(phrase: &str, symbols: &HashSet<String>) -> String {
+    phrase
+        .chars()
+        .filter(|c| !c.is_whitespace() && !symbols.contains(&c.to_string()))
+        .collect()
+}
+
+/// Drop a trailing per-hotword boost such as `" :2.0"`.
+fn strip_boost(line: &str) -> &str {
+    match line.rsplit_once(" :") {
+        Some((phrase, boost)) if boost.trim().parse::<f32>().is_ok() => phrase.trim(),
+        _ => line.trim(),
+    }
+}
+
+pub(crate) fn read_symbols(tokens: &str) -> Result<HashSet<String>> {
+    let contents =
+        fs::read_to_string(tokens).map_err(|_| Error::model_not_found("tokens", tokens))?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect())
+}
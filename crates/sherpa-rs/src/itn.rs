@@ -0,0 +1,126 @@
+//! Rewrite rules applied to recognizer output: inverse text normalization with
+//! `rule_fsts`/`rule_fars` (e.g. "twenty three" to "23") and homophone replacement.
+
+use std::{
+    ffi::{c_char, CString},
+    path::PathBuf,
+    ptr::null,
+};
+
+use crate::{
+    utils::{cstring_from_str, join_rule_paths},
+    Error, Result,
+};
+
+/// Chinese number ITN rules, released by sherpa-onnx as `asr-models/itn_zh_number.fst`.
+pub const CHINESE_ITN_FST: &str = "itn_zh_number.fst";
+
+/// Rules shared by the offline and online recognizer configs. Nothing is applied by
+/// default.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ItnConfig {
+    /// FSTs applied in order to the text.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::path_bufs")
+    )]
+    pub rule_fsts: Vec<PathBuf>,
+    /// FST archives, each applied as a whole after `rule_fsts`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::path_bufs")
+    )]
+    pub rule_fars: Vec<PathBuf>,
+    pub homophone_replacer: Option<HomophoneReplacerConfig>,
+}
+
+/// Replace words with the homophone the rules pick, looked up by pronunciation.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct HomophoneReplacerConfig {
+    /// jieba dict directory, only needed by sherpa-onnx versions that segment with it.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::opt_path_buf")
+    )]
+    pub dict_dir: Option<PathBuf>,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path_buf"))]
+    pub lexicon: PathBuf,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::path_bufs")
+    )]
+    pub rule_fsts: Vec<PathBuf>,
+}
+
+/// Joined, validated paths of an [`ItnConfig`], alive while the recognizer is created.
+pub(crate) struct ItnRaw {
+    rule_fsts: Option<CString>,
+    rule_fars: Option<CString>,
+    hr_dict_dir: Option<CString>,
+    hr_lexicon: Option<CString>,
+    hr_rule_fsts: Option<CString>,
+}
+
+impl ItnConfig {
+    /// Join the paths for the C API, failing if any of them don't exist.
+    pub(crate) fn to_raw(&self) -> Result<ItnRaw> {
+        let mut raw = ItnRaw {
+            rule_fsts: join_rule_paths("rule_fsts", &self.rule_fsts)?,
+            rule_fars: join_rule_paths("rule_fars", &self.rule_fars)?,
+            hr_dict_dir: None,
+            hr_lexicon: None,
+            hr_rule_fsts: None,
+        };
+        if let Some(hr) = &self.homophone_replacer {
+            if let Some(dict_dir) = &hr.dict_dir {
+                if !dict_dir.is_dir() {
+                    return Err(Error::model_not_found(
+                        "homophone replacer dict_dir",
+                        dict_dir,
+                    ));
+                }
+                raw.hr_dict_dir = Some(cstring_from_str(&dict_dir.to_string_lossy()));
+            }
+            if !hr.lexicon.is_file() {
+                return Err(Error::model_not_found(
+                    "homophone replacer lexicon",
+                    &hr.lexicon,
+                ));
+            }
+            if hr.rule_fsts.is_empty() {
+                return Err(Error::invalid_input(
+                    "homophone replacer needs at least one rule fst",
+                ));
+            }
+            raw.hr_lexicon = Some(cstring_from_str(&hr.lexicon.to_string_lossy()));
+            raw.hr_rule_fsts = join_rule_paths("homophone replacer rule_fsts", &hr.rule_fsts)?;
+        }
+        Ok(raw)
+    }
+}
+
+fn as_ptr(value: &Option<CString>) -> *const c_char {
+    value.as_ref().map(|value| value.as_ptr()).unwrap_or(null())
+}
+
+impl ItnRaw {
+    pub(crate) fn rule_fsts(&self) -> *const c_char {
+        as_ptr(&self.rule_fsts)
+    }
+
+    pub(crate) fn rule_fars(&self) -> *const c_char {
+        as_ptr(&self.rule_fars)
+    }
+
+    pub(crate) fn homophone_replacer(&self) -> sherpa_rs_sys::SherpaOnnxHomophoneReplacerConfig {
+        sherpa_rs_sys::SherpaOnnxHomophoneReplacerConfig {
+            dict_dir: as_ptr(&self.hr_dict_dir),
+            lexicon: as_ptr(&self.hr_lexicon),
+            rule_fsts: as_ptr(&self.hr_rule_fsts),
+        }
+    }
+}
@@ -1,31 +1,54 @@
-use std::mem;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::{CStr, CString},
+    marker::PhantomData,
+    mem,
+};
 
 use crate::{
     get_default_provider,
+    handle::NativeHandle,
+    online::StreamClock,
+    providers::{create_with_fallback, ProviderSelection},
+    trace::timed,
     utils::{cstr_to_string, cstring_from_str},
+    Error, FallbackPolicy, Provider, Result, Threads,
 };
-use eyre::{bail, Result};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct KeywordSpotConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub zipformer_encoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub zipformer_decoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub zipformer_joiner: String,
 
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
+    /// Path to a keywords file.
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub keywords: String,
+    /// Keywords given in memory, used instead of the `keywords` file when non-empty.
+    pub keyword_list: Vec<Keyword>,
     pub max_active_path: i32,
     pub keywords_threshold: f32,
     pub keywords_score: f32,
 
     pub num_trailing_blanks: i32,
+    /// Ignore repeats of the same keyword within this window, see [`KeywordSpotter::detect`].
+    pub debounce_ms: u32,
 
     pub sample_rate: i32,
     pub feature_dim: i32,
 
     pub debug: bool,
-    pub num_threads: Option<i32>,
-    pub provider: Option<String>,
+    pub num_threads: Threads,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
 }
 
 impl Default for KeywordSpotConfig {
@@ -35,88 +58,61 @@ impl Default for KeywordSpotConfig {
             max_active_path: 4,
             keywords_score: 3.0,
             keywords: String::new(),
+            keyword_list: Vec::new(),
             tokens: String::new(),
 
             sample_rate: 16000,
             feature_dim: 80,
             num_trailing_blanks: 1,
+            debounce_ms: 0,
 
             zipformer_decoder: String::new(),
             zipformer_encoder: String::new(),
             zipformer_joiner: String::new(),
 
             debug: false,
-            num_threads: None,
-            provider: Some("cpu".into()),
+            num_threads: Threads::ONE,
+            provider: Some(Provider::Cpu),
+            fallback: FallbackPolicy::default(),
         }
     }
 }
 
 pub struct KeywordSpot {
-    spotter: *const sherpa_rs_sys::SherpaOnnxKeywordSpotter,
-    stream: *const sherpa_rs_sys::SherpaOnnxOnlineStream,
+    // Declared first so the stream is destroyed before its spotter
+    stream: NativeHandle<sherpa_rs_sys::SherpaOnnxOnlineStream>,
+    spotter: NativeHandle<sherpa_rs_sys::SherpaOnnxKeywordSpotter>,
+    provider: ProviderSelection,
 }
 
 impl KeywordSpot {
     // Create new keyboard spotter along with stream
     // Ready for streaming or regular use
     pub fn new(config: KeywordSpotConfig) -> Result<Self> {
-        let provider = cstring_from_str(&config.provider.unwrap_or(get_default_provider()));
-
-        let zipformer_encoder = cstring_from_str(&config.zipformer_encoder);
-        let zipformer_decoder = cstring_from_str(&config.zipformer_decoder);
-        let zipformer_joiner = cstring_from_str(&config.zipformer_joiner);
-
-        let tokens = cstring_from_str(&config.tokens);
-        let keywords = cstring_from_str(&config.keywords);
+        let (spotter, provider) = create_spotter(config)?;
+        let stream = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateKeywordStream(spotter.as_ptr()),
+                sherpa_rs_sys::SherpaOnnxDestroyOnlineStream,
+            )
+        }
+        .ok_or_else(|| Error::processing_failed("keyword spotter", "failed to create a stream"))?;
 
-        let sherpa_config = unsafe {
-            sherpa_rs_sys::SherpaOnnxKeywordSpotterConfig {
-                feat_config: sherpa_rs_sys::SherpaOnnxFeatureConfig {
-                    sample_rate: config.sample_rate,
-                    feature_dim: config.feature_dim,
-                },
-                keywords_buf: mem::zeroed::<_>(),
-                keywords_buf_size: 0,
-                keywords_file: keywords.as_ptr(),
-                max_active_paths: config.max_active_path,
-                keywords_score: config.keywords_score,
-                keywords_threshold: config.keywords_threshold,
-                num_trailing_blanks: config.num_trailing_blanks,
-                model_config: sherpa_rs_sys::SherpaOnnxOnlineModelConfig {
-                    transducer: sherpa_rs_sys::SherpaOnnxOnlineTransducerModelConfig {
-                        encoder: zipformer_encoder.as_ptr(),
-                        decoder: zipformer_decoder.as_ptr(),
-                        joiner: zipformer_joiner.as_ptr(),
-                    },
-                    num_threads: config.num_threads.unwrap_or(1),
-                    provider: provider.as_ptr(),
-                    debug: config.debug.into(),
-                    tokens: tokens.as_ptr(),
-
-                    paraformer: mem::zeroed::<_>(),
-                    zipformer2_ctc: mem::zeroed::<_>(),
-                    model_type: mem::zeroed::<_>(),
-                    modeling_unit: mem::zeroed::<_>(),
-                    bpe_vocab: mem::zeroed::<_>(),
-                    tokens_buf: mem::zeroed::<_>(),
-                    tokens_buf_size: mem::zeroed::<_>(),
-                    nemo_ctc: mem::zeroed::<_>(),
-                    t_one_ctc: mem::zeroed::<_>(),
-                },
-            }
-        };
-        let spotter = unsafe { sherpa_rs_sys::SherpaOnnxCreateKeywordSpotter(&sherpa_config) };
+        Ok(Self {
+            spotter,
+            stream,
+            provider,
+        })
+    }
 
-        if spotter.is_null() {
-            bail!("Failed to create keyword spotter");
-        }
-        let stream = unsafe { sherpa_rs_sys::SherpaOnnxCreateKeywordStream(spotter) };
-        if stream.is_null() {
-            bail!("Failed to create SherpaOnnx keyword stream");
-        }
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
 
-        Ok(Self { spotter, stream })
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
     }
 
     pub fn extract_keyword(
@@ -124,19 +120,30 @@ impl KeywordSpot {
         samples: Vec<f32>,
         sample_rate: u32,
     ) -> Result<Option<String>> {
+        let _timed = timed!("keyword_spot.extract", sample_rate);
         // Create keyword spotting stream
         unsafe {
             sherpa_rs_sys::SherpaOnnxOnlineStreamAcceptWaveform(
-                self.stream,
+                self.stream.as_ptr(),
                 sample_rate as i32,
                 samples.as_ptr(),
                 samples.len() as i32,
             );
-            sherpa_rs_sys::SherpaOnnxOnlineStreamInputFinished(self.stream);
-            while sherpa_rs_sys::SherpaOnnxIsKeywordStreamReady(self.spotter, self.stream) == 1 {
-                sherpa_rs_sys::SherpaOnnxDecodeKeywordStream(self.spotter, self.stream);
+            sherpa_rs_sys::SherpaOnnxOnlineStreamInputFinished(self.stream.as_ptr());
+            while sherpa_rs_sys::SherpaOnnxIsKeywordStreamReady(
+                self.spotter.as_ptr(),
+                self.stream.as_ptr(),
+            ) == 1
+            {
+                sherpa_rs_sys::SherpaOnnxDecodeKeywordStream(
+                    self.spotter.as_ptr(),
+                    self.stream.as_ptr(),
+                );
             }
-            let result_ptr = sherpa_rs_sys::SherpaOnnxGetKeywordResult(self.spotter, self.stream);
+            let result_ptr = sherpa_rs_sys::SherpaOnnxGetKeywordResult(
+                self.spotter.as_ptr(),
+                self.stream.as_ptr(),
+            );
             let mut keyword = None;
             if !result_ptr.is_null() {
                 let decoded_keyword = cstr_to_string((*result_ptr).keyword as _);
@@ -150,14 +157,393 @@ impl KeywordSpot {
     }
 }
 
+fn create_spotter(
+    config: KeywordSpotConfig,
+) -> Result<(
+    NativeHandle<sherpa_rs_sys::SherpaOnnxKeywordSpotter>,
+    ProviderSelection,
+)> {
+    create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+        create_spotter_on(KeywordSpotConfig {
+            provider: Some(provider),
+            ..config.clone()
+        })
+    })
+}
+
+fn create_spotter_on(
+    config: KeywordSpotConfig,
+) -> Result<NativeHandle<sherpa_rs_sys::SherpaOnnxKeywordSpotter>> {
+    let provider = config
+        .provider
+        .unwrap_or_else(get_default_provider)
+        .to_cstring()?;
+
+    let zipformer_encoder = cstring_from_str(&config.zipformer_encoder);
+    let zipformer_decoder = cstring_from_str(&config.zipformer_decoder);
+    let zipformer_joiner = cstring_from_str(&config.zipformer_joiner);
+
+    let tokens = cstring_from_str(&config.tokens);
+    let keywords = cstring_from_str(&config.keywords);
+    let keywords_buf = keywords_to_buf(&config.keyword_list)?;
+
+    let sherpa_config = unsafe {
+        sherpa_rs_sys::SherpaOnnxKeywordSpotterConfig {
+            feat_config: sherpa_rs_sys::SherpaOnnxFeatureConfig {
+                sample_rate: config.sample_rate,
+                feature_dim: config.feature_dim,
+            },
+            keywords_buf: if config.keyword_list.is_empty() {
+                mem::zeroed::<_>()
+            } else {
+                keywords_buf.as_ptr()
+            },
+            keywords_buf_size: keywords_buf.as_bytes().len() as i32,
+            keywords_file: keywords.as_ptr(),
+            max_active_paths: config.max_active_path,
+            keywords_score: config.keywords_score,
+            keywords_threshold: config.keywords_threshold,
+            num_trailing_blanks: config.num_trailing_blanks,
+            model_config: sherpa_rs_sys::SherpaOnnxOnlineModelConfig {
+                transducer: sherpa_rs_sys::SherpaOnnxOnlineTransducerModelConfig {
+                    encoder: zipformer_encoder.as_ptr(),
+                    decoder: zipformer_decoder.as_ptr(),
+                    joiner: zipformer_joiner.as_ptr(),
+                },
+                num_threads: config.num_threads.to_raw(),
+                provider: provider.as_ptr(),
+                debug: config.debug.into(),
+                tokens: tokens.as_ptr(),
+
+                paraformer: mem::zeroed::<_>(),
+                zipformer2_ctc: mem::zeroed::<_>(),
+                model_type: mem::zeroed::<_>(),
+                modeling_unit: mem::zeroed::<_>(),
+                bpe_vocab: mem::zeroed::<_>(),
+                tokens_buf: mem::zeroed::<_>(),
+                tokens_buf_size: mem::zeroed::<_>(),
+                nemo_ctc: mem::zeroed::<_>(),
+                t_one_ctc: mem::zeroed::<_>(),
+            },
+        }
+    };
+    unsafe {
+        NativeHandle::new(
+            sherpa_rs_sys::SherpaOnnxCreateKeywordSpotter(&sherpa_config),
+            sherpa_rs_sys::SherpaOnnxDestroyKeywordSpotter,
+        )
+    }
+    .ok_or_else(|| Error::native_creation_failed("keyword spotter"))
+}
+
+/// A keyword to spot, given in memory instead of a keywords file.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Keyword {
+    /// Tokens of the keyword separated by spaces, as in a keywords file,
+    /// e.g. `"▁HE LL O ▁WORLD"` for BPE models or `"你 好"` for Chinese.
+    pub phrase: String,
+    /// Overrides `keywords_score` for this keyword.
+    pub boost: Option<f32>,
+    /// Overrides `keywords_threshold` for this keyword.
+    pub threshold: Option<f32>,
+}
+
+fn keywords_to_buf(keywords: &[Keyword]) -> Result<CString> {
+    let mut buf = String::new();
+    for keyword in keywords {
+        let phrase = keyword.phrase.trim();
+        if phrase.is_empty() {
+            return Err(Error::invalid_input("keyword phrase is empty"));
+        }
+        if phrase.contains(['\n', '\r', '/']) {
+            return Err(Error::invalid_input(format!(
+                "keyword {:?} contains a separator character",
+                phrase
+            )));
+        }
+        buf.push_str(phrase);
+        if let Some(boost) = keyword.boost {
+            buf.push_str(&format!(" :{}", boost));
+        }
+        if let Some(threshold) = keyword.threshold {
+            buf.push_str(&format!(" #{}", threshold));
+        }
+        buf.push('\n');
+    }
+    Ok(cstring_from_str(&buf))
+}
+
+#[derive(Debug, Clone)]
+pub struct KeywordResult {
+    pub keyword: String,
+    pub tokens: Vec<String>,
+    /// Token times in seconds, relative to `start_time`.
+    pub timestamps: Vec<f32>,
+    /// Seconds since the last reset of the stream.
+    pub start_time: f32,
+    /// Seconds of audio fed to the stream when the keyword fired.
+    pub detected_at: f32,
+    /// Seconds of stream audio before the last reset, see
+    /// [`KeywordStream::absolute_time_offset_secs`].
+    pub offset: f32,
+}
+
+impl KeywordResult {
+    /// When the keyword started, in seconds since the stream started.
+    pub fn absolute_start_time(&self) -> f32 {
+        self.offset + self.start_time
+    }
+
+    /// Token times in seconds since the stream started, across resets.
+    pub fn absolute_timestamps(&self) -> Vec<f32> {
+        let start = self.absolute_start_time();
+        self.timestamps.iter().map(|t| t + start).collect()
+    }
+}
+
+/// Keyword spotter that can serve several streams, each with its own keyword list.
+pub struct KeywordSpotter {
+    spotter: NativeHandle<sherpa_rs_sys::SherpaOnnxKeywordSpotter>,
+    debounce_secs: f64,
+    provider: ProviderSelection,
+}
+
+/// Audio fed to a [`KeywordSpotter`]. Borrows the spotter it was created from.
+pub struct KeywordStream<'a> {
+    stream: NativeHandle<sherpa_rs_sys::SherpaOnnxOnlineStream>,
+    clock: Cell<StreamClock>,
+    /// When each keyword last fired, for debouncing.
+    last_detected: RefCell<HashMap<String, f64>>,
+    _spotter: PhantomData<&'a KeywordSpotter>,
+}
+
+impl KeywordSpotter {
+    pub fn new(config: KeywordSpotConfig) -> Result<Self> {
+        let debounce_secs = config.debounce_ms as f64 / 1000.0;
+        let (spotter, provider) = create_spotter(config)?;
+        Ok(Self {
+            spotter,
+            debounce_secs,
+            provider,
+        })
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Stream using the keywords from the config.
+    pub fn create_stream(&self) -> Result<KeywordStream<'_>> {
+        let stream = unsafe { sherpa_rs_sys::SherpaOnnxCreateKeywordStream(self.spotter.as_ptr()) };
+        self.wrap_stream(stream)
+    }
+
+    /// Stream spotting `keywords` instead of the keywords from the config.
+    pub fn create_stream_with_keywords(&self, keywords: &[Keyword]) -> Result<KeywordStream<'_>> {
+        if keywords.is_empty() {
+            return Err(Error::invalid_input("keyword list is empty"));
+        }
+        // The per-stream API takes '/' separated keywords
+        let buf = keywords_to_buf(keywords)?;
+        let buf = cstring_from_str(buf.to_string_lossy().trim_end().replace('\n', "/").as_str());
+        let stream = unsafe {
+            sherpa_rs_sys::SherpaOnnxCreateKeywordStreamWithKeywords(
+                self.spotter.as_ptr(),
+                buf.as_ptr(),
+            )
+        };
+        self.wrap_stream(stream)
+    }
+
+    fn wrap_stream(
+        &self,
+        stream: *const sherpa_rs_sys::SherpaOnnxOnlineStream,
+    ) -> Result<KeywordStream<'_>> {
+        let stream =
+            unsafe { NativeHandle::new(stream, sherpa_rs_sys::SherpaOnnxDestroyOnlineStream) }
+                .ok_or_else(|| {
+                    Error::processing_failed("keyword spotter", "failed to create a stream")
+                })?;
+        Ok(KeywordStream {
+            stream,
+            clock: Cell::default(),
+            last_detected: RefCell::new(HashMap::new()),
+            _spotter: PhantomData,
+        })
+    }
+
+    /// Drop the decoding context of `stream`, so residual audio can't match again.
+    pub fn reset(&self, stream: &KeywordStream) {
+        let mut clock = stream.clock.get();
+        clock.reset();
+        stream.clock.set(clock);
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxResetKeywordStream(
+                self.spotter.as_ptr(),
+                stream.stream.as_ptr(),
+            )
+        }
+    }
+
+    /// Decode the buffered audio and return the keyword spotted in it, if any.
+    ///
+    /// The stream is reset after a detection. Repeats of a keyword within `debounce_ms`
+    /// of its last detection on the same stream are dropped.
+    pub fn detect(&self, stream: &KeywordStream) -> Option<KeywordResult> {
+        unsafe {
+            while sherpa_rs_sys::SherpaOnnxIsKeywordStreamReady(
+                self.spotter.as_ptr(),
+                stream.stream.as_ptr(),
+            ) == 1
+            {
+                sherpa_rs_sys::SherpaOnnxDecodeKeywordStream(
+                    self.spotter.as_ptr(),
+                    stream.stream.as_ptr(),
+                );
+                let result_ptr = sherpa_rs_sys::SherpaOnnxGetKeywordResult(
+                    self.spotter.as_ptr(),
+                    stream.stream.as_ptr(),
+                );
+                if result_ptr.is_null() {
+                    continue;
+                }
+                let raw = result_ptr.read();
+                let keyword = cstr_to_string(raw.keyword);
+                let result = if keyword.is_empty() {
+                    None
+                } else {
+                    let count = raw.count.max(0) as usize;
+                    let tokens = if raw.tokens_arr.is_null() {
+                        Vec::new()
+                    } else {
+                        std::slice::from_raw_parts(raw.tokens_arr, count)
+                            .iter()
+                            .map(|&t| CStr::from_ptr(t).to_string_lossy().into_owned())
+                            .collect()
+                    };
+                    let timestamps = if raw.timestamps.is_null() {
+                        Vec::new()
+                    } else {
+                        std::slice::from_raw_parts(raw.timestamps, count).to_vec()
+                    };
+                    Some(KeywordResult {
+                        keyword,
+                        tokens,
+                        timestamps,
+                        start_time: raw.start_time,
+                        detected_at: stream.clock.get().elapsed as f32,
+                        offset: stream.clock.get().segment_start as f32,
+                    })
+                };
+                sherpa_rs_sys::SherpaOnnxDestroyKeywordResult(result_ptr);
+                if let Some(result) = result {
+                    self.reset(stream);
+                    if stream.debounce(&result.keyword, self.debounce_secs) {
+                        return Some(result);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl KeywordStream<'_> {
+    pub fn accept_waveform(&self, sample_rate: u32, samples: &[f32]) {
+        let mut clock = self.clock.get();
+        clock.advance(sample_rate, samples.len());
+        self.clock.set(clock);
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxOnlineStreamAcceptWaveform(
+                self.stream.as_ptr(),
+                sample_rate as i32,
+                samples.as_ptr(),
+                samples.len().try_into().unwrap(),
+            )
+        }
+    }
+
+    /// Signal that no more audio follows, so the tail can be decoded.
+    pub fn input_finished(&self) {
+        unsafe { sherpa_rs_sys::SherpaOnnxOnlineStreamInputFinished(self.stream.as_ptr()) }
+    }
+
+    /// Seconds of audio fed before the last reset, the stream time `start_time` of a
+    /// [`KeywordResult`] is relative to.
+    pub fn absolute_time_offset_secs(&self) -> f64 {
+        self.clock.get().segment_start
+    }
+
+    /// Record a detection of `keyword`, returning false if it repeats within `window` seconds.
+    fn debounce(&self, keyword: &str, window: f64) -> bool {
+        debounce(
+            &mut self.last_detected.borrow_mut(),
+            keyword,
+            self.clock.get().elapsed,
+            window,
+        )
+    }
+}
+
+/// Record that `keyword` fired at `now` in `last`, unless it already fired less than
+/// `window` seconds before. A suppressed repeat doesn't move the window.
+fn debounce(last: &mut HashMap<String, f64>, keyword: &str, now: f64, window: f64) -> bool {
+    match last.get(keyword) {
+        Some(&at) if window > 0.0 && now - at < window => false,
+        _ => {
+            last.insert(keyword.to_string(), now);
+            true
+        }
+    }
+}
+
+unsafe impl Send for KeywordSpotter {}
+unsafe impl Sync for KeywordSpotter {}
+
+unsafe impl Send for KeywordStream<'_> {}
+
 unsafe impl Send for KeywordSpot {}
 unsafe impl Sync for KeywordSpot {}
 
-impl Drop for KeywordSpot {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOnlineStream(self.stream);
-            sherpa_rs_sys::SherpaOnnxDestroyKeywordSpotter(self.spotter);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debounce_drops_repeats_inside_the_window() {
+        let mut last = HashMap::new();
+        assert!(debounce(&mut last, "hey", 1.0, 2.0));
+        assert!(!debounce(&mut last, "hey", 1.5, 2.0));
+        assert!(!debounce(&mut last, "hey", 2.9, 2.0));
+        // Measured from the last one let through, not the dropped repeats
+        assert!(debounce(&mut last, "hey", 3.0, 2.0));
+        assert!(!debounce(&mut last, "hey", 4.0, 2.0));
+        assert!(debounce(&mut last, "hey", 10.0, 2.0));
+    }
+
+    #[test]
+    fn debounce_is_per_keyword() {
+        let mut last = HashMap::new();
+        assert!(debounce(&mut last, "hey", 1.0, 2.0));
+        assert!(debounce(&mut last, "stop", 1.2, 2.0));
+        assert!(!debounce(&mut last, "hey", 1.4, 2.0));
+        assert!(!debounce(&mut last, "stop", 1.4, 2.0));
+        assert!(debounce(&mut last, "go", 1.4, 2.0));
+    }
+
+    #[test]
+    fn zero_window_keeps_every_detection() {
+        let mut last = HashMap::new();
+        for _ in 0..3 {
+            assert!(debounce(&mut last, "hey", 1.0, 0.0));
         }
     }
 }
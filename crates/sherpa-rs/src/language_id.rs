@@ -1,30 +1,104 @@
 use crate::{
     get_default_provider,
+    handle::NativeHandle,
+    providers::{create_with_fallback, ProviderSelection},
+    read_audio_file,
+    trace::{debug_event, timed},
     utils::{cstr_to_string, cstring_from_str},
+    Error, FallbackPolicy, Provider, Result, Threads,
 };
-use eyre::{bail, Result};
+use std::path::Path;
 
 #[derive(Debug)]
 pub struct SpokenLanguageId {
-    slid: *const sherpa_rs_sys::SherpaOnnxSpokenLanguageIdentification,
+    slid: NativeHandle<sherpa_rs_sys::SherpaOnnxSpokenLanguageIdentification>,
+    provider: ProviderSelection,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SpokenLanguageIdConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub encoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub decoder: String,
     pub debug: bool,
-    pub provider: Option<String>,
-    pub num_threads: Option<i32>,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub num_threads: Threads,
+}
+
+#[derive(Debug, Clone)]
+pub struct LanguageResult {
+    /// Whisper language code, e.g. `en`.
+    pub lang: String,
+}
+
+/// A window of [`SpokenLanguageId::detect_windows`].
+#[derive(Debug, Clone)]
+pub struct LanguageWindow {
+    pub start: f32,
+    pub end: f32,
+    /// `None` if the window couldn't be identified.
+    pub lang: Option<String>,
+}
+
+impl SpokenLanguageIdConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, path) in [("encoder", &self.encoder), ("decoder", &self.decoder)] {
+            if path.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "language id {} path is empty",
+                    name
+                )));
+            }
+            if !Path::new(path).exists() {
+                return Err(Error::model_not_found(
+                    format!("language id {}", name),
+                    path,
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl SpokenLanguageId {
-    pub fn new(config: SpokenLanguageIdConfig) -> Self {
+    pub fn new(config: SpokenLanguageIdConfig) -> Result<Self> {
+        let _timed = timed!("language_id.create");
+        debug_event!(?config, "creating spoken language identification");
+        let (mut slid, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(SpokenLanguageIdConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        slid.provider = provider;
+        Ok(slid)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    fn create(config: SpokenLanguageIdConfig) -> Result<Self> {
+        config.validate()?;
         let debug = config.debug.into();
 
         let decoder = cstring_from_str(&config.decoder);
         let encoder = cstring_from_str(&config.encoder);
-        let provider = cstring_from_str(&config.provider.unwrap_or(get_default_provider()));
+        let provider = config
+            .provider
+            .unwrap_or_else(get_default_provider)
+            .to_cstring()?;
 
         let whisper = sherpa_rs_sys::SherpaOnnxSpokenLanguageIdentificationWhisperConfig {
             decoder: decoder.as_ptr(),
@@ -33,49 +107,105 @@ impl SpokenLanguageId {
         };
         let sherpa_config = sherpa_rs_sys::SherpaOnnxSpokenLanguageIdentificationConfig {
             debug,
-            num_threads: config.num_threads.unwrap_or(1),
+            num_threads: config.num_threads.to_raw(),
             provider: provider.as_ptr(),
             whisper,
         };
-        let slid =
-            unsafe { sherpa_rs_sys::SherpaOnnxCreateSpokenLanguageIdentification(&sherpa_config) };
+        let slid = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateSpokenLanguageIdentification(&sherpa_config),
+                sherpa_rs_sys::SherpaOnnxDestroySpokenLanguageIdentification,
+            )
+        }
+        .ok_or_else(|| Error::native_creation_failed("spoken language identification"))?;
 
-        Self { slid }
+        Ok(Self {
+            slid,
+            provider: ProviderSelection::default(),
+        })
     }
 
-    pub fn compute(&mut self, samples: Vec<f32>, sample_rate: u32) -> Result<String> {
+    pub fn detect(&mut self, samples: &[f32], sample_rate: u32) -> Result<LanguageResult> {
+        let _timed = timed!("language_id.detect", sample_rate, samples = samples.len());
         unsafe {
-            let stream =
-                sherpa_rs_sys::SherpaOnnxSpokenLanguageIdentificationCreateOfflineStream(self.slid);
+            let stream = sherpa_rs_sys::SherpaOnnxSpokenLanguageIdentificationCreateOfflineStream(
+                self.slid.as_ptr(),
+            );
+            if stream.is_null() {
+                return Err(Error::processing_failed(
+                    "spoken language identification",
+                    "failed to create a stream",
+                ));
+            }
             sherpa_rs_sys::SherpaOnnxAcceptWaveformOffline(
                 stream,
                 sample_rate as i32,
                 samples.as_ptr(),
-                samples.len().try_into().unwrap(),
+                samples.len() as i32,
+            );
+            let result_ptr = sherpa_rs_sys::SherpaOnnxSpokenLanguageIdentificationCompute(
+                self.slid.as_ptr(),
+                stream,
             );
-            let language_result_ptr =
-                sherpa_rs_sys::SherpaOnnxSpokenLanguageIdentificationCompute(self.slid, stream);
-            if language_result_ptr.is_null() || (*language_result_ptr).lang.is_null() {
-                bail!("language ptr is null");
-            }
-            let language_ptr = (*language_result_ptr).lang;
-            let language = cstr_to_string(language_ptr as _);
-            // Free
-            sherpa_rs_sys::SherpaOnnxDestroySpokenLanguageIdentificationResult(language_result_ptr);
             sherpa_rs_sys::SherpaOnnxDestroyOfflineStream(stream);
+            if result_ptr.is_null() {
+                return Err(Error::LanguageNotIdentified);
+            }
+            let lang = if (*result_ptr).lang.is_null() {
+                String::new()
+            } else {
+                cstr_to_string((*result_ptr).lang as _)
+            };
+            sherpa_rs_sys::SherpaOnnxDestroySpokenLanguageIdentificationResult(result_ptr);
 
-            Ok(language)
+            if lang.is_empty() {
+                return Err(Error::LanguageNotIdentified);
+            }
+            Ok(LanguageResult { lang })
         }
     }
-}
 
-unsafe impl Send for SpokenLanguageId {}
-unsafe impl Sync for SpokenLanguageId {}
+    pub fn detect_file(&mut self, path: &str) -> Result<LanguageResult> {
+        let (samples, sample_rate) = read_audio_file(path)?;
+        self.detect(&samples, sample_rate)
+    }
 
-impl Drop for SpokenLanguageId {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroySpokenLanguageIdentification(self.slid);
+    /// Identify each `window_secs` of a long recording separately, e.g. to find where
+    /// the speaker switches language.
+    pub fn detect_windows(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        window_secs: f32,
+    ) -> Result<Vec<LanguageWindow>> {
+        if sample_rate == 0 || window_secs <= 0.0 {
+            return Err(Error::invalid_input(
+                "sample rate and window length must be positive",
+            ));
+        }
+        let window = ((window_secs * sample_rate as f32) as usize).max(1);
+        let sr = sample_rate as f32;
+        let mut windows = Vec::with_capacity(samples.len().div_ceil(window));
+        for (i, chunk) in samples.chunks(window).enumerate() {
+            let lang = match self.detect(chunk, sample_rate) {
+                Ok(result) => Some(result.lang),
+                Err(Error::LanguageNotIdentified) => None,
+                Err(err) => return Err(err),
+            };
+            let start = (i * window) as f32 / sr;
+            windows.push(LanguageWindow {
+                start,
+                end: start + chunk.len() as f32 / sr,
+                lang,
+            });
         }
+        Ok(windows)
+    }
+
+    pub fn compute(&mut self, samples: Vec<f32>, sample_rate: u32) -> Result<String> {
+        Ok(self.detect(&samples, sample_rate)?.lang)
     }
 }
+
+unsafe impl Send for SpokenLanguageId {}
+unsafe impl Sync for SpokenLanguageId {}
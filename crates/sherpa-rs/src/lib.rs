@@ -1,65 +1,90 @@
+pub mod align;
 pub mod audio_tag;
+pub mod cancel;
+pub mod decoding;
+pub mod denoise;
 pub mod diarize;
 pub mod dolphin;
 pub mod embedding_manager;
+pub mod error;
+pub mod fire_red_asr;
+pub mod itn;
 pub mod keyword_spot;
 pub mod language_id;
+pub mod model_source;
+pub mod models;
 pub mod moonshine;
+pub mod online;
 pub mod paraformer;
+pub mod pipeline;
+pub mod providers;
 pub mod punctuate;
 pub mod sense_voice;
 pub mod silero_vad;
 pub mod source_separation;
 pub mod speaker_id;
+pub mod subtitle;
 pub mod ten_vad;
 pub mod transducer;
+pub mod vad;
 pub mod whisper;
 pub mod zipformer;
 
-mod utils;
+mod handle;
+mod hotwords;
+mod trace;
+mod version;
+
+#[cfg(test)]
+mod testing;
+
+pub mod utils;
 
 #[cfg(feature = "tts")]
 pub mod tts;
 
+#[cfg(feature = "capture")]
+pub mod capture;
+
+#[cfg(feature = "serde")]
+pub mod config;
+
+#[cfg(feature = "playback")]
+pub mod playback;
+
 use std::ffi::CStr;
 
 #[cfg(feature = "sys")]
 pub use sherpa_rs_sys;
 
-use eyre::{bail, Result};
 use utils::cstr_to_string;
 
-pub fn get_default_provider() -> String {
-    "cpu".into()
-    // Other providers has many issues with different models!!
-    // if cfg!(feature = "cuda") {
-    //     "cuda"
-    // } else if cfg!(target_os = "macos") {
-    //     "coreml"
-    // } else if cfg!(feature = "directml") {
-    //     "directml"
-    // } else {
-    //     "cpu"
-    // }
-    // .into()
+pub use cancel::CancellationToken;
+pub use decoding::DecodingMethod;
+pub use error::{Error, Result};
+pub use providers::{FallbackPolicy, Provider, ProviderSelection, Threads};
+pub use version::{version, VersionInfo};
+
+/// Provider used when a config doesn't set one: the GPU provider the crate was built
+/// for if its runtime is installed, otherwise CPU.
+pub fn get_default_provider() -> Provider {
+    // CoreML is left out on purpose, it has many issues with different models
+    [Provider::Cuda, Provider::DirectMl]
+        .into_iter()
+        .find(providers::is_available)
+        .unwrap_or(Provider::Cpu)
 }
 
 pub fn read_audio_file(path: &str) -> Result<(Vec<f32>, u32)> {
-    let mut reader = hound::WavReader::open(path)?;
-    let sample_rate = reader.spec().sample_rate;
+    let wav = utils::read_wav(path)?;
 
     // Check if the sample rate is 16000
-    if sample_rate != 16000 {
-        bail!("The sample rate must be 16000.");
+    if wav.sample_rate != 16000 {
+        return Err(Error::invalid_input("The sample rate must be 16000."));
     }
 
-    // Collect samples into a Vec<f32>
-    let samples: Vec<f32> = reader
-        .samples::<i16>()
-        .map(|s| (s.unwrap() as f32) / (i16::MAX as f32))
-        .collect();
-
-    Ok((samples, sample_rate))
+    let samples = wav.to_mono(utils::ChannelMix::Average);
+    Ok((samples, wav.sample_rate))
 }
 
 pub fn write_audio_file(path: &str, samples: &[f32], sample_rate: u32) -> Result<()> {
@@ -84,27 +109,54 @@ pub fn write_audio_file(path: &str, samples: &[f32], sample_rate: u32) -> Result
     Ok(())
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct OnnxConfig {
-    pub provider: String,
+    pub provider: Provider,
+    pub fallback: FallbackPolicy,
     pub debug: bool,
-    pub num_threads: i32,
+    pub num_threads: Threads,
 }
 
+/// Text and token-level detail of an offline decode.
+///
+/// Fields a model doesn't report are empty or `None`.
 #[derive(Debug, Clone)]
 pub struct OfflineRecognizerResult {
+    /// Language as reported by sherpa-onnx, e.g. `<|en|>`.
     pub lang: String,
     pub text: String,
+    /// Start of each token in seconds.
     pub timestamps: Vec<f32>,
+    /// Duration of each token in seconds, reported by TDT models.
+    pub durations: Vec<f32>,
     pub tokens: Vec<String>,
+    /// Detected language without the tag markers, e.g. `en`.
+    pub language: Option<String>,
+    /// Emotion reported by SenseVoice, e.g. `HAPPY`.
+    pub emotion: Option<String>,
+    /// Audio events reported by SenseVoice, e.g. `Speech` or `BGM`.
+    pub events: Vec<String>,
+    /// Geometric mean of the token probabilities, in `[0, 1]`.
+    ///
+    /// `None` when the model does not report per-token log probabilities.
+    pub confidence: Option<f32>,
+    /// The JSON sherpa-onnx builds the result from, for fields not mapped above.
+    pub raw_json: Option<String>,
 }
 
+/// Same as [`OfflineRecognizerResult`], named after what it holds.
+pub type TranscriptionResult = OfflineRecognizerResult;
+
 impl OfflineRecognizerResult {
     fn new(result: &sherpa_rs_sys::SherpaOnnxOfflineRecognizerResult) -> Self {
         let lang = unsafe { cstr_to_string(result.lang) };
         let text = unsafe { cstr_to_string(result.text) };
         let count = result.count.try_into().unwrap();
+        let json = unsafe { cstr_to_string(result.json) };
         let timestamps = if result.timestamps.is_null() {
-            Vec::new()
+            json_f32_array(&json, "timestamps").unwrap_or_default()
         } else {
             unsafe { std::slice::from_raw_parts(result.timestamps, count).to_vec() }
         };
@@ -118,21 +170,315 @@ impl OfflineRecognizerResult {
                 .wrapping_byte_offset(token.to_bytes_with_nul().len().try_into().unwrap());
         }
 
+        let language = tag_values(&lang).into_iter().next();
+        let emotion = tag_values(&unsafe { cstr_to_string(result.emotion) })
+            .into_iter()
+            .find(|e| e != "EMO_UNKNOWN");
+        let events = tag_values(&unsafe { cstr_to_string(result.event) })
+            .into_iter()
+            .filter(|e| e != "Event_UNK")
+            .collect();
+        let confidence = json_f32_array(&json, "ys_log_probs")
+            .filter(|probs| !probs.is_empty())
+            .map(|probs| {
+                let mean = probs.iter().sum::<f32>() / probs.len() as f32;
+                mean.exp().clamp(0.0, 1.0)
+            });
+        let durations = json_f32_array(&json, "durations").unwrap_or_default();
+        let raw_json = (!json.is_empty()).then_some(json);
+
         Self {
             lang,
             text,
             timestamps,
+            durations,
             tokens,
+            language,
+            emotion,
+            events,
+            confidence,
+            raw_json,
         }
     }
+
+    /// Merge sub-word tokens into words with start and end times in seconds.
+    ///
+    /// Word boundaries come from the tokenizer markers: `##` continues a WordPiece word,
+    /// `▁` or a leading space starts a new BPE word, and a trailing `@@` (Paraformer)
+    /// joins a token to the next one. Tokens without a marker (e.g. CJK characters) are
+    /// treated as one word each.
+    /// Returns an empty Vec if the model does not produce timestamps.
+    pub fn words(&self) -> Vec<WordSpan> {
+        if self.timestamps.is_empty() || self.timestamps.len() != self.tokens.len() {
+            return Vec::new();
+        }
+
+        let wordpiece = self.tokens.iter().any(|t| t.starts_with("##"));
+        let space_prefix = !wordpiece
+            && self
+                .tokens
+                .iter()
+                .any(|t| t.starts_with('\u{2581}') || t.starts_with(' '));
+        let suffix_joined =
+            !wordpiece && !space_prefix && self.tokens.iter().any(|t| t.ends_with("@@"));
+        let mut joins_next = false;
+
+        let mut words: Vec<WordSpan> = Vec::new();
+        for (token, &start) in self.tokens.iter().zip(&self.timestamps) {
+            let (piece, continues) = if wordpiece {
+                match token.strip_prefix("##") {
+                    Some(rest) => (rest, true),
+                    None => (token.as_str(), false),
+                }
+            } else if space_prefix {
+                match token.strip_prefix(['\u{2581}', ' ']) {
+                    Some(rest) => (rest, false),
+                    None => (token.as_str(), true),
+                }
+            } else if suffix_joined {
+                let continues = joins_next;
+                let piece = token.strip_suffix("@@");
+                joins_next = piece.is_some();
+                (piece.unwrap_or(token), continues)
+            } else {
+                (token.as_str(), false)
+            };
+
+            match words.last_mut() {
+                Some(word) if continues => word.word.push_str(piece),
+                _ => {
+                    if let Some(prev) = words.last_mut() {
+                        prev.end = start;
+                    }
+                    words.push(WordSpan {
+                        word: piece.to_string(),
+                        start,
+                        end: start,
+                    });
+                }
+            }
+        }
+
+        // The last word has no successor, end it at its last token
+        if let (Some(word), Some(&last)) = (words.last_mut(), self.timestamps.last()) {
+            word.end = last;
+        }
+        words.retain(|w| !w.word.trim().is_empty());
+        words
+    }
+}
+
+/// Read a flat array of numbers from the recognizer's JSON result.
+fn json_f32_array(json: &str, key: &str) -> Option<Vec<f32>> {
+    let rest = &json[json.find(&format!("\"{}\"", key))? + key.len() + 2..];
+    let rest = rest
+        .trim_start()
+        .strip_prefix(':')?
+        .trim_start()
+        .strip_prefix('[')?;
+    let body = &rest[..rest.find(']')?];
+    if body.trim().is_empty() {
+        return Some(Vec::new());
+    }
+    body.split(',').map(|v| v.trim().parse().ok()).collect()
+}
+
+/// Values of `<|tag|>` markers in `s`, or `s` itself if it has none.
+fn tag_values(s: &str) -> Vec<String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Vec::new();
+    }
+    if !s.contains("<|") {
+        return vec![s.to_string()];
+    }
+    s.split("<|")
+        .filter_map(|part| part.split_once("|>"))
+        .map(|(tag, _)| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Decode `inputs` of `(samples, sample_rate)` in a single native batch call.
+///
+/// Results are in input order. Inputs that can't be fed get an error in their slot
+/// and are left out of the batch.
+pub(crate) fn decode_offline_batch(
+    recognizer: *const sherpa_rs_sys::SherpaOnnxOfflineRecognizer,
+    inputs: &[(&[f32], u32)],
+) -> Vec<Result<OfflineRecognizerResult>> {
+    if inputs.is_empty() {
+        return Vec::new();
+    }
+    let _timed = trace::timed!("offline.transcribe_batch", batch = inputs.len());
+
+    let mut slots: Vec<Result<*const sherpa_rs_sys::SherpaOnnxOfflineStream>> = inputs
+        .iter()
+        .map(|&(samples, sample_rate)| {
+            if sample_rate == 0 {
+                return Err(Error::invalid_input("sample rate must be non-zero"));
+            }
+            let len = i32::try_from(samples.len()).map_err(|_| {
+                Error::invalid_input(format!("input of {} samples is too long", samples.len()))
+            })?;
+            let stream = unsafe { sherpa_rs_sys::SherpaOnnxCreateOfflineStream(recognizer) };
+            if stream.is_null() {
+                return Err(Error::processing_failed(
+                    "recognizer",
+                    "SherpaOnnxCreateOfflineStream returned null",
+                ));
+            }
+            unsafe {
+                sherpa_rs_sys::SherpaOnnxAcceptWaveformOffline(
+                    stream,
+                    sample_rate as i32,
+                    samples.as_ptr(),
+                    len,
+                );
+            }
+            Ok(stream)
+        })
+        .collect();
+
+    let mut streams: Vec<_> = slots
+        .iter()
+        .filter_map(|s| s.as_ref().ok().copied())
+        .collect();
+    if !streams.is_empty() {
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxDecodeMultipleOfflineStreams(
+                recognizer,
+                streams.as_mut_ptr(),
+                streams.len() as i32,
+            );
+        }
+    }
+
+    slots
+        .drain(..)
+        .map(|slot| {
+            let stream = slot?;
+            unsafe {
+                let result_ptr = sherpa_rs_sys::SherpaOnnxGetOfflineStreamResult(stream);
+                let result = if result_ptr.is_null() {
+                    Err(Error::processing_failed(
+                        "recognizer",
+                        "SherpaOnnxGetOfflineStreamResult returned null",
+                    ))
+                } else {
+                    let result = OfflineRecognizerResult::new(&result_ptr.read());
+                    sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizerResult(result_ptr);
+                    Ok(result)
+                };
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineStream(stream);
+                result
+            }
+        })
+        .collect()
+}
+
+/// A word from [`OfflineRecognizerResult::words`], times in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordSpan {
+    pub word: String,
+    pub start: f32,
+    pub end: f32,
 }
 
 impl Default for OnnxConfig {
     fn default() -> Self {
         Self {
             provider: get_default_provider(),
+            fallback: FallbackPolicy::default(),
             debug: false,
-            num_threads: 1,
+            num_threads: Threads::ONE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recognized(tokens: &[&str], timestamps: &[f32]) -> OfflineRecognizerResult {
+        OfflineRecognizerResult {
+            lang: String::new(),
+            text: tokens.concat(),
+            timestamps: timestamps.to_vec(),
+            durations: Vec::new(),
+            tokens: tokens.iter().map(|t| t.to_string()).collect(),
+            language: None,
+            emotion: None,
+            events: Vec::new(),
+            confidence: None,
+            raw_json: None,
         }
     }
+
+    fn spans(words: &[WordSpan]) -> Vec<(&str, f32, f32)> {
+        words
+            .iter()
+            .map(|w| (w.word.as_str(), w.start, w.end))
+            .collect()
+    }
+
+    #[test]
+    fn words_merge_suffix_joined_tokens() {
+        let result = recognized(
+            &["HEL@@", "LO", "WOR@@", "L@@", "D", "AGAIN"],
+            &[0.0, 0.25, 0.5, 0.625, 0.75, 1.0],
+        );
+        assert_eq!(
+            spans(&result.words()),
+            [
+                ("HELLO", 0.0, 0.5),
+                ("WORLD", 0.5, 1.0),
+                ("AGAIN", 1.0, 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn words_keep_unmarked_cjk_tokens_apart() {
+        let result = recognized(
+            &["你", "好", "HEL@@", "LO", "吗"],
+            &[0.0, 0.25, 0.5, 0.75, 1.0],
+        );
+        assert_eq!(
+            spans(&result.words()),
+            [
+                ("你", 0.0, 0.25),
+                ("好", 0.25, 0.5),
+                ("HELLO", 0.5, 1.0),
+                ("吗", 1.0, 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn words_drop_a_dangling_suffix_marker() {
+        let result = recognized(&["A", "B@@"], &[0.0, 0.5]);
+        assert_eq!(spans(&result.words()), [("A", 0.0, 0.5), ("B", 0.5, 0.5)]);
+    }
+
+    #[test]
+    fn words_merge_wordpiece_and_bpe_tokens() {
+        let result = recognized(&["hel", "##lo", "there"], &[0.0, 0.25, 0.5]);
+        assert_eq!(
+            spans(&result.words()),
+            [("hello", 0.0, 0.5), ("there", 0.5, 0.5)]
+        );
+
+        let result = recognized(&["\u{2581}hel", "lo", "\u{2581}there"], &[0.0, 0.25, 0.5]);
+        assert_eq!(
+            spans(&result.words()),
+            [("hello", 0.0, 0.5), ("there", 0.5, 0.5)]
+        );
+    }
+
+    #[test]
+    fn words_need_a_timestamp_per_token() {
+        assert!(recognized(&["HEL@@", "LO"], &[]).words().is_empty());
+        assert!(recognized(&["HEL@@", "LO"], &[0.0]).words().is_empty());
+    }
 }
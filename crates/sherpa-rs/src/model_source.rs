@@ -0,0 +1,153 @@
+//! Models that come from memory instead of a file, e.g. embedded with `include_bytes!`.
+//!
+//! sherpa-onnx's C API takes a file path for every model it loads, in all backends: TTS,
+//! offline and online recognizers, VAD, speaker embedding and the rest. Only keyword
+//! lists are passed as buffers, see [`crate::keyword_spot`]. Bytes are therefore written
+//! to a private temp directory owned by [`ModelFiles`] and the configs get its paths.
+//! Models are read once when an engine is created, so the files can be dropped right
+//! after, unless the engine reloads them later (e.g. `reconfigure` on TTS engines).
+//!
+//! ```ignore
+//! let mut files = ModelFiles::new();
+//! let config = WhisperConfig {
+//!     encoder: files.path("encoder.onnx", include_bytes!("tiny-encoder.onnx"))?,
+//!     decoder: files.path("decoder.onnx", include_bytes!("tiny-decoder.onnx"))?,
+//!     tokens: files.path("tokens.txt", "models/tiny-tokens.txt")?,
+//!     ..Default::default()
+//! };
+//! let recognizer = WhisperRecognizer::new(config)?;
+//! drop(files);
+//! ```
+
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{Error, Result};
+
+/// Where a model is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelSource {
+    Path(PathBuf),
+    Bytes(Cow<'static, [u8]>),
+}
+
+impl From<PathBuf> for ModelSource {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<&Path> for ModelSource {
+    fn from(path: &Path) -> Self {
+        Self::Path(path.to_path_buf())
+    }
+}
+
+impl From<String> for ModelSource {
+    fn from(path: String) -> Self {
+        Self::Path(path.into())
+    }
+}
+
+impl From<&str> for ModelSource {
+    fn from(path: &str) -> Self {
+        Self::Path(path.into())
+    }
+}
+
+impl From<&'static [u8]> for ModelSource {
+    fn from(bytes: &'static [u8]) -> Self {
+        Self::Bytes(Cow::Borrowed(bytes))
+    }
+}
+
+impl<const N: usize> From<&'static [u8; N]> for ModelSource {
+    fn from(bytes: &'static [u8; N]) -> Self {
+        Self::Bytes(Cow::Borrowed(bytes))
+    }
+}
+
+impl From<Vec<u8>> for ModelSource {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(Cow::Owned(bytes))
+    }
+}
+
+/// Temp directory holding models given as bytes, removed with everything in it on drop.
+///
+/// The directory is only created once the first bytes are written.
+#[derive(Debug, Default)]
+pub struct ModelFiles {
+    dir: Option<PathBuf>,
+}
+
+impl ModelFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Path to pass to a config for `source`. Paths are returned as they are, bytes are
+    /// written to `name` in the temp directory. Keep the original file name in `name`, it
+    /// shows up in sherpa-onnx's errors.
+    pub fn path(&mut self, name: &str, source: impl Into<ModelSource>) -> Result<String> {
+        match source.into() {
+            ModelSource::Path(path) => Ok(path.to_string_lossy().into_owned()),
+            ModelSource::Bytes(bytes) => {
+                let file_name = Path::new(name).file_name().ok_or_else(|| {
+                    Error::invalid_input(format!("invalid model file name {:?}", name))
+                })?;
+                let path = self.dir()?.join(file_name);
+                if path.exists() {
+                    return Err(Error::invalid_input(format!(
+                        "model file {:?} was already written",
+                        name
+                    )));
+                }
+                fs::write(&path, &bytes)?;
+                Ok(path.to_string_lossy().into_owned())
+            }
+        }
+    }
+
+    /// The temp directory, if any bytes were written.
+    pub fn dir_path(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    fn dir(&mut self) -> Result<&Path> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        if self.dir.is_none() {
+            let dir = loop {
+                let dir = std::env::temp_dir().join(format!(
+                    "sherpa-rs-{}-{}",
+                    process::id(),
+                    NEXT.fetch_add(1, Ordering::Relaxed)
+                ));
+                match fs::create_dir(&dir) {
+                    Ok(()) => break dir,
+                    // Left over from an earlier process with the same id
+                    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                    Err(err) => return Err(err.into()),
+                }
+            };
+            self.dir = Some(dir);
+        }
+        Ok(self.dir.as_deref().unwrap())
+    }
+}
+
+impl Drop for ModelFiles {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            if let Err(err) = fs::remove_dir_all(dir) {
+                tracing::warn!("Failed to remove {}: {}", dir.display(), err);
+            }
+        }
+    }
+}
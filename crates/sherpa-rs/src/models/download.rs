@@ -0,0 +1,445 @@
+//! Fetch sherpa-onnx release archives, check them against a pinned SHA-256 and unpack them.
+//!
+//! Checksums for [`ModelId`] come from `model-checksums.txt`, in the same
+//! `<file name>\t<sha256>` format as sherpa-rs-sys's `checksum.txt`. As for the
+//! binaries, `UNSAFE_DISABLE_CHECKSUM_VALIDATION=1` skips the check.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+
+use super::ModelDir;
+use crate::{
+    trace::{debug_event, timed},
+    Error, Result,
+};
+
+const RELEASES_URL: &str = "https://github.com/k2-fsa/sherpa-onnx/releases/download";
+
+static CHECKSUM_CONTENT: &str = include_str!("../../model-checksums.txt");
+
+/// Models [`download`] knows the URL and checksum of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModelId {
+    WhisperTiny,
+    WhisperTinyEn,
+    WhisperBase,
+    MoonshineTinyEn,
+    SenseVoice,
+    ParaformerZh,
+    StreamingZipformerEn,
+    SileroVad,
+    VitsPiperEnAmy,
+    Kokoro,
+    KokoroEn,
+    KittenNano,
+    Spleeter,
+}
+
+impl ModelId {
+    /// File name of the release asset.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ModelId::WhisperTiny => "sherpa-onnx-whisper-tiny.tar.bz2",
+            ModelId::WhisperTinyEn => "sherpa-onnx-whisper-tiny.en.tar.bz2",
+            ModelId::WhisperBase => "sherpa-onnx-whisper-base.tar.bz2",
+            ModelId::MoonshineTinyEn => "sherpa-onnx-moonshine-tiny-en-int8.tar.bz2",
+            ModelId::SenseVoice => "sherpa-onnx-sense-voice-zh-en-ja-ko-yue-2024-07-17.tar.bz2",
+            ModelId::ParaformerZh => "sherpa-onnx-paraformer-zh-2024-03-09.tar.bz2",
+            ModelId::StreamingZipformerEn => {
+                "sherpa-onnx-streaming-zipformer-en-2023-06-26.tar.bz2"
+            }
+            ModelId::SileroVad => "silero_vad.onnx",
+            ModelId::VitsPiperEnAmy => "vits-piper-en_US-amy-low.tar.bz2",
+            ModelId::Kokoro => "kokoro-multi-lang-v1_0.tar.bz2",
+            ModelId::KokoroEn => "kokoro-en-v0_19.tar.bz2",
+            ModelId::KittenNano => "kitten-nano-en-v0_2-fp16.tar.bz2",
+            ModelId::Spleeter => "sherpa-onnx-spleeter-2stems-fp16.tar.bz2",
+        }
+    }
+
+    pub fn url(&self) -> String {
+        let release = match self {
+            ModelId::VitsPiperEnAmy | ModelId::Kokoro | ModelId::KokoroEn | ModelId::KittenNano => {
+                "tts-models"
+            }
+            ModelId::Spleeter => "source-separation-models",
+            _ => "asr-models",
+        };
+        format!("{}/{}/{}", RELEASES_URL, release, self.file_name())
+    }
+
+    /// Pinned SHA-256 of the release asset, `None` if the manifest doesn't have it yet.
+    pub fn sha256(&self) -> Option<&'static str> {
+        static CHECKSUMS: OnceLock<HashMap<&'static str, &'static str>> = OnceLock::new();
+        CHECKSUMS
+            .get_or_init(|| {
+                CHECKSUM_CONTENT
+                    .lines()
+                    .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+                    .filter_map(|line| {
+                        let mut parts = line.split_whitespace();
+                        Some((parts.next()?, parts.next()?))
+                    })
+                    .collect()
+            })
+            .get(self.file_name())
+            .copied()
+    }
+}
+
+impl fmt::Display for ModelId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(archive_stem(self.file_name()))
+    }
+}
+
+/// Download `id` into `dest_dir` unless it's already there, and open it.
+///
+/// The model ends up in `dest_dir/<archive name>`. Interrupted downloads are resumed
+/// from the `.part` file left in `dest_dir`.
+pub fn download<P: AsRef<Path>>(id: ModelId, dest_dir: P) -> Result<ModelDir> {
+    let sha256 = id.sha256();
+    if sha256.is_none() && !checksum_check_disabled() {
+        return Err(Error::invalid_input(format!(
+            "no checksum for {} in model-checksums.txt, use download_from with the sha256 you expect",
+            id
+        )));
+    }
+    fetch(&id.url(), sha256, dest_dir.as_ref())
+}
+
+/// Download and open a model that isn't in [`ModelId`], checking it against `sha256`.
+///
+/// `url` must point to a `.tar.bz2` or `.zip` archive, or to a single model file.
+pub fn download_from<P: AsRef<Path>>(url: &str, sha256: &str, dest_dir: P) -> Result<ModelDir> {
+    fetch(url, Some(sha256), dest_dir.as_ref())
+}
+
+fn fetch(url: &str, sha256: Option<&str>, dest_dir: &Path) -> Result<ModelDir> {
+    let file_name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|url| url.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| Error::invalid_input(format!("no file name in {:?}", url)))?;
+    let model_dir = dest_dir.join(archive_stem(file_name));
+    if model_dir.is_dir() {
+        return ModelDir::open(model_dir);
+    }
+
+    let _timed = timed!("models.download", url);
+    fs::create_dir_all(dest_dir)?;
+    let part = dest_dir.join(format!("{}.part", file_name));
+    fetch_file(url, &part)?;
+
+    let actual = sha256_file(&part)?;
+    match sha256 {
+        Some(expected) if !expected.eq_ignore_ascii_case(&actual) => {
+            if checksum_check_disabled() {
+                tracing::warn!("UNSAFE: checksum validation disabled for {}", url);
+            } else {
+                fs::remove_file(&part)?;
+                return Err(Error::processing_failed(
+                    "model download",
+                    format!(
+                        "checksum mismatch for {}, expected {} but got {}",
+                        url, expected, actual
+                    ),
+                ));
+            }
+        }
+        Some(_) => {}
+        None => tracing::warn!("UNSAFE: no checksum to validate {} with", url),
+    }
+
+    // Unpack next to the destination first so an interrupted extraction is never
+    // mistaken for a complete model.
+    let staging = dest_dir.join(format!("{}.extracting", file_name));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+    let unpacked = if file_name.ends_with(".tar.bz2") {
+        let decoder = bzip2::read::BzDecoder::new(BufReader::new(File::open(&part)?));
+        tar::Archive::new(decoder).unpack(&staging)?;
+        single_subdir(&staging)?.unwrap_or_else(|| staging.clone())
+    } else if file_name.ends_with(".zip") {
+        zip::ZipArchive::new(BufReader::new(File::open(&part)?))
+            .and_then(|mut archive| archive.extract(&staging))
+            .map_err(|err| Error::processing_failed("model download", err.to_string()))?;
+        single_subdir(&staging)?.unwrap_or_else(|| staging.clone())
+    } else {
+        fs::copy(&part, staging.join(file_name))?;
+        staging.clone()
+    };
+    fs::rename(&unpacked, &model_dir)?;
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::remove_file(&part)?;
+    debug_event!(path = %model_dir.display(), "model downloaded");
+
+    ModelDir::open(model_dir)
+}
+
+/// Download `url` into `part`, continuing from where an earlier attempt stopped.
+fn fetch_file(url: &str, part: &Path) -> Result<()> {
+    let offset = fs::metadata(part).map(|meta| meta.len()).unwrap_or(0);
+    let mut request = ureq::AgentBuilder::new()
+        .try_proxy_from_env(true)
+        .build()
+        .get(url)
+        .timeout(Duration::from_secs(1800));
+    if offset > 0 {
+        request = request.set("Range", &format!("bytes={}-", offset));
+    }
+    let response = match request.call() {
+        Ok(response) => response,
+        // Everything was already downloaded
+        Err(ureq::Error::Status(416, _)) if offset > 0 => return Ok(()),
+        Err(err) => {
+            return Err(Error::processing_failed(
+                "model download",
+                format!("GET {}: {}", url, err),
+            ))
+        }
+    };
+    debug_event!(url, offset, status = response.status(), "downloading");
+
+    // Servers that ignore the range send the whole file again
+    let resumed = response.status() == 206;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(part)?;
+    io::copy(&mut response.into_reader(), &mut file)?;
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// The directory an archive unpacked into, if it holds nothing else.
+fn single_subdir(dir: &Path) -> Result<Option<PathBuf>> {
+    let entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    Ok(match &entries[..] {
+        [entry] if entry.file_type()?.is_dir() => Some(entry.path()),
+        _ => None,
+    })
+}
+
+fn archive_stem(file_name: &str) -> &str {
+    [".tar.bz2", ".zip", ".onnx"]
+        .iter()
+        .find_map(|ext| file_name.strip_suffix(ext))
+        .unwrap_or(file_name)
+}
+
+fn checksum_check_disabled() -> bool {
+    std::env::var("UNSAFE_DISABLE_CHECKSUM_VALIDATION").unwrap_or_default() == "1"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, Write},
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    const BODY: &[u8] = b"not really an onnx model, but enough bytes to split in two";
+
+    /// Serve `BODY` as `name` on localhost for a single request, answering a
+    /// `Range: bytes=N-` header with 206 or 416 if `ranges` is set. The handle returns the
+    /// requested offset.
+    fn serve(name: &str, ranges: bool) -> (String, JoinHandle<Option<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/{}", listener.local_addr().unwrap(), name);
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+            let mut offset = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(range) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                    offset = range.trim().trim_end_matches('-').parse().ok();
+                }
+            }
+            let (status, body) = match offset.filter(|_| ranges) {
+                Some(offset) if offset >= BODY.len() => ("416 Range Not Satisfiable", &[][..]),
+                Some(offset) => ("206 Partial Content", &BODY[offset..]),
+                None => ("200 OK", BODY),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(body).unwrap();
+            offset
+        });
+        (url, handle)
+    }
+
+    fn sha256_hex(bytes: &[u8]) -> String {
+        Sha256::digest(bytes)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    #[test]
+    fn fetch_file_resumes_from_the_part_file() {
+        let dir = ScratchDir::new("download-resume");
+        let part = dir.join("model.onnx.part");
+        fs::write(&part, &BODY[..20]).unwrap();
+        let (url, server) = serve("model.onnx", true);
+
+        fetch_file(&url, Path::new(&part)).unwrap();
+        assert_eq!(server.join().unwrap(), Some(20));
+        assert_eq!(fs::read(&part).unwrap(), BODY);
+    }
+
+    #[test]
+    fn fetch_file_accepts_416_for_a_complete_part_file() {
+        let dir = ScratchDir::new("download-416");
+        let part = dir.join("model.onnx.part");
+        fs::write(&part, BODY).unwrap();
+        let (url, server) = serve("model.onnx", true);
+
+        fetch_file(&url, Path::new(&part)).unwrap();
+        assert_eq!(server.join().unwrap(), Some(BODY.len()));
+        assert_eq!(fs::read(&part).unwrap(), BODY);
+    }
+
+    #[test]
+    fn fetch_file_starts_over_if_the_server_ignores_the_range() {
+        let dir = ScratchDir::new("download-no-range");
+        let part = dir.join("model.onnx.part");
+        fs::write(&part, b"stale bytes from another file").unwrap();
+        let (url, server) = serve("model.onnx", false);
+
+        fetch_file(&url, Path::new(&part)).unwrap();
+        assert!(server.join().unwrap().is_some());
+        assert_eq!(fs::read(&part).unwrap(), BODY);
+    }
+
+    #[test]
+    fn checksum_mismatch_removes_the_part_file() {
+        let dir = ScratchDir::new("download-mismatch");
+        let (url, server) = serve("silero_vad.onnx", true);
+
+        let err = download_from(&url, &sha256_hex(b"something else"), dir.join("")).unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(err, Error::ProcessingFailed { .. }), "{:?}", err);
+        assert!(!Path::new(&dir.join("silero_vad.onnx.part")).exists());
+        assert!(!Path::new(&dir.join("silero_vad")).exists());
+    }
+
+    #[test]
+    fn verified_download_is_unpacked_and_reused() {
+        let dir = ScratchDir::new("download-ok");
+        let (url, server) = serve("silero_vad.onnx", true);
+
+        let model = download_from(&url, &sha256_hex(BODY), dir.join("")).unwrap();
+        server.join().unwrap();
+        assert_eq!(model.kind(), super::super::ModelKind::SileroVad);
+        assert_eq!(
+            fs::read(dir.join("silero_vad/silero_vad.onnx")).unwrap(),
+            BODY
+        );
+        assert!(!Path::new(&dir.join("silero_vad.onnx.part")).exists());
+
+        // Nothing is listening any more, so this must not download again
+        download_from(&url, &sha256_hex(BODY), dir.join("")).unwrap();
+    }
+
+    #[test]
+    fn single_subdir_only_for_a_lone_directory() {
+        let dir = ScratchDir::new("download-subdir");
+        let root = Path::new(&dir.join("")).to_path_buf();
+        assert_eq!(single_subdir(&root).unwrap(), None);
+
+        dir.mkdir("model");
+        assert_eq!(single_subdir(&root).unwrap(), Some(root.join("model")));
+
+        dir.touch("README.md");
+        assert_eq!(single_subdir(&root).unwrap(), None);
+
+        let lone_file = ScratchDir::new("download-subdir-file");
+        lone_file.touch("model.onnx");
+        assert_eq!(single_subdir(Path::new(&lone_file.join(""))).unwrap(), None);
+    }
+
+    #[test]
+    fn archive_stem_strips_known_extensions() {
+        assert_eq!(
+            archive_stem("sherpa-onnx-whisper-tiny.en.tar.bz2"),
+            "sherpa-onnx-whisper-tiny.en"
+        );
+        assert_eq!(archive_stem("model.zip"), "model");
+        assert_eq!(archive_stem("silero_vad.onnx"), "silero_vad");
+        assert_eq!(archive_stem("model.tar.gz"), "model.tar.gz");
+        assert_eq!(
+            ModelId::WhisperTinyEn.to_string(),
+            "sherpa-onnx-whisper-tiny.en"
+        );
+    }
+
+    #[test]
+    #[ignore = "model-checksums.txt has no sums yet, they need the release assets to compute"]
+    fn every_model_id_has_a_checksum() {
+        let ids = [
+            ModelId::WhisperTiny,
+            ModelId::WhisperTinyEn,
+            ModelId::WhisperBase,
+            ModelId::MoonshineTinyEn,
+            ModelId::SenseVoice,
+            ModelId::ParaformerZh,
+            ModelId::StreamingZipformerEn,
+            ModelId::SileroVad,
+            ModelId::VitsPiperEnAmy,
+            ModelId::Kokoro,
+            ModelId::KokoroEn,
+            ModelId::KittenNano,
+            ModelId::Spleeter,
+        ];
+        let missing: Vec<_> = ids.iter().filter(|id| id.sha256().is_none()).collect();
+        assert!(missing.is_empty(), "no checksum for {:?}", missing);
+    }
+}
@@ -0,0 +1,994 @@
+//! Configs from the model directories sherpa-onnx distributes.
+//!
+//! ```ignore
+//! let dir = ModelDir::open("sherpa-onnx-whisper-tiny.en")?;
+//! let recognizer = VadAsr::new(VadAsrConfig::new(vad, dir.into_offline_recognizer_config()?))?;
+//! ```
+//!
+//! With the `download-models` feature, [`download`] fetches and unpacks them first.
+
+#[cfg(feature = "download-models")]
+mod download;
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    dolphin::DolphinConfig,
+    fire_red_asr::FireRedAsrConfig,
+    get_default_provider,
+    moonshine::MoonshineConfig,
+    online::{OnlineModel, OnlineRecognizerConfig},
+    paraformer::ParaformerConfig,
+    pipeline::AsrConfig,
+    sense_voice::SenseVoiceConfig,
+    silero_vad::SileroVadConfig,
+    source_separation::SourceSeparationConfig,
+    transducer::TransducerConfig,
+    whisper::WhisperConfig,
+    DecodingMethod, Error, Provider, Result,
+};
+
+#[cfg(feature = "tts")]
+use crate::tts::{
+    KittenTtsConfig, KokoroTtsConfig, MatchaTtsConfig, TtsEngineConfig, VitsTtsConfig,
+    ZipVoiceTtsConfig,
+};
+
+#[cfg(feature = "download-models")]
+pub use download::{download, download_from, ModelId};
+
+/// Which model a directory holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModelKind {
+    Whisper,
+    FireRedAsr,
+    Moonshine,
+    /// Zipformer and other transducers, offline or streaming.
+    Transducer,
+    Paraformer,
+    SenseVoice,
+    Dolphin,
+    Vits,
+    /// Only the acoustic model, sherpa-onnx releases the vocoders separately.
+    Matcha,
+    Kokoro,
+    Kitten,
+    ZipVoice,
+    Spleeter,
+    /// UVR / MDX-Net source separation.
+    Uvr,
+    SileroVad,
+}
+
+/// Which ONNX files to load from a directory that has both full precision and int8
+/// versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ModelPrecision {
+    /// Int8 on CPU, where it's usually faster, and full precision on GPU providers.
+    /// Falls back to whichever the directory has.
+    #[default]
+    Auto,
+    /// `model.onnx` over `model.int8.onnx`. Releases that only ship fp16 count as full
+    /// precision.
+    Fp32,
+    /// `model.int8.onnx`, smaller and usually faster on CPU.
+    Int8,
+}
+
+impl ModelPrecision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModelPrecision::Auto => "auto",
+            ModelPrecision::Fp32 => "fp32",
+            ModelPrecision::Int8 => "int8",
+        }
+    }
+
+    /// What [`ModelPrecision::Auto`] means on `provider`, `self` for the others.
+    pub fn resolve(self, provider: &Provider) -> ModelPrecision {
+        match (self, provider) {
+            (ModelPrecision::Auto, Provider::Cpu | Provider::Xnnpack) => ModelPrecision::Int8,
+            (ModelPrecision::Auto, _) => ModelPrecision::Fp32,
+            (precision, _) => precision,
+        }
+    }
+
+    /// Precision of a model file, from its name as sherpa-onnx releases it.
+    pub fn of_file<P: AsRef<Path>>(path: P) -> ModelPrecision {
+        let name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if name.contains(".int8.") || name.ends_with("int8.onnx") {
+            ModelPrecision::Int8
+        } else {
+            ModelPrecision::Fp32
+        }
+    }
+}
+
+impl fmt::Display for ModelPrecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ModelKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModelKind::Whisper => "whisper",
+            ModelKind::FireRedAsr => "fire_red_asr",
+            ModelKind::Moonshine => "moonshine",
+            ModelKind::Transducer => "transducer",
+            ModelKind::Paraformer => "paraformer",
+            ModelKind::SenseVoice => "sense_voice",
+            ModelKind::Dolphin => "dolphin",
+            ModelKind::Vits => "vits",
+            ModelKind::Matcha => "matcha",
+            ModelKind::Kokoro => "kokoro",
+            ModelKind::Kitten => "kitten",
+            ModelKind::ZipVoice => "zipvoice",
+            ModelKind::Spleeter => "spleeter",
+            ModelKind::Uvr => "uvr",
+            ModelKind::SileroVad => "silero_vad",
+        }
+    }
+
+    /// Words in the names of the directories sherpa-onnx releases this model in.
+    fn dir_names(&self) -> &'static [&'static str] {
+        match self {
+            ModelKind::Whisper => &["whisper"],
+            ModelKind::FireRedAsr => &["fire-red", "fire_red", "firered"],
+            ModelKind::Moonshine => &["moonshine"],
+            ModelKind::Transducer => &["zipformer", "transducer", "conformer", "lstm"],
+            ModelKind::Paraformer => &["paraformer"],
+            ModelKind::SenseVoice => &["sense-voice", "sense_voice", "sensevoice"],
+            ModelKind::Dolphin => &["dolphin"],
+            ModelKind::Vits => &["vits", "piper"],
+            ModelKind::Matcha => &["matcha"],
+            ModelKind::Kokoro => &["kokoro"],
+            ModelKind::Kitten => &["kitten"],
+            ModelKind::ZipVoice => &["zipvoice"],
+            ModelKind::Spleeter => &["spleeter"],
+            ModelKind::Uvr => &["uvr", "mdx"],
+            ModelKind::SileroVad => &["silero"],
+        }
+    }
+
+    /// Files the model can't be loaded without.
+    fn required(&self) -> &'static [Role] {
+        use Role::*;
+        match self {
+            ModelKind::Whisper | ModelKind::FireRedAsr => &[Encoder, Decoder, Tokens],
+            ModelKind::Moonshine => &[
+                Preprocessor,
+                MoonshineEncoder,
+                UncachedDecoder,
+                CachedDecoder,
+                Tokens,
+            ],
+            ModelKind::Transducer => &[Encoder, Decoder, Joiner, Tokens],
+            ModelKind::Paraformer | ModelKind::SenseVoice | ModelKind::Dolphin => &[Model, Tokens],
+            ModelKind::Vits | ModelKind::Matcha => &[Model, Tokens],
+            ModelKind::Kokoro | ModelKind::Kitten => &[Model, Voices, Tokens, DataDir],
+            ModelKind::ZipVoice => &[TextEncoder, FmDecoder, Vocoder, Tokens],
+            ModelKind::Spleeter => &[Vocals, Accompaniment],
+            ModelKind::Uvr => &[Model],
+            ModelKind::SileroVad => &[SileroVad],
+        }
+    }
+
+    /// Files used if they are there.
+    fn optional(&self) -> &'static [Role] {
+        match self {
+            ModelKind::Vits => &[Role::DataDir, Role::DictDir, Role::Lexicon],
+            ModelKind::Matcha => &[Role::Vocoder, Role::DataDir, Role::DictDir, Role::Lexicon],
+            ModelKind::Kokoro => &[Role::DictDir, Role::Lexicon],
+            ModelKind::ZipVoice => &[Role::DataDir, Role::Lexicon],
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for ModelKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A file or directory a model is made of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Role {
+    Model,
+    Encoder,
+    Decoder,
+    Joiner,
+    Preprocessor,
+    MoonshineEncoder,
+    UncachedDecoder,
+    CachedDecoder,
+    TextEncoder,
+    FmDecoder,
+    Vocoder,
+    Tokens,
+    Voices,
+    DataDir,
+    DictDir,
+    Lexicon,
+    Vocals,
+    Accompaniment,
+    SileroVad,
+}
+
+impl Role {
+    /// How the file is named in sherpa-onnx releases, for errors.
+    fn pattern(&self) -> &'static str {
+        match self {
+            Role::Model => "*.onnx",
+            Role::Encoder => "*encoder*.onnx",
+            Role::Decoder => "*decoder*.onnx",
+            Role::Joiner => "*joiner*.onnx",
+            Role::Preprocessor => "preprocess*.onnx",
+            Role::MoonshineEncoder => "encode*.onnx",
+            Role::UncachedDecoder => "uncached_decode*.onnx",
+            Role::CachedDecoder => "cached_decode*.onnx",
+            Role::TextEncoder => "text_encoder*.onnx",
+            Role::FmDecoder => "fm_decoder*.onnx",
+            Role::Vocoder => "vocos*.onnx",
+            Role::Tokens => "tokens.txt",
+            Role::Voices => "voices.bin",
+            Role::DataDir => "espeak-ng-data/",
+            Role::DictDir => "dict/",
+            Role::Lexicon => "lexicon*.txt",
+            Role::Vocals => "vocals*.onnx",
+            Role::Accompaniment => "accompaniment*.onnx",
+            Role::SileroVad => "silero_vad*.onnx",
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        let onnx = name.ends_with(".onnx");
+        match self {
+            Role::Model => onnx,
+            Role::Encoder => onnx && (name.starts_with("encoder") || name.contains("-encoder")),
+            Role::Decoder => onnx && (name.starts_with("decoder") || name.contains("-decoder")),
+            Role::Joiner => onnx && (name.starts_with("joiner") || name.contains("-joiner")),
+            Role::Preprocessor => onnx && name.starts_with("preprocess"),
+            Role::MoonshineEncoder => onnx && name.starts_with("encode."),
+            Role::UncachedDecoder => onnx && name.starts_with("uncached_decode"),
+            Role::CachedDecoder => onnx && name.starts_with("cached_decode"),
+            Role::TextEncoder => onnx && name.starts_with("text_encoder"),
+            Role::FmDecoder => onnx && name.starts_with("fm_decoder"),
+            Role::Vocoder => onnx && (name.starts_with("vocos") || name.starts_with("hifigan")),
+            Role::Tokens => name == "tokens.txt" || name.ends_with("-tokens.txt"),
+            Role::Voices => name == "voices.bin",
+            Role::DataDir => name == "espeak-ng-data",
+            Role::DictDir => name == "dict",
+            Role::Lexicon => name.starts_with("lexicon") && name.ends_with(".txt"),
+            Role::Vocals => onnx && name.starts_with("vocals"),
+            Role::Accompaniment => onnx && name.starts_with("accompaniment"),
+            Role::SileroVad => onnx && name.starts_with("silero_vad"),
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self, Role::DataDir | Role::DictDir)
+    }
+
+    /// Whether releases may ship the role in several precisions. Only ONNX models are,
+    /// and not vocoders, which come as a single full precision file.
+    fn has_precisions(&self) -> bool {
+        !matches!(
+            self,
+            Role::Tokens
+                | Role::Voices
+                | Role::DataDir
+                | Role::DictDir
+                | Role::Lexicon
+                | Role::Vocoder
+        )
+    }
+}
+
+/// A model directory as released by sherpa-onnx, with the paths its config needs.
+#[derive(Debug, Clone)]
+pub struct ModelDir {
+    path: PathBuf,
+    kind: ModelKind,
+    files: BTreeMap<Role, String>,
+    precision: ModelPrecision,
+}
+
+impl ModelDir {
+    /// Inspect `path` and work out which model it holds.
+    ///
+    /// Fails naming the candidates if the layout fits several models and the directory
+    /// name doesn't tell them apart, and naming the missing and the present files if the
+    /// model is incomplete. Int8 or full precision models are picked as
+    /// [`ModelPrecision::Auto`] would for the default provider.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_precision(path, ModelPrecision::default())
+    }
+
+    /// Like [`Self::open`], loading `precision` models. Fails naming the ones that are
+    /// there if the directory doesn't have that precision.
+    pub fn open_with_precision<P: AsRef<Path>>(path: P, precision: ModelPrecision) -> Result<Self> {
+        let path = path.as_ref();
+        let (files, dirs) = list_dir(path)?;
+
+        let has = |role: Role| {
+            let names = if role.is_dir() { &dirs } else { &files };
+            names.iter().any(|name| role.matches(name))
+        };
+        let candidates = if has(Role::SileroVad) {
+            vec![ModelKind::SileroVad]
+        } else if has(Role::Preprocessor) || has(Role::CachedDecoder) {
+            vec![ModelKind::Moonshine]
+        } else if has(Role::Joiner) {
+            vec![ModelKind::Transducer]
+        } else if has(Role::Vocals) || has(Role::Accompaniment) {
+            vec![ModelKind::Spleeter]
+        } else if has(Role::TextEncoder) || has(Role::FmDecoder) {
+            vec![ModelKind::ZipVoice]
+        } else if has(Role::Voices) {
+            vec![ModelKind::Kokoro, ModelKind::Kitten]
+        } else if has(Role::Encoder) || has(Role::Decoder) {
+            vec![ModelKind::Whisper, ModelKind::FireRedAsr]
+        } else if has(Role::DataDir) || has(Role::Lexicon) {
+            vec![ModelKind::Vits]
+        } else if has(Role::Model) {
+            vec![
+                ModelKind::Paraformer,
+                ModelKind::SenseVoice,
+                ModelKind::Dolphin,
+                ModelKind::Vits,
+                ModelKind::Uvr,
+            ]
+        } else {
+            return Err(Error::invalid_input(format!(
+                "no known model layout in {}, it has {}",
+                path.display(),
+                listing(&files, &dirs)
+            )));
+        };
+
+        let dir_name = path
+            .canonicalize()
+            .unwrap_or_else(|_| path.to_path_buf())
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if dir_name.contains("matcha") {
+            return Err(Error::unsupported(
+                "detecting matcha model directories, whose vocoder is released separately, \
+                 open them with ModelDir::open_as",
+            ));
+        }
+        let kind = Self::pick_kind(path, &dir_name, candidates)?;
+        Self::resolve(
+            path,
+            kind,
+            &files,
+            &dirs,
+            precision,
+            &get_default_provider(),
+        )
+    }
+
+    /// Open `path` as a `kind` model without working out the kind from the layout, for
+    /// callers that already know it. [`ModelPrecision::Auto`] picks for `provider`.
+    pub fn open_as<P: AsRef<Path>>(
+        path: P,
+        kind: ModelKind,
+        precision: ModelPrecision,
+        provider: &Provider,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let (files, dirs) = list_dir(path)?;
+        Self::resolve(path, kind, &files, &dirs, precision, provider)
+    }
+
+    /// Find the files of `kind` in the listing of `path`.
+    fn resolve(
+        path: &Path,
+        kind: ModelKind,
+        files: &[String],
+        dirs: &[String],
+        precision: ModelPrecision,
+        provider: &Provider,
+    ) -> Result<Self> {
+        let wanted = precision.resolve(provider);
+        let mut found = BTreeMap::new();
+        let mut missing = Vec::new();
+        for role in kind.required() {
+            match Self::find(*role, files, dirs, wanted) {
+                Some(name) => {
+                    found.insert(*role, name);
+                }
+                None => missing.push(role.pattern()),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(Error::model_not_found(
+                format!(
+                    "{} model files {} among {}",
+                    kind,
+                    missing.join(", "),
+                    listing(files, dirs)
+                ),
+                path,
+            ));
+        }
+        for role in kind.optional() {
+            if let Some(name) = Self::find(*role, files, dirs, wanted) {
+                found.insert(*role, name);
+            }
+        }
+
+        let models: Vec<(&Role, &String)> = found
+            .iter()
+            .filter(|(role, _)| role.has_precisions())
+            .collect();
+        if precision != ModelPrecision::Auto {
+            for (role, name) in &models {
+                if ModelPrecision::of_file(name) != wanted {
+                    let available: Vec<&str> = files
+                        .iter()
+                        .filter(|name| role.matches(name))
+                        .map(String::as_str)
+                        .collect();
+                    return Err(Error::model_not_found(
+                        format!(
+                            "{} {} (available: {})",
+                            wanted,
+                            role.pattern(),
+                            available.join(", ")
+                        ),
+                        path,
+                    ));
+                }
+            }
+        }
+        let precision = if models
+            .iter()
+            .any(|(_, name)| ModelPrecision::of_file(name) == ModelPrecision::Int8)
+        {
+            ModelPrecision::Int8
+        } else {
+            ModelPrecision::Fp32
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            kind,
+            files: found,
+            precision,
+        })
+    }
+
+    /// Narrow `candidates` down with the directory name.
+    fn pick_kind(path: &Path, dir_name: &str, candidates: Vec<ModelKind>) -> Result<ModelKind> {
+        if let [kind] = candidates[..] {
+            return Ok(kind);
+        }
+        let named: Vec<ModelKind> = candidates
+            .iter()
+            .copied()
+            .filter(|kind| kind.dir_names().iter().any(|word| dir_name.contains(word)))
+            .collect();
+        match named[..] {
+            [kind] => Ok(kind),
+            _ => Err(Error::invalid_input(format!(
+                "can't tell which model {} holds, it could be {}",
+                path.display(),
+                candidates
+                    .iter()
+                    .map(ModelKind::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+
+    /// Name of the file or directory for `role`, preferring `precision`. Lexicons are all
+    /// returned, comma separated.
+    fn find(
+        role: Role,
+        files: &[String],
+        dirs: &[String],
+        precision: ModelPrecision,
+    ) -> Option<String> {
+        let names = if role.is_dir() { dirs } else { files };
+        let mut matching: Vec<&String> = names.iter().filter(|name| role.matches(name)).collect();
+        if role == Role::Lexicon {
+            return (!matching.is_empty()).then(|| {
+                matching
+                    .iter()
+                    .map(|name| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            });
+        }
+        // Prefer model.onnx over other names, then the requested precision
+        matching.sort_by_key(|name| {
+            (
+                !name.to_lowercase().starts_with("model"),
+                ModelPrecision::of_file(name) != precision,
+            )
+        });
+        matching.first().map(|name| name.to_string())
+    }
+
+    pub fn kind(&self) -> ModelKind {
+        self.kind
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Precision of the model files that were picked, never `Auto`.
+    pub fn precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Path for `role`, empty if the model doesn't have it.
+    pub(crate) fn file(&self, role: Role) -> String {
+        match self.files.get(&role) {
+            Some(names) => names
+                .split(',')
+                .map(|name| self.path.join(name).to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join(","),
+            None => String::new(),
+        }
+    }
+
+    /// Path for `role`, `fallback` if the model doesn't have it.
+    pub(crate) fn file_or(&self, role: Role, fallback: String) -> String {
+        if self.files.contains_key(&role) {
+            self.file(role)
+        } else {
+            fallback
+        }
+    }
+
+    fn wrong_kind(&self, expected: &str) -> Error {
+        Error::invalid_input(format!(
+            "{} holds a {} model, not {}",
+            self.path.display(),
+            self.kind,
+            expected
+        ))
+    }
+
+    /// Config for [`VadAsr`](crate::pipeline::VadAsr) and the other offline pipelines.
+    pub fn into_offline_recognizer_config(self) -> Result<AsrConfig> {
+        Ok(match self.kind {
+            ModelKind::Whisper => AsrConfig::Whisper(WhisperConfig {
+                encoder: self.file(Role::Encoder),
+                decoder: self.file(Role::Decoder),
+                tokens: self.file(Role::Tokens),
+                ..Default::default()
+            }),
+            ModelKind::FireRedAsr => AsrConfig::FireRedAsr(FireRedAsrConfig {
+                encoder: self.file(Role::Encoder),
+                decoder: self.file(Role::Decoder),
+                tokens: self.file(Role::Tokens),
+                ..Default::default()
+            }),
+            ModelKind::Moonshine => AsrConfig::Moonshine(MoonshineConfig {
+                preprocessor: self.file(Role::Preprocessor),
+                encoder: self.file(Role::MoonshineEncoder),
+                uncached_decoder: self.file(Role::UncachedDecoder),
+                cached_decoder: self.file(Role::CachedDecoder),
+                tokens: self.file(Role::Tokens),
+                ..Default::default()
+            }),
+            ModelKind::Paraformer => AsrConfig::Paraformer(ParaformerConfig {
+                model: self.file(Role::Model),
+                tokens: self.file(Role::Tokens),
+                ..Default::default()
+            }),
+            ModelKind::SenseVoice => AsrConfig::SenseVoice(SenseVoiceConfig {
+                model: self.file(Role::Model),
+                tokens: self.file(Role::Tokens),
+                ..Default::default()
+            }),
+            ModelKind::Dolphin => AsrConfig::Dolphin(DolphinConfig {
+                model: self.file(Role::Model),
+                tokens: self.file(Role::Tokens),
+                ..Default::default()
+            }),
+            _ => return Err(self.wrong_kind("an offline recognizer")),
+        })
+    }
+
+    /// Config for [`TransducerRecognizer`](crate::transducer::TransducerRecognizer).
+    pub fn into_transducer_config(self) -> Result<TransducerConfig> {
+        if self.kind != ModelKind::Transducer {
+            return Err(self.wrong_kind("a transducer"));
+        }
+        Ok(TransducerConfig {
+            encoder: self.file(Role::Encoder),
+            decoder: self.file(Role::Decoder),
+            joiner: self.file(Role::Joiner),
+            tokens: self.file(Role::Tokens),
+            sample_rate: 16000,
+            feature_dim: 80,
+            decoding_method: DecodingMethod::GreedySearch,
+            ..Default::default()
+        })
+    }
+
+    /// Config for [`OnlineRecognizer`](crate::online::OnlineRecognizer). Only streaming
+    /// transducers, usually with `streaming` in their directory name, can be loaded
+    /// with it.
+    pub fn into_online_recognizer_config(self) -> Result<OnlineRecognizerConfig> {
+        if self.kind != ModelKind::Transducer {
+            return Err(self.wrong_kind("a streaming transducer"));
+        }
+        Ok(OnlineRecognizerConfig {
+            model: OnlineModel::Transducer {
+                encoder: self.file(Role::Encoder),
+                decoder: self.file(Role::Decoder),
+                joiner: self.file(Role::Joiner),
+            },
+            tokens: self.file(Role::Tokens),
+            ..Default::default()
+        })
+    }
+
+    /// Config for [`tts::from_config`](crate::tts::from_config). Matcha's vocoder is
+    /// only filled in if it was put in the directory.
+    #[cfg(feature = "tts")]
+    pub fn into_tts_config(self) -> Result<TtsEngineConfig> {
+        Ok(match self.kind {
+            ModelKind::Vits => TtsEngineConfig::Vits(VitsTtsConfig {
+                model: self.file(Role::Model),
+                tokens: self.file(Role::Tokens),
+                data_dir: self.file(Role::DataDir),
+                dict_dir: self.file(Role::DictDir),
+                lexicon: self.file(Role::Lexicon),
+                length_scale: 1.0,
+                noise_scale: 0.667,
+                noise_scale_w: 0.8,
+                ..Default::default()
+            }),
+            ModelKind::Matcha => TtsEngineConfig::Matcha(MatchaTtsConfig {
+                acoustic_model: self.file(Role::Model),
+                vocoder: self.file(Role::Vocoder),
+                tokens: self.file(Role::Tokens),
+                data_dir: self.file(Role::DataDir),
+                dict_dir: self.file(Role::DictDir),
+                lexicon: self.file(Role::Lexicon),
+                ..Default::default()
+            }),
+            ModelKind::Kokoro => TtsEngineConfig::Kokoro(KokoroTtsConfig {
+                model: self.file(Role::Model),
+                voices: self.file(Role::Voices),
+                tokens: self.file(Role::Tokens),
+                data_dir: self.file(Role::DataDir),
+                dict_dir: self.file(Role::DictDir),
+                lexicon: self.file(Role::Lexicon),
+                length_scale: 1.0,
+                ..Default::default()
+            }),
+            ModelKind::Kitten => TtsEngineConfig::Kitten(KittenTtsConfig {
+                model: self.file(Role::Model),
+                voices: self.file(Role::Voices),
+                tokens: self.file(Role::Tokens),
+                data_dir: self.file(Role::DataDir),
+                length_scale: 1.0,
+                ..Default::default()
+            }),
+            ModelKind::ZipVoice => TtsEngineConfig::ZipVoice(ZipVoiceTtsConfig {
+                encoder: self.file(Role::TextEncoder),
+                decoder: self.file(Role::FmDecoder),
+                vocoder: self.file(Role::Vocoder),
+                tokens: self.file(Role::Tokens),
+                data_dir: self.file(Role::DataDir),
+                lexicon: self.file(Role::Lexicon),
+                feat_scale: 0.1,
+                t_shift: 0.5,
+                target_rms: 0.1,
+                guidance_scale: 1.0,
+                ..Default::default()
+            }),
+            _ => return Err(self.wrong_kind("a tts model")),
+        })
+    }
+
+    /// Config for [`SourceSeparation`](crate::source_separation::SourceSeparation).
+    pub fn into_source_separation_config(self) -> Result<SourceSeparationConfig> {
+        Ok(match self.kind {
+            ModelKind::Spleeter => SourceSeparationConfig::spleeter(
+                self.file(Role::Vocals),
+                self.file(Role::Accompaniment),
+            )
+            .build(),
+            ModelKind::Uvr => SourceSeparationConfig::uvr(self.file(Role::Model)).build(),
+            _ => return Err(self.wrong_kind("a source separation model")),
+        })
+    }
+
+    /// Config for [`SileroVad`](crate::silero_vad::SileroVad).
+    pub fn into_silero_vad_config(self) -> Result<SileroVadConfig> {
+        if self.kind != ModelKind::SileroVad {
+            return Err(self.wrong_kind("a silero vad model"));
+        }
+        Ok(SileroVadConfig {
+            model: self.file(Role::SileroVad),
+            ..Default::default()
+        })
+    }
+}
+
+/// Names of the files and directories in `path`, sorted.
+fn list_dir(path: &Path) -> Result<(Vec<String>, Vec<String>)> {
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            dirs.push(name);
+        } else {
+            files.push(name);
+        }
+    }
+    files.sort();
+    dirs.sort();
+    Ok((files, dirs))
+}
+
+/// What a directory holds, for errors.
+fn listing(files: &[String], dirs: &[String]) -> String {
+    if files.is_empty() && dirs.is_empty() {
+        return "nothing".into();
+    }
+    dirs.iter()
+        .map(|dir| format!("{}/", dir))
+        .chain(files.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    /// Lay out a model directory `name` in `scratch`, entries ending in `/` are
+    /// directories.
+    fn layout(scratch: &ScratchDir, name: &str, entries: &[&str]) -> String {
+        let dir = scratch.mkdir(name);
+        for entry in entries {
+            match entry.strip_suffix('/') {
+                Some(sub) => scratch.mkdir(&format!("{}/{}", name, sub)),
+                None => scratch.touch(&format!("{}/{}", name, entry)),
+            };
+        }
+        dir
+    }
+
+    fn file_name(dir: &ModelDir, role: Role) -> String {
+        Path::new(&dir.file(role))
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    const WHISPER: &[&str] = &[
+        "tiny.en-decoder.int8.onnx",
+        "tiny.en-decoder.onnx",
+        "tiny.en-encoder.int8.onnx",
+        "tiny.en-encoder.onnx",
+        "tiny.en-tokens.txt",
+    ];
+
+    const TRANSDUCER: &[&str] = &[
+        "decoder-epoch-99-avg-1.int8.onnx",
+        "decoder-epoch-99-avg-1.onnx",
+        "encoder-epoch-99-avg-1.int8.onnx",
+        "encoder-epoch-99-avg-1.onnx",
+        "joiner-epoch-99-avg-1.int8.onnx",
+        "joiner-epoch-99-avg-1.onnx",
+        "tokens.txt",
+    ];
+
+    const KOKORO: &[&str] = &["model.onnx", "voices.bin", "tokens.txt", "espeak-ng-data/"];
+
+    #[test]
+    fn opens_whisper() {
+        let scratch = ScratchDir::new("model-dir-whisper");
+        let path = layout(&scratch, "sherpa-onnx-whisper-tiny.en", WHISPER);
+        let dir = ModelDir::open_with_precision(&path, ModelPrecision::Fp32).unwrap();
+        assert_eq!(dir.kind(), ModelKind::Whisper);
+        assert_eq!(file_name(&dir, Role::Encoder), "tiny.en-encoder.onnx");
+        assert_eq!(file_name(&dir, Role::Decoder), "tiny.en-decoder.onnx");
+        assert_eq!(file_name(&dir, Role::Tokens), "tiny.en-tokens.txt");
+        assert!(matches!(
+            dir.into_offline_recognizer_config().unwrap(),
+            AsrConfig::Whisper(_)
+        ));
+    }
+
+    #[test]
+    fn opens_transducer() {
+        let scratch = ScratchDir::new("model-dir-transducer");
+        let path = layout(
+            &scratch,
+            "sherpa-onnx-streaming-zipformer-en-2023-06-26",
+            TRANSDUCER,
+        );
+        let dir = ModelDir::open_with_precision(&path, ModelPrecision::Int8).unwrap();
+        assert_eq!(dir.kind(), ModelKind::Transducer);
+        assert_eq!(
+            file_name(&dir, Role::Joiner),
+            "joiner-epoch-99-avg-1.int8.onnx"
+        );
+        assert_eq!(file_name(&dir, Role::Tokens), "tokens.txt");
+    }
+
+    #[test]
+    fn tells_kokoro_and_kitten_apart_by_name() {
+        let scratch = ScratchDir::new("model-dir-kokoro-kitten");
+        let kokoro = layout(&scratch, "kokoro-en-v0_19", KOKORO);
+        let kitten = layout(&scratch, "kitten-nano-en-v0_1-fp16", KOKORO);
+        assert_eq!(ModelDir::open(kokoro).unwrap().kind(), ModelKind::Kokoro);
+        assert_eq!(ModelDir::open(kitten).unwrap().kind(), ModelKind::Kitten);
+
+        let unnamed = layout(&scratch, "my-tts", KOKORO);
+        let err = ModelDir::open(unnamed).unwrap_err();
+        match err {
+            Error::InvalidInput { reason } => {
+                assert!(reason.contains("kokoro, kitten"), "{}", reason)
+            }
+            err => panic!("expected InvalidInput, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn opens_zipvoice() {
+        let scratch = ScratchDir::new("model-dir-zipvoice");
+        let path = layout(
+            &scratch,
+            "sherpa-onnx-zipvoice-distill-zh-en-emilia",
+            &[
+                "fm_decoder.onnx",
+                "fm_decoder_int8.onnx",
+                "text_encoder.onnx",
+                "text_encoder_int8.onnx",
+                "vocos_24khz.onnx",
+                "tokens.txt",
+                "lexicon.txt",
+                "prompt.wav",
+                "espeak-ng-data/",
+            ],
+        );
+        let dir = ModelDir::open_with_precision(&path, ModelPrecision::Int8).unwrap();
+        assert_eq!(dir.kind(), ModelKind::ZipVoice);
+        assert_eq!(file_name(&dir, Role::TextEncoder), "text_encoder_int8.onnx");
+        assert_eq!(file_name(&dir, Role::FmDecoder), "fm_decoder_int8.onnx");
+        assert_eq!(file_name(&dir, Role::Vocoder), "vocos_24khz.onnx");
+        assert_eq!(file_name(&dir, Role::Lexicon), "lexicon.txt");
+    }
+
+    #[test]
+    fn matcha_is_opened_as_matcha_only() {
+        let scratch = ScratchDir::new("model-dir-matcha");
+        let path = layout(
+            &scratch,
+            "matcha-icefall-en_US-ljspeech",
+            &["model-steps-3.onnx", "tokens.txt", "espeak-ng-data/"],
+        );
+        let err = ModelDir::open(&path).unwrap_err();
+        assert!(matches!(err, Error::Unsupported { .. }), "{:?}", err);
+
+        let open = || {
+            ModelDir::open_as(
+                &path,
+                ModelKind::Matcha,
+                ModelPrecision::Auto,
+                &Provider::Cpu,
+            )
+            .unwrap()
+        };
+        let dir = open();
+        assert_eq!(file_name(&dir, Role::Model), "model-steps-3.onnx");
+        assert_eq!(dir.file(Role::Vocoder), "");
+
+        // A vocoder put next to it is picked up, and not taken for the acoustic model
+        scratch.touch("matcha-icefall-en_US-ljspeech/hifigan_v2.onnx");
+        let dir = open();
+        assert_eq!(file_name(&dir, Role::Model), "model-steps-3.onnx");
+        assert_eq!(file_name(&dir, Role::Vocoder), "hifigan_v2.onnx");
+    }
+
+    #[test]
+    fn missing_files_are_named() {
+        let scratch = ScratchDir::new("model-dir-missing");
+        let path = layout(
+            &scratch,
+            "sherpa-onnx-whisper-tiny.en",
+            &["tiny.en-encoder.onnx"],
+        );
+        match ModelDir::open(&path).unwrap_err() {
+            Error::ModelNotFound { component, path } => {
+                assert!(component.starts_with("whisper"), "{}", component);
+                assert!(component.contains("*decoder*.onnx"), "{}", component);
+                assert!(component.contains("tokens.txt"), "{}", component);
+                assert!(!component.contains("*encoder*.onnx"), "{}", component);
+                assert!(component.contains("tiny.en-encoder.onnx"), "{}", component);
+                assert!(path.ends_with("sherpa-onnx-whisper-tiny.en"));
+            }
+            err => panic!("expected ModelNotFound, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn prefers_the_requested_precision() {
+        let scratch = ScratchDir::new("model-dir-precision");
+        let path = layout(&scratch, "sherpa-onnx-zipformer-en", TRANSDUCER);
+
+        let int8 = ModelDir::open_with_precision(&path, ModelPrecision::Int8).unwrap();
+        assert_eq!(int8.precision(), ModelPrecision::Int8);
+        assert_eq!(
+            file_name(&int8, Role::Encoder),
+            "encoder-epoch-99-avg-1.int8.onnx"
+        );
+
+        let fp32 = ModelDir::open_with_precision(&path, ModelPrecision::Fp32).unwrap();
+        assert_eq!(fp32.precision(), ModelPrecision::Fp32);
+        assert_eq!(
+            file_name(&fp32, Role::Encoder),
+            "encoder-epoch-99-avg-1.onnx"
+        );
+
+        // Auto is int8 on CPU and full precision on GPUs
+        let auto = |provider| {
+            ModelDir::open_as(
+                &path,
+                ModelKind::Transducer,
+                ModelPrecision::Auto,
+                &provider,
+            )
+            .unwrap()
+            .precision()
+        };
+        assert_eq!(auto(Provider::Cpu), ModelPrecision::Int8);
+        assert_eq!(auto(Provider::Cuda), ModelPrecision::Fp32);
+    }
+
+    #[test]
+    fn missing_precision_lists_what_is_there() {
+        let scratch = ScratchDir::new("model-dir-no-int8");
+        let path = layout(&scratch, "kokoro-en-v0_19", KOKORO);
+
+        // Auto falls back to the full precision model
+        let dir = ModelDir::open_as(
+            &path,
+            ModelKind::Kokoro,
+            ModelPrecision::Auto,
+            &Provider::Cpu,
+        )
+        .unwrap();
+        assert_eq!(dir.precision(), ModelPrecision::Fp32);
+
+        match ModelDir::open_with_precision(&path, ModelPrecision::Int8).unwrap_err() {
+            Error::ModelNotFound { component, .. } => {
+                assert!(component.starts_with("int8 *.onnx"), "{}", component);
+                assert!(component.contains("available: model.onnx"), "{}", component);
+            }
+            err => panic!("expected ModelNotFound, got {:?}", err),
+        }
+    }
+}
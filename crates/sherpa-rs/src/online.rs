@@ -0,0 +1,988 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    ffi::CStr,
+    hash::Hash,
+    marker::PhantomData,
+    mem,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use crate::{
+    decoding::{warn_unused_beam_options, DEFAULT_MAX_ACTIVE_PATHS},
+    get_default_provider,
+    handle::NativeHandle,
+    hotwords::{self, HotwordsFile, HotwordsModel},
+    itn::ItnConfig,
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{
+        cstr_to_string, cstring_from_str, stereo_to_mono_into, time_warm_up, warm_up_silence,
+        ChannelMix, WARM_UP_SAMPLE_RATE,
+    },
+    DecodingMethod, Error, FallbackPolicy, Provider, Result, Threads,
+};
+
+/// Streaming model files, by family.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum OnlineModel {
+    Transducer {
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+        encoder: String,
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+        decoder: String,
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+        joiner: String,
+    },
+    Paraformer {
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+        encoder: String,
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+        decoder: String,
+    },
+    Zipformer2Ctc {
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+        model: String,
+    },
+    NemoCtc {
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+        model: String,
+    },
+}
+
+impl Default for OnlineModel {
+    fn default() -> Self {
+        Self::Transducer {
+            encoder: String::new(),
+            decoder: String::new(),
+            joiner: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct OnlineRecognizerConfig {
+    pub model: OnlineModel,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+    pub tokens: String,
+    pub model_type: String,
+    pub modeling_unit: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+    pub bpe_vocab: String,
+
+    pub decoding_method: DecodingMethod,
+    /// Hypotheses kept by modified beam search.
+    pub max_active_paths: i32,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::opt_path_buf")
+    )]
+    pub hotwords_file: Option<PathBuf>,
+    pub hotwords_score: f32,
+    /// Extra hotwords, see [`crate::transducer::TransducerConfig::hotwords`].
+    pub hotwords: Vec<String>,
+    /// Subtracted from the blank score before search, see
+    /// [`TransducerConfig::blank_penalty`](crate::transducer::TransducerConfig::blank_penalty).
+    pub blank_penalty: f32,
+
+    /// Detect the end of an utterance with the three rules below.
+    pub enable_endpoint: bool,
+    /// Trailing silence, in seconds, that ends an utterance with nothing decoded yet.
+    pub rule1_min_trailing_silence: f32,
+    /// Trailing silence, in seconds, that ends an utterance after something was decoded.
+    pub rule2_min_trailing_silence: f32,
+    /// Utterance length, in seconds, after which an endpoint is forced.
+    pub rule3_min_utterance_length: f32,
+
+    pub sample_rate: i32,
+    pub feature_dim: i32,
+
+    pub num_threads: Threads,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub debug: bool,
+    /// Inverse text normalization and homophone replacement applied to the text.
+    pub itn: ItnConfig,
+}
+
+impl Default for OnlineRecognizerConfig {
+    fn default() -> Self {
+        Self {
+            model: OnlineModel::default(),
+            tokens: String::new(),
+            model_type: String::new(),
+            modeling_unit: String::new(),
+            bpe_vocab: String::new(),
+
+            decoding_method: DecodingMethod::GreedySearch,
+            max_active_paths: DEFAULT_MAX_ACTIVE_PATHS,
+            hotwords_file: None,
+            hotwords_score: 1.5,
+            hotwords: Vec::new(),
+            blank_penalty: 0.0,
+
+            enable_endpoint: true,
+            rule1_min_trailing_silence: 2.4,
+            rule2_min_trailing_silence: 1.2,
+            rule3_min_utterance_length: 20.0,
+
+            sample_rate: 16000,
+            feature_dim: 80,
+
+            num_threads: Threads::ONE,
+            provider: None,
+            fallback: FallbackPolicy::default(),
+            debug: false,
+            itn: ItnConfig::default(),
+        }
+    }
+}
+
+/// Whether an [`OnlineRecognizerResult`] can still change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultState {
+    /// More audio may revise the text.
+    Partial,
+    /// An endpoint was detected or the input finished, the text won't change until a reset.
+    Final,
+}
+
+#[derive(Debug, Clone)]
+pub struct OnlineRecognizerResult {
+    pub text: String,
+    pub tokens: Vec<String>,
+    /// Token times in seconds, relative to the last [`OnlineRecognizer::reset`].
+    pub timestamps: Vec<f32>,
+    pub state: ResultState,
+    /// Seconds of stream audio before the last reset, see
+    /// [`OnlineStream::absolute_time_offset_secs`].
+    pub offset: f32,
+}
+
+impl OnlineRecognizerResult {
+    fn new(
+        result: &sherpa_rs_sys::SherpaOnnxOnlineRecognizerResult,
+        state: ResultState,
+        offset: f32,
+    ) -> Self {
+        let text = unsafe { cstr_to_string(result.text) };
+        let count = result.count.max(0) as usize;
+        let timestamps = if result.timestamps.is_null() || count == 0 {
+            Vec::new()
+        } else {
+            unsafe { std::slice::from_raw_parts(result.timestamps, count).to_vec() }
+        };
+        let tokens = if result.tokens_arr.is_null() || count == 0 {
+            Vec::new()
+        } else {
+            unsafe { std::slice::from_raw_parts(result.tokens_arr, count) }
+                .iter()
+                .map(|&t| unsafe { CStr::from_ptr(t) }.to_string_lossy().into_owned())
+                .collect()
+        };
+        Self {
+            text,
+            tokens,
+            timestamps,
+            state,
+            offset,
+        }
+    }
+
+    /// Token times in seconds since the stream started, across resets.
+    pub fn absolute_timestamps(&self) -> Vec<f32> {
+        self.timestamps.iter().map(|t| t + self.offset).collect()
+    }
+}
+
+pub struct OnlineRecognizer {
+    recognizer: NativeHandle<sherpa_rs_sys::SherpaOnnxOnlineRecognizer>,
+    tokens: String,
+    modeling_unit: String,
+    decoding_method: DecodingMethod,
+    /// Token table, loaded on first use to check per-stream hotwords.
+    symbols: OnceLock<HashSet<String>>,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
+}
+
+/// Stream time of an [`OnlineStream`] or a
+/// [`KeywordStream`](crate::keyword_spot::KeywordStream), in seconds of input audio.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct StreamClock {
+    /// Seconds of audio fed so far.
+    pub(crate) elapsed: f64,
+    /// `elapsed` at the last reset, where the decoder's timestamps start from.
+    pub(crate) segment_start: f64,
+}
+
+impl StreamClock {
+    /// Count `samples` at the input rate, sherpa-onnx resamples to the model rate
+    /// itself.
+    pub(crate) fn advance(&mut self, sample_rate: u32, samples: usize) {
+        if sample_rate > 0 {
+            self.elapsed += samples as f64 / sample_rate as f64;
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.segment_start = self.elapsed;
+    }
+}
+
+/// Audio fed to an [`OnlineRecognizer`]. Borrows the recognizer it was created from.
+pub struct OnlineStream<'a> {
+    stream: NativeHandle<sherpa_rs_sys::SherpaOnnxOnlineStream>,
+    finished: Cell<bool>,
+    clock: Cell<StreamClock>,
+    hotword_warnings: Vec<String>,
+    /// Reused for i16 and stereo conversion so feeding doesn't allocate per frame.
+    scratch: RefCell<Vec<f32>>,
+    _recognizer: PhantomData<&'a OnlineRecognizer>,
+}
+
+impl OnlineRecognizer {
+    /// Like [`Self::new`], with the streaming transducer of the sherpa-onnx release
+    /// unpacked in `dir`.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: OnlineRecognizerConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Transducer,
+            precision,
+            &config.provider.clone().unwrap_or_else(get_default_provider),
+        )?;
+        Self::new(OnlineRecognizerConfig {
+            model: OnlineModel::Transducer {
+                encoder: dir.file(Role::Encoder),
+                decoder: dir.file(Role::Decoder),
+                joiner: dir.file(Role::Joiner),
+            },
+            tokens: dir.file(Role::Tokens),
+            ..config
+        })
+    }
+
+    pub fn new(config: OnlineRecognizerConfig) -> Result<Self> {
+        let _timed = timed!("online.create");
+        debug_event!(?config, "creating online recognizer");
+        let (mut recognizer, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(OnlineRecognizerConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        recognizer.provider = provider;
+        Ok(recognizer)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model files.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Decode half a second of silence on a throwaway stream, so the first real stream
+    /// doesn't pay for ONNX Runtime's one-time setup. Returns how long it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("online.warm_up");
+        time_warm_up(|| {
+            let stream = self.create_stream()?;
+            stream.accept_waveform(WARM_UP_SAMPLE_RATE, &warm_up_silence(WARM_UP_SAMPLE_RATE));
+            stream.input_finished();
+            self.decode_ready(&stream);
+            Ok(())
+        })
+    }
+
+    fn create(config: OnlineRecognizerConfig) -> Result<Self> {
+        let precision = ModelPrecision::of_file(match &config.model {
+            OnlineModel::Transducer { encoder, .. } | OnlineModel::Paraformer { encoder, .. } => {
+                encoder
+            }
+            OnlineModel::Zipformer2Ctc { model } | OnlineModel::NemoCtc { model } => model,
+        });
+        let hotwords = HotwordsFile::new(
+            config.hotwords_file.as_deref(),
+            &config.hotwords,
+            &HotwordsModel {
+                tokens: &config.tokens,
+                modeling_unit: &config.modeling_unit,
+                bpe_vocab: &config.bpe_vocab,
+                decoding_method: config.decoding_method,
+            },
+        )?;
+        let itn = config.itn.to_raw()?;
+        warn_unused_beam_options(
+            "online recognizer",
+            config.decoding_method,
+            config.max_active_paths,
+        );
+        let hotwords_file = hotwords.to_cstring();
+
+        let provider = config
+            .provider
+            .unwrap_or_else(get_default_provider)
+            .to_cstring()?;
+        let tokens = cstring_from_str(&config.tokens);
+        let model_type = cstring_from_str(&config.model_type);
+        let modeling_unit = cstring_from_str(&config.modeling_unit);
+        let bpe_vocab = cstring_from_str(&config.bpe_vocab);
+        let decoding_method = config.decoding_method.to_cstring();
+
+        let (first, second, third, family) = match &config.model {
+            OnlineModel::Transducer {
+                encoder,
+                decoder,
+                joiner,
+            } => (encoder, decoder, joiner.as_str(), "transducer"),
+            OnlineModel::Paraformer { encoder, decoder } => (encoder, decoder, "", "paraformer"),
+            OnlineModel::Zipformer2Ctc { model } => (model, model, "", "zipformer2 ctc"),
+            OnlineModel::NemoCtc { model } => (model, model, "", "nemo ctc"),
+        };
+        let first = cstring_from_str(first);
+        let second = cstring_from_str(second);
+        let third = cstring_from_str(third);
+
+        let model_config = unsafe {
+            let mut model_config = sherpa_rs_sys::SherpaOnnxOnlineModelConfig {
+                tokens: tokens.as_ptr(),
+                num_threads: config.num_threads.to_raw(),
+                provider: provider.as_ptr(),
+                debug: config.debug.into(),
+                model_type: model_type.as_ptr(),
+                modeling_unit: modeling_unit.as_ptr(),
+                bpe_vocab: bpe_vocab.as_ptr(),
+
+                // NULLs
+                transducer: mem::zeroed::<_>(),
+                paraformer: mem::zeroed::<_>(),
+                zipformer2_ctc: mem::zeroed::<_>(),
+                nemo_ctc: mem::zeroed::<_>(),
+                t_one_ctc: mem::zeroed::<_>(),
+                tokens_buf: mem::zeroed::<_>(),
+                tokens_buf_size: 0,
+            };
+            match &config.model {
+                OnlineModel::Transducer { .. } => {
+                    model_config.transducer = sherpa_rs_sys::SherpaOnnxOnlineTransducerModelConfig {
+                        encoder: first.as_ptr(),
+                        decoder: second.as_ptr(),
+                        joiner: third.as_ptr(),
+                    }
+                }
+                OnlineModel::Paraformer { .. } => {
+                    model_config.paraformer = sherpa_rs_sys::SherpaOnnxOnlineParaformerModelConfig {
+                        encoder: first.as_ptr(),
+                        decoder: second.as_ptr(),
+                    }
+                }
+                OnlineModel::Zipformer2Ctc { .. } => {
+                    model_config.zipformer2_ctc =
+                        sherpa_rs_sys::SherpaOnnxOnlineZipformer2CtcModelConfig {
+                            model: first.as_ptr(),
+                        }
+                }
+                OnlineModel::NemoCtc { .. } => {
+                    model_config.nemo_ctc = sherpa_rs_sys::SherpaOnnxOnlineNemoCtcModelConfig {
+                        model: first.as_ptr(),
+                    }
+                }
+            }
+            model_config
+        };
+
+        let recognizer_config = unsafe {
+            sherpa_rs_sys::SherpaOnnxOnlineRecognizerConfig {
+                feat_config: sherpa_rs_sys::SherpaOnnxFeatureConfig {
+                    sample_rate: config.sample_rate,
+                    feature_dim: config.feature_dim,
+                },
+                model_config,
+                decoding_method: decoding_method.as_ptr(),
+                max_active_paths: config.max_active_paths,
+                enable_endpoint: config.enable_endpoint.into(),
+                rule1_min_trailing_silence: config.rule1_min_trailing_silence,
+                rule2_min_trailing_silence: config.rule2_min_trailing_silence,
+                rule3_min_utterance_length: config.rule3_min_utterance_length,
+                hotwords_file: hotwords_file.as_ptr(),
+                hotwords_score: config.hotwords_score,
+                blank_penalty: config.blank_penalty,
+
+                rule_fsts: itn.rule_fsts(),
+                rule_fars: itn.rule_fars(),
+                hr: itn.homophone_replacer(),
+
+                // NULLs
+                ctc_fst_decoder_config: mem::zeroed::<_>(),
+                hotwords_buf: mem::zeroed::<_>(),
+                hotwords_buf_size: 0,
+            }
+        };
+
+        let recognizer = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOnlineRecognizer(&recognizer_config),
+                sherpa_rs_sys::SherpaOnnxDestroyOnlineRecognizer,
+            )
+        }
+        .ok_or_else(|| Error::native_creation_failed(format!("online {} recognizer", family)))?;
+        Ok(Self {
+            recognizer,
+            tokens: config.tokens,
+            modeling_unit: config.modeling_unit,
+            decoding_method: config.decoding_method,
+            symbols: OnceLock::new(),
+            provider: ProviderSelection::default(),
+            precision,
+        })
+    }
+
+    pub fn create_stream(&self) -> Result<OnlineStream<'_>> {
+        let stream = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOnlineStream(self.recognizer.as_ptr()),
+                sherpa_rs_sys::SherpaOnnxDestroyOnlineStream,
+            )
+        }
+        .ok_or_else(|| {
+            Error::processing_failed(
+                "online recognizer",
+                "SherpaOnnxCreateOnlineStream returned null",
+            )
+        })?;
+        Ok(OnlineStream {
+            stream,
+            finished: Cell::new(false),
+            clock: Cell::default(),
+            hotword_warnings: Vec::new(),
+            scratch: RefCell::new(Vec::new()),
+            _recognizer: PhantomData,
+        })
+    }
+
+    /// Create a stream biased towards `hotwords`, without rebuilding the recognizer.
+    ///
+    /// Hotwords that can't be tokenized are skipped and listed in
+    /// [`OnlineStream::hotword_warnings`]. Needs [`DecodingMethod::ModifiedBeamSearch`].
+    pub fn create_stream_with_hotwords(
+        &self,
+        hotwords: &[&str],
+        score: f32,
+    ) -> Result<OnlineStream<'_>> {
+        if self.decoding_method != DecodingMethod::ModifiedBeamSearch {
+            return Err(Error::invalid_input(format!(
+                "hotwords need decoding_method modified_beam_search, got {}",
+                self.decoding_method
+            )));
+        }
+
+        let check_chars = hotwords::modeling_unit(&self.modeling_unit) == "cjkchar";
+        let symbols = if check_chars {
+            match self.symbols.get() {
+                Some(symbols) => Some(symbols),
+                None => {
+                    let symbols = hotwords::read_symbols(&self.tokens)?;
+                    Some(self.symbols.get_or_init(|| symbols))
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut warnings = Vec::new();
+        let mut lines = Vec::with_capacity(hotwords.len());
+        for &word in hotwords {
+            let word = word.trim();
+            if word.is_empty() {
+                continue;
+            }
+            // sherpa-onnx splits the list on '/' and line breaks
+            if word.contains(['/', '\n', '\r']) {
+                warnings.push(format!("{:?} contains a separator character", word));
+                continue;
+            }
+            if let Some(symbols) = symbols {
+                let missing = hotwords::missing_symbols(word, symbols);
+                if !missing.is_empty() {
+                    warnings.push(format!(
+                        "{:?} has characters not in tokens: {:?}",
+                        word, missing
+                    ));
+                    continue;
+                }
+            }
+            lines.push(format!("{} :{}", word, score));
+        }
+
+        let hotwords = cstring_from_str(&lines.join("\n"));
+        let stream = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOnlineStreamWithHotwords(
+                    self.recognizer.as_ptr(),
+                    hotwords.as_ptr(),
+                ),
+                sherpa_rs_sys::SherpaOnnxDestroyOnlineStream,
+            )
+        }
+        .ok_or_else(|| {
+            Error::processing_failed(
+                "online recognizer",
+                "SherpaOnnxCreateOnlineStreamWithHotwords returned null",
+            )
+        })?;
+        Ok(OnlineStream {
+            stream,
+            finished: Cell::new(false),
+            clock: Cell::default(),
+            hotword_warnings: warnings,
+            scratch: RefCell::new(Vec::new()),
+            _recognizer: PhantomData,
+        })
+    }
+
+    /// Like [`Self::create_stream`] without tying the lifetime to `self`.
+    ///
+    /// For owners that hold both; the stream must be dropped before the recognizer.
+    pub(crate) fn create_detached_stream(&self) -> Result<OnlineStream<'static>> {
+        let stream = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOnlineStream(self.recognizer.as_ptr()),
+                sherpa_rs_sys::SherpaOnnxDestroyOnlineStream,
+            )
+        }
+        .ok_or_else(|| {
+            Error::processing_failed(
+                "online recognizer",
+                "SherpaOnnxCreateOnlineStream returned null",
+            )
+        })?;
+        Ok(OnlineStream {
+            stream,
+            finished: Cell::new(false),
+            clock: Cell::default(),
+            hotword_warnings: Vec::new(),
+            scratch: RefCell::new(Vec::new()),
+            _recognizer: PhantomData,
+        })
+    }
+
+    /// Whether `stream` has enough audio buffered for [`Self::decode`].
+    pub fn is_ready(&self, stream: &OnlineStream) -> bool {
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxIsOnlineStreamReady(
+                self.recognizer.as_ptr(),
+                stream.stream.as_ptr(),
+            ) == 1
+        }
+    }
+
+    pub fn decode(&self, stream: &OnlineStream) {
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxDecodeOnlineStream(
+                self.recognizer.as_ptr(),
+                stream.stream.as_ptr(),
+            )
+        }
+    }
+
+    /// Decode one chunk of each stream in a single native batch call.
+    pub fn decode_streams(&self, streams: &[&OnlineStream]) {
+        if streams.is_empty() {
+            return;
+        }
+        let mut ptrs: Vec<_> = streams.iter().map(|s| s.stream.as_ptr()).collect();
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxDecodeMultipleOnlineStreams(
+                self.recognizer.as_ptr(),
+                ptrs.as_mut_ptr(),
+                ptrs.len() as i32,
+            )
+        }
+    }
+
+    /// Decode until the stream runs out of buffered audio.
+    pub fn decode_ready(&self, stream: &OnlineStream) {
+        while self.is_ready(stream) {
+            self.decode(stream);
+        }
+    }
+
+    /// Text decoded since the last [`Self::reset`].
+    pub fn result(&self, stream: &OnlineStream) -> OnlineRecognizerResult {
+        let state = if stream.finished.get() || self.is_endpoint(stream) {
+            ResultState::Final
+        } else {
+            ResultState::Partial
+        };
+        let offset = stream.absolute_time_offset_secs() as f32;
+        unsafe {
+            let result_ptr = sherpa_rs_sys::SherpaOnnxGetOnlineStreamResult(
+                self.recognizer.as_ptr(),
+                stream.stream.as_ptr(),
+            );
+            if result_ptr.is_null() {
+                return OnlineRecognizerResult {
+                    text: String::new(),
+                    tokens: Vec::new(),
+                    timestamps: Vec::new(),
+                    state,
+                    offset,
+                };
+            }
+            let result = OnlineRecognizerResult::new(&result_ptr.read(), state, offset);
+            sherpa_rs_sys::SherpaOnnxDestroyOnlineRecognizerResult(result_ptr);
+            result
+        }
+    }
+
+    /// Whether one of the endpoint rules fired. Always false unless `enable_endpoint` is set.
+    pub fn is_endpoint(&self, stream: &OnlineStream) -> bool {
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxOnlineStreamIsEndpoint(
+                self.recognizer.as_ptr(),
+                stream.stream.as_ptr(),
+            ) == 1
+        }
+    }
+
+    /// Clear the decoded text and endpoint state to start the next utterance.
+    ///
+    /// Timestamps of the next utterance start from zero again, at
+    /// [`OnlineStream::absolute_time_offset_secs`].
+    pub fn reset(&self, stream: &OnlineStream) {
+        let mut clock = stream.clock.get();
+        clock.reset();
+        stream.clock.set(clock);
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxOnlineStreamReset(
+                self.recognizer.as_ptr(),
+                stream.stream.as_ptr(),
+            )
+        }
+    }
+}
+
+impl OnlineStream<'_> {
+    pub fn accept_waveform(&self, sample_rate: u32, samples: &[f32]) {
+        let mut clock = self.clock.get();
+        clock.advance(sample_rate, samples.len());
+        self.clock.set(clock);
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxOnlineStreamAcceptWaveform(
+                self.stream.as_ptr(),
+                sample_rate as i32,
+                samples.as_ptr(),
+                samples.len().try_into().unwrap(),
+            )
+        }
+    }
+
+    /// Feed 16-bit PCM mono audio.
+    pub fn accept_waveform_i16(&self, sample_rate: u32, samples: &[i16]) {
+        let mut scratch = self.scratch.borrow_mut();
+        scratch.clear();
+        scratch.extend(samples.iter().map(|&s| s as f32 / 32768.0));
+        self.accept_waveform(sample_rate, &scratch);
+    }
+
+    /// Feed interleaved stereo audio, mixed down to mono as `mix` says.
+    pub fn accept_waveform_stereo(&self, sample_rate: u32, samples: &[f32], mix: ChannelMix) {
+        let mut scratch = self.scratch.borrow_mut();
+        scratch.clear();
+        stereo_to_mono_into(samples, mix, &mut scratch);
+        self.accept_waveform(sample_rate, &scratch);
+    }
+
+    /// Signal that no more audio follows, so the tail can be decoded.
+    pub fn input_finished(&self) {
+        self.finished.set(true);
+        unsafe { sherpa_rs_sys::SherpaOnnxOnlineStreamInputFinished(self.stream.as_ptr()) }
+    }
+
+    /// Hotwords skipped by [`OnlineRecognizer::create_stream_with_hotwords`], with the reason.
+    pub fn hotword_warnings(&self) -> &[String] {
+        &self.hotword_warnings
+    }
+
+    /// Whether [`Self::input_finished`] was called.
+    pub fn is_finished(&self) -> bool {
+        self.finished.get()
+    }
+
+    /// Seconds of audio fed so far, whatever the sample rate of each call.
+    pub fn elapsed_secs(&self) -> f64 {
+        self.clock.get().elapsed
+    }
+
+    /// Seconds of audio fed before the last [`OnlineRecognizer::reset`], the stream
+    /// time the timestamps of the current result are relative to.
+    ///
+    /// Audio still buffered in the decoder at the reset counts towards the offset, so
+    /// reset right after [`OnlineRecognizer::decode_ready`] as on an endpoint.
+    pub fn absolute_time_offset_secs(&self) -> f64 {
+        self.clock.get().segment_start
+    }
+}
+
+unsafe impl Send for OnlineRecognizer {}
+unsafe impl Sync for OnlineRecognizer {}
+
+unsafe impl Send for OnlineStream<'_> {}
+
+/// Streams for many clients sharing one recognizer, decoded together on [`Self::tick`].
+pub struct StreamManager<'a, K> {
+    recognizer: &'a OnlineRecognizer,
+    streams: HashMap<K, OnlineStream<'a>>,
+    max_streams: usize,
+}
+
+impl<'a, K: Hash + Eq + Clone> StreamManager<'a, K> {
+    pub fn new(recognizer: &'a OnlineRecognizer, max_streams: usize) -> Self {
+        Self {
+            recognizer,
+            streams: HashMap::new(),
+            max_streams,
+        }
+    }
+
+    /// Create a stream for `id`. Fails if it already has one or the manager is full.
+    pub fn open(&mut self, id: K) -> Result<()> {
+        if self.streams.contains_key(&id) {
+            return Err(Error::invalid_input(
+                "a stream is already open for this client",
+            ));
+        }
+        if self.streams.len() >= self.max_streams {
+            return Err(Error::invalid_input(format!(
+                "stream limit of {} reached",
+                self.max_streams
+            )));
+        }
+        let stream = self.recognizer.create_stream()?;
+        self.streams.insert(id, stream);
+        Ok(())
+    }
+
+    /// Drop the stream of a disconnected client. Returns false if there was none.
+    pub fn close(&mut self, id: &K) -> bool {
+        self.streams.remove(id).is_some()
+    }
+
+    pub fn accept_waveform(&self, id: &K, sample_rate: u32, samples: &[f32]) -> Result<()> {
+        self.get(id)?.accept_waveform(sample_rate, samples);
+        Ok(())
+    }
+
+    pub fn input_finished(&self, id: &K) -> Result<()> {
+        self.get(id)?.input_finished();
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Batch decode every stream with pending frames and return the updated results.
+    ///
+    /// A stream whose result is final because of an endpoint is reset afterwards,
+    /// so its next result starts a new utterance.
+    pub fn tick(&mut self) -> Vec<(K, OnlineRecognizerResult)> {
+        let mut updated: Vec<&K> = Vec::new();
+        loop {
+            let ready: Vec<(&K, &OnlineStream)> = self
+                .streams
+                .iter()
+                .filter(|(_, stream)| self.recognizer.is_ready(stream))
+                .collect();
+            if ready.is_empty() {
+                break;
+            }
+            let streams: Vec<&OnlineStream> = ready.iter().map(|(_, s)| *s).collect();
+            self.recognizer.decode_streams(&streams);
+            for (id, _) in ready {
+                if !updated.contains(&id) {
+                    updated.push(id);
+                }
+            }
+        }
+
+        updated
+            .into_iter()
+            .map(|id| {
+                let stream = &self.streams[id];
+                let result = self.recognizer.result(stream);
+                if result.state == ResultState::Final && !stream.is_finished() {
+                    self.recognizer.reset(stream);
+                }
+                (id.clone(), result)
+            })
+            .collect()
+    }
+
+    fn get(&self, id: &K) -> Result<&OnlineStream<'a>> {
+        match self.streams.get(id) {
+            Some(stream) => Ok(stream),
+            None => Err(Error::invalid_input("no stream open for this client")),
+        }
+    }
+}
+
+/// One utterance from [`UtteranceSegmenter`], times in seconds of fed audio.
+#[derive(Debug, Clone)]
+pub struct Utterance {
+    pub text: String,
+    pub start: f32,
+    pub end: f32,
+}
+
+/// Splits a continuous stream into utterances at the recognizer's endpoints.
+pub struct UtteranceSegmenter<'a> {
+    recognizer: &'a OnlineRecognizer,
+    stream: OnlineStream<'a>,
+    /// Seconds of audio fed so far.
+    elapsed: f64,
+    utterance_start: f64,
+}
+
+/// Audio is fed in chunks of this length so endpoints inside a long buffer aren't missed.
+const FEED_CHUNK_SECS: f64 = 0.1;
+
+impl<'a> UtteranceSegmenter<'a> {
+    pub fn new(recognizer: &'a OnlineRecognizer) -> Result<Self> {
+        Ok(Self {
+            recognizer,
+            stream: recognizer.create_stream()?,
+            elapsed: 0.0,
+            utterance_start: 0.0,
+        })
+    }
+
+    /// Feed audio and return the utterances that ended in it.
+    pub fn feed(&mut self, sample_rate: u32, samples: &[f32]) -> Vec<Utterance> {
+        let mut utterances = Vec::new();
+        if sample_rate == 0 {
+            return utterances;
+        }
+        let chunk = ((sample_rate as f64 * FEED_CHUNK_SECS) as usize).max(1);
+        for samples in samples.chunks(chunk) {
+            self.stream.accept_waveform(sample_rate, samples);
+            self.elapsed += samples.len() as f64 / sample_rate as f64;
+            self.recognizer.decode_ready(&self.stream);
+            if self.recognizer.is_endpoint(&self.stream) {
+                utterances.extend(self.take());
+                self.recognizer.reset(&self.stream);
+            }
+        }
+        utterances
+    }
+
+    /// Decode the remaining audio and return the last utterance, if any.
+    ///
+    /// The segmenter starts over on a fresh stream afterwards.
+    pub fn finish(&mut self) -> Result<Option<Utterance>> {
+        self.stream.input_finished();
+        self.recognizer.decode_ready(&self.stream);
+        let last = self.take();
+        self.stream = self.recognizer.create_stream()?;
+        self.elapsed = 0.0;
+        self.utterance_start = 0.0;
+        Ok(last)
+    }
+
+    fn take(&mut self) -> Option<Utterance> {
+        let text = self.recognizer.result(&self.stream).text.trim().to_string();
+        let start = self.utterance_start;
+        self.utterance_start = self.elapsed;
+        if text.is_empty() {
+            return None;
+        }
+        Some(Utterance {
+            text,
+            start: start as f32,
+            end: self.elapsed as f32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn clock_counts_each_call_at_its_input_rate() {
+        // Whatever the model rate, input at 48 kHz, 8 kHz and 16 kHz counts in seconds
+        // of its own rate
+        let mut clock = StreamClock::default();
+        for _ in 0..10 {
+            clock.advance(48000, 4800);
+        }
+        clock.advance(8000, 4000);
+        assert_close(clock.elapsed, 1.5);
+        assert_close(clock.segment_start, 0.0);
+
+        clock.reset();
+        assert_close(clock.segment_start, 1.5);
+        clock.advance(16000, 16000);
+        clock.advance(0, 100);
+        assert_close(clock.elapsed, 2.5);
+        assert_close(clock.segment_start, 1.5);
+
+        clock.reset();
+        assert_close(clock.segment_start, 2.5);
+    }
+
+    #[test]
+    fn timestamps_after_reset_are_absolute() {
+        // A one second utterance and 0.75 s of silence before the endpoint reset, then
+        // the second utterance
+        let mut clock = StreamClock::default();
+        clock.advance(44100, 44100);
+        clock.advance(8000, 6000);
+        clock.reset();
+        clock.advance(16000, 8000);
+
+        let result = OnlineRecognizerResult {
+            text: "hi".into(),
+            tokens: vec!["h".into(), "i".into()],
+            timestamps: vec![0.0, 0.25],
+            state: ResultState::Final,
+            offset: clock.segment_start as f32,
+        };
+        assert_eq!(result.absolute_timestamps(), [1.75, 2.0]);
+        assert_close(clock.elapsed, 2.25);
+    }
+}
@@ -1,21 +1,41 @@
-use crate::{get_default_provider, utils::cstring_from_str};
-use eyre::{bail, Result};
-use std::{mem, ptr::null};
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    itn::{ItnConfig, CHINESE_ITN_FST},
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{cstring_from_str, time_warm_up, warm_up_silence, WARM_UP_SAMPLE_RATE},
+    Error, FallbackPolicy, Provider, Result, Threads,
+};
+use std::{mem, path::Path, ptr::null, time::Duration};
 
 #[derive(Debug)]
 pub struct ParaformerRecognizer {
-    recognizer: *const sherpa_rs_sys::SherpaOnnxOfflineRecognizer,
+    recognizer: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineRecognizer>,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
 }
 
 pub type ParaformerRecognizerResult = super::OfflineRecognizerResult;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ParaformerConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
-    pub provider: Option<String>,
-    pub num_threads: Option<i32>,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub num_threads: Threads,
     pub debug: bool,
+    /// Inverse text normalization and homophone replacement applied to the text.
+    pub itn: ItnConfig,
+    /// Write Chinese numbers as digits with the [`CHINESE_ITN_FST`] found next to
+    /// `model`, applied before `itn.rule_fsts`.
+    pub chinese_itn: bool,
 }
 
 impl Default for ParaformerConfig {
@@ -24,19 +44,97 @@ impl Default for ParaformerConfig {
             model: String::new(),
             tokens: String::new(),
             debug: false,
+            itn: ItnConfig::default(),
+            chinese_itn: false,
             provider: None,
-            num_threads: Some(1),
+            fallback: FallbackPolicy::default(),
+            num_threads: Threads::ONE,
         }
     }
 }
 
 impl ParaformerRecognizer {
+    /// Like [`Self::new`], with the model and tokens found in `dir`, an unpacked
+    /// sherpa-onnx release.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: ParaformerConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Paraformer,
+            precision,
+            &config.provider.clone().unwrap_or_else(get_default_provider),
+        )?;
+        Self::new(ParaformerConfig {
+            model: dir.file(Role::Model),
+            tokens: dir.file(Role::Tokens),
+            ..config
+        })
+    }
+
     pub fn new(config: ParaformerConfig) -> Result<Self> {
+        let _timed = timed!("paraformer.create");
+        debug_event!(?config, "creating paraformer recognizer");
+        let (mut recognizer, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(ParaformerConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        recognizer.provider = provider;
+        Ok(recognizer)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model files.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Transcribe half a second of silence and discard it, so the first real
+    /// [`Self::transcribe`] doesn't pay for ONNX Runtime's one-time setup. Returns how long
+    /// it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("paraformer.warm_up");
+        time_warm_up(|| {
+            self.transcribe(WARM_UP_SAMPLE_RATE, &warm_up_silence(WARM_UP_SAMPLE_RATE));
+            Ok(())
+        })
+    }
+
+    fn create(config: ParaformerConfig) -> Result<Self> {
+        let precision = ModelPrecision::of_file(&config.model);
         let debug = config.debug.into();
-        let provider = config.provider.unwrap_or(get_default_provider());
+        let itn = if config.chinese_itn {
+            let fst = Path::new(&config.model).with_file_name(CHINESE_ITN_FST);
+            if !fst.is_file() {
+                return Err(Error::model_not_found(
+                    "chinese itn rules, download them from the asr-models release",
+                    fst,
+                ));
+            }
+            let mut itn = config.itn.clone();
+            itn.rule_fsts.insert(0, fst);
+            itn.to_raw()?
+        } else {
+            config.itn.to_raw()?
+        };
+        let provider = config.provider.unwrap_or_else(get_default_provider);
 
         // Prepare C strings
-        let provider_ptr = cstring_from_str(&provider);
+        let provider_ptr = provider.to_cstring()?;
         let model_ptr = cstring_from_str(&config.model);
         let tokens_ptr = cstring_from_str(&config.tokens);
 
@@ -52,7 +150,7 @@ impl ParaformerRecognizer {
         let model_config = unsafe {
             sherpa_rs_sys::SherpaOnnxOfflineModelConfig {
                 debug,
-                num_threads: config.num_threads.unwrap_or(1),
+                num_threads: config.num_threads.to_raw(),
                 provider: provider_ptr.as_ptr(),
                 tokens: tokens_ptr.as_ptr(),
                 paraformer: paraformer_config,
@@ -90,32 +188,43 @@ impl ParaformerRecognizer {
                 hotwords_score: 0.0,
                 lm_config: mem::zeroed::<_>(),
                 max_active_paths: 0,
-                rule_fars: null(),
-                rule_fsts: null(),
+                rule_fars: itn.rule_fars(),
+                rule_fsts: itn.rule_fsts(),
                 blank_penalty: 0.0,
-                hr: mem::zeroed::<_>(),
+                hr: itn.homophone_replacer(),
             }
         };
 
-        let recognizer =
-            unsafe { sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&recognizer_config) };
-        if recognizer.is_null() {
-            bail!("Failed to create Paraformer recognizer");
+        let recognizer = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&recognizer_config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer,
+            )
         }
+        .ok_or_else(|| Error::native_creation_failed("paraformer recognizer"))?;
 
-        Ok(Self { recognizer })
+        Ok(Self {
+            recognizer,
+            provider: ProviderSelection::default(),
+            precision,
+        })
     }
 
     pub fn transcribe(&mut self, sample_rate: u32, samples: &[f32]) -> ParaformerRecognizerResult {
+        let _timed = timed!(
+            "paraformer.transcribe",
+            sample_rate,
+            samples = samples.len()
+        );
         unsafe {
-            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer);
+            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer.as_ptr());
             sherpa_rs_sys::SherpaOnnxAcceptWaveformOffline(
                 stream,
                 sample_rate as i32,
                 samples.as_ptr(),
                 samples.len() as i32,
             );
-            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer, stream);
+            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer.as_ptr(), stream);
             let result_ptr = sherpa_rs_sys::SherpaOnnxGetOfflineStreamResult(stream);
             let raw_result = result_ptr.read();
             let result = ParaformerRecognizerResult::new(&raw_result);
@@ -126,15 +235,17 @@ impl ParaformerRecognizer {
             result
         }
     }
+
+    /// Decode several clips of `(samples, sample_rate)` in one native batch call.
+    ///
+    /// Results are in input order, an empty batch returns an empty Vec.
+    pub fn transcribe_batch(
+        &mut self,
+        inputs: &[(&[f32], u32)],
+    ) -> Vec<Result<ParaformerRecognizerResult>> {
+        crate::decode_offline_batch(self.recognizer.as_ptr(), inputs)
+    }
 }
 
 unsafe impl Send for ParaformerRecognizer {}
 unsafe impl Sync for ParaformerRecognizer {}
-
-impl Drop for ParaformerRecognizer {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer(self.recognizer);
-        }
-    }
-}
@@ -0,0 +1,1806 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fmt::Write,
+    mem,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    align::{normalize, similarity},
+    denoise::{SpeechDenoiser, SpeechDenoiserConfig},
+    diarize::{Diarize, DiarizeConfig},
+    dolphin::{DolphinConfig, DolphinRecognizer},
+    fire_red_asr::{FireRedAsrConfig, FireRedAsrRecognizer},
+    moonshine::{MoonshineConfig, MoonshineRecognizer},
+    online::{OnlineRecognizer, OnlineRecognizerConfig, OnlineRecognizerResult, OnlineStream},
+    paraformer::{ParaformerConfig, ParaformerRecognizer},
+    read_audio_file,
+    sense_voice::{SenseVoiceConfig, SenseVoiceRecognizer},
+    silero_vad::{SileroVad, SileroVadConfig},
+    subtitle::srt_time,
+    trace::debug_event,
+    utils::{downmix, read_wav, resample, AudioSource, Resampler, WavData},
+    vad::{Vad, VadConfig},
+    whisper::{WhisperConfig, WhisperRecognizer},
+    CancellationToken, Error, OfflineRecognizerResult, Result, WordSpan,
+};
+
+/// Offline recognizer used by [`VadAsr`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum AsrConfig {
+    Whisper(WhisperConfig),
+    SenseVoice(SenseVoiceConfig),
+    Moonshine(MoonshineConfig),
+    Paraformer(ParaformerConfig),
+    Dolphin(DolphinConfig),
+    FireRedAsr(FireRedAsrConfig),
+}
+
+pub(crate) enum Recognizer {
+    Whisper(WhisperRecognizer),
+    SenseVoice(SenseVoiceRecognizer),
+    Moonshine(MoonshineRecognizer),
+    Paraformer(ParaformerRecognizer),
+    Dolphin(DolphinRecognizer),
+    FireRedAsr(FireRedAsrRecognizer),
+}
+
+impl Recognizer {
+    pub(crate) fn new(config: AsrConfig) -> Result<Self> {
+        Ok(match config {
+            AsrConfig::Whisper(c) => Self::Whisper(WhisperRecognizer::new(c)?),
+            AsrConfig::SenseVoice(c) => Self::SenseVoice(SenseVoiceRecognizer::new(c)?),
+            AsrConfig::Moonshine(c) => Self::Moonshine(MoonshineRecognizer::new(c)?),
+            AsrConfig::Paraformer(c) => Self::Paraformer(ParaformerRecognizer::new(c)?),
+            AsrConfig::Dolphin(c) => Self::Dolphin(DolphinRecognizer::new(c)?),
+            AsrConfig::FireRedAsr(c) => Self::FireRedAsr(FireRedAsrRecognizer::new(c)?),
+        })
+    }
+
+    pub(crate) fn transcribe(
+        &mut self,
+        sample_rate: u32,
+        samples: &[f32],
+    ) -> OfflineRecognizerResult {
+        match self {
+            Self::Whisper(r) => r.transcribe(sample_rate, samples),
+            Self::SenseVoice(r) => r.transcribe(sample_rate, samples),
+            Self::Moonshine(r) => r.transcribe(sample_rate, samples),
+            Self::Paraformer(r) => r.transcribe(sample_rate, samples),
+            Self::Dolphin(r) => r.transcribe(sample_rate, samples),
+            Self::FireRedAsr(r) => r.transcribe(sample_rate, samples),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VadAsrConfig {
+    pub vad: SileroVadConfig,
+    pub asr: AsrConfig,
+    /// Audio kept before and after each detected speech segment.
+    pub padding_ms: u32,
+    /// Segments closer than this are decoded together.
+    pub merge_gap_ms: u32,
+    /// Don't merge segments past this length. Whisper only sees 30 seconds at a time.
+    pub max_merged_secs: f32,
+    /// Size of the VAD's internal speech buffer.
+    pub vad_buffer_secs: f32,
+}
+
+impl VadAsrConfig {
+    pub fn new(vad: SileroVadConfig, asr: AsrConfig) -> Self {
+        Self {
+            vad,
+            asr,
+            padding_ms: 200,
+            merge_gap_ms: 300,
+            max_merged_secs: 25.0,
+            vad_buffer_secs: 60.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscribedSegment {
+    pub start_secs: f32,
+    pub end_secs: f32,
+    pub text: String,
+    /// Words with times relative to the start of the input, empty if the model has no timestamps.
+    pub words: Vec<WordSpan>,
+    pub lang: String,
+    /// [`OfflineRecognizerResult::confidence`] of the decoded audio.
+    pub confidence: Option<f32>,
+    /// Input channel the segment was heard on, set by [`MultiChannelTranscriber`].
+    pub channel: Option<usize>,
+    /// Label of [`Self::channel`], e.g. the participant on that microphone.
+    pub speaker: Option<String>,
+}
+
+impl TranscribedSegment {
+    /// Shift word times of a result decoded from a slice starting at `start_secs`.
+    fn new(result: OfflineRecognizerResult, start_secs: f32, end_secs: f32) -> Self {
+        let words = result
+            .words()
+            .into_iter()
+            .map(|w| WordSpan {
+                start: w.start + start_secs,
+                end: w.end + start_secs,
+                ..w
+            })
+            .collect();
+        Self {
+            start_secs,
+            end_secs,
+            text: result.text.trim().to_string(),
+            words,
+            lang: result.lang,
+            confidence: result.confidence,
+            channel: None,
+            speaker: None,
+        }
+    }
+}
+
+/// Audio read from an [`AudioSource`] at a time by [`VadAsr::transcribe_source`].
+const SOURCE_CHUNK_SECS: f32 = 10.0;
+
+/// Audio [`VadAsr::transcribe_source`] keeps behind the read position while there's no
+/// speech, since the VAD reports segments starting a little before it detects them.
+const VAD_LOOKBACK_SECS: f32 = 1.0;
+
+/// Transcribe long recordings by decoding each VAD speech segment separately.
+pub struct VadAsr {
+    vad: SileroVad,
+    recognizer: Recognizer,
+    sample_rate: u32,
+    window_size: usize,
+    spans: SpanMerge,
+}
+
+impl VadAsr {
+    pub fn new(config: VadAsrConfig) -> Result<Self> {
+        let sample_rate = config.vad.sample_rate;
+        let window_size = config.vad.window_size;
+        if sample_rate == 0 || window_size <= 0 {
+            return Err(Error::invalid_input(
+                "vad sample_rate and window_size must be positive",
+            ));
+        }
+        let vad = SileroVad::new(config.vad, config.vad_buffer_secs)?;
+        let recognizer = Recognizer::new(config.asr)?;
+        Ok(Self {
+            vad,
+            recognizer,
+            sample_rate,
+            window_size: window_size as usize,
+            spans: SpanMerge::new(&config, sample_rate),
+        })
+    }
+
+    pub fn transcribe_file(&mut self, path: &str) -> Result<Vec<TranscribedSegment>> {
+        let (samples, sample_rate) = read_audio_file(path)?;
+        self.transcribe_samples(&samples, sample_rate)
+    }
+
+    pub fn transcribe_samples(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+    ) -> Result<Vec<TranscribedSegment>> {
+        self.transcribe_samples_with_progress(samples, sample_rate, |_, _| {})
+    }
+
+    /// Like [`Self::transcribe_samples`], calling `progress(done, total)` after each decoded segment.
+    pub fn transcribe_samples_with_progress<F>(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        progress: F,
+    ) -> Result<Vec<TranscribedSegment>>
+    where
+        F: FnMut(usize, usize),
+    {
+        self.transcribe_segments(samples, sample_rate, progress, None)
+    }
+
+    /// Like [`Self::transcribe_samples`], returning [`Error::Cancelled`] before the next
+    /// segment is decoded once `token` is cancelled.
+    pub fn transcribe_samples_with_cancel(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        token: &CancellationToken,
+    ) -> Result<Vec<TranscribedSegment>> {
+        self.transcribe_segments(samples, sample_rate, |_, _| {}, Some(token))
+    }
+
+    fn transcribe_segments<F>(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        mut progress: F,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Vec<TranscribedSegment>>
+    where
+        F: FnMut(usize, usize),
+    {
+        if sample_rate == 0 {
+            return Err(Error::invalid_input("sample rate must be non-zero"));
+        }
+        let resampled;
+        let samples = if sample_rate == self.sample_rate {
+            samples
+        } else {
+            resampled = resample(samples, sample_rate, self.sample_rate);
+            &resampled
+        };
+
+        let spans = self.detect(samples);
+        let spans = self.spans.merge(spans, samples.len());
+        let sr = self.sample_rate as f32;
+        let mut segments = Vec::with_capacity(spans.len());
+        for (i, &(start, end)) in spans.iter().enumerate() {
+            if let Some(token) = cancel {
+                token.check("vad asr")?;
+            }
+            let result = self
+                .recognizer
+                .transcribe(self.sample_rate, &samples[start..end]);
+            segments.push(TranscribedSegment::new(
+                result,
+                start as f32 / sr,
+                end as f32 / sr,
+            ));
+            progress(i + 1, spans.len());
+        }
+        Ok(segments)
+    }
+
+    /// Like [`Self::transcribe_samples`], reading `source` a chunk at a time. Several
+    /// channels are averaged to mono.
+    ///
+    /// Only the audio of segments that aren't decoded yet is kept, so memory use doesn't
+    /// grow with the length of the recording.
+    pub fn transcribe_source<S: AudioSource>(
+        &mut self,
+        source: &mut S,
+    ) -> Result<Vec<TranscribedSegment>> {
+        let sample_rate = source.sample_rate();
+        if sample_rate == 0 {
+            return Err(Error::invalid_input("sample rate must be non-zero"));
+        }
+        let channels = source.channels().max(1) as usize;
+        let mut resampler = if sample_rate == self.sample_rate {
+            None
+        } else {
+            Some(Resampler::new(sample_rate, self.sample_rate, 1)?)
+        };
+        let chunk_frames = ((SOURCE_CHUNK_SECS * sample_rate as f32) as usize).max(1);
+
+        self.vad.reset();
+        let mut buffer = SourceBuffer::default();
+        let mut segments = Vec::new();
+        loop {
+            let chunk = source.read_frames(chunk_frames)?;
+            let done = chunk.is_empty();
+            let samples = match &mut resampler {
+                Some(resampler) if done => resampler.flush(),
+                Some(resampler) => resampler.process(&downmix(&chunk, channels)),
+                None => downmix(&chunk, channels),
+            };
+            self.feed_source(&mut buffer, &samples, done, &mut segments);
+            if done {
+                return Ok(segments);
+            }
+        }
+    }
+
+    fn feed_source(
+        &mut self,
+        buffer: &mut SourceBuffer,
+        samples: &[f32],
+        done: bool,
+        segments: &mut Vec<TranscribedSegment>,
+    ) {
+        let mut spans = Vec::new();
+        let vad = &mut self.vad;
+        buffer.push(samples, self.window_size, |window| {
+            vad.accept_waveform(window);
+            drain_spans(vad, &mut spans);
+        });
+        if done {
+            if !buffer.window.is_empty() {
+                self.vad.accept_waveform(mem::take(&mut buffer.window));
+            }
+            self.vad.flush();
+            drain_spans(&mut self.vad, &mut spans);
+        }
+
+        for span in spans {
+            if let Some(span) = buffer.add_span(span, &self.spans) {
+                self.decode_span(buffer, span, segments);
+            }
+        }
+
+        if done {
+            if let Some(span) = buffer.pending.take() {
+                self.decode_span(buffer, span, segments);
+            }
+            return;
+        }
+        if self.vad.is_speech() {
+            return;
+        }
+        // Nothing heard from here on can start before `keep_from`
+        let lookback = (VAD_LOOKBACK_SECS * self.sample_rate as f32) as usize;
+        let keep_from = buffer.end().saturating_sub(lookback + self.spans.pad);
+        if let Some(span) = buffer.take_finished(keep_from, &self.spans) {
+            self.decode_span(buffer, span, segments);
+        }
+        buffer.trim(keep_from);
+    }
+
+    fn decode_span(
+        &mut self,
+        buffer: &SourceBuffer,
+        span: (usize, usize),
+        segments: &mut Vec<TranscribedSegment>,
+    ) {
+        let Some((start, end, samples)) = buffer.get(span) else {
+            return;
+        };
+        let result = self.recognizer.transcribe(self.sample_rate, samples);
+        let sr = self.sample_rate as f32;
+        segments.push(TranscribedSegment::new(
+            result,
+            start as f32 / sr,
+            end as f32 / sr,
+        ));
+    }
+
+    /// Speech spans as `(start, end)` sample indices.
+    fn detect(&mut self, samples: &[f32]) -> Vec<(usize, usize)> {
+        self.vad.reset();
+        let mut spans = Vec::new();
+        for window in samples.chunks(self.window_size) {
+            self.vad.accept_waveform(window.to_vec());
+            drain_spans(&mut self.vad, &mut spans);
+        }
+        self.vad.flush();
+        drain_spans(&mut self.vad, &mut spans);
+        spans
+    }
+}
+
+/// Padding and merging of [`VadAsr`] speech spans, in samples at the VAD rate.
+#[derive(Debug, Clone, Copy)]
+struct SpanMerge {
+    pad: usize,
+    gap: usize,
+    max_len: usize,
+}
+
+impl SpanMerge {
+    fn new(config: &VadAsrConfig, sample_rate: u32) -> Self {
+        Self {
+            pad: (config.padding_ms as u64 * sample_rate as u64 / 1000) as usize,
+            gap: (config.merge_gap_ms as u64 * sample_rate as u64 / 1000) as usize,
+            max_len: (config.max_merged_secs.max(0.0) * sample_rate as f32) as usize,
+        }
+    }
+
+    /// `(start, end)` widened by the padding, clamped to `total` samples.
+    fn pad(&self, (start, end): (usize, usize), total: usize) -> (usize, usize) {
+        (
+            start.saturating_sub(self.pad).min(total),
+            end.saturating_add(self.pad).min(total),
+        )
+    }
+
+    /// Whether padded `span` is close enough to `last` to be decoded with it.
+    fn joins(&self, last: (usize, usize), span: (usize, usize)) -> bool {
+        span.0 <= last.1 + self.gap && span.1 - last.0 <= self.max_len
+    }
+
+    /// Pad spans and join the ones closer than the merge gap.
+    fn merge(&self, spans: Vec<(usize, usize)>, total: usize) -> Vec<(usize, usize)> {
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+        for span in spans {
+            let (start, end) = self.pad(span, total);
+            match merged.last_mut() {
+                Some(last) if self.joins(*last, (start, end)) => {
+                    last.1 = last.1.max(end);
+                }
+                _ if end > start => merged.push((start, end)),
+                _ => {}
+            }
+        }
+        merged
+    }
+}
+
+/// Move the speech segments the VAD finished into `spans` as `(start, end)` sample
+/// indices.
+fn drain_spans(vad: &mut SileroVad, spans: &mut Vec<(usize, usize)>) {
+    while !vad.is_empty() {
+        let segment = vad.front();
+        let start = segment.start.max(0) as usize;
+        spans.push((start, start + segment.samples.len()));
+        vad.pop();
+    }
+}
+
+/// Audio of an [`AudioSource`] that [`VadAsr::transcribe_source`] may still decode.
+#[derive(Default)]
+struct SourceBuffer {
+    /// Mono audio at the VAD rate, from sample `start` of the source on.
+    samples: Vec<f32>,
+    start: usize,
+    /// Samples not given to the VAD yet, less than a window.
+    window: Vec<f32>,
+    /// Padded speech span that a following one may still be merged into.
+    pending: Option<(usize, usize)>,
+}
+
+impl SourceBuffer {
+    fn end(&self) -> usize {
+        self.start + self.samples.len()
+    }
+
+    /// Append `samples`, passing each window of `window_size` completed to `feed`.
+    fn push(&mut self, samples: &[f32], window_size: usize, mut feed: impl FnMut(Vec<f32>)) {
+        self.samples.extend_from_slice(samples);
+        let mut rest = samples;
+        while !rest.is_empty() {
+            let take = (window_size - self.window.len()).min(rest.len());
+            self.window.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.window.len() == window_size {
+                feed(mem::take(&mut self.window));
+            }
+        }
+    }
+
+    /// Pad a detected span and merge it into the pending one. Returns the span it
+    /// replaced as pending, which nothing can join any more.
+    fn add_span(&mut self, span: (usize, usize), merge: &SpanMerge) -> Option<(usize, usize)> {
+        let (start, end) = merge.pad(span, usize::MAX);
+        match &mut self.pending {
+            Some(last) if merge.joins(*last, (start, end)) => {
+                last.1 = last.1.max(end);
+                None
+            }
+            _ if end > start => self.pending.replace((start, end)),
+            _ => None,
+        }
+    }
+
+    /// Take the pending span if speech starting at `keep_from` or later is too far
+    /// away to join it.
+    fn take_finished(&mut self, keep_from: usize, merge: &SpanMerge) -> Option<(usize, usize)> {
+        let span = self.pending.filter(|span| keep_from > span.1 + merge.gap)?;
+        self.pending = None;
+        Some(span)
+    }
+
+    /// Drop audio before `keep_from` that the pending span doesn't need.
+    fn trim(&mut self, keep_from: usize) {
+        let keep_from = self
+            .pending
+            .map_or(keep_from, |span| span.0.min(keep_from))
+            .min(self.end());
+        if keep_from > self.start {
+            self.samples.drain(..keep_from - self.start);
+            self.start = keep_from;
+        }
+    }
+
+    /// `span` clamped to the audio still held, with its samples. `None` if none of it is.
+    fn get(&self, (start, end): (usize, usize)) -> Option<(usize, usize, &[f32])> {
+        let (start, end) = (start.max(self.start), end.min(self.end()));
+        (end > start).then(|| {
+            (
+                start,
+                end,
+                &self.samples[start - self.start..end - self.start],
+            )
+        })
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TwoPassConfig {
+    pub online: OnlineRecognizerConfig,
+    pub offline: AsrConfig,
+    /// Utterances longer than this keep the online result. Also bounds the audio buffer.
+    pub max_second_pass_secs: f32,
+}
+
+impl TwoPassConfig {
+    pub fn new(online: OnlineRecognizerConfig, offline: AsrConfig) -> Self {
+        Self {
+            online,
+            offline,
+            max_second_pass_secs: 25.0,
+        }
+    }
+}
+
+/// A finished utterance from [`TwoPassRecognizer`], times in seconds of fed audio.
+#[derive(Debug, Clone)]
+pub struct TwoPassResult {
+    /// Offline result, or the online one if the second pass was skipped.
+    pub text: String,
+    pub online_text: String,
+    /// Whether the offline recognizer produced `text`.
+    pub rescored: bool,
+    pub start: f32,
+    pub end: f32,
+}
+
+type FinalCallback = Box<dyn FnMut(TwoPassResult) + Send>;
+
+/// Streaming recognition with fast online partials, re-decoded offline at each endpoint.
+pub struct TwoPassRecognizer {
+    // Declared before `online` so it is dropped first
+    stream: OnlineStream<'static>,
+    online: OnlineRecognizer,
+    offline: Recognizer,
+    on_final: FinalCallback,
+    max_second_pass_secs: f32,
+    /// Audio of the current utterance, cleared and reused after each endpoint.
+    buffer: Vec<f32>,
+    buffer_sample_rate: u32,
+    /// The current utterance outgrew the buffer, so it keeps the online result.
+    overflowed: bool,
+    elapsed: f64,
+    utterance_start: f64,
+}
+
+impl TwoPassRecognizer {
+    pub fn new<F>(config: TwoPassConfig, on_final: F) -> Result<Self>
+    where
+        F: FnMut(TwoPassResult) + Send + 'static,
+    {
+        let online = OnlineRecognizer::new(config.online)?;
+        let offline = Recognizer::new(config.offline)?;
+        let stream = online.create_detached_stream()?;
+        Ok(Self {
+            stream,
+            online,
+            offline,
+            on_final: Box::new(on_final),
+            max_second_pass_secs: config.max_second_pass_secs.max(0.0),
+            buffer: Vec::new(),
+            buffer_sample_rate: 0,
+            overflowed: false,
+            elapsed: 0.0,
+            utterance_start: 0.0,
+        })
+    }
+
+    /// Feed audio, calling the final-result callback for every utterance that ends in it.
+    pub fn feed(&mut self, sample_rate: u32, samples: &[f32]) {
+        if sample_rate == 0 {
+            return;
+        }
+        if sample_rate != self.buffer_sample_rate {
+            let capacity = (self.max_second_pass_secs * sample_rate as f32) as usize;
+            self.buffer = Vec::with_capacity(capacity);
+            self.buffer_sample_rate = sample_rate;
+            // Audio already buffered was at another rate
+            self.overflowed = self.elapsed > self.utterance_start;
+        }
+
+        let chunk = (sample_rate as usize / 10).max(1);
+        for samples in samples.chunks(chunk) {
+            self.stream.accept_waveform(sample_rate, samples);
+            self.elapsed += samples.len() as f64 / sample_rate as f64;
+            if !self.overflowed {
+                if self.buffer.len() + samples.len() <= self.buffer.capacity() {
+                    self.buffer.extend_from_slice(samples);
+                } else {
+                    self.overflowed = true;
+                }
+            }
+            self.online.decode_ready(&self.stream);
+            if self.online.is_endpoint(&self.stream) {
+                self.finalize();
+                self.online.reset(&self.stream);
+            }
+        }
+    }
+
+    /// Text of the current utterance from the online pass, may still change.
+    pub fn partial(&self) -> String {
+        self.online.result(&self.stream).text.trim().to_string()
+    }
+
+    /// Flush the remaining audio and finalize the last utterance.
+    pub fn finish(&mut self) -> Result<()> {
+        self.stream.input_finished();
+        self.online.decode_ready(&self.stream);
+        self.finalize();
+        self.stream = self.online.create_detached_stream()?;
+        self.elapsed = 0.0;
+        self.utterance_start = 0.0;
+        Ok(())
+    }
+
+    fn finalize(&mut self) {
+        let online_text = self.online.result(&self.stream).text.trim().to_string();
+        let start = self.utterance_start;
+        self.utterance_start = self.elapsed;
+
+        if !online_text.is_empty() {
+            let (text, rescored) = if self.overflowed || self.buffer.is_empty() {
+                (online_text.clone(), false)
+            } else {
+                let result = self
+                    .offline
+                    .transcribe(self.buffer_sample_rate, &self.buffer);
+                (result.text.trim().to_string(), true)
+            };
+            (self.on_final)(TwoPassResult {
+                text,
+                online_text,
+                rescored,
+                start: start as f32,
+                end: self.elapsed as f32,
+            });
+        }
+        self.buffer.clear();
+        self.overflowed = false;
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiarizedTranscriberConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path_buf"))]
+    pub segmentation_model: PathBuf,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path_buf"))]
+    pub embedding_model: PathBuf,
+    pub diarize: DiarizeConfig,
+    pub asr: AsrConfig,
+    /// Audio kept before and after each diarization segment when decoding it.
+    pub padding_ms: u32,
+    /// Turns of the same speaker closer than this are joined.
+    pub merge_gap_ms: u32,
+}
+
+impl DiarizedTranscriberConfig {
+    pub fn new(segmentation_model: PathBuf, embedding_model: PathBuf, asr: AsrConfig) -> Self {
+        Self {
+            segmentation_model,
+            embedding_model,
+            diarize: DiarizeConfig::default(),
+            asr,
+            padding_ms: 100,
+            merge_gap_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpeakerTurn {
+    pub speaker: i32,
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+}
+
+/// Who said what: diarizes the audio, then decodes each speaker segment.
+pub struct DiarizedTranscriber {
+    diarize: Diarize,
+    recognizer: Recognizer,
+    padding_ms: u32,
+    merge_gap_ms: u32,
+}
+
+impl DiarizedTranscriber {
+    pub fn new(config: DiarizedTranscriberConfig) -> Result<Self> {
+        let diarize = Diarize::new(
+            &config.segmentation_model,
+            &config.embedding_model,
+            config.diarize,
+        )?;
+        let recognizer = Recognizer::new(config.asr)?;
+        Ok(Self {
+            diarize,
+            recognizer,
+            padding_ms: config.padding_ms,
+            merge_gap_ms: config.merge_gap_ms,
+        })
+    }
+
+    pub fn transcribe_file(&mut self, path: &str) -> Result<Vec<SpeakerTurn>> {
+        let (samples, sample_rate) = read_audio_file(path)?;
+        self.transcribe(&samples, sample_rate)
+    }
+
+    /// Turns in order of start time. Overlapping speech gives overlapping turns.
+    pub fn transcribe(&mut self, samples: &[f32], sample_rate: u32) -> Result<Vec<SpeakerTurn>> {
+        self.transcribe_turns(samples, sample_rate, None)
+    }
+
+    /// Like [`Self::transcribe`], returning [`Error::Cancelled`] once `token` is
+    /// cancelled, checked after diarization and before each segment is decoded.
+    pub fn transcribe_with_cancel(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        token: &CancellationToken,
+    ) -> Result<Vec<SpeakerTurn>> {
+        self.transcribe_turns(samples, sample_rate, Some(token))
+    }
+
+    fn transcribe_turns(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Vec<SpeakerTurn>> {
+        if sample_rate == 0 {
+            return Err(Error::invalid_input("sample rate must be non-zero"));
+        }
+        let model_rate = self.diarize.sample_rate();
+        let resampled;
+        let samples = if sample_rate == model_rate {
+            samples
+        } else {
+            resampled = resample(samples, sample_rate, model_rate);
+            &resampled
+        };
+
+        let segments = match cancel {
+            Some(token) => self
+                .diarize
+                .process_with_cancel(samples, model_rate, token)?,
+            None => self.diarize.process(samples, model_rate)?,
+        };
+        let sr = model_rate as f32;
+        let pad = self.padding_ms as f32 / 1000.0;
+        let gap = self.merge_gap_ms as f32 / 1000.0;
+
+        let mut turns: Vec<SpeakerTurn> = Vec::with_capacity(segments.len());
+        for segment in segments {
+            if let Some(token) = cancel {
+                token.check("diarized transcriber")?;
+            }
+            // Each segment is decoded on its own, so overlapping speakers both get text
+            let start = ((segment.start - pad).max(0.0) * sr) as usize;
+            let end = (((segment.end + pad) * sr) as usize).min(samples.len());
+            if start >= end {
+                continue;
+            }
+            let text = self
+                .recognizer
+                .transcribe(model_rate, &samples[start..end])
+                .text
+                .trim()
+                .to_string();
+            if text.is_empty() {
+                continue;
+            }
+            match turns.last_mut() {
+                Some(last) if last.speaker == segment.speaker && segment.start - last.end < gap => {
+                    last.end = last.end.max(segment.end);
+                    last.text.push(' ');
+                    last.text.push_str(&text);
+                }
+                _ => turns.push(SpeakerTurn {
+                    speaker: segment.speaker,
+                    start: segment.start,
+                    end: segment.end,
+                    text,
+                }),
+            }
+        }
+        Ok(turns)
+    }
+}
+
+/// Format turns as RTTM, one `SPEAKER` line per turn.
+pub fn turns_to_rttm(turns: &[SpeakerTurn], file_id: &str) -> String {
+    let mut out = String::new();
+    for turn in turns {
+        let _ = writeln!(
+            out,
+            "SPEAKER {} 1 {:.3} {:.3} <NA> <NA> speaker_{} <NA> <NA>",
+            file_id,
+            turn.start,
+            (turn.end - turn.start).max(0.0),
+            turn.speaker
+        );
+    }
+    out
+}
+
+/// Format turns as SRT cues prefixed with the speaker.
+pub fn turns_to_srt(turns: &[SpeakerTurn]) -> String {
+    let mut out = String::new();
+    for (i, turn) in turns.iter().enumerate() {
+        let _ = write!(
+            out,
+            "{}\n{} --> {}\n[speaker_{}] {}\n\n",
+            i + 1,
+            srt_time(turn.start),
+            srt_time(turn.end),
+            turn.speaker,
+            turn.text
+        );
+    }
+    out
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MultiChannelConfig {
+    pub vad_asr: VadAsrConfig,
+    /// Channels decoded at the same time, each worker with its own VAD and recognizer.
+    pub workers: usize,
+    /// [`TranscribedSegment::speaker`] of each channel, in channel order. Channels
+    /// without a label are called `channel_<index>`.
+    pub labels: Vec<String>,
+    /// Drop a segment when another channel heard the same words starting within this
+    /// many seconds, keeping the copy with the higher confidence. Off if `None`.
+    pub dedup_window_secs: Option<f32>,
+    /// How alike two texts must be to count as the same words, from 0.0 to 1.0.
+    pub dedup_similarity: f32,
+}
+
+impl MultiChannelConfig {
+    pub fn new(vad_asr: VadAsrConfig) -> Self {
+        Self {
+            vad_asr,
+            workers: 1,
+            labels: Vec::new(),
+            dedup_window_secs: None,
+            dedup_similarity: 0.8,
+        }
+    }
+}
+
+/// Transcribe recordings with a microphone per speaker, decoding each channel on its
+/// own and merging the segments in order of time.
+pub struct MultiChannelTranscriber {
+    workers: Vec<VadAsr>,
+    /// Kept to replace a worker that panicked.
+    vad_asr: VadAsrConfig,
+    labels: Vec<String>,
+    dedup_window_secs: Option<f32>,
+    dedup_similarity: f32,
+}
+
+impl MultiChannelTranscriber {
+    pub fn new(config: MultiChannelConfig) -> Result<Self> {
+        if config.workers == 0 {
+            return Err(Error::invalid_input(
+                "multi-channel transcriber needs at least one worker",
+            ));
+        }
+        if !(0.0..=1.0).contains(&config.dedup_similarity) {
+            return Err(Error::invalid_input(format!(
+                "dedup_similarity must be between 0.0 and 1.0, got {}",
+                config.dedup_similarity
+            )));
+        }
+        let workers = (0..config.workers)
+            .map(|_| VadAsr::new(config.vad_asr.clone()))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            workers,
+            vad_asr: config.vad_asr,
+            labels: config.labels,
+            dedup_window_secs: config.dedup_window_secs,
+            dedup_similarity: config.dedup_similarity,
+        })
+    }
+
+    pub fn transcribe_file(&mut self, path: &str) -> Result<Vec<TranscribedSegment>> {
+        let wav = read_wav(path)?;
+        self.transcribe(&wav)
+    }
+
+    /// Segments of every channel in order of start time, each with its
+    /// [`TranscribedSegment::channel`] and [`TranscribedSegment::speaker`].
+    pub fn transcribe(&mut self, wav: &WavData) -> Result<Vec<TranscribedSegment>> {
+        self.transcribe_channels(wav, None)
+    }
+
+    /// Like [`Self::transcribe`], returning [`Error::Cancelled`] before the next segment
+    /// is decoded once `token` is cancelled.
+    pub fn transcribe_with_cancel(
+        &mut self,
+        wav: &WavData,
+        token: &CancellationToken,
+    ) -> Result<Vec<TranscribedSegment>> {
+        self.transcribe_channels(wav, Some(token))
+    }
+
+    /// Label of channel `index`.
+    fn label(&self, index: usize) -> String {
+        self.labels
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| format!("channel_{}", index))
+    }
+
+    fn transcribe_channels(
+        &mut self,
+        wav: &WavData,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Vec<TranscribedSegment>> {
+        if wav.sample_rate == 0 {
+            return Err(Error::invalid_input("sample rate must be non-zero"));
+        }
+        let channels: Vec<Vec<f32>> = (0..wav.channels.max(1) as usize)
+            .filter_map(|index| wav.channel(index))
+            .collect();
+        let rate = wav.sample_rate;
+        let step = self.workers.len().min(channels.len()).max(1);
+
+        // Worker k decodes channels k, k + step, k + 2 * step and so on
+        let decoded: Vec<thread::Result<Result<Vec<TranscribedSegment>>>> = if step == 1 {
+            vec![Ok(decode_channels(
+                &mut self.workers[0],
+                &channels,
+                0,
+                step,
+                rate,
+                cancel,
+            ))]
+        } else {
+            thread::scope(|scope| {
+                let channels = &channels;
+                let handles: Vec<_> = self.workers[..step]
+                    .iter_mut()
+                    .enumerate()
+                    .map(|(first, worker)| {
+                        scope.spawn(move || {
+                            decode_channels(worker, channels, first, step, rate, cancel)
+                        })
+                    })
+                    .collect();
+                handles.into_iter().map(|handle| handle.join()).collect()
+            })
+        };
+
+        let mut segments = Vec::new();
+        let mut first_err = None;
+        for (k, result) in decoded.into_iter().enumerate() {
+            match result {
+                Ok(Ok(found)) => segments.extend(found),
+                Ok(Err(err)) => {
+                    first_err.get_or_insert(err);
+                }
+                Err(_) => {
+                    self.workers[k] = VadAsr::new(self.vad_asr.clone())?;
+                    first_err.get_or_insert(Error::processing_failed(
+                        "multi-channel transcriber",
+                        "a worker panicked while decoding",
+                    ));
+                }
+            }
+        }
+        if let Some(err) = first_err {
+            return Err(err);
+        }
+
+        for segment in &mut segments {
+            segment.speaker = segment.channel.map(|index| self.label(index));
+        }
+        segments.sort_by(|a, b| {
+            a.start_secs
+                .total_cmp(&b.start_secs)
+                .then(a.channel.cmp(&b.channel))
+        });
+        match self.dedup_window_secs {
+            Some(window) => Ok(drop_cross_talk(
+                segments,
+                &channels,
+                rate,
+                window,
+                self.dedup_similarity,
+            )),
+            None => Ok(segments),
+        }
+    }
+}
+
+/// Decode channels `first`, `first + step` and so on with `worker`.
+///
+/// Each channel is a separate [`VadAsr::transcribe_samples`] call, which resets the VAD,
+/// so segment times of every channel start from zero.
+fn decode_channels(
+    worker: &mut VadAsr,
+    channels: &[Vec<f32>],
+    first: usize,
+    step: usize,
+    sample_rate: u32,
+    cancel: Option<&CancellationToken>,
+) -> Result<Vec<TranscribedSegment>> {
+    let mut segments = Vec::new();
+    for index in (first..channels.len()).step_by(step) {
+        let found = match cancel {
+            Some(token) => {
+                worker.transcribe_samples_with_cancel(&channels[index], sample_rate, token)?
+            }
+            None => worker.transcribe_samples(&channels[index], sample_rate)?,
+        };
+        segments.extend(found.into_iter().map(|segment| TranscribedSegment {
+            channel: Some(index),
+            ..segment
+        }));
+    }
+    Ok(segments)
+}
+
+/// Drop segments another channel also picked up, sorted by start time.
+///
+/// Of two alike segments on different channels starting less than `window` seconds
+/// apart, the one with the lower confidence goes. Without a confidence from the model,
+/// the quieter one goes, since the speaker's own microphone hears them loudest.
+fn drop_cross_talk(
+    segments: Vec<TranscribedSegment>,
+    channels: &[Vec<f32>],
+    sample_rate: u32,
+    window: f32,
+    min_similarity: f32,
+) -> Vec<TranscribedSegment> {
+    let keys: Vec<String> = segments.iter().map(|s| normalize(&s.text)).collect();
+    let levels: Vec<f32> = segments
+        .iter()
+        .map(|s| {
+            let samples = s.channel.and_then(|c| channels.get(c));
+            samples.map_or(0.0, |samples| {
+                let start = (s.start_secs * sample_rate as f32) as usize;
+                let end = (s.end_secs * sample_rate as f32) as usize;
+                rms(&samples[start.min(samples.len())..end.min(samples.len())])
+            })
+        })
+        .collect();
+    // Whether segment `a` is the better copy of segment `b`
+    let beats = |a: usize, b: usize| match (segments[a].confidence, segments[b].confidence) {
+        (Some(x), Some(y)) if x != y => x > y,
+        _ => levels[a] > levels[b],
+    };
+
+    let mut keep = vec![true; segments.len()];
+    for i in 0..segments.len() {
+        if keys[i].is_empty() {
+            continue;
+        }
+        for j in i + 1..segments.len() {
+            if !keep[i] || segments[j].start_secs - segments[i].start_secs > window {
+                break;
+            }
+            if !keep[j]
+                || keys[j].is_empty()
+                || segments[i].channel == segments[j].channel
+                || similarity(&keys[i], &keys[j]) < min_similarity
+            {
+                continue;
+            }
+            debug_event!(
+                start = segments[i].start_secs,
+                text = segments[i].text.as_str(),
+                "dropping cross-talk"
+            );
+            if beats(j, i) {
+                keep[i] = false;
+            } else {
+                keep[j] = false;
+            }
+        }
+    }
+    segments
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(segment, keep)| keep.then_some(segment))
+        .collect()
+}
+
+/// Root mean square of `samples`, 0.0 if there are none.
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = samples.iter().map(|&s| s as f64 * s as f64).sum();
+    (sum / samples.len() as f64).sqrt() as f32
+}
+
+/// Time spent in each stage of a [`Preprocessor`] or [`StreamingPreprocessor`].
+#[derive(Debug, Clone, Default)]
+pub struct PipelineStats {
+    pub denoise: Duration,
+    pub resample: Duration,
+    pub vad: Duration,
+    pub asr: Duration,
+    /// Length of the input processed.
+    pub audio_secs: f64,
+}
+
+/// Chooses the stages of a [`Preprocessor`] or [`StreamingPreprocessor`].
+#[derive(Default)]
+pub struct PreprocessorBuilder {
+    denoise: Option<SpeechDenoiserConfig>,
+    sample_rate: Option<u32>,
+    vad: Option<VadConfig>,
+    pre_roll_ms: Option<u32>,
+    stats: bool,
+}
+
+/// Pre-roll of a [`StreamingPreprocessor`] on top of the VAD's `min_speech_duration`.
+const PRE_ROLL_MARGIN_SECS: f32 = 0.3;
+
+impl PreprocessorBuilder {
+    /// Denoise the input first. Offline only.
+    pub fn denoise(mut self, config: SpeechDenoiserConfig) -> Self {
+        self.denoise = Some(config);
+        self
+    }
+
+    /// Sample rate the recognizer gets. Defaults to the VAD rate, or 16 kHz.
+    pub fn resample(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// Only decode detected speech.
+    pub fn vad(mut self, config: VadConfig) -> Self {
+        self.vad = Some(config);
+        self
+    }
+
+    /// Audio from before the VAD reported speech that a [`StreamingPreprocessor`] still
+    /// gives the recognizer. Defaults to the VAD's `min_speech_duration` plus 300 ms,
+    /// since speech is only reported once it lasted that long.
+    pub fn pre_roll_ms(mut self, pre_roll_ms: u32) -> Self {
+        self.pre_roll_ms = Some(pre_roll_ms);
+        self
+    }
+
+    /// Record [`PipelineStats`].
+    pub fn stats(mut self, enabled: bool) -> Self {
+        self.stats = enabled;
+        self
+    }
+
+    fn build_vad(vad: Option<VadConfig>, sample_rate: Option<u32>) -> Result<(Option<Vad>, u32)> {
+        let vad = vad.map(Vad::new).transpose()?;
+        let sample_rate = match (&vad, sample_rate) {
+            (Some(vad), Some(rate)) if rate != vad.sample_rate() => {
+                return Err(Error::unsupported(format!(
+                    "resampling to {} Hz after a vad running at {} Hz",
+                    rate,
+                    vad.sample_rate()
+                )));
+            }
+            (Some(vad), _) => vad.sample_rate(),
+            (None, Some(0)) => return Err(Error::invalid_input("sample rate must be positive")),
+            (None, rate) => rate.unwrap_or(16000),
+        };
+        Ok((vad, sample_rate))
+    }
+
+    pub fn offline(self, asr: AsrConfig) -> Result<Preprocessor> {
+        let (vad, sample_rate) = Self::build_vad(self.vad, self.sample_rate)?;
+        let denoiser = self
+            .denoise
+            .map(|config| {
+                SpeechDenoiser::new(SpeechDenoiserConfig {
+                    // Rates are bridged here
+                    auto_resample: false,
+                    ..config
+                })
+            })
+            .transpose()?;
+        Ok(Preprocessor {
+            denoiser,
+            vad,
+            sample_rate,
+            recognizer: Recognizer::new(asr)?,
+            stats: self.stats.then(PipelineStats::default),
+        })
+    }
+
+    pub fn online(self, config: OnlineRecognizerConfig) -> Result<StreamingPreprocessor> {
+        if self.denoise.is_some() {
+            return Err(Error::unsupported(
+                "denoising in a streaming pipeline, it needs the whole recording",
+            ));
+        }
+        let (vad, sample_rate) = Self::build_vad(self.vad, self.sample_rate)?;
+        let pre_roll_secs = match (&vad, self.pre_roll_ms) {
+            (None, _) => 0.0,
+            (Some(_), Some(ms)) => ms as f32 / 1000.0,
+            (Some(vad), None) => {
+                let min_speech = match vad.config() {
+                    VadConfig::Silero(c) => c.min_speech_duration,
+                    VadConfig::Ten(c) => c.min_speech_duration,
+                };
+                min_speech.max(0.0) + PRE_ROLL_MARGIN_SECS
+            }
+        };
+        let online = OnlineRecognizer::new(config)?;
+        let stream = online.create_detached_stream()?;
+        Ok(StreamingPreprocessor {
+            stream,
+            online,
+            vad,
+            sample_rate,
+            resampler: None,
+            pre_roll: PreRoll::new((pre_roll_secs * sample_rate as f32) as usize),
+            in_speech: false,
+            stats: self.stats.then(PipelineStats::default),
+        })
+    }
+}
+
+/// Denoise, resample and VAD stages in front of an offline recognizer.
+pub struct Preprocessor {
+    denoiser: Option<SpeechDenoiser>,
+    vad: Option<Vad>,
+    sample_rate: u32,
+    recognizer: Recognizer,
+    stats: Option<PipelineStats>,
+}
+
+impl Preprocessor {
+    pub fn builder() -> PreprocessorBuilder {
+        PreprocessorBuilder::default()
+    }
+
+    /// Stats of the last call, if enabled.
+    pub fn stats(&self) -> Option<&PipelineStats> {
+        self.stats.as_ref()
+    }
+
+    /// Segments in order, one for the whole input if there is no VAD stage.
+    pub fn transcribe(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+    ) -> Result<Vec<TranscribedSegment>> {
+        if sample_rate == 0 {
+            return Err(Error::invalid_input("sample rate must be non-zero"));
+        }
+        let mut stats = PipelineStats {
+            audio_secs: samples.len() as f64 / sample_rate as f64,
+            ..Default::default()
+        };
+        let mut audio = Cow::Borrowed(samples);
+        let mut rate = sample_rate;
+
+        if let Some(denoiser) = &mut self.denoiser {
+            let model_rate = denoiser.sample_rate();
+            if model_rate > 0 && model_rate != rate {
+                let start = Instant::now();
+                audio = Cow::Owned(resample_once(&audio, rate, model_rate)?);
+                rate = model_rate;
+                stats.resample += start.elapsed();
+            }
+            let start = Instant::now();
+            let denoised = denoiser.run(&audio, rate)?;
+            audio = Cow::Owned(denoised.samples);
+            rate = denoised.sample_rate;
+            stats.denoise += start.elapsed();
+        }
+
+        if rate != self.sample_rate {
+            let start = Instant::now();
+            audio = Cow::Owned(resample_once(&audio, rate, self.sample_rate)?);
+            stats.resample += start.elapsed();
+        }
+
+        let start = Instant::now();
+        let spans: Vec<(usize, usize)> = match &mut self.vad {
+            Some(vad) => vad
+                .segments(&audio, self.sample_rate)
+                .map(|s| (s.start_sample, s.start_sample + s.samples.len()))
+                .collect(),
+            None => vec![(0, audio.len())],
+        };
+        stats.vad += start.elapsed();
+
+        let sr = self.sample_rate as f32;
+        let start = Instant::now();
+        let mut segments = Vec::with_capacity(spans.len());
+        for (start, end) in spans {
+            let end = end.min(audio.len());
+            if start >= end {
+                continue;
+            }
+            let result = self
+                .recognizer
+                .transcribe(self.sample_rate, &audio[start..end]);
+            segments.push(TranscribedSegment::new(
+                result,
+                start as f32 / sr,
+                end as f32 / sr,
+            ));
+        }
+        stats.asr += start.elapsed();
+
+        if self.stats.is_some() {
+            self.stats = Some(stats);
+        }
+        Ok(segments)
+    }
+}
+
+/// Resample `samples` in one go with the [`Resampler`] the streaming pipeline uses.
+fn resample_once(samples: &[f32], from_hz: u32, to_hz: u32) -> Result<Vec<f32>> {
+    let mut resampler = Resampler::new(from_hz, to_hz, 1)?;
+    let mut out = resampler.process(samples);
+    resampler.flush_into(&mut out);
+    Ok(out)
+}
+
+/// The most recent audio without speech, up to a fixed length.
+struct PreRoll {
+    len: usize,
+    samples: VecDeque<f32>,
+}
+
+impl PreRoll {
+    fn new(len: usize) -> Self {
+        Self {
+            len,
+            samples: VecDeque::with_capacity(len),
+        }
+    }
+
+    fn push(&mut self, frame: &[f32]) {
+        let frame = &frame[frame.len().saturating_sub(self.len)..];
+        let excess = (self.samples.len() + frame.len()).saturating_sub(self.len);
+        self.samples.drain(..excess);
+        self.samples.extend(frame);
+    }
+
+    /// The kept audio, oldest first, leaving it empty.
+    fn take(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+
+    fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Resample and VAD stages in front of an online recognizer.
+///
+/// With a VAD stage only speech reaches the recognizer, and the end of each speech
+/// segment finishes an utterance. Without one, the recognizer's endpointing decides.
+///
+/// The VAD only reports speech once it has lasted `min_speech_duration`, so each
+/// utterance starts with the pre-roll kept from before that.
+pub struct StreamingPreprocessor {
+    // Declared before `online` so it is dropped first
+    stream: OnlineStream<'static>,
+    online: OnlineRecognizer,
+    vad: Option<Vad>,
+    sample_rate: u32,
+    // Input rate and the resampler bridging it, kept so frame edges are filtered smoothly
+    resampler: Option<(u32, Resampler)>,
+    /// Audio from before the current utterance, fed when speech is detected.
+    pre_roll: PreRoll,
+    in_speech: bool,
+    stats: Option<PipelineStats>,
+}
+
+impl StreamingPreprocessor {
+    /// Stats accumulated since creation, if enabled.
+    pub fn stats(&self) -> Option<&PipelineStats> {
+        self.stats.as_ref()
+    }
+
+    /// Feed a frame of mono audio, returning utterances it finished.
+    pub fn push(&mut self, sample_rate: u32, frame: &[f32]) -> Result<Vec<OnlineRecognizerResult>> {
+        if sample_rate == 0 {
+            return Err(Error::invalid_input("sample rate must be non-zero"));
+        }
+        let mut stats = self.stats.take();
+        if let Some(stats) = &mut stats {
+            stats.audio_secs += frame.len() as f64 / sample_rate as f64;
+        }
+
+        let start = Instant::now();
+        let frame = if sample_rate == self.sample_rate {
+            Cow::Borrowed(frame)
+        } else {
+            let resampler = match &mut self.resampler {
+                Some((rate, resampler)) if *rate == sample_rate => resampler,
+                slot => {
+                    let resampler = Resampler::new(sample_rate, self.sample_rate, 1)?;
+                    &mut slot.insert((sample_rate, resampler)).1
+                }
+            };
+            Cow::Owned(resampler.process(frame))
+        };
+        let resample_time = start.elapsed();
+
+        let mut finished = Vec::new();
+        let start = Instant::now();
+        let speech = match &mut self.vad {
+            Some(vad) => {
+                vad.accept_waveform(&frame);
+                let speech = vad.is_speech_detected();
+                vad.clear();
+                Some(speech)
+            }
+            None => None,
+        };
+        let vad_time = start.elapsed();
+
+        let start = Instant::now();
+        match speech {
+            Some(true) | None => {
+                if !self.in_speech {
+                    let pre_roll = self.pre_roll.take();
+                    if !pre_roll.is_empty() {
+                        self.stream.accept_waveform(self.sample_rate, &pre_roll);
+                    }
+                }
+                self.in_speech = true;
+                self.stream.accept_waveform(self.sample_rate, &frame);
+                self.online.decode_ready(&self.stream);
+                if speech.is_none() && self.online.is_endpoint(&self.stream) {
+                    finished.extend(self.take_final(false)?);
+                }
+            }
+            Some(false) if self.in_speech => {
+                // The segment ended, finish the utterance instead of waiting for an endpoint
+                self.stream.accept_waveform(self.sample_rate, &frame);
+                finished.extend(self.take_final(true)?);
+            }
+            Some(false) => self.pre_roll.push(&frame),
+        }
+        let asr_time = start.elapsed();
+
+        if let Some(stats) = &mut stats {
+            stats.resample += resample_time;
+            stats.vad += vad_time;
+            stats.asr += asr_time;
+        }
+        self.stats = stats;
+        Ok(finished)
+    }
+
+    /// Text of the current utterance so far.
+    pub fn partial(&self) -> String {
+        self.online.result(&self.stream).text.trim().to_string()
+    }
+
+    /// Finish the last utterance.
+    pub fn finish(&mut self) -> Result<Option<OnlineRecognizerResult>> {
+        if let Some(vad) = &mut self.vad {
+            vad.reset();
+        }
+        self.pre_roll.clear();
+        self.take_final(true)
+    }
+
+    fn take_final(&mut self, input_finished: bool) -> Result<Option<OnlineRecognizerResult>> {
+        if input_finished {
+            self.stream.input_finished();
+        }
+        self.online.decode_ready(&self.stream);
+        let result = self.online.result(&self.stream);
+        if input_finished {
+            // A finished stream can't take more audio
+            self.stream = self.online.create_detached_stream()?;
+        } else {
+            self.online.reset(&self.stream);
+        }
+        self.in_speech = false;
+        Ok((!result.text.trim().is_empty()).then_some(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const MERGE: SpanMerge = SpanMerge {
+        pad: 10,
+        gap: 20,
+        max_len: 200,
+    };
+
+    #[test]
+    fn merge_pads_and_clamps_spans() {
+        assert_eq!(MERGE.merge(vec![(5, 50)], 1000), vec![(0, 60)]);
+        assert_eq!(MERGE.merge(vec![(100, 995)], 1000), vec![(90, 1000)]);
+        // Speech reported past the end of the input is dropped, not decoded as nothing
+        assert_eq!(
+            MERGE.merge(vec![(100, 150), (1020, 1100)], 1000),
+            vec![(90, 160)]
+        );
+    }
+
+    #[test]
+    fn merge_joins_spans_within_the_gap() {
+        // Padded: (90, 160) and (175, 210) are 15 apart, (300, 360) is 90 past that
+        let spans = vec![(100, 150), (185, 200), (310, 350)];
+        assert_eq!(MERGE.merge(spans, 1000), vec![(90, 210), (300, 360)]);
+    }
+
+    #[test]
+    fn merge_stops_at_the_length_limit() {
+        // Joined, the first two would span 90..310, longer than 200
+        let spans = vec![(100, 150), (170, 300)];
+        assert_eq!(MERGE.merge(spans, 1000), vec![(90, 160), (160, 310)]);
+    }
+
+    #[test]
+    fn merge_absorbs_contained_spans() {
+        let spans = vec![(100, 180), (120, 150)];
+        assert_eq!(MERGE.merge(spans, 1000), vec![(90, 190)]);
+    }
+
+    #[test]
+    fn source_buffer_splits_windows_across_reads() {
+        let mut buffer = SourceBuffer::default();
+        let mut windows = Vec::new();
+        let input: Vec<f32> = (0..11).map(|i| i as f32).collect();
+        buffer.push(&input[..5], 4, |w| windows.push(w));
+        buffer.push(&input[5..], 4, |w| windows.push(w));
+        assert_eq!(windows, [&input[..4], &input[4..8]]);
+        assert_eq!(buffer.window, input[8..]);
+        assert_eq!(buffer.samples, input);
+        assert_eq!(buffer.end(), 11);
+    }
+
+    #[test]
+    fn source_buffer_hands_out_spans_nothing_can_join() {
+        let mut buffer = SourceBuffer::default();
+        assert_eq!(buffer.add_span((100, 150), &MERGE), None);
+        assert_eq!(buffer.add_span((185, 200), &MERGE), None);
+        assert_eq!(buffer.pending, Some((90, 210)));
+        // Too far away to join, so the pending span is done
+        assert_eq!(buffer.add_span((310, 350), &MERGE), Some((90, 210)));
+        assert_eq!(buffer.pending, Some((300, 360)));
+
+        // Speech starting at 380 could still join, at 381 it couldn't
+        assert_eq!(buffer.take_finished(380, &MERGE), None);
+        assert_eq!(buffer.take_finished(381, &MERGE), Some((300, 360)));
+        assert_eq!(buffer.pending, None);
+    }
+
+    #[test]
+    fn source_buffer_trims_what_no_span_needs() {
+        let mut buffer = SourceBuffer {
+            samples: (0..1000).map(|i| i as f32).collect(),
+            ..Default::default()
+        };
+        buffer.pending = Some((300, 360));
+        buffer.trim(500);
+        assert_eq!((buffer.start, buffer.end()), (300, 1000));
+        assert_eq!(buffer.samples[0], 300.0);
+
+        buffer.pending = None;
+        buffer.trim(500);
+        assert_eq!((buffer.start, buffer.end()), (500, 1000));
+        // Never past the end, or backwards
+        buffer.trim(2000);
+        assert_eq!((buffer.start, buffer.end()), (1000, 1000));
+        buffer.trim(10);
+        assert_eq!(buffer.start, 1000);
+    }
+
+    #[test]
+    fn source_buffer_clamps_spans_to_the_audio_held() {
+        let buffer = SourceBuffer {
+            samples: (100..200).map(|i| i as f32).collect(),
+            start: 100,
+            ..Default::default()
+        };
+        let (start, end, samples) = buffer.get((90, 110)).unwrap();
+        assert_eq!((start, end), (100, 110));
+        assert_eq!(samples[0], 100.0);
+        assert_eq!(buffer.get((190, 250)).unwrap().1, 200);
+        assert!(buffer.get((10, 100)).is_none());
+        assert!(buffer.get((200, 300)).is_none());
+    }
+
+    #[test]
+    fn pre_roll_keeps_the_latest_audio() {
+        let mut pre_roll = PreRoll::new(5);
+        pre_roll.push(&[1.0, 2.0, 3.0]);
+        pre_roll.push(&[4.0, 5.0, 6.0]);
+        assert_eq!(pre_roll.take(), [2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert!(pre_roll.take().is_empty());
+
+        // A frame longer than the pre-roll keeps its end
+        pre_roll.push(&[0.0; 3]);
+        pre_roll.push(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+        assert_eq!(pre_roll.take(), [3.0, 4.0, 5.0, 6.0, 7.0]);
+
+        let mut none = PreRoll::new(0);
+        none.push(&[1.0, 2.0]);
+        assert!(none.take().is_empty());
+    }
+
+    fn turns() -> Vec<SpeakerTurn> {
+        vec![
+            SpeakerTurn {
+                speaker: 0,
+                start: 0.5,
+                end: 2.25,
+                text: "Hello.".into(),
+            },
+            SpeakerTurn {
+                speaker: 1,
+                start: 61.0,
+                end: 62.0625,
+                text: "Hi there.".into(),
+            },
+        ]
+    }
+
+    #[test]
+    fn turns_as_rttm() {
+        assert_eq!(
+            turns_to_rttm(&turns(), "meeting"),
+            "SPEAKER meeting 1 0.500 1.750 <NA> <NA> speaker_0 <NA> <NA>\n\
+             SPEAKER meeting 1 61.000 1.062 <NA> <NA> speaker_1 <NA> <NA>\n"
+        );
+        // A turn ending before it starts gets no negative duration
+        let backwards = SpeakerTurn {
+            end: 0.0,
+            ..turns()[0].clone()
+        };
+        assert!(turns_to_rttm(&[backwards], "f").contains(" 0.500 0.000 "));
+    }
+
+    #[test]
+    fn turns_as_srt() {
+        assert_eq!(
+            turns_to_srt(&turns()),
+            "1\n00:00:00,500 --> 00:00:02,250\n[speaker_0] Hello.\n\n\
+             2\n00:01:01,000 --> 00:01:02,063\n[speaker_1] Hi there.\n\n"
+        );
+        assert_eq!(turns_to_srt(&[]), "");
+    }
+
+    #[test]
+    fn resample_once_matches_the_streaming_resampler() {
+        let input: Vec<f32> = (0..4410).map(|i| (i as f32 * 0.05).sin()).collect();
+        let mut resampler = Resampler::new(44100, 16000, 1).unwrap();
+        let mut streamed: Vec<f32> = input
+            .chunks(512)
+            .flat_map(|frame| resampler.process(frame))
+            .collect();
+        streamed.extend(resampler.flush());
+        assert_eq!(resample_once(&input, 44100, 16000).unwrap(), streamed);
+        assert!(resample_once(&input, 0, 16000).is_err());
+    }
+
+    /// Channel 0 is loud and channel 1 quiet, one second each at 100 Hz.
+    fn conference() -> Vec<Vec<f32>> {
+        vec![vec![0.5; 100], vec![0.1; 100]]
+    }
+
+    fn heard(
+        channel: usize,
+        start: f32,
+        text: &str,
+        confidence: Option<f32>,
+    ) -> TranscribedSegment {
+        TranscribedSegment {
+            start_secs: start,
+            end_secs: start + 0.5,
+            text: text.into(),
+            words: Vec::new(),
+            lang: String::new(),
+            confidence,
+            channel: Some(channel),
+            speaker: None,
+        }
+    }
+
+    fn kept(segments: Vec<TranscribedSegment>) -> Vec<(usize, String)> {
+        drop_cross_talk(segments, &conference(), 100, 0.5, 0.8)
+            .into_iter()
+            .map(|s| (s.channel.unwrap(), s.text))
+            .collect()
+    }
+
+    #[test]
+    fn cross_talk_keeps_the_more_confident_copy() {
+        let segments = vec![
+            heard(0, 0.0, "Hello there", Some(0.4)),
+            heard(1, 0.1, "hello there.", Some(0.9)),
+        ];
+        assert_eq!(kept(segments), vec![(1, "hello there.".into())]);
+    }
+
+    #[test]
+    fn cross_talk_falls_back_to_the_louder_channel() {
+        let segments = vec![
+            heard(1, 0.0, "good morning", None),
+            heard(0, 0.2, "good morning", None),
+        ];
+        assert_eq!(kept(segments), vec![(0, "good morning".into())]);
+
+        // Equal confidence doesn't decide either
+        let segments = vec![
+            heard(0, 0.0, "good morning", Some(0.7)),
+            heard(1, 0.2, "good morning", Some(0.7)),
+        ];
+        assert_eq!(kept(segments), vec![(0, "good morning".into())]);
+    }
+
+    #[test]
+    fn cross_talk_only_drops_alike_text_on_other_channels_within_the_window() {
+        let different = vec![
+            heard(0, 0.0, "good morning", None),
+            heard(1, 0.1, "see you later", None),
+        ];
+        assert_eq!(kept(different).len(), 2);
+
+        let same_channel = vec![heard(0, 0.0, "yes", None), heard(0, 0.3, "yes", None)];
+        assert_eq!(kept(same_channel).len(), 2);
+
+        let too_late = vec![
+            heard(0, 0.0, "good morning", None),
+            heard(1, 0.6, "good morning", None),
+        ];
+        assert_eq!(kept(too_late).len(), 2);
+
+        let empty = vec![heard(0, 0.0, "", None), heard(1, 0.1, "...", None)];
+        assert_eq!(kept(empty).len(), 2);
+    }
+
+    #[test]
+    fn rms_of_known_signals() {
+        assert_eq!(rms(&[]), 0.0);
+        assert!((rms(&[0.5; 64]) - 0.5).abs() < 1e-6);
+        assert!((rms(&[1.0, -1.0, 1.0, -1.0]) - 1.0).abs() < 1e-6);
+        let sine: Vec<f32> = (0..1000)
+            .map(|i| (2.0 * std::f32::consts::PI * i as f32 / 100.0).sin())
+            .collect();
+        assert!((rms(&sine) - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-4);
+    }
+
+    #[test]
+    #[ignore = "needs silero_vad.onnx and sherpa-onnx-whisper-tiny"]
+    fn vad_asr_restarts_times_for_each_input() {
+        let mut vad_asr = VadAsr::new(VadAsrConfig::new(
+            SileroVadConfig {
+                model: testing::model("silero_vad.onnx"),
+                ..Default::default()
+            },
+            AsrConfig::Whisper(WhisperConfig {
+                decoder: testing::model("sherpa-onnx-whisper-tiny/tiny-decoder.onnx"),
+                encoder: testing::model("sherpa-onnx-whisper-tiny/tiny-encoder.onnx"),
+                tokens: testing::model("sherpa-onnx-whisper-tiny/tiny-tokens.txt"),
+                language: Some("en".into()),
+                ..Default::default()
+            }),
+        ))
+        .unwrap();
+        let (samples, sample_rate) =
+            read_audio_file(&testing::model("sherpa-onnx-whisper-tiny/test_wavs/0.wav")).unwrap();
+
+        let first = vad_asr.transcribe_samples(&samples, sample_rate).unwrap();
+        let second = vad_asr.transcribe_samples(&samples, sample_rate).unwrap();
+        assert!(!first.is_empty());
+        let times = |segments: &[TranscribedSegment]| -> Vec<(f32, f32)> {
+            segments
+                .iter()
+                .map(|s| (s.start_secs, s.end_secs))
+                .collect()
+        };
+        assert_eq!(times(&first), times(&second));
+    }
+}
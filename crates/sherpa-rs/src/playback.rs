@@ -0,0 +1,193 @@
+//! Play audio on an output device with cpal, for demos and quick tooling.
+//!
+//! Audio is converted to the channel count the device is opened with, and resampled
+//! with [`Resampler`](crate::utils::Resampler) when the device can't run at its rate.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample, StreamConfig, SupportedStreamConfig,
+};
+
+use crate::{
+    trace::debug_event,
+    utils::{downmix, resample_interleaved},
+    CancellationToken, Error, Result,
+};
+
+/// How often the playing thread checks for the end of the audio or cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Names of the output devices of the default host, for [`play`].
+pub fn output_devices() -> Result<Vec<String>> {
+    let devices = cpal::default_host()
+        .output_devices()
+        .map_err(|err| Error::processing_failed("playback", err.to_string()))?;
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+/// Play interleaved `samples` and return once they've been played.
+///
+/// Uses the default output device unless `device_name` is one of [`output_devices`].
+/// Fails with [`Error::Cancelled`] soon after `token` is cancelled.
+pub fn play(
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+    device_name: Option<&str>,
+    token: Option<&CancellationToken>,
+) -> Result<()> {
+    if sample_rate == 0 || channels == 0 {
+        return Err(Error::invalid_input(
+            "sample rate and channel count must be non-zero",
+        ));
+    }
+    let host = cpal::default_host();
+    let device = match device_name {
+        Some(name) => host
+            .output_devices()
+            .map_err(|err| Error::processing_failed("playback", err.to_string()))?
+            .find(|device| device.name().is_ok_and(|n| n == name))
+            .ok_or_else(|| Error::invalid_input(format!("no output device named {:?}", name)))?,
+        None => host
+            .default_output_device()
+            .ok_or_else(|| Error::unsupported("playback without an output device"))?,
+    };
+
+    let supported = output_config(&device, sample_rate, channels)?;
+    let config: StreamConfig = supported.config();
+    debug_event!(
+        sample_rate,
+        channels,
+        device_rate = config.sample_rate.0,
+        device_channels = config.channels,
+        "playing audio"
+    );
+    let samples: Arc<[f32]> = convert(
+        samples,
+        sample_rate,
+        channels,
+        config.sample_rate.0,
+        config.channels,
+    )
+    .into();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let stream = match supported.sample_format() {
+        SampleFormat::F32 => build::<f32>(&device, &config, samples, done.clone()),
+        SampleFormat::I16 => build::<i16>(&device, &config, samples, done.clone()),
+        SampleFormat::U16 => build::<u16>(&device, &config, samples, done.clone()),
+        SampleFormat::I32 => build::<i32>(&device, &config, samples, done.clone()),
+        other => {
+            return Err(Error::unsupported(format!(
+                "playback to a device with {} samples",
+                other
+            )))
+        }
+    }?;
+    stream
+        .play()
+        .map_err(|err| Error::processing_failed("playback", err.to_string()))?;
+
+    while !done.load(Ordering::Relaxed) {
+        if let Some(token) = token {
+            token.check("playback")?;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// Config the device supports for the audio, preferring its own rate and channel count
+/// so nothing has to be converted.
+fn output_config(
+    device: &cpal::Device,
+    sample_rate: u32,
+    channels: u16,
+) -> Result<SupportedStreamConfig> {
+    let rate = cpal::SampleRate(sample_rate);
+    let exact = device
+        .supported_output_configs()
+        .map_err(|err| Error::processing_failed("playback", err.to_string()))?
+        .filter(|range| range.min_sample_rate() <= rate && rate <= range.max_sample_rate())
+        .min_by_key(|range| {
+            (
+                range.channels() != channels,
+                range.sample_format() != SampleFormat::F32,
+            )
+        });
+    match exact {
+        Some(range) => Ok(range.with_sample_rate(rate)),
+        None => device
+            .default_output_config()
+            .map_err(|err| Error::processing_failed("playback", err.to_string())),
+    }
+}
+
+fn build<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    samples: Arc<[f32]>,
+    done: Arc<AtomicBool>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let position = AtomicUsize::new(0);
+    device
+        .build_output_stream(
+            config,
+            move |out: &mut [T], _| {
+                let start = position.load(Ordering::Relaxed);
+                let end = (start + out.len()).min(samples.len());
+                for (out, &sample) in out.iter_mut().zip(&samples[start..end]) {
+                    *out = T::from_sample(sample);
+                }
+                for out in &mut out[end - start..] {
+                    *out = T::EQUILIBRIUM;
+                }
+                position.store(end, Ordering::Relaxed);
+                // Set one callback after the last sample, once it has reached the device
+                if start == samples.len() {
+                    done.store(true, Ordering::Relaxed);
+                }
+            },
+            |err| tracing::warn!("playback stream error: {}", err),
+            None,
+        )
+        .map_err(|err| Error::processing_failed("playback", err.to_string()))
+}
+
+/// Interleaved `samples` converted to `out_rate` and `out_channels`.
+///
+/// Mono is copied to every output channel, other layouts are mixed down to mono first
+/// unless the channel counts match.
+pub(crate) fn convert(
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+    out_rate: u32,
+    out_channels: u16,
+) -> Vec<f32> {
+    let (channels, out_channels) = (channels.max(1) as usize, out_channels.max(1) as usize);
+    let samples = resample_interleaved(samples, channels, sample_rate, out_rate);
+    if channels == out_channels {
+        return samples;
+    }
+    let mono = if channels == 1 {
+        samples
+    } else {
+        downmix(&samples, channels)
+    };
+    mono.iter()
+        .flat_map(|&sample| std::iter::repeat(sample).take(out_channels))
+        .collect()
+}
@@ -0,0 +1,374 @@
+use std::{ffi::CString, fmt, num::NonZeroU32, str::FromStr, sync::OnceLock, thread};
+
+use crate::{get_default_provider, trace::debug_event, utils::cstring_from_str, Error, Result};
+
+#[cfg(windows)]
+const CUDA_RUNTIMES: &[&str] = &["cudart64_12.dll", "cudart64_110.dll"];
+#[cfg(not(windows))]
+const CUDA_RUNTIMES: &[&str] = &["libcudart.so.12", "libcudart.so.11.0", "libcudart.so"];
+const DIRECTML_RUNTIMES: &[&str] = &["DirectML.dll"];
+
+/// ONNX Runtime execution provider a model runs on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Provider {
+    #[default]
+    Cpu,
+    Cuda,
+    CoreMl,
+    DirectMl,
+    Xnnpack,
+    /// Passed to sherpa-onnx as is, for providers this enum doesn't know yet.
+    Custom(String),
+}
+
+impl Provider {
+    /// Name sherpa-onnx expects.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Provider::Cpu => "cpu",
+            Provider::Cuda => "cuda",
+            Provider::CoreMl => "coreml",
+            Provider::DirectMl => "directml",
+            Provider::Xnnpack => "xnnpack",
+            Provider::Custom(name) => name,
+        }
+    }
+
+    /// Error if this build can't run the provider. sherpa-onnx would fall back to CPU
+    /// without telling anyone.
+    pub fn ensure_supported(&self) -> Result<()> {
+        match self {
+            Provider::Cuda if !cfg!(feature = "cuda") => Err(Error::unsupported(
+                "provider cuda without the `cuda` feature of sherpa-rs",
+            )),
+            Provider::DirectMl if !cfg!(feature = "directml") => Err(Error::unsupported(
+                "provider directml without the `directml` feature of sherpa-rs",
+            )),
+            Provider::CoreMl if !cfg!(target_vendor = "apple") => Err(Error::unsupported(
+                "provider coreml outside of Apple platforms",
+            )),
+            Provider::Custom(name) if name.trim().is_empty() => {
+                Err(Error::invalid_input("provider name is empty"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Validated name to put in a sherpa-onnx config.
+    pub(crate) fn to_cstring(&self) -> Result<CString> {
+        self.ensure_supported()?;
+        Ok(cstring_from_str(self.as_str()))
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Provider {
+    type Err = Error;
+
+    /// Case-insensitive and ignores surrounding whitespace. Unknown names become
+    /// [`Provider::Custom`].
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim();
+        Ok(match name.to_ascii_lowercase().as_str() {
+            "" => return Err(Error::invalid_input("provider name is empty")),
+            "cpu" => Provider::Cpu,
+            "cuda" => Provider::Cuda,
+            "coreml" => Provider::CoreMl,
+            "directml" => Provider::DirectMl,
+            "xnnpack" => Provider::Xnnpack,
+            _ => Provider::Custom(name.to_string()),
+        })
+    }
+}
+
+/// Written as the name sherpa-onnx expects.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Provider {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Read from a provider name, see [`Provider::from_str`].
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Provider {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// What to do when the requested provider can't be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum FallbackPolicy {
+    /// Fail to create the model.
+    #[default]
+    Strict,
+    /// Create the model on CPU instead and keep a warning saying why.
+    FallbackToCpu,
+}
+
+/// Intra-op threads ONNX Runtime runs a model with.
+///
+/// sherpa-onnx's C API has no inter-op setting, so that stays at ONNX Runtime's
+/// default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Threads {
+    /// One per core, from [`std::thread::available_parallelism`].
+    Auto,
+    Fixed(NonZeroU32),
+}
+
+impl Threads {
+    pub const ONE: Threads = Threads::Fixed(NonZeroU32::MIN);
+
+    /// `n` threads, failing if `n` is 0.
+    pub fn fixed(n: u32) -> Result<Self> {
+        NonZeroU32::new(n)
+            .map(Threads::Fixed)
+            .ok_or_else(|| Error::invalid_input("num_threads must be at least 1"))
+    }
+
+    /// Number of threads, one if the core count can't be read for `Auto`.
+    pub fn count(&self) -> u32 {
+        match self {
+            Threads::Auto => {
+                let count = thread::available_parallelism().map_or(1, |n| n.get() as u32);
+                debug_event!(count, "automatic thread count");
+                count
+            }
+            Threads::Fixed(n) => n.get(),
+        }
+    }
+
+    /// Count to put in a sherpa-onnx config.
+    pub(crate) fn to_raw(self) -> i32 {
+        self.count().min(i32::MAX as u32) as i32
+    }
+}
+
+/// A single thread.
+impl Default for Threads {
+    fn default() -> Self {
+        Threads::ONE
+    }
+}
+
+impl From<NonZeroU32> for Threads {
+    fn from(n: NonZeroU32) -> Self {
+        Threads::Fixed(n)
+    }
+}
+
+impl fmt::Display for Threads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Threads::Auto => f.write_str("auto"),
+            Threads::Fixed(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl FromStr for Threads {
+    type Err = Error;
+
+    /// `auto` or a positive count.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Threads::Auto);
+        }
+        let n = s.parse().map_err(|_| {
+            Error::invalid_input(format!("num_threads must be auto or a count, got {:?}", s))
+        })?;
+        Threads::fixed(n)
+    }
+}
+
+/// Written as `"auto"` or the count.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Threads {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Threads::Auto => serializer.serialize_str("auto"),
+            Threads::Fixed(n) => serializer.serialize_u32(n.get()),
+        }
+    }
+}
+
+/// Read from `"auto"` or a positive count.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Threads {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Count(u32),
+            Name(String),
+        }
+        let threads = match Repr::deserialize(deserializer)? {
+            Repr::Count(n) => Threads::fixed(n),
+            Repr::Name(name) => name.parse(),
+        };
+        threads.map_err(serde::de::Error::custom)
+    }
+}
+
+/// Provider a model was actually created with.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProviderSelection {
+    pub provider: Provider,
+    /// Set when the requested provider failed and the model fell back to CPU.
+    pub warning: Option<String>,
+}
+
+/// Providers usable on this machine, probed once per process.
+///
+/// CUDA and DirectML are listed if the crate was built with their feature and their
+/// runtime library can be loaded. CoreML is listed on Apple platforms.
+pub fn available() -> Vec<Provider> {
+    static AVAILABLE: OnceLock<Vec<Provider>> = OnceLock::new();
+    AVAILABLE
+        .get_or_init(|| {
+            [
+                Provider::Cpu,
+                Provider::Cuda,
+                Provider::CoreMl,
+                Provider::DirectMl,
+            ]
+            .into_iter()
+            .filter(probe)
+            .collect()
+        })
+        .clone()
+}
+
+/// Whether `provider` can be used on this machine. Providers that can't be probed,
+/// XNNPACK and custom ones, are assumed to be available.
+pub fn is_available(provider: &Provider) -> bool {
+    match provider {
+        Provider::Cpu | Provider::Cuda | Provider::CoreMl | Provider::DirectMl => {
+            available().contains(provider)
+        }
+        Provider::Xnnpack | Provider::Custom(_) => provider.ensure_supported().is_ok(),
+    }
+}
+
+fn probe(provider: &Provider) -> bool {
+    if provider.ensure_supported().is_err() {
+        return false;
+    }
+    match provider {
+        Provider::Cuda => can_load(CUDA_RUNTIMES),
+        Provider::DirectMl => can_load(DIRECTML_RUNTIMES),
+        _ => true,
+    }
+}
+
+#[cfg(any(feature = "cuda", feature = "directml"))]
+fn can_load(names: &[&str]) -> bool {
+    names
+        .iter()
+        // Loading only maps the library, it is unloaded again when dropped
+        .any(|name| unsafe { libloading::Library::new(name) }.is_ok())
+}
+
+#[cfg(not(any(feature = "cuda", feature = "directml")))]
+fn can_load(_names: &[&str]) -> bool {
+    false
+}
+
+/// Run `create` with the requested provider, or the default one, following `policy` if
+/// the provider is unavailable or `create` fails with it.
+pub(crate) fn create_with_fallback<T, F>(
+    requested: Option<Provider>,
+    policy: FallbackPolicy,
+    mut create: F,
+) -> Result<(T, ProviderSelection)>
+where
+    F: FnMut(Provider) -> Result<T>,
+{
+    let requested = requested.unwrap_or_else(get_default_provider);
+    let attempt = match requested.ensure_supported() {
+        Err(err) => Err(err),
+        Ok(()) if !is_available(&requested) => Err(Error::unsupported(format!(
+            "provider {} on this machine",
+            requested
+        ))),
+        Ok(()) => create(requested.clone()),
+    };
+    match attempt {
+        Ok(value) => {
+            debug_event!(provider = %requested, "created");
+            Ok((
+                value,
+                ProviderSelection {
+                    provider: requested,
+                    warning: None,
+                },
+            ))
+        }
+        Err(err) if policy == FallbackPolicy::FallbackToCpu && requested != Provider::Cpu => {
+            let warning = format!("{}, falling back to cpu", err);
+            tracing::warn!("{}", warning);
+            let value = create(Provider::Cpu)?;
+            debug_event!(provider = %Provider::Cpu, "created");
+            Ok((
+                value,
+                ProviderSelection {
+                    provider: Provider::Cpu,
+                    warning: Some(warning),
+                },
+            ))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_threads_follow_available_parallelism() {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get() as u32);
+        assert_eq!(Threads::Auto.count(), cores);
+        assert_eq!(Threads::Auto.to_raw(), cores as i32);
+    }
+
+    #[test]
+    fn threads_default_to_one() {
+        assert_eq!(Threads::default(), Threads::ONE);
+        assert_eq!(Threads::default().to_raw(), 1);
+    }
+
+    #[test]
+    fn parse_threads() {
+        assert_eq!("auto".parse::<Threads>().unwrap(), Threads::Auto);
+        assert_eq!(
+            " 4 ".parse::<Threads>().unwrap(),
+            Threads::fixed(4).unwrap()
+        );
+        for bad in ["0", "-1", "many"] {
+            let err = bad.parse::<Threads>().unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+        }
+    }
+}
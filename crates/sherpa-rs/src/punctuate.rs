@@ -1,70 +1,497 @@
-use eyre::{bail, Result};
-
 use crate::{
     get_default_provider,
+    handle::NativeHandle,
+    online::{OnlineRecognizerResult, ResultState},
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
     utils::{cstr_to_string, cstring_from_str},
+    Error, FallbackPolicy, Provider, Result, Threads,
 };
 
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct PunctuationConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
     pub debug: bool,
-    pub num_threads: Option<i32>,
-    pub provider: Option<String>,
+    pub num_threads: Threads,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    /// Longest input sent to the model at once, in words, counting each CJK character
+    /// as a word. Longer text is split and joined back. Defaults to 200.
+    pub max_chunk_len: Option<usize>,
 }
 
+const DEFAULT_MAX_CHUNK_LEN: usize = 200;
+
 pub struct Punctuation {
-    audio_punctuation: *const sherpa_rs_sys::SherpaOnnxOfflinePunctuation,
+    audio_punctuation: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflinePunctuation>,
+    max_chunk_len: usize,
+    provider: ProviderSelection,
 }
 
 impl Punctuation {
     pub fn new(config: PunctuationConfig) -> Result<Self> {
+        let _timed = timed!("punctuation.create");
+        debug_event!(?config, "creating punctuation");
+        // TODO: sherpa-onnx/issues/1448
+        let requested = config
+            .provider
+            .clone()
+            .or_else(|| cfg!(target_os = "macos").then_some(Provider::Cpu));
+        let (mut punctuation, provider) =
+            create_with_fallback(requested, config.fallback, |provider| {
+                Self::create(PunctuationConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        punctuation.provider = provider;
+        Ok(punctuation)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    fn create(config: PunctuationConfig) -> Result<Self> {
+        if config.model.is_empty() {
+            return Err(Error::invalid_input("punctuation model path is empty"));
+        }
+        if !std::path::Path::new(&config.model).exists() {
+            return Err(Error::model_not_found("punctuation model", &config.model));
+        }
+        let max_chunk_len = config.max_chunk_len.unwrap_or(DEFAULT_MAX_CHUNK_LEN).max(1);
         let model = cstring_from_str(&config.model);
-        let provider = cstring_from_str(&config.provider.unwrap_or(if cfg!(target_os = "macos") {
-            // TODO: sherpa-onnx/issues/1448
-            "cpu".into()
-        } else {
-            get_default_provider()
-        }));
+        let provider = config
+            .provider
+            .unwrap_or_else(get_default_provider)
+            .to_cstring()?;
 
         let sherpa_config = sherpa_rs_sys::SherpaOnnxOfflinePunctuationConfig {
             model: sherpa_rs_sys::SherpaOnnxOfflinePunctuationModelConfig {
                 ct_transformer: model.as_ptr(),
-                num_threads: config.num_threads.unwrap_or(1),
+                num_threads: config.num_threads.to_raw(),
                 debug: config.debug.into(),
                 provider: provider.as_ptr(),
             },
         };
-        let audio_punctuation =
-            unsafe { sherpa_rs_sys::SherpaOnnxCreateOfflinePunctuation(&sherpa_config) };
+        let audio_punctuation = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflinePunctuation(&sherpa_config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflinePunctuation,
+            )
+        }
+        .ok_or_else(|| Error::native_creation_failed("punctuation"))?;
+        Ok(Self {
+            audio_punctuation,
+            max_chunk_len,
+            provider: ProviderSelection::default(),
+        })
+    }
 
-        if audio_punctuation.is_null() {
-            bail!("Failed to create audio punctuation");
+    pub fn add_punctuation(&mut self, text: &str) -> Result<String> {
+        let _timed = timed!("punctuation.add", chars = text.chars().count());
+        let mut out = String::new();
+        for chunk in split_chunks(text, self.max_chunk_len) {
+            let punctuated = self.punctuate_chunk(&chunk)?;
+            join_chunk(&mut out, punctuated.trim());
         }
-        Ok(Self { audio_punctuation })
+        Ok(out)
     }
 
-    pub fn add_punctuation(&mut self, text: &str) -> String {
+    pub fn add_punctuation_batch(&mut self, texts: &[&str]) -> Result<Vec<String>> {
+        texts
+            .iter()
+            .map(|text| self.add_punctuation(text))
+            .collect()
+    }
+
+    fn punctuate_chunk(&mut self, text: &str) -> Result<String> {
         let text = cstring_from_str(text);
         unsafe {
             let text_with_punct_ptr = sherpa_rs_sys::SherpaOfflinePunctuationAddPunct(
-                self.audio_punctuation,
+                self.audio_punctuation.as_ptr(),
                 text.as_ptr(),
             );
+            if text_with_punct_ptr.is_null() {
+                return Err(Error::processing_failed("punctuation", "no text returned"));
+            }
             let text_with_punct = cstr_to_string(text_with_punct_ptr as _);
             sherpa_rs_sys::SherpaOfflinePunctuationFreeText(text_with_punct_ptr);
-            text_with_punct
+            Ok(text_with_punct)
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct OnlinePunctuationConfig {
+    /// CNN-BiLSTM model.
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+    pub model: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
+    pub bpe_vocab: String,
+    pub debug: bool,
+    pub num_threads: Threads,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    /// Number of trailing words whose punctuation may still change. Defaults to 20.
+    pub context_words: Option<usize>,
+}
+
+const DEFAULT_CONTEXT_WORDS: usize = 20;
+
+/// Punctuates text as it streams in, for live captions.
+///
+/// Only the last `context_words` words are re-punctuated on each update, everything
+/// before them is final.
+pub struct OnlinePunctuation {
+    punctuation: NativeHandle<sherpa_rs_sys::SherpaOnnxOnlinePunctuation>,
+    context_words: usize,
+    /// Raw words of the current utterance.
+    words: Vec<String>,
+    /// Punctuated words that won't change, one per word of `words`.
+    stable: Vec<String>,
+    /// Punctuated words after `stable`.
+    tail: Vec<String>,
+    provider: ProviderSelection,
+}
+
+impl OnlinePunctuation {
+    pub fn new(config: OnlinePunctuationConfig) -> Result<Self> {
+        let (mut punctuation, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(OnlinePunctuationConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        punctuation.provider = provider;
+        Ok(punctuation)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    fn create(config: OnlinePunctuationConfig) -> Result<Self> {
+        for (name, path) in [("model", &config.model), ("bpe_vocab", &config.bpe_vocab)] {
+            if path.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "online punctuation {} path is empty",
+                    name
+                )));
+            }
+            if !std::path::Path::new(path).exists() {
+                return Err(Error::model_not_found(
+                    format!("online punctuation {}", name),
+                    path,
+                ));
+            }
+        }
+        let model = cstring_from_str(&config.model);
+        let bpe_vocab = cstring_from_str(&config.bpe_vocab);
+        let provider = config
+            .provider
+            .unwrap_or_else(get_default_provider)
+            .to_cstring()?;
+
+        let sherpa_config = sherpa_rs_sys::SherpaOnnxOnlinePunctuationConfig {
+            model: sherpa_rs_sys::SherpaOnnxOnlinePunctuationModelConfig {
+                cnn_bilstm: model.as_ptr(),
+                bpe_vocab: bpe_vocab.as_ptr(),
+                num_threads: config.num_threads.to_raw(),
+                debug: config.debug.into(),
+                provider: provider.as_ptr(),
+            },
+        };
+        let punctuation = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOnlinePunctuation(&sherpa_config),
+                sherpa_rs_sys::SherpaOnnxDestroyOnlinePunctuation,
+            )
+        }
+        .ok_or_else(|| Error::native_creation_failed("online punctuation"))?;
+        Ok(Self {
+            punctuation,
+            context_words: config.context_words.unwrap_or(DEFAULT_CONTEXT_WORDS).max(1),
+            words: Vec::new(),
+            stable: Vec::new(),
+            tail: Vec::new(),
+            provider: ProviderSelection::default(),
+        })
+    }
+
+    /// Append newly recognized words, returning the punctuated text so far.
+    pub fn push(&mut self, new_words: &str) -> Result<String> {
+        let words: Vec<String> = self
+            .words
+            .iter()
+            .map(String::as_str)
+            .chain(new_words.split_whitespace())
+            .map(str::to_string)
+            .collect();
+        self.set_words(words)?;
+        Ok(self.text())
+    }
+
+    /// Replace the text of the current utterance, e.g. with a newer partial result.
+    ///
+    /// Words the recognizer revised inside the stable part are punctuated again.
+    pub fn update(&mut self, text: &str) -> Result<String> {
+        let words = text.split_whitespace().map(str::to_string).collect();
+        self.set_words(words)?;
+        Ok(self.text())
+    }
+
+    /// Punctuate an online recognizer result. A final result also finishes the
+    /// utterance, so the next result starts a new one.
+    pub fn update_result(&mut self, result: &OnlineRecognizerResult) -> Result<String> {
+        self.update(&result.text)?;
+        if result.state == ResultState::Final {
+            let text = self.finalize()?;
+            self.reset();
+            return Ok(text);
+        }
+        Ok(self.text())
+    }
+
+    /// Punctuate the whole remaining context and make it final.
+    pub fn finalize(&mut self) -> Result<String> {
+        self.repunctuate()?;
+        self.stable.append(&mut self.tail);
+        Ok(self.text())
+    }
+
+    /// Start a new utterance.
+    pub fn reset(&mut self) {
+        self.words.clear();
+        self.stable.clear();
+        self.tail.clear();
+    }
+
+    /// Punctuated text of the current utterance.
+    pub fn text(&self) -> String {
+        let mut text = self.stable.join(" ");
+        if !self.tail.is_empty() {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(&self.tail.join(" "));
         }
+        text
+    }
+
+    fn set_words(&mut self, words: Vec<String>) -> Result<()> {
+        let common = self
+            .words
+            .iter()
+            .zip(&words)
+            .take_while(|(a, b)| a == b)
+            .count();
+        self.stable.truncate(common);
+        self.words = words;
+        self.repunctuate()?;
+
+        let excess = self.tail.len().saturating_sub(self.context_words);
+        self.stable.extend(self.tail.drain(..excess));
+        Ok(())
+    }
+
+    /// Punctuate the words after `stable`, with some stable words before them as context
+    /// so they aren't treated as the start of a sentence.
+    fn repunctuate(&mut self) -> Result<()> {
+        let stable = self.stable.len();
+        let start = stable.saturating_sub(self.context_words);
+        if stable == self.words.len() {
+            self.tail.clear();
+            return Ok(());
+        }
+        let output = self.punctuate(&self.words[start..].join(" "))?;
+        let output: Vec<&str> = output.split_whitespace().collect();
+        self.tail = if output.len() == self.words.len() - start {
+            output[stable - start..]
+                .iter()
+                .map(|w| w.to_string())
+                .collect()
+        } else {
+            // Can't line the output up with the input words, keep them as they are
+            self.words[stable..].to_vec()
+        };
+        Ok(())
     }
+
+    fn punctuate(&self, text: &str) -> Result<String> {
+        let text = cstring_from_str(text);
+        unsafe {
+            let ptr = sherpa_rs_sys::SherpaOnnxOnlinePunctuationAddPunct(
+                self.punctuation.as_ptr(),
+                text.as_ptr(),
+            );
+            if ptr.is_null() {
+                return Err(Error::processing_failed("punctuation", "no text returned"));
+            }
+            let punctuated = cstr_to_string(ptr as _);
+            sherpa_rs_sys::SherpaOnnxOnlinePunctuationFreeText(ptr);
+            Ok(punctuated)
+        }
+    }
+}
+
+unsafe impl Send for OnlinePunctuation {}
+unsafe impl Sync for OnlinePunctuation {}
+
+/// CJK text has no spaces, so each character counts as a word.
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF // Kana
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xAC00..=0xD7AF // Hangul
+        | 0xF900..=0xFAFF
+        | 0xFF00..=0xFFEF // Full width forms
+        | 0x3000..=0x303F // CJK punctuation
+        | 0x20000..=0x2FA1F)
+}
+
+fn word_len(word: &str) -> usize {
+    let cjk = word.chars().filter(|&c| is_cjk(c)).count();
+    let other = word.split(is_cjk).filter(|w| !w.is_empty()).count();
+    (cjk + other).max(1)
+}
+
+/// Split `text` into pieces of at most `max_len` words, at spaces where possible.
+///
+/// A run of CJK characters without spaces is split between characters, never inside one.
+fn split_chunks(text: &str, max_len: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for word in text.split_whitespace() {
+        let len = word_len(word);
+        if current_len + len > max_len && !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        if len <= max_len {
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+            current_len += len;
+            continue;
+        }
+
+        // Longer than a whole chunk on its own, count every character
+        for c in word.chars() {
+            if current_len >= max_len {
+                chunks.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+            current.push(c);
+            current_len += 1;
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Append a punctuated chunk, with a space only between non-CJK text.
+fn join_chunk(out: &mut String, chunk: &str) {
+    let needs_space = match (out.chars().last(), chunk.chars().next()) {
+        (Some(prev), Some(next)) => !(is_cjk(prev) && is_cjk(next)),
+        _ => false,
+    };
+    if needs_space {
+        out.push(' ');
+    }
+    out.push_str(chunk);
 }
 
 unsafe impl Send for Punctuation {}
 unsafe impl Sync for Punctuation {}
 
-impl Drop for Punctuation {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflinePunctuation(self.audio_punctuation);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejoin(chunks: &[String]) -> String {
+        let mut out = String::new();
+        for chunk in chunks {
+            join_chunk(&mut out, chunk);
         }
+        out
+    }
+
+    #[test]
+    fn splits_at_spaces() {
+        assert_eq!(split_chunks("a b c d e", 2), ["a b", "c d", "e"]);
+        assert_eq!(split_chunks("  a   b  ", 5), ["a b"]);
+        assert!(split_chunks("", 5).is_empty());
+    }
+
+    #[test]
+    fn splits_long_cjk_runs_between_characters() {
+        let text = "今天天气很好我们去公园";
+        let chunks = split_chunks(text, 4);
+        assert_eq!(chunks, ["今天天气", "很好我们", "去公园"]);
+        assert_eq!(rejoin(&chunks), text);
+    }
+
+    #[test]
+    fn counts_mixed_scripts() {
+        // A CJK character counts as a word, and so does each latin run next to them
+        assert_eq!(word_len("GPU加速"), 3);
+        assert_eq!(
+            split_chunks("hello 世界 world 你好吗", 3),
+            ["hello 世界", "world", "你好吗"]
+        );
+        assert_eq!(
+            rejoin(&split_chunks("hello 世界 world 你好吗", 3)),
+            "hello 世界 world 你好吗"
+        );
+    }
+
+    #[test]
+    fn never_splits_inside_a_character() {
+        // Four-byte CJK, and an emoji in a CJK run
+        let chunks = split_chunks("𠀀𠀁𠀂𠀃𠀄", 2);
+        assert_eq!(chunks, ["𠀀𠀁", "𠀂𠀃", "𠀄"]);
+
+        let chunks = split_chunks("中文😀文字", 2);
+        assert_eq!(chunks, ["中文", "😀文", "字"]);
+        assert_eq!(chunks.concat(), "中文😀文字");
+    }
+
+    #[test]
+    fn joins_with_spaces_only_outside_cjk() {
+        let mut out = String::new();
+        join_chunk(&mut out, "今天。");
+        assert_eq!(out, "今天。");
+        join_chunk(&mut out, "明天好。");
+        assert_eq!(out, "今天。明天好。");
+        join_chunk(&mut out, "Then,");
+        assert_eq!(out, "今天。明天好。 Then,");
+        join_chunk(&mut out, "we left.");
+        assert_eq!(out, "今天。明天好。 Then, we left.");
+        join_chunk(&mut out, "");
+        assert_eq!(out, "今天。明天好。 Then, we left.");
     }
 }
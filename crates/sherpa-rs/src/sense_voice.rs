@@ -1,23 +1,44 @@
-use crate::{get_default_provider, utils::cstring_from_str};
-use eyre::{bail, Result};
-use std::mem;
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    itn::ItnConfig,
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{cstring_from_str, time_warm_up, warm_up_silence, WARM_UP_SAMPLE_RATE},
+    Error, FallbackPolicy, Provider, Result, Threads,
+};
+use std::{mem, path::Path, time::Duration};
 
 #[derive(Debug)]
 pub struct SenseVoiceRecognizer {
-    recognizer: *const sherpa_rs_sys::SherpaOnnxOfflineRecognizer,
+    recognizer: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineRecognizer>,
+    keep_raw_text: bool,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
 }
 
 pub type SenseVoiceRecognizerResult = super::OfflineRecognizerResult;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SenseVoiceConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
     pub language: String,
     pub use_itn: bool,
-    pub provider: Option<String>,
-    pub num_threads: Option<i32>,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub num_threads: Threads,
     pub debug: bool,
+    /// Inverse text normalization and homophone replacement applied to the text.
+    pub itn: ItnConfig,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
+    /// Leave `<|...|>` tags in the result text instead of stripping them.
+    /// They are parsed into the result fields either way.
+    pub keep_raw_text: bool,
 }
 
 impl Default for SenseVoiceConfig {
@@ -27,19 +48,85 @@ impl Default for SenseVoiceConfig {
             language: "auto".into(),
             use_itn: true,
             provider: None,
-            num_threads: Some(1),
+            fallback: FallbackPolicy::default(),
+            num_threads: Threads::ONE,
             debug: false,
+            itn: ItnConfig::default(),
             tokens: String::new(),
+            keep_raw_text: false,
         }
     }
 }
 
 impl SenseVoiceRecognizer {
+    /// Like [`Self::new`], with the model and tokens found in `dir`, an unpacked
+    /// sherpa-onnx release.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: SenseVoiceConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::SenseVoice,
+            precision,
+            &config.provider.clone().unwrap_or_else(get_default_provider),
+        )?;
+        Self::new(SenseVoiceConfig {
+            model: dir.file(Role::Model),
+            tokens: dir.file(Role::Tokens),
+            ..config
+        })
+    }
+
     pub fn new(config: SenseVoiceConfig) -> Result<Self> {
+        let _timed = timed!("sense_voice.create");
+        debug_event!(?config, "creating sense voice recognizer");
+        let (mut recognizer, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(SenseVoiceConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        recognizer.provider = provider;
+        Ok(recognizer)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model files.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Transcribe half a second of silence and discard it, so the first real
+    /// [`Self::transcribe`] doesn't pay for ONNX Runtime's one-time setup. Returns how long
+    /// it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("sense_voice.warm_up");
+        time_warm_up(|| {
+            self.transcribe(WARM_UP_SAMPLE_RATE, &warm_up_silence(WARM_UP_SAMPLE_RATE));
+            Ok(())
+        })
+    }
+
+    fn create(config: SenseVoiceConfig) -> Result<Self> {
+        let precision = ModelPrecision::of_file(&config.model);
+        let keep_raw_text = config.keep_raw_text;
         let debug = config.debug.into();
-        let provider = config.provider.unwrap_or(get_default_provider());
-        let provider_ptr = cstring_from_str(&provider);
-        let num_threads = config.num_threads.unwrap_or(1);
+        let itn = config.itn.to_raw()?;
+        let provider = config.provider.unwrap_or_else(get_default_provider);
+        let provider_ptr = provider.to_cstring()?;
+        let num_threads = config.num_threads.to_raw();
 
         // SenseVoice specific config
         let model_ptr = cstring_from_str(&config.model);
@@ -97,49 +184,185 @@ impl SenseVoiceRecognizer {
                 },
                 max_active_paths: 0,
                 model_config,
-                rule_fars: mem::zeroed::<_>(),
-                rule_fsts: mem::zeroed::<_>(),
+                rule_fars: itn.rule_fars(),
+                rule_fsts: itn.rule_fsts(),
                 blank_penalty: 0.0,
-                hr: mem::zeroed::<_>(),
+                hr: itn.homophone_replacer(),
             }
         };
 
-        let recognizer = unsafe { sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&config) };
-        if recognizer.is_null() {
-            bail!("Failed to create recognizer");
+        let recognizer = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer,
+            )
         }
+        .ok_or_else(|| Error::native_creation_failed("sense voice recognizer"))?;
 
-        Ok(Self { recognizer })
+        Ok(Self {
+            recognizer,
+            keep_raw_text,
+            provider: ProviderSelection::default(),
+            precision,
+        })
     }
 
     pub fn transcribe(&mut self, sample_rate: u32, samples: &[f32]) -> SenseVoiceRecognizerResult {
+        let _timed = timed!(
+            "sense_voice.transcribe",
+            sample_rate,
+            samples = samples.len()
+        );
         unsafe {
-            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer);
+            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer.as_ptr());
             sherpa_rs_sys::SherpaOnnxAcceptWaveformOffline(
                 stream,
                 sample_rate as i32,
                 samples.as_ptr(),
                 samples.len().try_into().unwrap(),
             );
-            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer, stream);
+            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer.as_ptr(), stream);
             let result_ptr = sherpa_rs_sys::SherpaOnnxGetOfflineStreamResult(stream);
             let raw_result = result_ptr.read();
-            let result = SenseVoiceRecognizerResult::new(&raw_result);
+            let mut result = SenseVoiceRecognizerResult::new(&raw_result);
+            apply_tags(&mut result, self.keep_raw_text);
             // Free resources
             sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizerResult(result_ptr);
             sherpa_rs_sys::SherpaOnnxDestroyOfflineStream(stream);
             result
         }
     }
+
+    /// Decode several clips of `(samples, sample_rate)` in one native batch call.
+    ///
+    /// Results are in input order, an empty batch returns an empty Vec.
+    pub fn transcribe_batch(
+        &mut self,
+        inputs: &[(&[f32], u32)],
+    ) -> Vec<Result<SenseVoiceRecognizerResult>> {
+        let mut results = crate::decode_offline_batch(self.recognizer.as_ptr(), inputs);
+        for result in results.iter_mut().flatten() {
+            apply_tags(result, self.keep_raw_text);
+        }
+        results
+    }
+}
+
+const EMOTIONS: &[&str] = &[
+    "HAPPY",
+    "SAD",
+    "ANGRY",
+    "NEUTRAL",
+    "FEARFUL",
+    "DISGUSTED",
+    "SURPRISED",
+];
+
+/// Move inline SenseVoice tags such as `<|en|><|HAPPY|><|BGM|><|withitn|>` into the result fields.
+///
+/// Fields already filled from the native result are kept.
+fn apply_tags(result: &mut SenseVoiceRecognizerResult, keep_raw_text: bool) {
+    let mut text = String::with_capacity(result.text.len());
+    let mut rest = result.text.as_str();
+    while let Some(start) = rest.find("<|") {
+        let Some(len) = rest[start..].find("|>") else {
+            break;
+        };
+        text.push_str(&rest[..start]);
+        let tag = &rest[start + 2..start + len];
+        rest = &rest[start + len + 2..];
+
+        match tag {
+            "withitn" | "woitn" | "EMO_UNKNOWN" | "Event_UNK" | "" => {}
+            _ if EMOTIONS.contains(&tag) => {
+                result.emotion.get_or_insert_with(|| tag.to_string());
+            }
+            _ if tag.chars().all(|c| c.is_ascii_lowercase()) => {
+                result.language.get_or_insert_with(|| tag.to_string());
+            }
+            _ => {
+                if !result.events.iter().any(|e| e == tag) {
+                    result.events.push(tag.to_string());
+                }
+            }
+        }
+    }
+    text.push_str(rest);
+
+    if !keep_raw_text {
+        result.text = text.trim().to_string();
+    }
 }
 
 unsafe impl Send for SenseVoiceRecognizer {}
 unsafe impl Sync for SenseVoiceRecognizer {}
 
-impl Drop for SenseVoiceRecognizer {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer(self.recognizer);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged(text: &str, keep_raw_text: bool) -> SenseVoiceRecognizerResult {
+        let mut result = SenseVoiceRecognizerResult {
+            lang: String::new(),
+            text: text.to_string(),
+            timestamps: Vec::new(),
+            durations: Vec::new(),
+            tokens: Vec::new(),
+            language: None,
+            emotion: None,
+            events: Vec::new(),
+            confidence: None,
+            raw_json: None,
+        };
+        apply_tags(&mut result, keep_raw_text);
+        result
+    }
+
+    #[test]
+    fn known_tags_fill_fields() {
+        let result = tagged("<|en|><|HAPPY|><|BGM|><|withitn|>Hello there.", false);
+        assert_eq!(result.text, "Hello there.");
+        assert_eq!(result.language.as_deref(), Some("en"));
+        assert_eq!(result.emotion.as_deref(), Some("HAPPY"));
+        assert_eq!(result.events, ["BGM"]);
+    }
+
+    #[test]
+    fn placeholder_tags_are_dropped() {
+        let result = tagged("<|zh|><|EMO_UNKNOWN|><|Event_UNK|><|woitn|><||>你好", false);
+        assert_eq!(result.text, "你好");
+        assert_eq!(result.language.as_deref(), Some("zh"));
+        assert_eq!(result.emotion, None);
+        assert!(result.events.is_empty());
+    }
+
+    #[test]
+    fn unknown_tags_become_events_once() {
+        let result = tagged("<|Speech|><|Cough|> one <|Cough|>two", false);
+        assert_eq!(result.text, "one two");
+        assert_eq!(result.events, ["Speech", "Cough"]);
+        assert_eq!(result.language, None);
+    }
+
+    #[test]
+    fn text_without_tags_is_unchanged() {
+        let result = tagged("plain text, a < b | c", false);
+        assert_eq!(result.text, "plain text, a < b | c");
+        assert_eq!(result.language, None);
+        assert_eq!(result.emotion, None);
+        assert!(result.events.is_empty());
+
+        // An unclosed tag stays text
+        assert_eq!(tagged("<|en|>hi <|en", false).text, "hi <|en");
+    }
+
+    #[test]
+    fn raw_text_keeps_tags() {
+        let raw = "<|en|><|SAD|><|Speech|><|woitn|>bye";
+        let result = tagged(raw, true);
+        assert_eq!(result.text, raw);
+        assert_eq!(result.language.as_deref(), Some("en"));
+        assert_eq!(result.emotion.as_deref(), Some("SAD"));
+        assert_eq!(result.events, ["Speech"]);
     }
 }
@@ -1,15 +1,25 @@
 use std::mem;
 
-use crate::{get_default_provider, utils::cstring_from_str};
-use eyre::Result;
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::cstring_from_str,
+    Error, FallbackPolicy, Provider, Result, Threads,
+};
 
 #[derive(Debug)]
 pub struct SileroVad {
-    pub(crate) vad: *const sherpa_rs_sys::SherpaOnnxVoiceActivityDetector,
+    pub(crate) vad: NativeHandle<sherpa_rs_sys::SherpaOnnxVoiceActivityDetector>,
+    provider: ProviderSelection,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SileroVadConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
     pub min_silence_duration: f32,
     pub min_speech_duration: f32,
@@ -17,8 +27,9 @@ pub struct SileroVadConfig {
     pub threshold: f32,
     pub sample_rate: u32,
     pub window_size: i32,
-    pub provider: Option<String>,
-    pub num_threads: Option<i32>,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub num_threads: Threads,
     pub debug: bool,
 }
 
@@ -33,7 +44,8 @@ impl Default for SileroVadConfig {
             sample_rate: 16000,
             window_size: 512,
             provider: None,
-            num_threads: Some(1),
+            fallback: FallbackPolicy::default(),
+            num_threads: Threads::ONE,
             debug: false,
         }
     }
@@ -47,11 +59,38 @@ pub struct SpeechSegment {
 
 impl SileroVad {
     pub fn new(config: SileroVadConfig, buffer_size_in_seconds: f32) -> Result<Self> {
-        let provider = config.provider.unwrap_or(get_default_provider());
+        let _timed = timed!("silero_vad.create");
+        debug_event!(?config, "creating silero vad");
+        let (mut vad, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(
+                    SileroVadConfig {
+                        provider: Some(provider),
+                        ..config.clone()
+                    },
+                    buffer_size_in_seconds,
+                )
+            })?;
+        vad.provider = provider;
+        Ok(vad)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    fn create(config: SileroVadConfig, buffer_size_in_seconds: f32) -> Result<Self> {
+        let provider = config.provider.unwrap_or_else(get_default_provider);
 
         let model = cstring_from_str(&config.model);
         // let ten_model = cstring_from_str(&config.ten_model);
-        let provider = cstring_from_str(&provider);
+        let provider = provider.to_cstring()?;
 
         let silero_vad = sherpa_rs_sys::SherpaOnnxSileroVadModelConfig {
             model: model.as_ptr(),
@@ -66,30 +105,38 @@ impl SileroVad {
             sherpa_rs_sys::SherpaOnnxVadModelConfig {
                 debug,
                 provider: provider.as_ptr(),
-                num_threads: config.num_threads.unwrap_or(1),
+                num_threads: config.num_threads.to_raw(),
                 sample_rate: config.sample_rate as i32,
                 silero_vad,
                 ten_vad: mem::zeroed::<_>(),
             }
         };
 
-        unsafe {
-            let vad = sherpa_rs_sys::SherpaOnnxCreateVoiceActivityDetector(
-                &vad_config,
-                buffer_size_in_seconds,
-            );
-
-            Ok(Self { vad })
+        let vad = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateVoiceActivityDetector(
+                    &vad_config,
+                    buffer_size_in_seconds,
+                ),
+                sherpa_rs_sys::SherpaOnnxDestroyVoiceActivityDetector,
+            )
         }
+        .ok_or_else(|| Error::native_creation_failed("silero vad"))?;
+
+        Ok(Self {
+            vad,
+            provider: ProviderSelection::default(),
+        })
     }
 
     pub fn is_empty(&mut self) -> bool {
-        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorEmpty(self.vad) == 1 }
+        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorEmpty(self.vad.as_ptr()) == 1 }
     }
 
     pub fn front(&mut self) -> SpeechSegment {
         unsafe {
-            let segment_ptr = sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorFront(self.vad);
+            let segment_ptr =
+                sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorFront(self.vad.as_ptr());
             let raw_segment = segment_ptr.read();
             let samples: &[f32] =
                 std::slice::from_raw_parts(raw_segment.samples, raw_segment.n as usize);
@@ -108,7 +155,7 @@ impl SileroVad {
 
     pub fn flush(&mut self) {
         unsafe {
-            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorFlush(self.vad);
+            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorFlush(self.vad.as_ptr());
         }
     }
 
@@ -117,7 +164,7 @@ impl SileroVad {
         let samples_length = samples.len();
         unsafe {
             sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorAcceptWaveform(
-                self.vad,
+                self.vad.as_ptr(),
                 samples_ptr,
                 samples_length.try_into().unwrap(),
             );
@@ -126,28 +173,28 @@ impl SileroVad {
 
     pub fn pop(&mut self) {
         unsafe {
-            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorPop(self.vad);
+            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorPop(self.vad.as_ptr());
         }
     }
 
     pub fn is_speech(&mut self) -> bool {
-        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorDetected(self.vad) == 1 }
+        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorDetected(self.vad.as_ptr()) == 1 }
     }
 
     pub fn clear(&mut self) {
         unsafe {
-            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorClear(self.vad);
+            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorClear(self.vad.as_ptr());
         }
     }
-}
 
-unsafe impl Send for SileroVad {}
-unsafe impl Sync for SileroVad {}
-
-impl Drop for SileroVad {
-    fn drop(&mut self) {
+    /// Clear everything and restart sample indices at zero. [`Self::clear`] only drops
+    /// the queued segments, so `start` keeps counting from the audio fed before it.
+    pub fn reset(&mut self) {
         unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyVoiceActivityDetector(self.vad);
+            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorReset(self.vad.as_ptr());
         }
     }
 }
+
+unsafe impl Send for SileroVad {}
+unsafe impl Sync for SileroVad {}
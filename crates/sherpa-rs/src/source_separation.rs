@@ -1,42 +1,508 @@
-use crate::{get_default_provider, utils::cstring_from_str};
-use eyre::{bail, Result};
-use std::path::Path;
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{
+        cstring_from_str, pcm16_to_f32, read_wav, resample_interleaved, resample_interleaved_into,
+        time_warm_up, warm_up_silence, AudioSource, WavData,
+    },
+    CancellationToken, Error, FallbackPolicy, Provider, Result, Threads,
+};
+use std::{
+    mem,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{mpsc, Arc, Barrier, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Window length used by [`SourceSeparation::process_with_progress`].
+pub const PROGRESS_CHUNK_SECS: f32 = 30.0;
+/// Overlap between windows used by [`SourceSeparation::process_with_progress`].
+pub const PROGRESS_OVERLAP_SECS: f32 = 1.0;
 
 #[derive(Debug)]
 pub struct SourceSeparation {
-    ss: *const sherpa_rs_sys::SherpaOnnxOfflineSourceSeparation,
+    ss: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineSourceSeparation>,
+    family: ModelFamily,
+    auto_resample: bool,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
+    scratch: Mutex<ResampleScratch>,
+}
+
+/// Buffers [`SourceSeparation::process_into`] keeps between calls when it resamples.
+#[derive(Debug, Default)]
+struct ResampleScratch {
+    /// Input at the model rate.
+    input: Vec<f32>,
+    /// Stems at the model rate.
+    native: SourceSeparationResult,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModelFamily {
+    Spleeter,
+    /// sherpa-onnx returns what the model separates first and the rest of the mix
+    /// second, so the order depends on the model.
+    Uvr {
+        vocals_first: bool,
+    },
+}
+
+/// What a separated stem contains.
+///
+/// The native API doesn't name its outputs, so this is inferred from the configured
+/// model: Spleeter emits vocals first and accompaniment second, UVR starts with
+/// [`UvrModelConfig::primary_stem`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StemKind {
+    Vocals,
+    Accompaniment,
+    Other(String),
 }
 
 #[derive(Debug, Clone)]
 pub struct SeparatedStem {
+    pub kind: StemKind,
     pub samples: Vec<f32>,
     pub sample_rate: i32,
     pub num_channels: i32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct SourceSeparationResult {
     pub stems: Vec<SeparatedStem>,
+    /// Sample rate the model ran at.
+    pub native_sample_rate: i32,
+    /// Sample rate of the returned stems.
+    pub sample_rate: i32,
+}
+
+impl SeparatedStem {
+    /// De-interleave the stem into one buffer per channel.
+    pub fn channels(&self) -> Vec<Vec<f32>> {
+        let num_channels = self.num_channels.max(1) as usize;
+        let mut channels =
+            vec![Vec::with_capacity(self.samples.len() / num_channels); num_channels];
+        for frame in self.samples.chunks_exact(num_channels) {
+            for (channel, &sample) in channels.iter_mut().zip(frame) {
+                channel.push(sample);
+            }
+        }
+        channels
+    }
+
+    /// Write the stem as a 32-bit float WAV.
+    pub fn write_wav<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let spec = hound::WavSpec {
+            channels: self.num_channels as u16,
+            sample_rate: self.sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for &sample in &self.samples {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    /// Largest absolute sample value.
+    pub fn peak(&self) -> f32 {
+        self.samples
+            .iter()
+            .fold(0.0f32, |peak, s| peak.max(s.abs()))
+    }
+
+    /// Root mean square over all channels.
+    pub fn rms(&self) -> f32 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.samples.iter().map(|&s| s as f64 * s as f64).sum();
+        (sum / self.samples.len() as f64).sqrt() as f32
+    }
+
+    /// Whether the stem's RMS level is below `threshold_db` dBFS.
+    pub fn is_silent(&self, threshold_db: f32) -> bool {
+        let rms = self.rms();
+        rms == 0.0 || 20.0 * rms.log10() < threshold_db
+    }
+
+    /// Length of the stem in seconds.
+    pub fn duration_secs(&self) -> f32 {
+        if self.sample_rate <= 0 {
+            return 0.0;
+        }
+        let frames = self.samples.len() / self.num_channels.max(1) as usize;
+        frames as f32 / self.sample_rate as f32
+    }
+}
+
+#[cfg(feature = "playback")]
+impl SeparatedStem {
+    /// Play on the default output device and return once it's done.
+    pub fn play_blocking(&self) -> Result<()> {
+        self.play(None, None)
+    }
+
+    /// Like [`Self::play_blocking`], failing with [`Error::Cancelled`] once `token` is
+    /// cancelled.
+    pub fn play_with_cancel(&self, token: &CancellationToken) -> Result<()> {
+        self.play(None, Some(token))
+    }
+
+    /// Play on the output device named `device_name`, one of
+    /// [`output_devices`](crate::playback::output_devices).
+    pub fn play_with_device(
+        &self,
+        device_name: &str,
+        token: Option<&CancellationToken>,
+    ) -> Result<()> {
+        self.play(Some(device_name), token)
+    }
+
+    fn play(&self, device_name: Option<&str>, token: Option<&CancellationToken>) -> Result<()> {
+        let sample_rate = u32::try_from(self.sample_rate)
+            .map_err(|_| Error::invalid_input("stem has a negative sample rate"))?;
+        let channels = u16::try_from(self.num_channels.max(1))
+            .map_err(|_| Error::invalid_input("stem has too many channels to play"))?;
+        crate::playback::play(&self.samples, sample_rate, channels, device_name, token)
+    }
+}
+
+impl SourceSeparationResult {
+    pub fn vocals(&self) -> Option<&SeparatedStem> {
+        self.stems.iter().find(|s| s.kind == StemKind::Vocals)
+    }
+
+    pub fn accompaniment(&self) -> Option<&SeparatedStem> {
+        self.stems
+            .iter()
+            .find(|s| s.kind == StemKind::Accompaniment)
+    }
+
+    /// Sum every non-vocal stem, e.g. for karaoke. Returns `None` if there is nothing
+    /// but vocals.
+    pub fn instrumental(&self) -> Option<SeparatedStem> {
+        if !self.stems.iter().any(|s| s.kind != StemKind::Vocals) {
+            return None;
+        }
+        let weights: Vec<f32> = self
+            .stems
+            .iter()
+            .map(|s| if s.kind == StemKind::Vocals { 0.0 } else { 1.0 })
+            .collect();
+        let mut stem = self.mix(&weights).ok()?;
+        stem.kind = StemKind::Accompaniment;
+        Some(stem)
+    }
+
+    /// Weighted sum of all stems, one weight per stem.
+    ///
+    /// Stems are truncated to the shortest one, and the mix is scaled down if it would clip.
+    pub fn mix(&self, weights: &[f32]) -> Result<SeparatedStem> {
+        if weights.len() != self.stems.len() {
+            return Err(Error::invalid_input(format!(
+                "Expected {} weights, one per stem, got {}",
+                self.stems.len(),
+                weights.len()
+            )));
+        }
+        let first = match self.stems.first() {
+            Some(first) => first,
+            None => return Err(Error::invalid_input("No stems to mix")),
+        };
+        if self
+            .stems
+            .iter()
+            .any(|s| s.sample_rate != first.sample_rate)
+        {
+            return Err(Error::invalid_input(
+                "Cannot mix stems with different sample rates",
+            ));
+        }
+        if self
+            .stems
+            .iter()
+            .any(|s| s.num_channels != first.num_channels)
+        {
+            return Err(Error::invalid_input(
+                "Cannot mix stems with different numbers of channels",
+            ));
+        }
+
+        let len = self
+            .stems
+            .iter()
+            .map(|s| s.samples.len())
+            .min()
+            .unwrap_or(0);
+        let mut samples = vec![0.0f32; len];
+        for (stem, &weight) in self.stems.iter().zip(weights) {
+            if weight == 0.0 {
+                continue;
+            }
+            for (out, &sample) in samples.iter_mut().zip(&stem.samples) {
+                *out += sample * weight;
+            }
+        }
+
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        if peak > 1.0 {
+            let gain = 1.0 / peak;
+            samples.iter_mut().for_each(|s| *s *= gain);
+        }
+
+        Ok(SeparatedStem {
+            kind: StemKind::Other("mix".into()),
+            samples,
+            sample_rate: first.sample_rate,
+            num_channels: first.num_channels,
+        })
+    }
+}
+
+impl ModelFamily {
+    fn stem_kind(self, index: usize) -> StemKind {
+        match (self, index) {
+            (ModelFamily::Spleeter, 0) | (ModelFamily::Uvr { vocals_first: true }, 0) => {
+                StemKind::Vocals
+            }
+            (ModelFamily::Spleeter, 1) | (ModelFamily::Uvr { vocals_first: true }, 1) => {
+                StemKind::Accompaniment
+            }
+            (
+                ModelFamily::Uvr {
+                    vocals_first: false,
+                },
+                0,
+            ) => StemKind::Accompaniment,
+            (
+                ModelFamily::Uvr {
+                    vocals_first: false,
+                },
+                1,
+            ) => StemKind::Vocals,
+            (ModelFamily::Spleeter, i) => StemKind::Other(format!("spleeter_stem_{}", i)),
+            (ModelFamily::Uvr { .. }, i) => StemKind::Other(format!("uvr_stem_{}", i)),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SpleeterModelConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub vocals: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub accompaniment: String,
 }
 
-#[derive(Debug, Clone)]
+/// UVR (MDX) model settings.
+///
+/// `segment_size`, `overlap` and `denoise` need the `uvr-options` feature. They're
+/// reserved for sherpa-onnx releases that expose them; the pinned C API only takes a
+/// model path, so setting any of them to a non-default value makes construction fail
+/// instead of silently ignoring it.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct UvrModelConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
+    /// Segment length in frames fed to the model per step.
+    #[cfg(feature = "uvr-options")]
+    pub segment_size: Option<i32>,
+    /// Overlap between consecutive segments, in `[0.0, 1.0)`.
+    #[cfg(feature = "uvr-options")]
+    pub overlap: Option<f32>,
+    #[cfg(feature = "uvr-options")]
+    pub denoise: bool,
+    /// The stem the model separates, either [`StemKind::Vocals`] or
+    /// [`StemKind::Accompaniment`]. The other one is what's left of the mix.
+    ///
+    /// `None` goes by the file name: UVR calls its instrumental models `*Inst*`, and
+    /// the rest separate vocals.
+    pub primary_stem: Option<StemKind>,
+}
+
+impl UvrModelConfig {
+    #[cfg(not(feature = "uvr-options"))]
+    fn check_supported(&self) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "uvr-options")]
+    fn check_supported(&self) -> Result<()> {
+        let mut unsupported = Vec::new();
+        if self.segment_size.is_some() {
+            unsupported.push("segment_size");
+        }
+        if self.overlap.is_some() {
+            unsupported.push("overlap");
+        }
+        if self.denoise {
+            unsupported.push("denoise");
+        }
+        if !unsupported.is_empty() {
+            return Err(Error::unsupported(format!(
+                "uvr option(s) {} in the linked sherpa-onnx",
+                unsupported.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    fn vocals_first(&self) -> Result<bool> {
+        match &self.primary_stem {
+            Some(StemKind::Vocals) => Ok(true),
+            Some(StemKind::Accompaniment) => Ok(false),
+            Some(other) => Err(Error::invalid_input(format!(
+                "uvr.primary_stem must be vocals or accompaniment, got {:?}",
+                other
+            ))),
+            None => {
+                let name = Path::new(&self.model)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                Ok(!name.contains("inst"))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SourceSeparationConfig {
     pub spleeter: Option<SpleeterModelConfig>,
     pub uvr: Option<UvrModelConfig>,
-    pub num_threads: i32,
-    pub provider: Option<String>,
+    pub num_threads: Threads,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
     pub debug: bool,
+    /// Resample input that doesn't match the model rate, and the stems back afterwards.
+    pub auto_resample: bool,
+}
+
+impl SourceSeparationConfig {
+    pub fn spleeter<P: AsRef<Path>>(vocals: P, accompaniment: P) -> SourceSeparationConfigBuilder {
+        SourceSeparationConfigBuilder {
+            config: Self {
+                spleeter: Some(SpleeterModelConfig {
+                    vocals: vocals.as_ref().to_string_lossy().into_owned(),
+                    accompaniment: accompaniment.as_ref().to_string_lossy().into_owned(),
+                }),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn uvr<P: AsRef<Path>>(model: P) -> SourceSeparationConfigBuilder {
+        SourceSeparationConfigBuilder {
+            config: Self {
+                uvr: Some(UvrModelConfig {
+                    model: model.as_ref().to_string_lossy().into_owned(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn validate(&self) -> Result<ModelFamily> {
+        match (&self.spleeter, &self.uvr) {
+            (Some(_), Some(_)) => Err(Error::invalid_input(
+                "Both spleeter and uvr models are configured, only one can be used",
+            )),
+            (None, None) => Err(Error::invalid_input(
+                "No model configured, set either spleeter or uvr",
+            )),
+            (Some(spleeter), None) => {
+                if spleeter.vocals.is_empty() {
+                    return Err(Error::invalid_input("spleeter.vocals model path is empty"));
+                }
+                if spleeter.accompaniment.is_empty() {
+                    return Err(Error::invalid_input(
+                        "spleeter.accompaniment model path is empty",
+                    ));
+                }
+                check_model_file("spleeter vocals", &spleeter.vocals)?;
+                check_model_file("spleeter accompaniment", &spleeter.accompaniment)?;
+                Ok(ModelFamily::Spleeter)
+            }
+            (None, Some(uvr)) => {
+                if uvr.model.is_empty() {
+                    return Err(Error::invalid_input("uvr.model path is empty"));
+                }
+                check_model_file("uvr", &uvr.model)?;
+                uvr.check_supported()?;
+                Ok(ModelFamily::Uvr {
+                    vocals_first: uvr.vocals_first()?,
+                })
+            }
+        }
+    }
+}
+
+fn check_model_file(component: &str, path: &str) -> Result<()> {
+    let path = Path::new(path);
+    if !path.exists() {
+        return Err(Error::model_not_found(format!("{} model", component), path));
+    }
+    if !path.is_file() {
+        return Err(Error::invalid_input(format!(
+            "{} model is not a file: {}",
+            component,
+            path.display()
+        )));
+    }
+    std::fs::File::open(path)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceSeparationConfigBuilder {
+    config: SourceSeparationConfig,
+}
+
+impl SourceSeparationConfigBuilder {
+    pub fn num_threads(mut self, num_threads: Threads) -> Self {
+        self.config.num_threads = num_threads;
+        self
+    }
+
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.config.provider = Some(provider);
+        self
+    }
+
+    pub fn fallback(mut self, fallback: FallbackPolicy) -> Self {
+        self.config.fallback = fallback;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.config.debug = debug;
+        self
+    }
+
+    pub fn auto_resample(mut self, auto_resample: bool) -> Self {
+        self.config.auto_resample = auto_resample;
+        self
+    }
+
+    pub fn build(self) -> SourceSeparationConfig {
+        self.config
+    }
 }
 
 impl SourceSeparation {
@@ -57,18 +523,85 @@ impl SourceSeparation {
         let mut cfg = config;
         cfg.uvr = Some(UvrModelConfig {
             model: model.as_ref().to_string_lossy().into_owned(),
+            ..Default::default()
         });
         Self::new(cfg)
     }
 
+    /// Like [`Self::new_spleeter`], with `vocals.onnx` and `accompaniment.onnx` from
+    /// `dir`, an unpacked sherpa-onnx release.
+    pub fn new_spleeter_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: SourceSeparationConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Spleeter,
+            precision,
+            &config.provider.clone().unwrap_or_else(get_default_provider),
+        )?;
+        Self::new_spleeter(
+            dir.file(Role::Vocals),
+            dir.file(Role::Accompaniment),
+            config,
+        )
+    }
+
+    /// Like [`Self::new_uvr`], with the model in `dir`.
+    pub fn new_uvr_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: SourceSeparationConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Uvr,
+            precision,
+            &config.provider.clone().unwrap_or_else(get_default_provider),
+        )?;
+        Self::new_uvr(dir.file(Role::Model), config)
+    }
+
     pub fn new(config: SourceSeparationConfig) -> Result<Self> {
+        let _timed = timed!("source_separation.create");
+        debug_event!(?config, "creating source separation");
+        let (mut separation, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(SourceSeparationConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        separation.provider = provider;
+        Ok(separation)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model files.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    fn create(config: SourceSeparationConfig) -> Result<Self> {
+        let family = config.validate()?;
         let provider = config.provider.unwrap_or_else(get_default_provider);
-        let debug = if config.debug { 1 } else { 0 };
-        let num_threads = if config.num_threads > 0 {
-            config.num_threads
-        } else {
-            1
+        let precision = match (&config.spleeter, &config.uvr) {
+            (Some(spleeter), _) => ModelPrecision::of_file(&spleeter.vocals),
+            (None, Some(uvr)) => ModelPrecision::of_file(&uvr.model),
+            (None, None) => ModelPrecision::Fp32,
         };
+        let debug = if config.debug { 1 } else { 0 };
+        let num_threads = config.num_threads.to_raw();
 
         let (spleeter_vocals, spleeter_accompaniment) = match &config.spleeter {
             Some(s) => (
@@ -83,7 +616,7 @@ impl SourceSeparation {
             None => cstring_from_str(""),
         };
 
-        let provider_cstr = cstring_from_str(&provider);
+        let provider_cstr = provider.to_cstring()?;
 
         let c_config = sherpa_rs_sys::SherpaOnnxOfflineSourceSeparationConfig {
             model: sherpa_rs_sys::SherpaOnnxOfflineSourceSeparationModelConfig {
@@ -100,22 +633,40 @@ impl SourceSeparation {
             },
         };
 
-        let ss =
-            unsafe { sherpa_rs_sys::SherpaOnnxCreateOfflineSourceSeparation(&c_config) };
-
-        if ss.is_null() {
-            bail!("Failed to create source separation instance");
+        let ss = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflineSourceSeparation(&c_config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineSourceSeparation,
+            )
         }
+        .ok_or_else(|| Error::native_creation_failed("source separation"))?;
 
-        Ok(Self { ss })
+        Ok(Self {
+            ss,
+            family,
+            auto_resample: config.auto_resample,
+            provider: ProviderSelection::default(),
+            precision,
+            scratch: Mutex::default(),
+        })
     }
 
     pub fn get_sample_rate(&self) -> i32 {
-        unsafe { sherpa_rs_sys::SherpaOnnxOfflineSourceSeparationGetSampleRate(self.ss) }
+        unsafe { sherpa_rs_sys::SherpaOnnxOfflineSourceSeparationGetSampleRate(self.ss.as_ptr()) }
     }
 
     pub fn get_num_stems(&self) -> i32 {
-        unsafe { sherpa_rs_sys::SherpaOnnxOfflineSourceSeparationGetNumStems(self.ss) }
+        unsafe { sherpa_rs_sys::SherpaOnnxOfflineSourceSeparationGetNumStems(self.ss.as_ptr()) }
+    }
+
+    /// Separate half a second of stereo silence and discard it, so the first real
+    /// [`Self::process`] doesn't pay for ONNX Runtime's one-time setup. Returns how long
+    /// it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("source_separation.warm_up");
+        let sample_rate = self.get_sample_rate();
+        let silence = warm_up_silence(sample_rate.max(0) as u32 * 2);
+        time_warm_up(|| self.process_native(&silence, sample_rate, 2))
     }
 
     pub fn process(
@@ -124,9 +675,115 @@ impl SourceSeparation {
         sample_rate: i32,
         num_channels: i32,
     ) -> Result<SourceSeparationResult> {
+        let mut out = SourceSeparationResult::default();
+        self.process_into(samples, sample_rate, num_channels, &mut out)?;
+        Ok(out)
+    }
+
+    /// Same as [`process`](Self::process) for 16-bit PCM input.
+    pub fn process_i16(
+        &self,
+        samples: &[i16],
+        sample_rate: i32,
+        num_channels: i32,
+    ) -> Result<SourceSeparationResult> {
+        self.process(&pcm16_to_f32(samples), sample_rate, num_channels)
+    }
+
+    /// Like [`process`](Self::process), but writes into `out`, reusing its stem buffers
+    /// instead of allocating new ones. `out.stems` is resized to the number of stems the
+    /// model produced, which is returned.
+    ///
+    /// With `auto_resample`, the input and stems at the model rate go through buffers
+    /// kept in `self`, so those are reused too. A call made while another is using them
+    /// allocates its own.
+    pub fn process_into(
+        &self,
+        samples: &[f32],
+        sample_rate: i32,
+        num_channels: i32,
+        out: &mut SourceSeparationResult,
+    ) -> Result<usize> {
+        let model_rate = self.get_sample_rate();
+        if !self.auto_resample || model_rate <= 0 || sample_rate <= 0 || model_rate == sample_rate {
+            return self.process_native_into(samples, sample_rate, num_channels, out);
+        }
+        check_frames(samples, num_channels)?;
+
+        let mut guard = self.scratch.try_lock().ok();
+        let mut own = ResampleScratch::default();
+        let ResampleScratch { input, native } = guard.as_deref_mut().unwrap_or(&mut own);
+        resample_interleaved_into(
+            samples,
+            num_channels as usize,
+            sample_rate as u32,
+            model_rate as u32,
+            input,
+        );
+        let num_stems = self.process_native_into(input, model_rate, num_channels, native)?;
+
+        out.stems.truncate(num_stems);
+        for (i, stem) in native.stems.iter().enumerate() {
+            if out.stems.len() == i {
+                out.stems.push(SeparatedStem {
+                    kind: stem.kind.clone(),
+                    samples: Vec::new(),
+                    sample_rate,
+                    num_channels: stem.num_channels,
+                });
+            }
+            let target = &mut out.stems[i];
+            target.kind = stem.kind.clone();
+            target.num_channels = stem.num_channels;
+            if stem.sample_rate > 0 {
+                resample_interleaved_into(
+                    &stem.samples,
+                    stem.num_channels.max(1) as usize,
+                    stem.sample_rate as u32,
+                    sample_rate as u32,
+                    &mut target.samples,
+                );
+                target.sample_rate = sample_rate;
+            } else {
+                target.samples.clear();
+                target.samples.extend_from_slice(&stem.samples);
+                target.sample_rate = stem.sample_rate;
+            }
+        }
+        out.native_sample_rate = native.native_sample_rate;
+        out.sample_rate = sample_rate;
+        Ok(num_stems)
+    }
+
+    fn process_native(
+        &self,
+        samples: &[f32],
+        sample_rate: i32,
+        num_channels: i32,
+    ) -> Result<SourceSeparationResult> {
+        let mut out = SourceSeparationResult::default();
+        self.process_native_into(samples, sample_rate, num_channels, &mut out)?;
+        Ok(out)
+    }
+
+    fn process_native_into(
+        &self,
+        samples: &[f32],
+        sample_rate: i32,
+        num_channels: i32,
+        out: &mut SourceSeparationResult,
+    ) -> Result<usize> {
+        let _timed = timed!(
+            "source_separation.process",
+            sample_rate,
+            num_channels,
+            samples = samples.len()
+        );
+        check_frames(samples, num_channels)?;
+
         let result = unsafe {
             sherpa_rs_sys::SherpaOnnxOfflineSourceSeparationProcess(
-                self.ss,
+                self.ss.as_ptr(),
                 samples.as_ptr(),
                 samples.len() as i32,
                 sample_rate,
@@ -135,41 +792,1168 @@ impl SourceSeparation {
         };
 
         if result.is_null() {
-            bail!("Source separation processing failed");
+            return Err(Error::processing_failed(
+                "source separation",
+                "no stems returned",
+            ));
         }
 
-        let mut stems = Vec::new();
-
+        let num_stems;
         unsafe {
-            let num_stems = (*result).num_stems;
+            num_stems = (*result).num_stems.max(0) as usize;
             let stems_ptr = (*result).stems;
 
+            out.stems.truncate(num_stems);
             for i in 0..num_stems {
-                let stem = &*stems_ptr.offset(i as isize);
-                let n = stem.n as usize;
-                let samples_slice = std::slice::from_raw_parts(stem.samples, n);
+                let stem = &*stems_ptr.add(i);
+                let samples_slice = std::slice::from_raw_parts(stem.samples, stem.n as usize);
+                let kind = self.family.stem_kind(i);
+
+                if let Some(existing) = out.stems.get_mut(i) {
+                    existing.kind = kind;
+                    existing.samples.clear();
+                    existing.samples.extend_from_slice(samples_slice);
+                    existing.sample_rate = stem.sample_rate;
+                    existing.num_channels = stem.num_channels;
+                } else {
+                    out.stems.push(SeparatedStem {
+                        kind,
+                        samples: samples_slice.to_vec(),
+                        sample_rate: stem.sample_rate,
+                        num_channels: stem.num_channels,
+                    });
+                }
+            }
+
+            sherpa_rs_sys::SherpaOnnxDestroyOfflineSourceSeparationResult(result);
+        }
+
+        out.native_sample_rate = self.get_sample_rate();
+        out.sample_rate = out
+            .stems
+            .first()
+            .map(|s| s.sample_rate)
+            .unwrap_or(sample_rate);
+        Ok(num_stems)
+    }
+
+    /// Separate planar input, one buffer per channel. The native API expects interleaved
+    /// samples, so the channels are interleaved before processing.
+    pub fn process_planar(
+        &self,
+        channels: &[Vec<f32>],
+        sample_rate: i32,
+    ) -> Result<SourceSeparationResult> {
+        if channels.is_empty() {
+            return Err(Error::invalid_input("At least one channel is required"));
+        }
+        let num_frames = channels[0].len();
+        if channels.iter().any(|c| c.len() != num_frames) {
+            return Err(Error::invalid_input(
+                "All channels must have the same length",
+            ));
+        }
+
+        let mut samples = Vec::with_capacity(num_frames * channels.len());
+        for i in 0..num_frames {
+            samples.extend(channels.iter().map(|c| c[i]));
+        }
+        self.process(&samples, sample_rate, channels.len() as i32)
+    }
+
+    /// Read a mono or stereo WAV, resample it to the model rate if needed and separate it.
+    pub fn process_file<P: AsRef<Path>>(&self, path: P) -> Result<SourceSeparationResult> {
+        let path = path.as_ref();
+        let wav = read_wav(path)?;
+        if wav.channels != 1 && wav.channels != 2 {
+            return Err(Error::unsupported(format!(
+                "{} channels in {}",
+                wav.channels,
+                path.display()
+            )));
+        }
+        let WavData {
+            samples,
+            sample_rate,
+            channels,
+        } = wav;
+
+        let num_channels = channels as i32;
+        let model_rate = self.get_sample_rate();
+        if model_rate > 0 && model_rate as u32 != sample_rate {
+            let samples =
+                resample_interleaved(&samples, channels as usize, sample_rate, model_rate as u32);
+            self.process(&samples, model_rate, num_channels)
+        } else {
+            self.process(&samples, sample_rate as i32, num_channels)
+        }
+    }
+
+    /// Separate long inputs window by window to bound the size of each native call.
+    ///
+    /// The input is split into windows of `chunk_secs` that overlap by `overlap_secs`,
+    /// and the overlapping regions of every stem are linearly crossfaded when stitched.
+    pub fn process_chunked(
+        &self,
+        samples: &[f32],
+        sample_rate: i32,
+        num_channels: i32,
+        chunk_secs: f32,
+        overlap_secs: f32,
+    ) -> Result<SourceSeparationResult> {
+        self.process_chunks(
+            samples,
+            sample_rate,
+            num_channels,
+            chunk_secs,
+            overlap_secs,
+            |_| true,
+        )
+    }
 
-                stems.push(SeparatedStem {
-                    samples: samples_slice.to_vec(),
+    /// Like [`Self::process`], but reports the processed fraction in `[0.0, 1.0]` after
+    /// every internal chunk. Returning `false` from `cb` aborts with
+    /// [`Error::Cancelled`].
+    pub fn process_with_progress(
+        &self,
+        samples: &[f32],
+        sample_rate: i32,
+        num_channels: i32,
+        cb: impl FnMut(f32) -> bool,
+    ) -> Result<SourceSeparationResult> {
+        self.process_chunks(
+            samples,
+            sample_rate,
+            num_channels,
+            PROGRESS_CHUNK_SECS,
+            PROGRESS_OVERLAP_SECS,
+            cb,
+        )
+    }
+
+    /// Like [`Self::process_with_progress`], stopping with [`Error::Cancelled`] at the
+    /// next chunk boundary once `token` is cancelled.
+    pub fn process_with_cancel(
+        &self,
+        samples: &[f32],
+        sample_rate: i32,
+        num_channels: i32,
+        token: &CancellationToken,
+    ) -> Result<SourceSeparationResult> {
+        token.check("source separation")?;
+        self.process_chunks(
+            samples,
+            sample_rate,
+            num_channels,
+            PROGRESS_CHUNK_SECS,
+            PROGRESS_OVERLAP_SECS,
+            |_| !token.is_cancelled(),
+        )
+    }
+
+    /// Separate `source` window by window like [`Self::process_chunked`], handing the
+    /// stems to `on_stems` as each window is finished instead of collecting them.
+    ///
+    /// Only one window of input and output is in memory at a time, whatever the length
+    /// of the source. Each call gets every stem, continuing where the previous call's
+    /// stems ended. An error from `on_stems` stops processing and is returned.
+    pub fn process_source<S: AudioSource>(
+        &self,
+        source: &mut S,
+        chunk_secs: f32,
+        overlap_secs: f32,
+        mut on_stems: impl FnMut(Vec<SeparatedStem>) -> Result<()>,
+    ) -> Result<()> {
+        let _timed = timed!("source_separation.process_source", chunk_secs);
+        let sample_rate = source.sample_rate() as i32;
+        let channels = source.channels() as usize;
+        if channels != 1 && channels != 2 {
+            return Err(Error::unsupported(format!("{} channels", channels)));
+        }
+        if sample_rate <= 0 {
+            return Err(Error::invalid_input(format!(
+                "Invalid sample rate {}",
+                sample_rate
+            )));
+        }
+        let (chunk_frames, hop_frames) = window_frames(sample_rate, chunk_secs, overlap_secs)?;
+        let overlap_frames = chunk_frames - hop_frames.min(chunk_frames);
+
+        let mut window = source.read_frames(chunk_frames)?;
+        // Output of the previous window that the current one overlaps
+        let mut tails: Vec<SeparatedStem> = Vec::new();
+        while !window.is_empty() {
+            let frames = window.len() / channels;
+            let result = self.process(&window, sample_rate, channels as i32)?;
+            let next = if frames < chunk_frames {
+                Vec::new()
+            } else {
+                source.read_frames(hop_frames)?
+            };
+            let last = next.is_empty();
+            if !tails.is_empty() {
+                check_stem_count(result.stems.len(), tails.len())?;
+            }
+
+            let mut stems = Vec::with_capacity(result.stems.len());
+            let mut next_tails = Vec::with_capacity(result.stems.len());
+            for (i, mut stem) in result.stems.into_iter().enumerate() {
+                let stem_channels = stem.num_channels.max(1) as usize;
+                if let Some(tail) = tails.get_mut(i) {
+                    let mut samples = mem::take(&mut tail.samples);
+                    stitch_stem(&mut samples, &stem.samples, stem_channels, 0);
+                    stem.samples = samples;
+                }
+                let keep = if last {
+                    0
+                } else {
+                    // Stems may come back at the model rate, so map the overlap
+                    let frames = (overlap_frames as f64 * stem.sample_rate as f64
+                        / sample_rate as f64)
+                        .round() as usize;
+                    (frames * stem_channels).min(stem.samples.len())
+                };
+                next_tails.push(SeparatedStem {
+                    kind: stem.kind.clone(),
+                    samples: stem.samples.split_off(stem.samples.len() - keep),
                     sample_rate: stem.sample_rate,
                     num_channels: stem.num_channels,
                 });
+                stems.push(stem);
+            }
+            on_stems(stems)?;
+            if last {
+                break;
             }
+            tails = next_tails;
+            window.drain(..(frames - overlap_frames) * channels);
+            window.extend(next);
+        }
+        Ok(())
+    }
 
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineSourceSeparationResult(result);
+    fn process_chunks(
+        &self,
+        samples: &[f32],
+        sample_rate: i32,
+        num_channels: i32,
+        chunk_secs: f32,
+        overlap_secs: f32,
+        on_progress: impl FnMut(f32) -> bool,
+    ) -> Result<SourceSeparationResult> {
+        separate_chunks(
+            samples,
+            sample_rate,
+            num_channels,
+            chunk_secs,
+            overlap_secs,
+            |chunk| self.process(chunk, sample_rate, num_channels),
+            on_progress,
+        )
+    }
+}
+
+/// The windowing behind [`SourceSeparation::process_chunks`], with `separate` running
+/// the model on each window.
+fn separate_chunks(
+    samples: &[f32],
+    sample_rate: i32,
+    num_channels: i32,
+    chunk_secs: f32,
+    overlap_secs: f32,
+    mut separate: impl FnMut(&[f32]) -> Result<SourceSeparationResult>,
+    mut on_progress: impl FnMut(f32) -> bool,
+) -> Result<SourceSeparationResult> {
+    if sample_rate <= 0 {
+        return Err(Error::invalid_input(format!(
+            "Invalid sample rate {}",
+            sample_rate
+        )));
+    }
+    // Checked up front, windows are cut on frames and would drop a partial one
+    check_frames(samples, num_channels)?;
+    let (chunk_frames, hop_frames) = window_frames(sample_rate, chunk_secs, overlap_secs)?;
+
+    let channels = num_channels as usize;
+    let total_frames = samples.len() / channels;
+
+    if total_frames <= chunk_frames {
+        let result = separate(samples)?;
+        on_progress(1.0);
+        return Ok(result);
+    }
+
+    let mut stems: Vec<SeparatedStem> = Vec::new();
+    let mut native_sample_rate;
+    let mut start = 0;
+    loop {
+        let end = (start + chunk_frames).min(total_frames);
+        let chunk = separate(&samples[start * channels..end * channels])?;
+        native_sample_rate = chunk.native_sample_rate;
+
+        if stems.is_empty() {
+            stems = chunk.stems;
+        } else {
+            check_stem_count(chunk.stems.len(), stems.len())?;
+            for (acc, stem) in stems.iter_mut().zip(chunk.stems) {
+                // Stems may come back at the model rate, so map the input position
+                let out_start =
+                    (start as f64 * stem.sample_rate as f64 / sample_rate as f64).round();
+                stitch_stem(
+                    &mut acc.samples,
+                    &stem.samples,
+                    stem.num_channels.max(1) as usize,
+                    out_start as usize,
+                );
+            }
         }
 
-        Ok(SourceSeparationResult { stems })
+        debug_event!(
+            start_frame = start,
+            end_frame = end,
+            total_frames,
+            "source separation chunk"
+        );
+        let keep_going = on_progress(end as f32 / total_frames as f32);
+        if end == total_frames {
+            break;
+        }
+        if !keep_going {
+            return Err(Error::cancelled("source separation"));
+        }
+        start += hop_frames;
+    }
+
+    let out_rate = stems.first().map(|s| s.sample_rate).unwrap_or(sample_rate);
+    Ok(SourceSeparationResult {
+        stems,
+        native_sample_rate,
+        sample_rate: out_rate,
+    })
+}
+
+/// Frames per window and between window starts, checking the window settings.
+fn window_frames(sample_rate: i32, chunk_secs: f32, overlap_secs: f32) -> Result<(usize, usize)> {
+    if chunk_secs <= 0.0 {
+        return Err(Error::invalid_input(format!(
+            "chunk_secs must be positive, got {}",
+            chunk_secs
+        )));
+    }
+    if overlap_secs < 0.0 || overlap_secs >= chunk_secs {
+        return Err(Error::invalid_input(format!(
+            "overlap_secs must be in [0, chunk_secs), got {} with chunk_secs {}",
+            overlap_secs, chunk_secs
+        )));
+    }
+    let chunk_frames = ((chunk_secs * sample_rate as f32) as usize).max(1);
+    let overlap_frames = (overlap_secs * sample_rate as f32) as usize;
+    let hop_frames = chunk_frames.saturating_sub(overlap_frames).max(1);
+    Ok((chunk_frames, hop_frames))
+}
+
+/// Check that `samples` holds whole frames of `num_channels`.
+fn check_frames(samples: &[f32], num_channels: i32) -> Result<()> {
+    if num_channels <= 0 {
+        return Err(Error::invalid_input(format!(
+            "Invalid number of channels {}",
+            num_channels
+        )));
+    }
+    if samples.len() % num_channels as usize != 0 {
+        return Err(Error::invalid_input(format!(
+            "Number of samples {} is not a multiple of the number of channels {}",
+            samples.len(),
+            num_channels
+        )));
+    }
+    Ok(())
+}
+
+fn check_stem_count(count: usize, expected: usize) -> Result<()> {
+    if count != expected {
+        return Err(Error::processing_failed(
+            "source separation",
+            format!(
+                "number of stems changed between chunks ({} != {})",
+                count, expected
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Append `chunk` to `acc` at `start_frame`, crossfading whatever region already exists.
+fn stitch_stem(acc: &mut Vec<f32>, chunk: &[f32], channels: usize, start_frame: usize) {
+    let start = start_frame * channels;
+    if acc.len() < start {
+        acc.resize(start, 0.0);
+    }
+
+    let overlap_frames = ((acc.len() - start) / channels).min(chunk.len() / channels);
+    let overlap = overlap_frames * channels;
+    for frame in 0..overlap_frames {
+        let fade_in = (frame as f32 + 0.5) / overlap_frames as f32;
+        for c in 0..channels {
+            let i = frame * channels + c;
+            let prev = acc[start + i];
+            acc[start + i] = prev * (1.0 - fade_in) + chunk[i] * fade_in;
+        }
+    }
+
+    acc.truncate(start + overlap);
+    acc.extend_from_slice(&chunk[overlap..]);
+}
+
+#[cfg(feature = "async")]
+impl SourceSeparation {
+    /// Run [`Self::process`] on tokio's blocking thread pool.
+    ///
+    /// Dropping the returned future doesn't interrupt the native call, which finishes in
+    /// the background while the `Arc` keeps the instance alive.
+    pub fn process_async(
+        self: &std::sync::Arc<Self>,
+        samples: Vec<f32>,
+        sample_rate: i32,
+        num_channels: i32,
+    ) -> impl std::future::Future<Output = Result<SourceSeparationResult>> {
+        let ss = std::sync::Arc::clone(self);
+        async move {
+            tokio::task::spawn_blocking(move || ss.process(&samples, sample_rate, num_channels))
+                .await
+                .map_err(|err| Error::processing_failed("source separation", err.to_string()))?
+        }
     }
 }
 
 unsafe impl Send for SourceSeparation {}
 unsafe impl Sync for SourceSeparation {}
 
-impl Drop for SourceSeparation {
+#[derive(Debug, Clone)]
+pub struct AudioInput {
+    pub samples: Vec<f32>,
+    pub sample_rate: i32,
+    pub num_channels: i32,
+}
+
+type JobResult = (usize, Result<SourceSeparationResult>);
+
+struct Job {
+    index: usize,
+    input: AudioInput,
+    cancel: Option<CancellationToken>,
+    reply: mpsc::Sender<JobResult>,
+}
+
+enum Task {
+    Process(Job),
+    /// Sent once per worker, each waits on `barrier` after its warm-up so none takes two.
+    WarmUp {
+        barrier: Arc<Barrier>,
+        reply: mpsc::Sender<Result<Duration>>,
+    },
+}
+
+/// A fixed set of worker threads, each owning its own native instance.
+pub struct SourceSeparationPool {
+    jobs: Option<mpsc::Sender<Task>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl SourceSeparationPool {
+    pub fn new(config: SourceSeparationConfig, num_workers: usize) -> Result<Self> {
+        if num_workers == 0 {
+            return Err(Error::invalid_input("num_workers must be at least 1"));
+        }
+
+        let instances = (0..num_workers)
+            .map(|_| SourceSeparation::new(config.clone()))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (jobs, receiver) = mpsc::channel::<Task>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = instances
+            .into_iter()
+            .map(|ss| {
+                let receiver = Arc::clone(&receiver);
+                let config = config.clone();
+                thread::spawn(move || worker_loop(ss, config, receiver))
+            })
+            .collect();
+
+        Ok(Self {
+            jobs: Some(jobs),
+            workers,
+        })
+    }
+
+    /// Process all inputs and return the results in input order.
+    pub fn process_batch(&self, inputs: Vec<AudioInput>) -> Vec<Result<SourceSeparationResult>> {
+        let len = inputs.len();
+        collect_in_order(self.submit(inputs, None), len)
+    }
+
+    /// Like [`Self::process_batch`], processing inputs in chunks so that cancelling
+    /// `token` stops running jobs at the next chunk boundary. Jobs that haven't started
+    /// yet fail with [`Error::Cancelled`] without being processed.
+    pub fn process_batch_with_cancel(
+        &self,
+        inputs: Vec<AudioInput>,
+        token: &CancellationToken,
+    ) -> Vec<Result<SourceSeparationResult>> {
+        let len = inputs.len();
+        collect_in_order(self.submit(inputs, Some(token)), len)
+    }
+
+    /// Run [`SourceSeparation::warm_up`] on every worker concurrently, after the jobs
+    /// already queued. Returns how long each worker took.
+    pub fn warm_up_all(&self) -> Result<Vec<Duration>> {
+        let shut_down = || Error::processing_failed("source separation pool", "pool is shut down");
+        let jobs = self.jobs.as_ref().ok_or_else(shut_down)?;
+        let barrier = Arc::new(Barrier::new(self.workers.len()));
+        let (reply, results) = mpsc::channel();
+        for _ in &self.workers {
+            let task = Task::WarmUp {
+                barrier: Arc::clone(&barrier),
+                reply: reply.clone(),
+            };
+            jobs.send(task).map_err(|_| shut_down())?;
+        }
+        drop(reply);
+        results.into_iter().collect()
+    }
+
+    /// Submit all inputs and yield `(input_index, result)` pairs as jobs complete.
+    pub fn map(&self, inputs: Vec<AudioInput>) -> impl Iterator<Item = JobResult> {
+        self.submit(inputs, None)
+    }
+
+    fn submit(
+        &self,
+        inputs: Vec<AudioInput>,
+        cancel: Option<&CancellationToken>,
+    ) -> mpsc::IntoIter<JobResult> {
+        let (reply, results) = mpsc::channel();
+        if let Some(jobs) = &self.jobs {
+            for (index, input) in inputs.into_iter().enumerate() {
+                let job = Job {
+                    index,
+                    input,
+                    cancel: cancel.cloned(),
+                    reply: reply.clone(),
+                };
+                if let Err(mpsc::SendError(Task::Process(job))) = jobs.send(Task::Process(job)) {
+                    let err =
+                        Error::processing_failed("source separation pool", "pool is shut down");
+                    let _ = reply.send((job.index, Err(err)));
+                }
+            }
+        }
+        results.into_iter()
+    }
+}
+
+fn collect_in_order(
+    results: impl Iterator<Item = JobResult>,
+    len: usize,
+) -> Vec<Result<SourceSeparationResult>> {
+    let mut ordered: Vec<Option<Result<SourceSeparationResult>>> = (0..len).map(|_| None).collect();
+    for (index, result) in results {
+        ordered[index] = Some(result);
+    }
+    ordered
+        .into_iter()
+        .map(|r| {
+            r.unwrap_or_else(|| {
+                Err(Error::processing_failed(
+                    "source separation pool",
+                    "job was not processed",
+                ))
+            })
+        })
+        .collect()
+}
+
+fn worker_loop(
+    ss: SourceSeparation,
+    config: SourceSeparationConfig,
+    receiver: Arc<Mutex<mpsc::Receiver<Task>>>,
+) {
+    let mut ss = Some(ss);
+    loop {
+        let task = {
+            let receiver = receiver.lock().unwrap_or_else(|e| e.into_inner());
+            match receiver.recv() {
+                Ok(task) => task,
+                Err(_) => return,
+            }
+        };
+
+        match task {
+            Task::Process(job) => {
+                let input = &job.input;
+                let result = run_guarded(&mut ss, &config, |instance| match &job.cancel {
+                    Some(token) => instance.process_with_cancel(
+                        &input.samples,
+                        input.sample_rate,
+                        input.num_channels,
+                        token,
+                    ),
+                    None => instance.process(&input.samples, input.sample_rate, input.num_channels),
+                });
+                let _ = job.reply.send((job.index, result));
+            }
+            Task::WarmUp { barrier, reply } => {
+                let _ = reply.send(run_guarded(&mut ss, &config, SourceSeparation::warm_up));
+                barrier.wait();
+            }
+        }
+    }
+}
+
+/// Run `f` on the worker's instance, creating it first if an earlier panic dropped it.
+fn run_guarded<T>(
+    ss: &mut Option<SourceSeparation>,
+    config: &SourceSeparationConfig,
+    f: impl FnOnce(&mut SourceSeparation) -> Result<T>,
+) -> Result<T> {
+    if ss.is_none() {
+        *ss = Some(SourceSeparation::new(config.clone())?);
+    }
+    let instance = ss.as_mut().unwrap();
+    match panic::catch_unwind(AssertUnwindSafe(|| f(instance))) {
+        Ok(result) => result,
+        Err(_) => {
+            // The native state may be inconsistent, rebuild it for the next job
+            *ss = None;
+            Err(Error::processing_failed(
+                "source separation",
+                "worker panicked",
+            ))
+        }
+    }
+}
+
+impl Drop for SourceSeparationPool {
     fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineSourceSeparation(self.ss);
+        // Closing the channel stops the workers once the queue is drained
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, ScratchDir};
+    use std::{cell::Cell, f32::consts::PI};
+
+    fn stem(samples: Vec<f32>, num_channels: i32) -> SeparatedStem {
+        SeparatedStem {
+            kind: StemKind::Vocals,
+            samples,
+            sample_rate: 48000,
+            num_channels,
+        }
+    }
+
+    /// One second of a 1 kHz sine at 48 kHz, a whole number of periods.
+    fn sine(amplitude: f32) -> Vec<f32> {
+        (0..48000)
+            .map(|i| amplitude * (2.0 * PI * 1000.0 * i as f32 / 48000.0).sin())
+            .collect()
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn sine_levels() {
+        let stem = stem(sine(0.5), 1);
+        assert_close(stem.peak(), 0.5, 1e-4);
+        assert_close(stem.rms(), 0.5 / 2f32.sqrt(), 1e-4);
+
+        // 0.5 / sqrt(2) is -9.03 dBFS
+        assert!(stem.is_silent(-9.0));
+        assert!(!stem.is_silent(-9.1));
+    }
+
+    #[test]
+    fn dc_levels() {
+        // Negative, and split over two channels
+        let stem = stem(vec![-0.25; 1000], 2);
+        assert_eq!(stem.peak(), 0.25);
+        assert_close(stem.rms(), 0.25, 1e-6);
+
+        // 0.25 is -12.04 dBFS
+        assert!(stem.is_silent(-12.0));
+        assert!(!stem.is_silent(-12.1));
+    }
+
+    #[test]
+    fn zero_is_silent() {
+        for samples in [vec![0.0; 1000], Vec::new()] {
+            let stem = stem(samples, 1);
+            assert_eq!(stem.peak(), 0.0);
+            assert_eq!(stem.rms(), 0.0);
+            assert!(stem.is_silent(-200.0));
+        }
+    }
+
+    #[test]
+    fn stitch_without_overlap_appends() {
+        let mut acc = Vec::new();
+        stitch_stem(&mut acc, &[1.0, 2.0, 3.0, 4.0], 2, 0);
+        assert_eq!(acc, [1.0, 2.0, 3.0, 4.0]);
+
+        // A gap is filled with silence
+        stitch_stem(&mut acc, &[5.0, 6.0], 2, 3);
+        assert_eq!(acc, [1.0, 2.0, 3.0, 4.0, 0.0, 0.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn stitch_crossfades_the_overlap() {
+        let mut acc = vec![1.0; 10];
+        stitch_stem(&mut acc, &[0.0; 10], 1, 6);
+        assert_eq!(acc.len(), 16);
+        assert_eq!(acc[..6], [1.0; 6]);
+        assert_eq!(acc[6..10], [0.875, 0.625, 0.375, 0.125]);
+        assert_eq!(acc[10..], [0.0; 6]);
+    }
+
+    #[test]
+    fn stitched_chunks_are_continuous() {
+        // Overlapping windows of a stereo ramp, as process_with_progress cuts them
+        let frames = 1000;
+        let signal: Vec<f32> = (0..frames)
+            .flat_map(|i| [i as f32 / 1000.0, -(i as f32) / 1000.0])
+            .collect();
+        let (chunk_frames, hop_frames) = (100, 80);
+        let mut acc = Vec::new();
+        let mut start = 0;
+        while start < frames {
+            let end = (start + chunk_frames).min(frames);
+            stitch_stem(&mut acc, &signal[start * 2..end * 2], 2, start);
+            start += hop_frames;
         }
+
+        assert_eq!(acc.len(), signal.len());
+        for (i, (&a, &s)) in acc.iter().zip(&signal).enumerate() {
+            assert!((a - s).abs() < 1e-6, "sample {}: {} != {}", i, a, s);
+        }
+    }
+
+    /// What the native side returns for two stems, labelled for `family`.
+    fn separated(family: ModelFamily) -> SourceSeparationResult {
+        SourceSeparationResult {
+            stems: (0..2)
+                .map(|i| SeparatedStem {
+                    kind: family.stem_kind(i),
+                    samples: vec![i as f32; 4],
+                    sample_rate: 44100,
+                    num_channels: 2,
+                })
+                .collect(),
+            native_sample_rate: 44100,
+            sample_rate: 44100,
+        }
+    }
+
+    #[test]
+    fn spleeter_stems() {
+        let result = separated(ModelFamily::Spleeter);
+        assert_eq!(result.vocals().unwrap().samples, [0.0; 4]);
+        assert_eq!(result.accompaniment().unwrap().samples, [1.0; 4]);
+        assert_eq!(
+            ModelFamily::Spleeter.stem_kind(2),
+            StemKind::Other("spleeter_stem_2".into())
+        );
+    }
+
+    #[test]
+    fn uvr_stems_follow_the_primary_stem() {
+        let result = separated(ModelFamily::Uvr { vocals_first: true });
+        assert_eq!(result.vocals().unwrap().samples, [0.0; 4]);
+        assert_eq!(result.accompaniment().unwrap().samples, [1.0; 4]);
+
+        let result = separated(ModelFamily::Uvr {
+            vocals_first: false,
+        });
+        assert_eq!(result.accompaniment().unwrap().samples, [0.0; 4]);
+        assert_eq!(result.vocals().unwrap().samples, [1.0; 4]);
+        assert_eq!(
+            ModelFamily::Uvr {
+                vocals_first: false
+            }
+            .stem_kind(2),
+            StemKind::Other("uvr_stem_2".into())
+        );
+    }
+
+    #[test]
+    fn uvr_primary_stem_from_config_or_file_name() {
+        let uvr = |model: &str, primary_stem| UvrModelConfig {
+            model: model.into(),
+            primary_stem,
+            ..Default::default()
+        };
+        assert!(uvr("models/UVR-MDX-NET-Voc_FT.onnx", None)
+            .vocals_first()
+            .unwrap());
+        assert!(!uvr("models/UVR-MDX-NET-Inst_HQ_4.onnx", None)
+            .vocals_first()
+            .unwrap());
+        // Only the file name counts
+        assert!(uvr("instruments/UVR_MDXNET_9482.onnx", None)
+            .vocals_first()
+            .unwrap());
+
+        assert!(
+            !uvr("UVR-MDX-NET-Voc_FT.onnx", Some(StemKind::Accompaniment))
+                .vocals_first()
+                .unwrap()
+        );
+        assert!(uvr("UVR-MDX-NET-Inst_HQ_4.onnx", Some(StemKind::Vocals))
+            .vocals_first()
+            .unwrap());
+        let err = uvr(
+            "UVR-MDX-NET-Voc_FT.onnx",
+            Some(StemKind::Other("drums".into())),
+        )
+        .vocals_first()
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+    }
+
+    fn stems(stems: Vec<(StemKind, Vec<f32>)>) -> SourceSeparationResult {
+        SourceSeparationResult {
+            stems: stems
+                .into_iter()
+                .map(|(kind, samples)| SeparatedStem {
+                    kind,
+                    ..stem(samples, 1)
+                })
+                .collect(),
+            native_sample_rate: 48000,
+            sample_rate: 48000,
+        }
+    }
+
+    #[test]
+    fn mix_applies_gains_and_sums() {
+        let result = stems(vec![
+            (StemKind::Vocals, vec![0.1, 0.2, -0.3]),
+            (StemKind::Accompaniment, vec![0.4, -0.2, 0.1]),
+        ]);
+        let mix = result.mix(&[1.0, 1.0]).unwrap();
+        assert_eq!(mix.kind, StemKind::Other("mix".into()));
+        for (&actual, expected) in mix.samples.iter().zip([0.5, 0.0, -0.2]) {
+            assert_close(actual, expected, 1e-6);
+        }
+
+        let mix = result.mix(&[0.5, 2.0]).unwrap();
+        for (&actual, expected) in mix.samples.iter().zip([0.85, -0.3, 0.05]) {
+            assert_close(actual, expected, 1e-6);
+        }
+
+        // Cut to the shortest stem
+        let result = stems(vec![
+            (StemKind::Vocals, vec![0.1; 4]),
+            (StemKind::Accompaniment, vec![0.1; 2]),
+        ]);
+        assert_eq!(result.mix(&[1.0, 1.0]).unwrap().samples.len(), 2);
+    }
+
+    #[test]
+    fn mix_scales_down_instead_of_clipping() {
+        let result = stems(vec![
+            (StemKind::Vocals, vec![0.8, -0.5, 0.2]),
+            (StemKind::Accompaniment, vec![0.8, -0.5, 0.2]),
+        ]);
+        // 1.6 at the peak, so everything is divided by 1.6
+        let mix = result.mix(&[1.0, 1.0]).unwrap();
+        for (&actual, expected) in mix.samples.iter().zip([1.0, -0.625, 0.25]) {
+            assert_close(actual, expected, 1e-6);
+        }
+
+        // A mix that fits is left alone
+        let mix = result.mix(&[0.5, 0.5]).unwrap();
+        assert_eq!(mix.samples, result.stems[0].samples);
+    }
+
+    #[test]
+    fn mix_rejects_mismatched_stems() {
+        let result = stems(vec![
+            (StemKind::Vocals, vec![0.0; 4]),
+            (StemKind::Accompaniment, vec![0.0; 4]),
+        ]);
+        let err = result.mix(&[1.0]).unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+
+        let mut other_rate = result.clone();
+        other_rate.stems[1].sample_rate = 44100;
+        let err = other_rate.mix(&[1.0, 1.0]).unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+
+        let mut other_channels = result.clone();
+        other_channels.stems[1].num_channels = 2;
+        let err = other_channels.mix(&[1.0, 1.0]).unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+
+        let err = stems(Vec::new()).mix(&[]).unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn instrumental_sums_everything_but_vocals() {
+        let result = stems(vec![
+            (StemKind::Vocals, vec![0.5, 0.5]),
+            (StemKind::Other("drums".into()), vec![0.25, -0.25]),
+            (StemKind::Other("bass".into()), vec![0.5, 0.0]),
+        ]);
+        let instrumental = result.instrumental().unwrap();
+        assert_eq!(instrumental.kind, StemKind::Accompaniment);
+        assert_eq!(instrumental.samples, [0.75, -0.25]);
+
+        let result = stems(vec![
+            (StemKind::Vocals, vec![0.9, -0.9]),
+            (StemKind::Accompaniment, vec![0.9, -0.9]),
+            (StemKind::Other("drums".into()), vec![0.9, 0.0]),
+        ]);
+        let instrumental = result.instrumental().unwrap();
+        assert_close(instrumental.samples[0], 1.0, 1e-6);
+        assert_close(instrumental.samples[1], -0.5, 1e-6);
+
+        let vocals_only = stems(vec![(StemKind::Vocals, vec![0.5, 0.5])]);
+        assert!(vocals_only.instrumental().is_none());
+    }
+
+    /// Stand-in for a 100 Hz stereo model, returning the window as both stems and
+    /// counting calls.
+    fn passthrough(
+        calls: &Cell<usize>,
+    ) -> impl FnMut(&[f32]) -> Result<SourceSeparationResult> + '_ {
+        move |window| {
+            calls.set(calls.get() + 1);
+            let mut result = stems(vec![
+                (StemKind::Vocals, window.to_vec()),
+                (StemKind::Accompaniment, window.to_vec()),
+            ]);
+            for stem in &mut result.stems {
+                stem.sample_rate = 100;
+                stem.num_channels = 2;
+            }
+            Ok(result)
+        }
+    }
+
+    #[test]
+    fn chunks_are_stitched_back_together() {
+        let signal: Vec<f32> = (0..1000)
+            .flat_map(|i| [i as f32 / 1000.0, -(i as f32) / 1000.0])
+            .collect();
+        let calls = Cell::new(0);
+        let mut progress = Vec::new();
+        let result = separate_chunks(&signal, 100, 2, 1.0, 0.2, passthrough(&calls), |fraction| {
+            progress.push(fraction);
+            true
+        })
+        .unwrap();
+
+        // Windows of 100 frames starting every 80, the last one at 960
+        assert_eq!(calls.get(), 13);
+        assert_eq!(progress.len(), 13);
+        assert!(progress.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(progress.last(), Some(&1.0));
+        for stem in &result.stems {
+            assert_eq!(stem.samples.len(), signal.len());
+            for (a, s) in stem.samples.iter().zip(&signal) {
+                assert_close(*a, *s, 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn cancel_from_progress_stops_midway() {
+        let signal = vec![0.0; 2 * 1000];
+        let calls = Cell::new(0);
+        let token = CancellationToken::new();
+        let err = separate_chunks(&signal, 100, 2, 1.0, 0.2, passthrough(&calls), |fraction| {
+            if fraction > 0.3 {
+                token.cancel();
+            }
+            !token.is_cancelled()
+        })
+        .unwrap_err();
+        assert!(matches!(err, Error::Cancelled { .. }), "{:?}", err);
+        // Stopped at the first window past 30%, the one ending at frame 340
+        assert_eq!(calls.get(), 4);
+    }
+
+    #[test]
+    fn cancel_after_the_last_window_still_succeeds() {
+        let signal = vec![0.0; 2 * 1000];
+        let calls = Cell::new(0);
+        let result = separate_chunks(&signal, 100, 2, 1.0, 0.2, passthrough(&calls), |fraction| {
+            fraction < 1.0
+        });
+        assert!(result.is_ok());
+        assert_eq!(calls.get(), 13);
+    }
+
+    #[test]
+    #[ignore = "needs sherpa-onnx-spleeter-2stems-fp16"]
+    fn cancelled_separation_can_run_again() {
+        let ss = spleeter();
+        let sample_rate = ss.get_sample_rate();
+        let samples = chord(sample_rate, 70.0);
+        let token = CancellationToken::new();
+        let err = ss
+            .process_with_progress(&samples, sample_rate, 2, |fraction| {
+                if fraction >= 0.4 {
+                    token.cancel();
+                }
+                !token.is_cancelled()
+            })
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled { .. }), "{:?}", err);
+
+        // The native result of every finished window was freed, and the instance still works
+        let result = ss.process(&samples[..sample_rate as usize * 2], sample_rate, 2);
+        assert_eq!(result.unwrap().stems.len(), 2);
+        drop(ss);
+    }
+
+    #[test]
+    #[cfg(feature = "uvr-options")]
+    fn uvr_options_are_unsupported() {
+        assert!(UvrModelConfig::default().check_supported().is_ok());
+        let config = UvrModelConfig {
+            segment_size: Some(256),
+            denoise: true,
+            ..Default::default()
+        };
+        match config.check_supported().unwrap_err() {
+            Error::Unsupported { what } => {
+                assert!(what.contains("segment_size, denoise"), "{}", what)
+            }
+            err => panic!("expected Unsupported, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn check_model_file_needs_an_existing_file() {
+        let dir = ScratchDir::new("check-model-file");
+
+        let err = check_model_file("uvr", &dir.join("model.onnx")).unwrap_err();
+        match err {
+            Error::ModelNotFound { component, path } => {
+                assert_eq!(component, "uvr model");
+                assert_eq!(path, Path::new(&dir.join("model.onnx")));
+            }
+            err => panic!("expected ModelNotFound, got {:?}", err),
+        }
+
+        let err = check_model_file("uvr", &dir.mkdir("model.onnx")).unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+
+        // Only the path is checked, not the contents
+        assert!(check_model_file("uvr", &dir.touch("dummy.onnx")).is_ok());
+    }
+
+    #[test]
+    fn check_frames_needs_whole_frames() {
+        assert!(check_frames(&[0.0; 6], 2).is_ok());
+        assert!(check_frames(&[], 1).is_ok());
+        for (len, channels) in [(5, 2), (6, 0), (6, -1)] {
+            let err = check_frames(&vec![0.0; len], channels).unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+        }
+    }
+
+    fn spleeter() -> SourceSeparation {
+        SourceSeparation::new(
+            SourceSeparationConfig::spleeter(
+                testing::model("sherpa-onnx-spleeter-2stems-fp16/vocals.fp16.onnx"),
+                testing::model("sherpa-onnx-spleeter-2stems-fp16/accompaniment.fp16.onnx"),
+            )
+            .build(),
+        )
+        .unwrap()
+    }
+
+    /// `secs` of a stereo chord at `sample_rate`, different on each side.
+    fn chord(sample_rate: i32, secs: f32) -> Vec<f32> {
+        let frames = (sample_rate as f32 * secs) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                let left = 0.3 * (2.0 * PI * 220.0 * t).sin() + 0.2 * (2.0 * PI * 554.0 * t).sin();
+                let right = 0.3 * (2.0 * PI * 330.0 * t).sin() + 0.2 * (2.0 * PI * 880.0 * t).sin();
+                [left, right]
+            })
+            .collect()
+    }
+
+    #[test]
+    #[ignore = "needs sherpa-onnx-spleeter-2stems-fp16"]
+    fn chunked_matches_whole() {
+        let ss = spleeter();
+        let sample_rate = ss.get_sample_rate();
+        let samples = chord(sample_rate, 12.5);
+
+        let whole = ss.process(&samples, sample_rate, 2).unwrap();
+        // The last window is shorter than the others
+        let chunked = ss
+            .process_chunked(&samples, sample_rate, 2, 4.0, 1.0)
+            .unwrap();
+
+        assert_eq!(chunked.stems.len(), whole.stems.len());
+        for (chunked, whole) in chunked.stems.iter().zip(&whole.stems) {
+            assert_eq!(chunked.kind, whole.kind);
+            assert_eq!(chunked.samples.len(), whole.samples.len());
+            let diff: Vec<f32> = chunked
+                .samples
+                .iter()
+                .zip(&whole.samples)
+                .map(|(a, b)| a - b)
+                .collect();
+            let error = stem(diff, 2).rms();
+            assert!(
+                error < 0.1 * whole.rms().max(1e-3),
+                "{:?}: rms error {} against {}",
+                whole.kind,
+                error,
+                whole.rms()
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "needs sherpa-onnx-spleeter-2stems-fp16"]
+    fn partial_frames_fail_on_both_paths() {
+        let ss = spleeter();
+        let sample_rate = ss.get_sample_rate();
+        let mut samples = chord(sample_rate, 6.0);
+        samples.push(0.0);
+
+        let whole = ss.process(&samples, sample_rate, 2).unwrap_err();
+        let chunked = ss
+            .process_chunked(&samples, sample_rate, 2, 4.0, 1.0)
+            .unwrap_err();
+        assert!(matches!(whole, Error::InvalidInput { .. }), "{:?}", whole);
+        assert!(
+            matches!(chunked, Error::InvalidInput { .. }),
+            "{:?}",
+            chunked
+        );
     }
 }
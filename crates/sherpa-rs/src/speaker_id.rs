@@ -1,106 +1,287 @@
-use eyre::{bail, Result};
-use std::path::PathBuf;
+use std::{marker::PhantomData, path::PathBuf};
 
-use crate::{get_default_provider, utils::cstring_from_str};
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::cstring_from_str,
+    Error, FallbackPolicy, Provider, Result, Threads,
+};
 
 /// If similarity is greater or equal to thresold than it's a match!
 pub const DEFAULT_SIMILARITY_THRESHOLD: f32 = 0.5;
 
-#[derive(Debug, Default)]
+/// Cosine similarity of two embeddings, in `[-1, 1]`.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> Result<f32> {
+    if a.len() != b.len() {
+        return Err(Error::invalid_input(format!(
+            "embedding dimensions differ: {} vs {}",
+            a.len(),
+            b.len()
+        )));
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return Err(Error::invalid_input(
+            "cosine similarity of a zero embedding",
+        ));
+    }
+    Ok((dot / (norm_a * norm_b)).clamp(-1.0, 1.0))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VerificationResult {
+    pub score: f32,
+    pub accepted: bool,
+}
+
+/// Checks probes against one enrolled speaker.
+///
+/// Scores are raw cosine similarities. Where the same and different speakers separate
+/// depends on the model and the audio, so tune `threshold` on your own recordings.
+/// [`DEFAULT_SIMILARITY_THRESHOLD`] is a reasonable start for 3D-Speaker and WeSpeaker
+/// models on clean speech of a few seconds. Enrolling several clips and using longer
+/// probes makes scores more stable.
+#[derive(Debug, Clone)]
+pub struct SpeakerVerifier {
+    enrollment: Vec<f32>,
+    pub threshold: f32,
+}
+
+impl SpeakerVerifier {
+    /// Enroll from one or more embeddings of the same speaker.
+    ///
+    /// Each embedding is normalized before averaging so louder or longer clips don't
+    /// dominate.
+    pub fn new(embeddings: &[Vec<f32>], threshold: f32) -> Result<Self> {
+        let Some(first) = embeddings.first() else {
+            return Err(Error::invalid_input(
+                "speaker verifier needs at least one enrollment embedding",
+            ));
+        };
+        let mut enrollment = vec![0.0; first.len()];
+        for embedding in embeddings {
+            if embedding.len() != first.len() {
+                return Err(Error::invalid_input(format!(
+                    "embedding dimensions differ: {} vs {}",
+                    first.len(),
+                    embedding.len()
+                )));
+            }
+            let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm == 0.0 {
+                return Err(Error::invalid_input("enrollment embedding is all zeros"));
+            }
+            for (sum, x) in enrollment.iter_mut().zip(embedding) {
+                *sum += x / norm;
+            }
+        }
+        let count = embeddings.len() as f32;
+        enrollment.iter_mut().for_each(|x| *x /= count);
+        Ok(Self {
+            enrollment,
+            threshold,
+        })
+    }
+
+    /// The averaged enrollment embedding.
+    pub fn enrollment(&self) -> &[f32] {
+        &self.enrollment
+    }
+
+    pub fn verify(&self, probe: &[f32]) -> Result<VerificationResult> {
+        let score = cosine_similarity(&self.enrollment, probe)?;
+        Ok(VerificationResult {
+            score,
+            accepted: score >= self.threshold,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ExtractorConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
-    pub provider: Option<String>,
-    pub num_threads: Option<usize>,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub num_threads: Threads,
     pub debug: bool,
 }
 
 #[derive(Debug)]
 pub struct EmbeddingExtractor {
-    pub(crate) extractor: *const sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractor,
+    pub(crate) extractor: NativeHandle<sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractor>,
     pub embedding_size: usize,
+    provider: ProviderSelection,
 }
 
 impl EmbeddingExtractor {
     pub fn new(config: ExtractorConfig) -> Result<Self> {
-        let provider = config.provider.unwrap_or(get_default_provider());
+        let _timed = timed!("speaker_id.create");
+        debug_event!(?config, "creating speaker embedding extractor");
+        let (mut extractor, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(ExtractorConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        extractor.provider = provider;
+        Ok(extractor)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    fn create(config: ExtractorConfig) -> Result<Self> {
+        let provider = config.provider.unwrap_or_else(get_default_provider);
 
-        let num_threads = config.num_threads.unwrap_or(1);
+        let num_threads = config.num_threads.to_raw();
         let debug = config.debug.into();
 
         let model_path = PathBuf::from(&config.model);
         if !model_path.exists() {
-            bail!("model not found at {}", model_path.display())
+            return Err(Error::model_not_found(
+                "speaker embedding model",
+                model_path,
+            ));
         }
         let model = cstring_from_str(&config.model);
-        let provider = cstring_from_str(&provider);
+        let provider = provider.to_cstring()?;
 
         let extractor_config = sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorConfig {
             debug,
             model: model.as_ptr(),
-            num_threads: num_threads as i32,
+            num_threads,
             provider: provider.as_ptr(),
         };
-        let extractor =
-            unsafe { sherpa_rs_sys::SherpaOnnxCreateSpeakerEmbeddingExtractor(&extractor_config) };
+        let extractor = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateSpeakerEmbeddingExtractor(&extractor_config),
+                sherpa_rs_sys::SherpaOnnxDestroySpeakerEmbeddingExtractor,
+            )
+        }
+        .ok_or_else(|| Error::native_creation_failed("speaker embedding extractor"))?;
         // Assume embedding size is known or can be retrieved
         let embedding_size =
-            unsafe { sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorDim(extractor) }
+            unsafe { sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorDim(extractor.as_ptr()) }
                 .try_into()
                 .unwrap();
         Ok(Self {
             extractor,
             embedding_size,
+            provider: ProviderSelection::default(),
         })
     }
 
-    pub fn compute_speaker_embedding(
-        &mut self,
-        samples: Vec<f32>,
-        sample_rate: u32,
-    ) -> Result<Vec<f32>> {
-        unsafe {
-            let stream =
-                sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorCreateStream(self.extractor);
-            if stream.is_null() {
-                bail!("Failed to create SherpaOnnxOnlineStream");
-            }
+    /// Embedding dimension.
+    pub fn dim(&self) -> usize {
+        self.embedding_size
+    }
 
-            sherpa_rs_sys::SherpaOnnxOnlineStreamAcceptWaveform(
-                stream,
-                sample_rate as i32,
-                samples.as_ptr(),
-                samples.len() as i32,
-            );
-            sherpa_rs_sys::SherpaOnnxOnlineStreamInputFinished(stream);
+    pub fn create_stream(&self) -> Result<EmbeddingStream<'_>> {
+        let stream = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorCreateStream(
+                    self.extractor.as_ptr(),
+                ),
+                sherpa_rs_sys::SherpaOnnxDestroyOnlineStream,
+            )
+        }
+        .ok_or_else(|| {
+            Error::processing_failed("speaker embedding extractor", "failed to create a stream")
+        })?;
+        Ok(EmbeddingStream {
+            stream,
+            samples: 0,
+            sample_rate: 0,
+            _extractor: PhantomData,
+        })
+    }
 
-            if !self.is_ready(stream) {
-                bail!("Embedding extractor is not ready");
+    /// Compute the embedding of a finished stream.
+    pub fn compute_stream(&self, stream: &EmbeddingStream) -> Result<Vec<f32>> {
+        let _timed = timed!("speaker_id.compute");
+        unsafe {
+            if sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorIsReady(
+                self.extractor.as_ptr(),
+                stream.stream.as_ptr(),
+            ) == 0
+            {
+                let secs = if stream.sample_rate > 0 {
+                    stream.samples as f32 / stream.sample_rate as f32
+                } else {
+                    0.0
+                };
+                return Err(Error::invalid_input(format!(
+                    "audio of {:.2}s is too short for the speaker embedding model",
+                    secs
+                )));
             }
 
             let embedding_ptr = sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorComputeEmbedding(
-                self.extractor,
-                stream,
+                self.extractor.as_ptr(),
+                stream.stream.as_ptr(),
             );
             if embedding_ptr.is_null() {
-                bail!("Failed to compute speaker embedding");
+                return Err(Error::processing_failed(
+                    "speaker embedding extractor",
+                    "no embedding returned",
+                ));
             }
             tracing::debug!("using dimensions {}", self.embedding_size);
             let embedding = std::slice::from_raw_parts(embedding_ptr, self.embedding_size).to_vec();
-            // Free
-            sherpa_rs_sys::SherpaOnnxDestroyOnlineStream(stream);
             sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorDestroyEmbedding(embedding_ptr);
             Ok(embedding)
         }
     }
 
+    /// Compute the embedding of a single clip.
+    pub fn compute(&mut self, samples: &[f32], sample_rate: u32) -> Result<Vec<f32>> {
+        let mut stream = self.create_stream()?;
+        stream.accept_waveform(sample_rate, samples);
+        stream.input_finished();
+        self.compute_stream(&stream)
+    }
+
+    /// Compute embeddings for many clips, e.g. when enrolling speakers.
+    pub fn compute_batch(&mut self, clips: &[(&[f32], u32)]) -> Vec<Result<Vec<f32>>> {
+        clips
+            .iter()
+            .map(|(samples, sample_rate)| self.compute(samples, *sample_rate))
+            .collect()
+    }
+
+    pub fn compute_speaker_embedding(
+        &mut self,
+        samples: Vec<f32>,
+        sample_rate: u32,
+    ) -> Result<Vec<f32>> {
+        self.compute(&samples, sample_rate)
+    }
+
     #[allow(clippy::missing_safety_doc)]
     pub unsafe fn is_ready(
         &mut self,
         stream: *const sherpa_rs_sys::SherpaOnnxOnlineStream,
     ) -> bool {
         unsafe {
-            let result =
-                sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorIsReady(self.extractor, stream);
+            let result = sherpa_rs_sys::SherpaOnnxSpeakerEmbeddingExtractorIsReady(
+                self.extractor.as_ptr(),
+                stream,
+            );
             result != 0
         }
     }
@@ -109,10 +290,96 @@ impl EmbeddingExtractor {
 unsafe impl Send for EmbeddingExtractor {}
 unsafe impl Sync for EmbeddingExtractor {}
 
-impl Drop for EmbeddingExtractor {
-    fn drop(&mut self) {
+/// Audio fed to an [`EmbeddingExtractor`], freed on drop.
+pub struct EmbeddingStream<'a> {
+    stream: NativeHandle<sherpa_rs_sys::SherpaOnnxOnlineStream>,
+    samples: usize,
+    sample_rate: u32,
+    _extractor: PhantomData<&'a EmbeddingExtractor>,
+}
+
+impl EmbeddingStream<'_> {
+    pub fn accept_waveform(&mut self, sample_rate: u32, samples: &[f32]) {
+        self.samples += samples.len();
+        self.sample_rate = sample_rate;
         unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroySpeakerEmbeddingExtractor(self.extractor);
+            sherpa_rs_sys::SherpaOnnxOnlineStreamAcceptWaveform(
+                self.stream.as_ptr(),
+                sample_rate as i32,
+                samples.as_ptr(),
+                samples.len() as i32,
+            );
         }
     }
+
+    pub fn input_finished(&mut self) {
+        unsafe { sherpa_rs_sys::SherpaOnnxOnlineStreamInputFinished(self.stream.as_ptr()) };
+    }
+}
+
+unsafe impl Send for EmbeddingStream<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn assert_invalid<T: std::fmt::Debug>(result: Result<T>) {
+        let err = result.unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn cosine_similarity_of_identical_and_orthogonal() {
+        let a = [0.3, -1.2, 2.5];
+        assert_close(cosine_similarity(&a, &a).unwrap(), 1.0);
+        // Scale doesn't matter
+        assert_close(cosine_similarity(&a, &[0.6, -2.4, 5.0]).unwrap(), 1.0);
+        assert_close(cosine_similarity(&a, &[-0.3, 1.2, -2.5]).unwrap(), -1.0);
+        assert_close(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]).unwrap(), 0.0);
+        assert_close(
+            cosine_similarity(&[1.0, 0.0], &[1.0, 1.0]).unwrap(),
+            std::f32::consts::FRAC_1_SQRT_2,
+        );
+    }
+
+    #[test]
+    fn cosine_similarity_rejects_bad_input() {
+        assert_invalid(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]));
+        assert_invalid(cosine_similarity(&[1.0, 0.0], &[0.0, 0.0]));
+        assert_invalid(cosine_similarity(&[1.0, 0.0], &[1.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn verifier_averages_normalized_embeddings() {
+        let verifier = SpeakerVerifier::new(&[vec![2.0, 0.0], vec![0.0, 0.5]], 0.9).unwrap();
+        assert_eq!(verifier.enrollment(), [0.5, 0.5]);
+
+        let same = verifier.verify(&[3.0, 3.0]).unwrap();
+        assert_close(same.score, 1.0);
+        assert!(same.accepted);
+
+        let other = verifier.verify(&[1.0, 0.0]).unwrap();
+        assert_close(other.score, std::f32::consts::FRAC_1_SQRT_2);
+        assert!(!other.accepted);
+    }
+
+    #[test]
+    fn verifier_rejects_bad_input() {
+        assert_invalid(SpeakerVerifier::new(&[], 0.5));
+        assert_invalid(SpeakerVerifier::new(&[vec![1.0, 0.0], vec![0.0, 0.0]], 0.5));
+        assert_invalid(SpeakerVerifier::new(&[vec![1.0, 0.0], vec![1.0]], 0.5));
+
+        let verifier = SpeakerVerifier::new(&[vec![1.0, 0.0]], 0.5).unwrap();
+        assert_invalid(verifier.verify(&[1.0, 0.0, 0.0]));
+        assert_invalid(verifier.verify(&[0.0, 0.0]));
+    }
 }
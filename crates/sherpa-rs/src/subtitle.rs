@@ -0,0 +1,565 @@
+//! SubRip (SRT) and WebVTT subtitles from [`TranscribedSegment`]s.
+//!
+//! Segments are split into cues of at most two lines and [`SubtitleOptions::max_cue_secs`]
+//! seconds, at word boundaries. Word timestamps place the splits when the model reports
+//! them, otherwise time is shared out by the number of characters. Cues never overlap.
+//!
+//! Files are written as UTF-8 without a byte order mark, with `\n` line endings.
+//! [`read_srt`] accepts a BOM and `\r\n` too.
+
+use std::{fmt::Write, fs, mem, ops::Range, path::Path};
+
+use crate::{pipeline::TranscribedSegment, punctuate::is_cjk, Error, Result};
+
+/// Lines of text in a cue.
+const LINES_PER_CUE: usize = 2;
+
+#[derive(Debug, Clone)]
+pub struct SubtitleOptions {
+    /// Longest line of a cue, in characters. A single longer word gets a line of its own.
+    pub max_line_chars: usize,
+    /// Longest a cue stays on screen, in seconds. Longer segments are split.
+    pub max_cue_secs: f32,
+    /// Cues shorter than this many seconds are merged into the next one when the two
+    /// fit in a single cue.
+    pub merge_under_secs: Option<f32>,
+}
+
+impl Default for SubtitleOptions {
+    fn default() -> Self {
+        Self {
+            max_line_chars: 42,
+            max_cue_secs: 7.0,
+            merge_under_secs: None,
+        }
+    }
+}
+
+/// A subtitle as it's written, text already wrapped into lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start_secs: f32,
+    pub end_secs: f32,
+    pub lines: Vec<String>,
+}
+
+impl TranscribedSegment {
+    /// Split into segments of at most `max_chars` characters, breaking between words.
+    ///
+    /// Each part keeps the times of its words when [`Self::words`] has them, otherwise
+    /// the segment's time is divided by the number of characters. The parts cover the
+    /// whole segment without gaps. A single word longer than `max_chars` stays whole.
+    pub fn split_at_word_boundaries(&self, max_chars: usize) -> Vec<TranscribedSegment> {
+        let text = self.text.trim();
+        if max_chars == 0 || text.chars().count() <= max_chars {
+            return vec![self.clone()];
+        }
+
+        let (units, times): (Vec<String>, Vec<(f32, f32)>) = if self.words.is_empty() {
+            let units = text_units(text);
+            let total = units.iter().map(|unit| unit.chars().count()).sum::<usize>();
+            let per_char = (self.end_secs - self.start_secs).max(0.0) / total.max(1) as f32;
+            let mut offset = 0;
+            let times = units
+                .iter()
+                .map(|unit| {
+                    let start = self.start_secs + offset as f32 * per_char;
+                    offset += unit.chars().count();
+                    (start, self.start_secs + offset as f32 * per_char)
+                })
+                .collect();
+            (units, times)
+        } else {
+            self.words
+                .iter()
+                .map(|word| (word.word.clone(), (word.start, word.end)))
+                .unzip()
+        };
+
+        let groups = group_units(&units, max_chars);
+        let last = groups.len() - 1;
+        groups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| {
+                // Each part runs until the next one starts, so there are no gaps
+                let start_secs = if i == 0 {
+                    self.start_secs
+                } else {
+                    times[group.start].0
+                };
+                let end_secs = if i == last {
+                    self.end_secs
+                } else {
+                    times[groups[i + 1].start].0
+                };
+                let words = if self.words.is_empty() {
+                    Vec::new()
+                } else {
+                    self.words[group.clone()].to_vec()
+                };
+                TranscribedSegment {
+                    start_secs,
+                    end_secs: end_secs.max(start_secs),
+                    text: join_units(&units[group.clone()]),
+                    words,
+                    lang: self.lang.clone(),
+                    confidence: self.confidence,
+                    channel: self.channel,
+                    speaker: self.speaker.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Cues for `segments`, in order and without overlaps. Segments without text are
+/// skipped.
+pub fn cues(segments: &[TranscribedSegment], options: &SubtitleOptions) -> Vec<Cue> {
+    let cue_chars = options.max_line_chars.saturating_mul(LINES_PER_CUE);
+    let mut parts: Vec<TranscribedSegment> = Vec::new();
+    for segment in segments {
+        if segment.text.trim().is_empty() {
+            continue;
+        }
+        for part in segment.split_at_word_boundaries(cue_chars) {
+            parts.extend(split_long(part, options.max_cue_secs));
+        }
+    }
+
+    if let Some(min_secs) = options.merge_under_secs {
+        let mut merged: Vec<TranscribedSegment> = Vec::new();
+        for part in parts {
+            match merged.last_mut() {
+                Some(prev) if fits_merged(prev, &part, min_secs, cue_chars, options) => {
+                    prev.text = join_units(&[prev.text.as_str(), part.text.as_str()]);
+                    prev.end_secs = part.end_secs;
+                    prev.words.extend(part.words);
+                }
+                _ => merged.push(part),
+            }
+        }
+        parts = merged;
+    }
+
+    let mut cues: Vec<Cue> = parts
+        .iter()
+        .map(|part| Cue {
+            start_secs: part.start_secs.max(0.0),
+            end_secs: part.end_secs.max(part.start_secs).max(0.0),
+            lines: wrap(&part.text, options.max_line_chars),
+        })
+        .collect();
+    for i in 1..cues.len() {
+        let start = cues[i].start_secs;
+        let prev = &mut cues[i - 1];
+        prev.end_secs = prev.end_secs.min(start).max(prev.start_secs);
+    }
+    cues
+}
+
+/// SRT text for `segments`, numbered from 1 with `HH:MM:SS,mmm` times.
+pub fn to_srt(segments: &[TranscribedSegment], options: &SubtitleOptions) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues(segments, options).iter().enumerate() {
+        let _ = write!(
+            out,
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            srt_time(cue.start_secs),
+            srt_time(cue.end_secs),
+            cue.lines.join("\n")
+        );
+    }
+    out
+}
+
+/// WebVTT text for `segments`, with `HH:MM:SS.mmm` times. `&` and `<` are escaped.
+pub fn to_vtt(segments: &[TranscribedSegment], options: &SubtitleOptions) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in cues(segments, options) {
+        let text = cue
+            .lines
+            .join("\n")
+            .replace('&', "&amp;")
+            .replace('<', "&lt;");
+        let _ = write!(
+            out,
+            "{} --> {}\n{}\n\n",
+            vtt_time(cue.start_secs),
+            vtt_time(cue.end_secs),
+            text
+        );
+    }
+    out
+}
+
+pub fn write_srt<P: AsRef<Path>>(
+    path: P,
+    segments: &[TranscribedSegment],
+    options: &SubtitleOptions,
+) -> Result<()> {
+    fs::write(path, to_srt(segments, options))?;
+    Ok(())
+}
+
+pub fn write_vtt<P: AsRef<Path>>(
+    path: P,
+    segments: &[TranscribedSegment],
+    options: &SubtitleOptions,
+) -> Result<()> {
+    fs::write(path, to_vtt(segments, options))?;
+    Ok(())
+}
+
+/// Segments of an SRT file, one per cue, with the lines of each cue joined back into
+/// one text. Words and language are empty.
+pub fn read_srt<P: AsRef<Path>>(path: P) -> Result<Vec<TranscribedSegment>> {
+    parse_srt(&fs::read_to_string(path)?)
+}
+
+/// Like [`read_srt`], from the text of a file.
+pub fn parse_srt(text: &str) -> Result<Vec<TranscribedSegment>> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut segments = Vec::new();
+    let mut lines = text.lines().map(str::trim_end).enumerate().peekable();
+    while let Some((_, line)) = lines.peek() {
+        if line.trim().is_empty() {
+            lines.next();
+            continue;
+        }
+        // The cue number is optional
+        let (mut number, mut timing) = lines.next().unwrap();
+        if !timing.contains("-->") {
+            (number, timing) = lines.next().ok_or_else(|| {
+                Error::invalid_input(format!("SRT cue at line {} has no times", number + 1))
+            })?;
+        }
+        let (start_secs, end_secs) = parse_timing(timing).ok_or_else(|| {
+            Error::invalid_input(format!(
+                "SRT line {}: expected `start --> end`, got {:?}",
+                number + 1,
+                timing
+            ))
+        })?;
+        let mut cue_lines = Vec::new();
+        while let Some((_, line)) = lines.next_if(|(_, line)| !line.trim().is_empty()) {
+            cue_lines.push(line.trim());
+        }
+        segments.push(TranscribedSegment {
+            start_secs,
+            end_secs,
+            text: join_units(&cue_lines),
+            words: Vec::new(),
+            lang: String::new(),
+            confidence: None,
+            channel: None,
+            speaker: None,
+        });
+    }
+    Ok(segments)
+}
+
+/// `HH:MM:SS,mmm`, as SRT writes times.
+pub(crate) fn srt_time(secs: f32) -> String {
+    let (h, m, s, ms) = split_time(secs);
+    format!("{:02}:{:02}:{:02},{:03}", h, m, s, ms)
+}
+
+/// `HH:MM:SS.mmm`, as WebVTT writes times.
+pub(crate) fn vtt_time(secs: f32) -> String {
+    let (h, m, s, ms) = split_time(secs);
+    format!("{:02}:{:02}:{:02}.{:03}", h, m, s, ms)
+}
+
+fn split_time(secs: f32) -> (u64, u64, u64, u64) {
+    let ms = (secs.max(0.0) as f64 * 1000.0).round() as u64;
+    (ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+/// Seconds of `HH:MM:SS,mmm`, also with a dot before the milliseconds.
+fn parse_time(time: &str) -> Option<f32> {
+    let (hms, ms) = time.trim().split_once([',', '.'])?;
+    let mut parts = hms.split(':').map(|part| part.parse::<u64>().ok());
+    let (h, m, s) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || ms.len() != 3 {
+        return None;
+    }
+    let ms: u64 = ms.parse().ok()?;
+    Some(((h * 3600 + m * 60 + s) * 1000 + ms) as f32 / 1000.0)
+}
+
+fn parse_timing(line: &str) -> Option<(f32, f32)> {
+    let (start, end) = line.split_once("-->")?;
+    // Position settings may follow the end time
+    let end = end.split_whitespace().next()?;
+    Some((parse_time(start)?, parse_time(end)?))
+}
+
+/// Split `part` into pieces of at most `max_secs`, if it has more than one word.
+fn split_long(part: TranscribedSegment, max_secs: f32) -> Vec<TranscribedSegment> {
+    let duration = part.end_secs - part.start_secs;
+    if max_secs <= 0.0 || duration <= max_secs {
+        return vec![part];
+    }
+    let pieces = (duration / max_secs).ceil() as usize;
+    let chars = part.text.chars().count().div_ceil(pieces).max(1);
+    let split = part.split_at_word_boundaries(chars);
+    if split.len() < 2 {
+        return split;
+    }
+    split
+        .into_iter()
+        .flat_map(|piece| split_long(piece, max_secs))
+        .collect()
+}
+
+fn fits_merged(
+    prev: &TranscribedSegment,
+    next: &TranscribedSegment,
+    min_secs: f32,
+    cue_chars: usize,
+    options: &SubtitleOptions,
+) -> bool {
+    let short = prev.end_secs - prev.start_secs < min_secs;
+    let chars = prev.text.chars().count() + 1 + next.text.chars().count();
+    short
+        && chars <= cue_chars
+        && (options.max_cue_secs <= 0.0 || next.end_secs - prev.start_secs <= options.max_cue_secs)
+}
+
+/// Words of `text`, with each CJK character a unit of its own. Punctuation stays on
+/// the unit before it.
+fn text_units(text: &str) -> Vec<String> {
+    let mut units: Vec<String> = Vec::new();
+    for chunk in text.split_whitespace() {
+        let mut pieces = Vec::new();
+        let mut current = String::new();
+        for c in chunk.chars() {
+            if is_cjk(c) && c.is_alphanumeric() {
+                if !current.is_empty() {
+                    pieces.push(mem::take(&mut current));
+                }
+                pieces.push(c.to_string());
+            } else {
+                current.push(c);
+            }
+        }
+        if !current.is_empty() {
+            pieces.push(current);
+        }
+        for (i, piece) in pieces.into_iter().enumerate() {
+            match units.last_mut() {
+                Some(last) if i > 0 && !piece.chars().any(char::is_alphanumeric) => {
+                    last.push_str(&piece)
+                }
+                _ => units.push(piece),
+            }
+        }
+    }
+    units
+}
+
+/// Join units with spaces, except between CJK characters.
+fn join_units<S: AsRef<str>>(units: &[S]) -> String {
+    let mut out = String::new();
+    for unit in units
+        .iter()
+        .map(|unit| unit.as_ref().trim())
+        .filter(|u| !u.is_empty())
+    {
+        let cjk_boundary =
+            out.chars().last().is_some_and(is_cjk) && unit.chars().next().is_some_and(is_cjk);
+        if !out.is_empty() && !cjk_boundary {
+            out.push(' ');
+        }
+        out.push_str(unit);
+    }
+    out
+}
+
+/// Consecutive runs of `units` of at most `max_chars` once joined.
+fn group_units<S: AsRef<str>>(units: &[S], max_chars: usize) -> Vec<Range<usize>> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for end in 1..=units.len() {
+        let fits = join_units(&units[start..end]).chars().count() <= max_chars;
+        if !fits && end - 1 > start {
+            groups.push(start..end - 1);
+            start = end - 1;
+        }
+    }
+    if start < units.len() || groups.is_empty() {
+        groups.push(start..units.len());
+    }
+    groups
+}
+
+/// Lines of at most `max_chars`, broken between words.
+fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let units = text_units(text);
+    if max_chars == 0 || units.is_empty() {
+        return vec![text.trim().to_string()];
+    }
+    group_units(&units, max_chars)
+        .into_iter()
+        .map(|group| join_units(&units[group]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::ScratchDir, WordSpan};
+
+    fn segment(start_secs: f32, end_secs: f32, text: &str) -> TranscribedSegment {
+        TranscribedSegment {
+            start_secs,
+            end_secs,
+            text: text.to_string(),
+            words: Vec::new(),
+            lang: String::new(),
+            confidence: None,
+            channel: None,
+            speaker: None,
+        }
+    }
+
+    fn word(word: &str, start: f32, end: f32) -> WordSpan {
+        WordSpan {
+            word: word.to_string(),
+            start,
+            end,
+        }
+    }
+
+    fn spans(parts: &[TranscribedSegment]) -> Vec<(&str, f32, f32)> {
+        parts
+            .iter()
+            .map(|part| (part.text.as_str(), part.start_secs, part.end_secs))
+            .collect()
+    }
+
+    #[test]
+    fn time_formats() {
+        assert_eq!(srt_time(0.0), "00:00:00,000");
+        assert_eq!(vtt_time(0.0), "00:00:00.000");
+        assert_eq!(srt_time(3661.25), "01:01:01,250");
+        assert_eq!(vtt_time(3661.25), "01:01:01.250");
+        assert_eq!(srt_time(-1.0), "00:00:00,000");
+    }
+
+    #[test]
+    fn time_rounds_up_into_the_next_minute() {
+        assert_eq!(srt_time(59.9995), "00:01:00,000");
+        assert_eq!(vtt_time(59.9995), "00:01:00.000");
+        assert_eq!(srt_time(59.9994), "00:00:59,999");
+    }
+
+    #[test]
+    fn parse_time_accepts_comma_and_dot() {
+        assert_eq!(parse_time("01:01:01,250"), Some(3661.25));
+        assert_eq!(parse_time("01:01:01.250"), Some(3661.25));
+        assert_eq!(parse_time("01:01:01,25"), None);
+        assert_eq!(parse_time("01:01,250"), None);
+    }
+
+    #[test]
+    fn parse_srt_accepts_bom_and_crlf() {
+        let text = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\nHello\r\nthere\r\n\r\n\
+                    2\r\n00:00:03,000 --> 00:00:04,000\r\nBye\r\n";
+        let segments = parse_srt(text).unwrap();
+        assert_eq!(
+            spans(&segments),
+            [("Hello there", 1.0, 2.5), ("Bye", 3.0, 4.0)]
+        );
+    }
+
+    #[test]
+    fn parse_srt_without_numbers_or_with_settings() {
+        let segments = parse_srt("00:00:01.000 --> 00:00:02.500 align:start\nNo number\n").unwrap();
+        assert_eq!(spans(&segments), [("No number", 1.0, 2.5)]);
+    }
+
+    #[test]
+    fn parse_srt_rejects_bad_timing() {
+        let err = parse_srt("1\n00:00:01,000 -> 00:00:02,000\nHello\n").unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn vtt_escapes_markup() {
+        let vtt = to_vtt(
+            &[segment(1.0, 2.5, "Tom & Jerry <3")],
+            &SubtitleOptions::default(),
+        );
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n00:00:01.000 --> 00:00:02.500\nTom &amp; Jerry &lt;3\n\n"
+        );
+    }
+
+    #[test]
+    fn srt_round_trip() {
+        let dir = ScratchDir::new("srt-round-trip");
+        let path = dir.join("out.srt");
+        let segments = [
+            segment(0.0, 1.5, "Hello world."),
+            segment(1.5, 3.25, "How are you?"),
+            segment(3661.0, 3662.125, "Fine & you?"),
+        ];
+        write_srt(&path, &segments, &SubtitleOptions::default()).unwrap();
+        let read = read_srt(&path).unwrap();
+        assert_eq!(spans(&read), spans(&segments));
+    }
+
+    #[test]
+    fn cues_do_not_overlap() {
+        let cues = cues(
+            &[segment(0.0, 2.0, "One."), segment(1.5, 3.0, "Two.")],
+            &SubtitleOptions::default(),
+        );
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].end_secs, 1.5);
+    }
+
+    #[test]
+    fn split_without_word_times_shares_time_by_characters() {
+        let parts = segment(0.0, 10.0, "aaaa bbbb cccc dddd").split_at_word_boundaries(9);
+        assert_eq!(
+            spans(&parts),
+            [("aaaa bbbb", 0.0, 5.0), ("cccc dddd", 5.0, 10.0)]
+        );
+        assert!(parts.iter().all(|part| part.words.is_empty()));
+    }
+
+    #[test]
+    fn split_with_word_times_follows_the_words() {
+        let mut whole = segment(0.0, 3.0, "one two three four");
+        whole.words = vec![
+            word("one", 0.1, 0.6),
+            word("two", 0.6, 1.2),
+            word("three", 1.5, 2.0),
+            word("four", 2.2, 2.9),
+        ];
+        let parts = whole.split_at_word_boundaries(9);
+        assert_eq!(
+            spans(&parts),
+            [
+                ("one two", 0.0, 1.5),
+                ("three", 1.5, 2.2),
+                ("four", 2.2, 3.0)
+            ]
+        );
+        let words: Vec<usize> = parts.iter().map(|part| part.words.len()).collect();
+        assert_eq!(words, [2, 1, 1]);
+    }
+
+    #[test]
+    fn split_keeps_short_text_and_long_words_whole() {
+        let short = segment(0.0, 1.0, "short");
+        assert_eq!(spans(&short.split_at_word_boundaries(9)), spans(&[short]));
+        let long = segment(0.0, 1.0, "supercalifragilistic");
+        assert_eq!(long.split_at_word_boundaries(5).len(), 1);
+    }
+}
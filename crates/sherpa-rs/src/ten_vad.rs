@@ -1,15 +1,25 @@
 use std::mem;
 
-use crate::{get_default_provider, utils::cstring_from_str};
-use eyre::Result;
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::cstring_from_str,
+    Error, FallbackPolicy, Provider, Result, Threads,
+};
 
 #[derive(Debug)]
 pub struct TenVad {
-    pub(crate) vad: *const sherpa_rs_sys::SherpaOnnxVoiceActivityDetector,
+    pub(crate) vad: NativeHandle<sherpa_rs_sys::SherpaOnnxVoiceActivityDetector>,
+    provider: ProviderSelection,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct TenVadConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
     pub threshold: f32,
     pub min_silence_duration: f32,
@@ -17,8 +27,9 @@ pub struct TenVadConfig {
     pub max_speech_duration: f32,
     pub sample_rate: u32,
     pub window_size: i32,
-    pub provider: Option<String>,
-    pub num_threads: Option<i32>,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub num_threads: Threads,
     pub debug: bool,
 }
 
@@ -33,7 +44,8 @@ impl Default for TenVadConfig {
             sample_rate: 16000,
             window_size: 256,
             provider: None,
-            num_threads: Some(1),
+            fallback: FallbackPolicy::default(),
+            num_threads: Threads::ONE,
             debug: false,
         }
     }
@@ -47,10 +59,37 @@ pub struct SpeechSegment {
 
 impl TenVad {
     pub fn new(config: TenVadConfig, buffer_size_in_seconds: f32) -> Result<Self> {
-        let provider = config.provider.unwrap_or(get_default_provider());
+        let _timed = timed!("ten_vad.create");
+        debug_event!(?config, "creating ten vad");
+        let (mut vad, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(
+                    TenVadConfig {
+                        provider: Some(provider),
+                        ..config.clone()
+                    },
+                    buffer_size_in_seconds,
+                )
+            })?;
+        vad.provider = provider;
+        Ok(vad)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    fn create(config: TenVadConfig, buffer_size_in_seconds: f32) -> Result<Self> {
+        let provider = config.provider.unwrap_or_else(get_default_provider);
 
         let model = cstring_from_str(&config.model);
-        let provider = cstring_from_str(&provider);
+        let provider = provider.to_cstring()?;
 
         let ten_vad = sherpa_rs_sys::SherpaOnnxTenVadModelConfig {
             model: model.as_ptr(),
@@ -65,30 +104,38 @@ impl TenVad {
             sherpa_rs_sys::SherpaOnnxVadModelConfig {
                 debug,
                 provider: provider.as_ptr(),
-                num_threads: config.num_threads.unwrap_or(1),
+                num_threads: config.num_threads.to_raw(),
                 sample_rate: config.sample_rate as i32,
                 silero_vad: mem::zeroed::<_>(),
                 ten_vad,
             }
         };
 
-        unsafe {
-            let vad = sherpa_rs_sys::SherpaOnnxCreateVoiceActivityDetector(
-                &vad_config,
-                buffer_size_in_seconds,
-            );
-
-            Ok(Self { vad })
+        let vad = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateVoiceActivityDetector(
+                    &vad_config,
+                    buffer_size_in_seconds,
+                ),
+                sherpa_rs_sys::SherpaOnnxDestroyVoiceActivityDetector,
+            )
         }
+        .ok_or_else(|| Error::native_creation_failed("ten vad"))?;
+
+        Ok(Self {
+            vad,
+            provider: ProviderSelection::default(),
+        })
     }
 
     pub fn is_empty(&mut self) -> bool {
-        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorEmpty(self.vad) == 1 }
+        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorEmpty(self.vad.as_ptr()) == 1 }
     }
 
     pub fn front(&mut self) -> SpeechSegment {
         unsafe {
-            let segment_ptr = sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorFront(self.vad);
+            let segment_ptr =
+                sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorFront(self.vad.as_ptr());
             let raw_segment = segment_ptr.read();
             let samples: &[f32] =
                 std::slice::from_raw_parts(raw_segment.samples, raw_segment.n as usize);
@@ -107,7 +154,7 @@ impl TenVad {
 
     pub fn flush(&mut self) {
         unsafe {
-            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorFlush(self.vad);
+            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorFlush(self.vad.as_ptr());
         }
     }
 
@@ -116,7 +163,7 @@ impl TenVad {
         let samples_length = samples.len();
         unsafe {
             sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorAcceptWaveform(
-                self.vad,
+                self.vad.as_ptr(),
                 samples_ptr,
                 samples_length.try_into().unwrap(),
             );
@@ -125,28 +172,20 @@ impl TenVad {
 
     pub fn pop(&mut self) {
         unsafe {
-            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorPop(self.vad);
+            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorPop(self.vad.as_ptr());
         }
     }
 
     pub fn is_speech(&mut self) -> bool {
-        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorDetected(self.vad) == 1 }
+        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorDetected(self.vad.as_ptr()) == 1 }
     }
 
     pub fn clear(&mut self) {
         unsafe {
-            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorClear(self.vad);
+            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorClear(self.vad.as_ptr());
         }
     }
 }
 
 unsafe impl Send for TenVad {}
 unsafe impl Sync for TenVad {}
-
-impl Drop for TenVad {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyVoiceActivityDetector(self.vad);
-        }
-    }
-}
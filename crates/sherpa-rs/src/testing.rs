@@ -0,0 +1,50 @@
+//! Helpers shared by the unit tests.
+
+use std::{fs, path::PathBuf};
+
+/// An empty directory under the system temp dir, removed again on drop.
+pub(crate) struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    /// `name` keeps tests running in parallel out of each other's way.
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("sherpa-rs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    /// Path of `name` in the directory, as the string configs take. Not created.
+    pub(crate) fn join(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().into_owned()
+    }
+
+    /// Create an empty file called `name`.
+    pub(crate) fn touch(&self, name: &str) -> String {
+        let path = self.join(name);
+        fs::write(&path, b"").unwrap();
+        path
+    }
+
+    /// Create an empty directory called `name`.
+    pub(crate) fn mkdir(&self, name: &str) -> String {
+        let path = self.join(name);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Path of a model file for the `#[ignore]`d tests that need one, under
+/// `SHERPA_RS_TEST_MODELS` or else the repository root, where the examples download to.
+pub(crate) fn model(name: &str) -> String {
+    let root = std::env::var_os("SHERPA_RS_TEST_MODELS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../.."));
+    root.join(name).to_string_lossy().into_owned()
+}
@@ -0,0 +1,63 @@
+//! Debug diagnostics behind the `tracing` feature: a span per call with its elapsed time,
+//! config summaries and the provider a model was created with.
+//!
+//! sherpa-onnx has no log callback in its C API, so what it prints with `debug: true`
+//! still goes to stderr.
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+/// Enter a debug span named `$name` until the returned guard drops, then log the elapsed
+/// time. Compiles to nothing without the `tracing` feature.
+#[cfg(feature = "tracing")]
+macro_rules! timed {
+    ($name:literal $(, $($field:tt)+)?) => {
+        $crate::trace::Timed::new(tracing::debug_span!($name $(, $($field)+)?))
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! timed {
+    ($name:literal $(, $($field:tt)+)?) => {
+        $crate::trace::Timed
+    };
+}
+
+/// `tracing::debug!` that compiles to nothing without the `tracing` feature.
+macro_rules! debug_event {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)+);
+    }};
+}
+
+pub(crate) use {debug_event, timed};
+
+#[cfg(feature = "tracing")]
+pub(crate) struct Timed {
+    start: Instant,
+    _span: tracing::span::EnteredSpan,
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Timed;
+
+#[cfg(feature = "tracing")]
+impl Timed {
+    pub(crate) fn new(span: tracing::Span) -> Self {
+        Self {
+            start: Instant::now(),
+            _span: span.entered(),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for Timed {
+    fn drop(&mut self) {
+        tracing::debug!(
+            elapsed_ms = self.start.elapsed().as_secs_f64() * 1000.0,
+            "finished"
+        );
+    }
+}
@@ -1,30 +1,68 @@
+use crate::hotwords::{HotwordsFile, HotwordsModel};
 use crate::utils::cstr_to_string;
-use crate::{get_default_provider, utils::cstring_from_str};
-use eyre::{bail, Result};
-use std::mem;
+use crate::{
+    decoding::{warn_unused_beam_options, DEFAULT_MAX_ACTIVE_PATHS},
+    get_default_provider,
+    handle::NativeHandle,
+    itn::ItnConfig,
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{cstring_from_str, time_warm_up, warm_up_silence, WARM_UP_SAMPLE_RATE},
+    DecodingMethod, Error, FallbackPolicy, Provider, Result, Threads,
+};
+use std::{
+    mem,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 pub struct TransducerRecognizer {
-    recognizer: *const sherpa_rs_sys::SherpaOnnxOfflineRecognizer,
+    recognizer: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineRecognizer>,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct TransducerConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub decoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub encoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub joiner: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
-    pub num_threads: i32,
+    pub num_threads: Threads,
     pub sample_rate: i32,
     pub feature_dim: i32,
-    pub decoding_method: String,
-    pub hotwords_file: String,
+    pub decoding_method: DecodingMethod,
+    /// Hypotheses kept by modified beam search.
+    pub max_active_paths: i32,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::opt_path_buf")
+    )]
+    pub hotwords_file: Option<PathBuf>,
     pub hotwords_score: f32,
+    /// Extra hotwords, one phrase per entry, merged with `hotwords_file`.
+    /// An entry may end with a boost such as `"HELLO WORLD :2.0"`.
+    /// Requires [`DecodingMethod::ModifiedBeamSearch`].
+    pub hotwords: Vec<String>,
     pub modeling_unit: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub bpe_vocab: String,
+    /// Subtracted from the blank score before search. Raise it when words get dropped,
+    /// e.g. quiet or fast speech, at the cost of more insertions.
     pub blank_penalty: f32,
     pub model_type: String,
     pub debug: bool,
-    pub provider: Option<String>,
+    /// Inverse text normalization and homophone replacement applied to the text.
+    pub itn: ItnConfig,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
 }
 
 impl Default for TransducerConfig {
@@ -35,27 +73,111 @@ impl Default for TransducerConfig {
             joiner: String::new(),
             tokens: String::new(),
             model_type: String::from("transducer"),
-            num_threads: 1,
+            num_threads: Threads::ONE,
             sample_rate: 0,
             feature_dim: 0,
-            decoding_method: String::new(),
-            hotwords_file: String::new(),
+            decoding_method: DecodingMethod::GreedySearch,
+            max_active_paths: DEFAULT_MAX_ACTIVE_PATHS,
+            hotwords_file: None,
             hotwords_score: 0.0,
+            hotwords: Vec::new(),
             modeling_unit: String::new(),
             bpe_vocab: String::new(),
             blank_penalty: 0.0,
             debug: false,
+            itn: ItnConfig::default(),
             provider: None,
+            fallback: FallbackPolicy::default(),
         }
     }
 }
 
 impl TransducerRecognizer {
+    /// Like [`Self::new`], with the encoder, decoder, joiner and tokens of the
+    /// sherpa-onnx release unpacked in `dir`.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: TransducerConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Transducer,
+            precision,
+            &config.provider.clone().unwrap_or_else(get_default_provider),
+        )?;
+        Self::new(TransducerConfig {
+            encoder: dir.file(Role::Encoder),
+            decoder: dir.file(Role::Decoder),
+            joiner: dir.file(Role::Joiner),
+            tokens: dir.file(Role::Tokens),
+            ..config
+        })
+    }
+
     pub fn new(config: TransducerConfig) -> Result<Self> {
+        let _timed = timed!("transducer.create");
+        debug_event!(?config, "creating transducer recognizer");
+        let (mut recognizer, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(TransducerConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        recognizer.provider = provider;
+        Ok(recognizer)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model files.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Transcribe half a second of silence and discard it, so the first real
+    /// [`Self::transcribe`] doesn't pay for ONNX Runtime's one-time setup. Returns how long
+    /// it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("transducer.warm_up");
+        time_warm_up(|| {
+            self.transcribe(WARM_UP_SAMPLE_RATE, &warm_up_silence(WARM_UP_SAMPLE_RATE));
+            Ok(())
+        })
+    }
+
+    fn create(config: TransducerConfig) -> Result<Self> {
+        let precision = ModelPrecision::of_file(&config.encoder);
+        let hotwords = HotwordsFile::new(
+            config.hotwords_file.as_deref(),
+            &config.hotwords,
+            &HotwordsModel {
+                tokens: &config.tokens,
+                modeling_unit: &config.modeling_unit,
+                bpe_vocab: &config.bpe_vocab,
+                decoding_method: config.decoding_method,
+            },
+        )?;
+        let itn = config.itn.to_raw()?;
+        warn_unused_beam_options(
+            "transducer",
+            config.decoding_method,
+            config.max_active_paths,
+        );
+
         let recognizer = unsafe {
             let debug = config.debug.into();
-            let provider = config.provider.unwrap_or(get_default_provider());
-            let provider_ptr = cstring_from_str(&provider);
+            let provider = config.provider.unwrap_or_else(get_default_provider);
+            let provider_ptr = provider.to_cstring()?;
 
             let encoder = cstring_from_str(&config.encoder);
             let decoder = cstring_from_str(&config.decoder);
@@ -63,9 +185,9 @@ impl TransducerRecognizer {
             let model_type = cstring_from_str(&config.model_type);
             let modeling_unit = cstring_from_str(&config.modeling_unit);
             let bpe_vocab = cstring_from_str(&config.bpe_vocab);
-            let hotwords_file = cstring_from_str(&config.hotwords_file);
+            let hotwords_file = hotwords.to_cstring();
             let tokens = cstring_from_str(&config.tokens);
-            let decoding_method = cstring_from_str(&config.decoding_method);
+            let decoding_method = config.decoding_method.to_cstring();
 
             let offline_model_config = sherpa_rs_sys::SherpaOnnxOfflineModelConfig {
                 transducer: sherpa_rs_sys::SherpaOnnxOfflineTransducerModelConfig {
@@ -74,7 +196,7 @@ impl TransducerRecognizer {
                     joiner: joiner.as_ptr(),
                 },
                 tokens: tokens.as_ptr(),
-                num_threads: config.num_threads,
+                num_threads: config.num_threads.to_raw(),
                 debug,
                 provider: provider_ptr.as_ptr(),
                 model_type: model_type.as_ptr(),
@@ -108,34 +230,44 @@ impl TransducerRecognizer {
                 decoding_method: decoding_method.as_ptr(),
                 hotwords_score: config.hotwords_score,
 
+                rule_fsts: itn.rule_fsts(),
+                rule_fars: itn.rule_fars(),
+                hr: itn.homophone_replacer(),
+
                 // NULLs
                 lm_config: mem::zeroed::<_>(),
-                rule_fsts: mem::zeroed::<_>(),
-                rule_fars: mem::zeroed::<_>(),
-                max_active_paths: mem::zeroed::<_>(),
-                hr: mem::zeroed::<_>(),
+                max_active_paths: config.max_active_paths,
             };
 
-            let recognizer = sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&recognizer_config);
-            if recognizer.is_null() {
-                bail!("SherpaOnnxCreateOfflineRecognizer failed");
-            }
-            recognizer
-        };
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&recognizer_config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer,
+            )
+        }
+        .ok_or_else(|| Error::native_creation_failed("transducer recognizer"))?;
 
-        Ok(Self { recognizer })
+        Ok(Self {
+            recognizer,
+            provider: ProviderSelection::default(),
+            precision,
+        })
     }
 
     pub fn transcribe(&mut self, sample_rate: u32, samples: &[f32]) -> String {
+        let _timed = timed!(
+            "transducer.transcribe",
+            sample_rate,
+            samples = samples.len()
+        );
         unsafe {
-            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer);
+            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer.as_ptr());
             sherpa_rs_sys::SherpaOnnxAcceptWaveformOffline(
                 stream,
                 sample_rate as i32,
                 samples.as_ptr(),
                 samples.len().try_into().unwrap(),
             );
-            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer, stream);
+            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer.as_ptr(), stream);
             let result_ptr = sherpa_rs_sys::SherpaOnnxGetOfflineStreamResult(stream);
             let raw_result = result_ptr.read();
             let text = cstr_to_string(raw_result.text as _);
@@ -146,15 +278,17 @@ impl TransducerRecognizer {
             text
         }
     }
+
+    /// Decode several clips of `(samples, sample_rate)` in one native batch call.
+    ///
+    /// Results are in input order, an empty batch returns an empty Vec.
+    pub fn transcribe_batch(&mut self, inputs: &[(&[f32], u32)]) -> Vec<Result<String>> {
+        crate::decode_offline_batch(self.recognizer.as_ptr(), inputs)
+            .into_iter()
+            .map(|result| result.map(|r| r.text))
+            .collect()
+    }
 }
 
 unsafe impl Send for TransducerRecognizer {}
 unsafe impl Sync for TransducerRecognizer {}
-
-impl Drop for TransducerRecognizer {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer(self.recognizer);
-        }
-    }
-}
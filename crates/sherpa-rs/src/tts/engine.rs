@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use crate::{utils::time_warm_up, Error, Result};
+
+use super::{
+    KittenTts, KittenTtsConfig, KokoroTts, KokoroTtsConfig, MatchaTts, MatchaTtsConfig, TtsAudio,
+    VitsTts, VitsTtsConfig, ZipVoicePrompt, ZipVoiceTts, ZipVoiceTtsConfig, WARM_UP_TEXT,
+};
+
+/// Common interface over the TTS engine wrappers, for choosing an engine at runtime.
+///
+/// Engine-specific methods stay on the concrete types.
+pub trait TtsEngine: Send {
+    fn synthesize(&mut self, req: &TtsRequest) -> Result<TtsAudio>;
+
+    /// Output sample rate, or 0 if the engine failed to load.
+    fn sample_rate(&self) -> u32;
+
+    /// Synthesize a short phrase and discard it, so the first real request doesn't pay
+    /// for ONNX Runtime's one-time setup. Returns how long it took.
+    fn warm_up(&mut self) -> Result<Duration> {
+        time_warm_up(|| self.synthesize(&TtsRequest::new(WARM_UP_TEXT)))
+    }
+
+    /// Like [`Self::synthesize`], but fails with [`Error::Unsupported`] unless the same
+    /// request gives the same audio every run.
+    ///
+    /// No engine can promise that with the linked sherpa-onnx, so all of them fail: VITS,
+    /// Matcha and ZipVoice sample noise, Kokoro and Kitten's decoders can add noise, and
+    /// the C API has no seed for any of it.
+    fn synthesize_deterministic(&mut self, _req: &TtsRequest) -> Result<TtsAudio> {
+        Err(Error::unsupported(
+            "deterministic synthesis with this engine",
+        ))
+    }
+}
+
+/// Options for [`TtsEngine::synthesize`].
+#[derive(Debug, Clone)]
+pub struct TtsRequest<'a> {
+    pub text: &'a str,
+    pub sid: i32,
+    pub speed: f32,
+    /// Required by ZipVoice, ignored by the other engines.
+    pub zipvoice: Option<ZipVoiceRequest<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ZipVoiceRequest<'a> {
+    pub prompt: &'a ZipVoicePrompt,
+    pub num_steps: i32,
+}
+
+impl<'a> TtsRequest<'a> {
+    /// Speaker 0 at normal speed.
+    pub fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            sid: 0,
+            speed: 1.0,
+            zipvoice: None,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum TtsEngineConfig {
+    Vits(VitsTtsConfig),
+    Matcha(MatchaTtsConfig),
+    Kokoro(KokoroTtsConfig),
+    Kitten(KittenTtsConfig),
+    ZipVoice(ZipVoiceTtsConfig),
+}
+
+/// Create the engine described by `config`.
+pub fn from_config(config: TtsEngineConfig) -> Result<Box<dyn TtsEngine>> {
+    let engine: Box<dyn TtsEngine> = match config {
+        TtsEngineConfig::Vits(config) => Box::new(VitsTts::new(config)?),
+        TtsEngineConfig::Matcha(config) => Box::new(MatchaTts::new(config)?),
+        TtsEngineConfig::Kokoro(config) => Box::new(KokoroTts::new(config)?),
+        TtsEngineConfig::Kitten(config) => Box::new(KittenTts::new(config)?),
+        TtsEngineConfig::ZipVoice(config) => Box::new(ZipVoiceTts::new(config)?),
+    };
+    Ok(engine)
+}
+
+macro_rules! impl_sid_engine {
+    ($($ty:ty),*) => {
+        $(
+            impl TtsEngine for $ty {
+                fn synthesize(&mut self, req: &TtsRequest) -> Result<TtsAudio> {
+                    self.create(req.text, req.sid, req.speed)
+                }
+
+                fn sample_rate(&self) -> u32 {
+                    <$ty>::sample_rate(self)
+                }
+
+                fn warm_up(&mut self) -> Result<Duration> {
+                    <$ty>::warm_up(self)
+                }
+
+                fn synthesize_deterministic(&mut self, req: &TtsRequest) -> Result<TtsAudio> {
+                    self.create_deterministic(req.text, req.sid, req.speed)
+                }
+            }
+        )*
+    };
+}
+
+impl_sid_engine!(VitsTts, MatchaTts, KokoroTts, KittenTts);
+
+impl TtsEngine for ZipVoiceTts {
+    fn synthesize(&mut self, req: &TtsRequest) -> Result<TtsAudio> {
+        let Some(zipvoice) = &req.zipvoice else {
+            return Err(Error::invalid_input(
+                "zipvoice requires a prompt, set TtsRequest::zipvoice",
+            ));
+        };
+        self.create_with_prompt(req.text, zipvoice.prompt, req.speed, zipvoice.num_steps)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        ZipVoiceTts::sample_rate(self)
+    }
+
+    fn warm_up(&mut self) -> Result<Duration> {
+        Err(Error::unsupported(
+            "warming up zipvoice without a prompt, use ZipVoiceTts::warm_up",
+        ))
+    }
+
+    fn synthesize_deterministic(&mut self, req: &TtsRequest) -> Result<TtsAudio> {
+        let Some(zipvoice) = &req.zipvoice else {
+            return Err(Error::invalid_input(
+                "zipvoice requires a prompt, set TtsRequest::zipvoice",
+            ));
+        };
+        self.create_deterministic(req.text, zipvoice.prompt, req.speed, zipvoice.num_steps)
+    }
+}
@@ -1,79 +1,253 @@
-use std::{mem, ptr::null};
+use std::{mem, path::Path, ptr::null, time::Duration};
 
-use crate::{utils::cstring_from_str, OnnxConfig};
-use eyre::Result;
+use crate::{
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::ProviderSelection,
+    trace::timed,
+    utils::{cstring_from_str, time_warm_up},
+    CancellationToken, Error, OnnxConfig, Provider, Result,
+};
 use sherpa_rs_sys;
 
-use super::{CommonTtsConfig, TtsAudio};
+use super::{
+    CommonTtsConfig, Segmenter, TtsAudio, TtsGeneration, TtsHandle, TtsProgress, WARM_UP_TEXT,
+};
 
 pub struct KittenTts {
-    tts: *const sherpa_rs_sys::SherpaOnnxOfflineTts,
+    tts: TtsHandle,
+    segmenter: Segmenter,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
 }
 
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct KittenTtsConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub voices: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub data_dir: String,
     pub length_scale: f32,
     pub onnx_config: OnnxConfig,
     pub common_config: CommonTtsConfig,
 }
 
+impl KittenTtsConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, path) in [
+            ("model", &self.model),
+            ("voices", &self.voices),
+            ("tokens", &self.tokens),
+        ] {
+            if path.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "kitten {} path is empty",
+                    name
+                )));
+            }
+            if !Path::new(path).is_file() {
+                return Err(Error::model_not_found(format!("kitten {}", name), path));
+            }
+        }
+        if !self.data_dir.is_empty() && !Path::new(&self.data_dir).is_dir() {
+            return Err(Error::model_not_found("kitten data_dir", &self.data_dir));
+        }
+        self.common_config.to_raw()?;
+        Ok(())
+    }
+}
+
 impl KittenTts {
-    pub fn new(config: KittenTtsConfig) -> Self {
-        let tts = unsafe {
-            let model = cstring_from_str(&config.model);
-            let voices = cstring_from_str(&config.voices);
-            let tokens = cstring_from_str(&config.tokens);
-            let data_dir = cstring_from_str(&config.data_dir);
-
-            let provider = cstring_from_str(&config.onnx_config.provider);
-
-            let tts_config = config.common_config.to_raw();
-
-            let model_config = sherpa_rs_sys::SherpaOnnxOfflineTtsModelConfig {
-                vits: mem::zeroed::<_>(),
-                num_threads: config.onnx_config.num_threads,
-                debug: config.onnx_config.debug.into(),
-                provider: provider.as_ptr(),
-                matcha: mem::zeroed::<_>(),
-                kokoro: mem::zeroed(),
-                kitten: sherpa_rs_sys::SherpaOnnxOfflineTtsKittenModelConfig {
-                    model: model.as_ptr(),
-                    voices: voices.as_ptr(),
-                    tokens: tokens.as_ptr(),
-                    data_dir: data_dir.as_ptr(),
-                    length_scale: config.length_scale,
-                },
-                zipvoice: mem::zeroed::<_>(),
-            };
-            let config = sherpa_rs_sys::SherpaOnnxOfflineTtsConfig {
-                max_num_sentences: config.common_config.max_num_sentences,
-                model: model_config,
-                rule_fars: tts_config.rule_fars.map(|v| v.as_ptr()).unwrap_or(null()),
-                rule_fsts: tts_config.rule_fsts.map(|v| v.as_ptr()).unwrap_or(null()),
-                silence_scale: 1.0,
-            };
-            sherpa_rs_sys::SherpaOnnxCreateOfflineTts(&config)
+    /// Like [`Self::new`], with the model, voices, tokens and espeak-ng data found in
+    /// `dir`, an unpacked sherpa-onnx release.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: KittenTtsConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Kitten,
+            precision,
+            &config.onnx_config.provider,
+        )?;
+        Self::new(KittenTtsConfig {
+            model: dir.file(Role::Model),
+            voices: dir.file(Role::Voices),
+            tokens: dir.file(Role::Tokens),
+            data_dir: dir.file(Role::DataDir),
+            ..config
+        })
+    }
+
+    pub fn new(config: KittenTtsConfig) -> Result<Self> {
+        config.validate()?;
+        let (tts, provider) = Self::create_with_fallback(&config)?;
+        Ok(Self {
+            tts,
+            segmenter: Segmenter::new(&config.common_config),
+            provider,
+            precision: ModelPrecision::of_file(&config.model),
+        })
+    }
+
+    /// # Panics
+    ///
+    /// If the config is invalid or the model can't be created.
+    #[deprecated(note = "use `KittenTts::new`, which reports invalid configs as errors")]
+    pub fn new_unchecked(config: KittenTtsConfig) -> Self {
+        Self::new(config).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model file.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Synthesize a short phrase and discard it, so the first real [`Self::create`]
+    /// doesn't pay for ONNX Runtime's one-time setup. Returns how long it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("tts.warm_up");
+        time_warm_up(|| self.create(WARM_UP_TEXT, 0, 1.0))
+    }
+
+    /// Always fails with [`Error::Unsupported`]. The iSTFTNet decoder can add random
+    /// noise to its harmonic source and sherpa-onnx has no seed for it, so nothing
+    /// guarantees two runs give the same audio.
+    pub fn create_deterministic(
+        &mut self,
+        _text: &str,
+        _sid: i32,
+        _speed: f32,
+    ) -> Result<TtsAudio> {
+        Err(super::unseeded_noise("kitten"))
+    }
+
+    fn create_with_fallback(config: &KittenTtsConfig) -> Result<(TtsHandle, ProviderSelection)> {
+        let name = format!("kitten tts (model={})", config.model);
+        super::create_tts_with_fallback(&name, &config.onnx_config, |onnx_config| unsafe {
+            Self::create_tts(config, onnx_config)
+        })
+    }
+
+    unsafe fn create_tts(
+        config: &KittenTtsConfig,
+        onnx_config: &OnnxConfig,
+    ) -> *const sherpa_rs_sys::SherpaOnnxOfflineTts {
+        let model = cstring_from_str(&config.model);
+        let voices = cstring_from_str(&config.voices);
+        let tokens = cstring_from_str(&config.tokens);
+        let data_dir = cstring_from_str(&config.data_dir);
+
+        let provider = match onnx_config.provider.to_cstring() {
+            Ok(provider) => provider,
+            Err(_) => return null(),
         };
 
-        Self { tts }
+        let tts_config = match config.common_config.to_raw() {
+            Ok(raw) => raw,
+            Err(_) => return null(),
+        };
+
+        let model_config = sherpa_rs_sys::SherpaOnnxOfflineTtsModelConfig {
+            vits: mem::zeroed::<_>(),
+            num_threads: onnx_config.num_threads.to_raw(),
+            debug: onnx_config.debug.into(),
+            provider: provider.as_ptr(),
+            matcha: mem::zeroed::<_>(),
+            kokoro: mem::zeroed(),
+            kitten: sherpa_rs_sys::SherpaOnnxOfflineTtsKittenModelConfig {
+                model: model.as_ptr(),
+                voices: voices.as_ptr(),
+                tokens: tokens.as_ptr(),
+                data_dir: data_dir.as_ptr(),
+                length_scale: config.length_scale,
+            },
+            zipvoice: mem::zeroed::<_>(),
+        };
+        let config = sherpa_rs_sys::SherpaOnnxOfflineTtsConfig {
+            max_num_sentences: config.common_config.max_num_sentences,
+            model: model_config,
+            rule_fars: tts_config
+                .rule_fars
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(null()),
+            rule_fsts: tts_config
+                .rule_fsts
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(null()),
+            silence_scale: 1.0,
+        };
+        sherpa_rs_sys::SherpaOnnxCreateOfflineTts(&config)
     }
 
     pub fn create(&mut self, text: &str, sid: i32, speed: f32) -> Result<TtsAudio> {
-        unsafe { super::create(self.tts, text, sid, speed) }
+        super::create_with_segmenter(&self.tts, &self.segmenter, text, sid, speed)
     }
-}
 
-unsafe impl Send for KittenTts {}
-unsafe impl Sync for KittenTts {}
+    /// Synthesize each segment of `text` separately, split by the configured
+    /// `sentence_splitter` or on sentence punctuation.
+    pub fn synthesize_segmented(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+    ) -> Result<Vec<TtsAudio>> {
+        super::create_segmented(&self.tts, &self.segmenter, text, sid, speed)
+    }
 
-impl Drop for KittenTts {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineTts(self.tts);
-        }
+    /// Generate with `callback` called after each sentence. Returning `false` from it
+    /// stops generation; the audio generated so far is returned marked as truncated.
+    pub fn create_with_progress<F>(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+        mut callback: F,
+    ) -> Result<TtsGeneration>
+    where
+        F: FnMut(TtsProgress) -> bool,
+    {
+        super::create_with_progress(&self.tts, text, sid, speed, &mut callback)
+    }
+
+    /// Like [`Self::create_with_progress`], but cancelling `token` stops generation at the
+    /// next sentence with [`Error::Cancelled`](crate::Error::Cancelled).
+    pub fn create_with_cancel(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+        token: &CancellationToken,
+    ) -> Result<TtsAudio> {
+        super::create_with_cancel(&self.tts, text, sid, speed, token)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        super::sample_rate(&self.tts)
+    }
+
+    pub fn num_speakers(&self) -> u32 {
+        super::num_speakers(&self.tts)
     }
 }
+
+unsafe impl Send for KittenTts {}
+unsafe impl Sync for KittenTts {}
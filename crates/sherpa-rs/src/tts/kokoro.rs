@@ -1,22 +1,43 @@
-use std::{mem, ptr::null};
+use std::{mem, path::Path, ptr::null, time::Duration};
 
-use crate::{utils::cstring_from_str, OnnxConfig};
-use eyre::Result;
+use crate::{
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::ProviderSelection,
+    trace::timed,
+    utils::{cstring_from_str, edit_distance, time_warm_up},
+    CancellationToken, Error, OnnxConfig, Provider, Result,
+};
 use sherpa_rs_sys;
 
-use super::{CommonTtsConfig, TtsAudio};
+use super::{
+    CommonTtsConfig, Segmenter, TtsAudio, TtsGeneration, TtsHandle, TtsProgress, WARM_UP_TEXT,
+};
 
 pub struct KokoroTts {
-    tts: *const sherpa_rs_sys::SherpaOnnxOfflineTts,
+    tts: TtsHandle,
+    segmenter: Segmenter,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
 }
 
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct KokoroTtsConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub voices: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub data_dir: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub dict_dir: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::path_list")
+    )]
     pub lexicon: String,
     pub length_scale: f32,
     pub onnx_config: OnnxConfig,
@@ -24,65 +45,407 @@ pub struct KokoroTtsConfig {
     pub lang: String,
 }
 
+impl KokoroTtsConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, path) in [
+            ("model", &self.model),
+            ("voices", &self.voices),
+            ("tokens", &self.tokens),
+        ] {
+            if path.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "kokoro {} path is empty",
+                    name
+                )));
+            }
+            if !Path::new(path).is_file() {
+                return Err(Error::model_not_found(format!("kokoro {}", name), path));
+            }
+        }
+        super::check_lexicon_and_dirs("kokoro", &self.lexicon, &self.data_dir, &self.dict_dir)?;
+        self.common_config.to_raw()?;
+        Ok(())
+    }
+}
+
 impl KokoroTts {
-    pub fn new(config: KokoroTtsConfig) -> Self {
-        let tts = unsafe {
-            let model = cstring_from_str(&config.model);
-            let voices = cstring_from_str(&config.voices);
-            let tokens = cstring_from_str(&config.tokens);
-            let data_dir = cstring_from_str(&config.data_dir);
-            let dict_dir = cstring_from_str(&config.dict_dir);
-            let lexicon = cstring_from_str(&config.lexicon);
-            let lang = cstring_from_str(&config.lang);
-
-            let provider = cstring_from_str(&config.onnx_config.provider);
-
-            let tts_config = config.common_config.to_raw();
-
-            let model_config = sherpa_rs_sys::SherpaOnnxOfflineTtsModelConfig {
-                vits: mem::zeroed::<_>(),
-                num_threads: config.onnx_config.num_threads,
-                debug: config.onnx_config.debug.into(),
-                provider: provider.as_ptr(),
-                matcha: mem::zeroed::<_>(),
-                kokoro: sherpa_rs_sys::SherpaOnnxOfflineTtsKokoroModelConfig {
-                    model: model.as_ptr(),
-                    voices: voices.as_ptr(),
-                    tokens: tokens.as_ptr(),
-                    data_dir: data_dir.as_ptr(),
-                    length_scale: config.length_scale,
-                    dict_dir: dict_dir.as_ptr(),
-                    lexicon: lexicon.as_ptr(),
-                    lang: lang.as_ptr(),
-                },
-                kitten: mem::zeroed::<_>(),
-                zipvoice: mem::zeroed::<_>(),
-            };
-            let config = sherpa_rs_sys::SherpaOnnxOfflineTtsConfig {
-                max_num_sentences: config.common_config.max_num_sentences,
-                model: model_config,
-                rule_fars: tts_config.rule_fars.map(|v| v.as_ptr()).unwrap_or(null()),
-                rule_fsts: tts_config.rule_fsts.map(|v| v.as_ptr()).unwrap_or(null()),
-                silence_scale: config.common_config.silence_scale,
-            };
-            sherpa_rs_sys::SherpaOnnxCreateOfflineTts(&config)
+    /// Like [`Self::new`], with the model, voices, tokens and espeak-ng data found in
+    /// `dir`, an unpacked sherpa-onnx release. Fails if the files aren't there.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: KokoroTtsConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Kokoro,
+            precision,
+            &config.onnx_config.provider,
+        )?;
+        Self::new(KokoroTtsConfig {
+            model: dir.file(Role::Model),
+            voices: dir.file(Role::Voices),
+            tokens: dir.file(Role::Tokens),
+            data_dir: dir.file(Role::DataDir),
+            dict_dir: dir.file_or(Role::DictDir, config.dict_dir),
+            lexicon: dir.file_or(Role::Lexicon, config.lexicon),
+            ..config
+        })
+    }
+
+    pub fn new(config: KokoroTtsConfig) -> Result<Self> {
+        config.validate()?;
+        let (tts, provider) = super::create_tts_with_fallback(
+            "kokoro tts",
+            &config.onnx_config,
+            |onnx_config| unsafe { Self::create_tts(&config, onnx_config) },
+        )?;
+        Ok(Self {
+            tts,
+            segmenter: Segmenter::new(&config.common_config),
+            provider,
+            precision: ModelPrecision::of_file(&config.model),
+        })
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model file.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Synthesize a short phrase and discard it, so the first real [`Self::create`]
+    /// doesn't pay for ONNX Runtime's one-time setup. Returns how long it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("tts.warm_up");
+        time_warm_up(|| self.create(WARM_UP_TEXT, 0, 1.0))
+    }
+
+    /// Always fails with [`Error::Unsupported`]. The iSTFTNet decoder can add random
+    /// noise to its harmonic source and sherpa-onnx has no seed for it, so nothing
+    /// guarantees two runs give the same audio.
+    pub fn create_deterministic(
+        &mut self,
+        _text: &str,
+        _sid: i32,
+        _speed: f32,
+    ) -> Result<TtsAudio> {
+        Err(super::unseeded_noise("kokoro"))
+    }
+
+    unsafe fn create_tts(
+        config: &KokoroTtsConfig,
+        onnx_config: &OnnxConfig,
+    ) -> *const sherpa_rs_sys::SherpaOnnxOfflineTts {
+        let model = cstring_from_str(&config.model);
+        let voices = cstring_from_str(&config.voices);
+        let tokens = cstring_from_str(&config.tokens);
+        let data_dir = cstring_from_str(&config.data_dir);
+        let dict_dir = cstring_from_str(&config.dict_dir);
+        let lexicon = cstring_from_str(&config.lexicon);
+        let lang = cstring_from_str(&config.lang);
+
+        let provider = match onnx_config.provider.to_cstring() {
+            Ok(provider) => provider,
+            Err(err) => {
+                tracing::error!("Invalid kokoro tts provider: {}", err);
+                return null();
+            }
         };
 
-        Self { tts }
+        let tts_config = match config.common_config.to_raw() {
+            Ok(raw) => raw,
+            Err(err) => {
+                tracing::error!("Invalid kokoro tts config: {}", err);
+                return null();
+            }
+        };
+
+        let model_config = sherpa_rs_sys::SherpaOnnxOfflineTtsModelConfig {
+            vits: mem::zeroed::<_>(),
+            num_threads: onnx_config.num_threads.to_raw(),
+            debug: onnx_config.debug.into(),
+            provider: provider.as_ptr(),
+            matcha: mem::zeroed::<_>(),
+            kokoro: sherpa_rs_sys::SherpaOnnxOfflineTtsKokoroModelConfig {
+                model: model.as_ptr(),
+                voices: voices.as_ptr(),
+                tokens: tokens.as_ptr(),
+                data_dir: data_dir.as_ptr(),
+                length_scale: config.length_scale,
+                dict_dir: dict_dir.as_ptr(),
+                lexicon: lexicon.as_ptr(),
+                lang: lang.as_ptr(),
+            },
+            kitten: mem::zeroed::<_>(),
+            zipvoice: mem::zeroed::<_>(),
+        };
+        let config = sherpa_rs_sys::SherpaOnnxOfflineTtsConfig {
+            max_num_sentences: config.common_config.max_num_sentences,
+            model: model_config,
+            rule_fars: tts_config
+                .rule_fars
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(null()),
+            rule_fsts: tts_config
+                .rule_fsts
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(null()),
+            silence_scale: config.common_config.silence_scale,
+        };
+        sherpa_rs_sys::SherpaOnnxCreateOfflineTts(&config)
     }
 
     pub fn create(&mut self, text: &str, sid: i32, speed: f32) -> Result<TtsAudio> {
-        unsafe { super::create(self.tts, text, sid, speed) }
+        super::create_with_segmenter(&self.tts, &self.segmenter, text, sid, speed)
     }
+
+    /// Synthesize each segment of `text` separately, split by the configured
+    /// `sentence_splitter` or on sentence punctuation.
+    pub fn synthesize_segmented(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+    ) -> Result<Vec<TtsAudio>> {
+        super::create_segmented(&self.tts, &self.segmenter, text, sid, speed)
+    }
+
+    /// Generate with `callback` called after each sentence. Returning `false` from it
+    /// stops generation; the audio generated so far is returned marked as truncated.
+    pub fn create_with_progress<F>(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+        mut callback: F,
+    ) -> Result<TtsGeneration>
+    where
+        F: FnMut(TtsProgress) -> bool,
+    {
+        super::create_with_progress(&self.tts, text, sid, speed, &mut callback)
+    }
+
+    /// Like [`Self::create_with_progress`], but cancelling `token` stops generation at the
+    /// next sentence with [`Error::Cancelled`](crate::Error::Cancelled).
+    pub fn create_with_cancel(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+        token: &CancellationToken,
+    ) -> Result<TtsAudio> {
+        super::create_with_cancel(&self.tts, text, sid, speed, token)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        super::sample_rate(&self.tts)
+    }
+
+    pub fn num_speakers(&self) -> u32 {
+        super::num_speakers(&self.tts)
+    }
+
+    /// Voices in the loaded model.
+    ///
+    /// `voices.bin` only stores embeddings, so names come from the published voice lists
+    /// of the official v0.19 and v1.0 releases, matched by speaker count. Other models
+    /// get placeholder names like `speaker_7` and no language.
+    pub fn voices(&self) -> Vec<KokoroVoice> {
+        let num_speakers = self.num_speakers() as usize;
+        let names = match num_speakers {
+            n if n == VOICES_V1_0.len() => VOICES_V1_0,
+            n if n == VOICES_V0_19.len() => VOICES_V0_19,
+            _ => &[],
+        };
+        (0..num_speakers)
+            .map(|id| match names.get(id) {
+                Some(name) => KokoroVoice {
+                    id: id as i32,
+                    name: name.to_string(),
+                    language: voice_language(name).map(str::to_string),
+                },
+                None => KokoroVoice {
+                    id: id as i32,
+                    name: format!("speaker_{}", id),
+                    language: None,
+                },
+            })
+            .collect()
+    }
+
+    /// Generate with a voice looked up by name, e.g. `af_heart`.
+    pub fn create_with_voice(&mut self, text: &str, voice: &str, speed: f32) -> Result<TtsAudio> {
+        let voices = self.voices();
+        let Some(found) = voices.iter().find(|v| v.name == voice) else {
+            let mut candidates: Vec<(usize, &str)> = voices
+                .iter()
+                .map(|v| (edit_distance(voice, &v.name), v.name.as_str()))
+                .filter(|(distance, name)| *distance <= 3 || name.contains(voice))
+                .collect();
+            candidates.sort();
+            let close: Vec<&str> = candidates.iter().take(5).map(|(_, name)| *name).collect();
+            if close.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "Unknown kokoro voice '{}'",
+                    voice
+                )));
+            }
+            return Err(Error::invalid_input(format!(
+                "Unknown kokoro voice '{}', did you mean: {}",
+                voice,
+                close.join(", ")
+            )));
+        };
+        self.create(text, found.id, speed)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KokoroVoice {
+    pub id: i32,
+    pub name: String,
+    /// BCP 47 tag derived from the voice name prefix.
+    pub language: Option<String>,
+}
+
+const VOICES_V0_19: &[&str] = &[
+    "af",
+    "af_bella",
+    "af_nicole",
+    "af_sarah",
+    "af_sky",
+    "am_adam",
+    "am_michael",
+    "bf_emma",
+    "bf_isabella",
+    "bm_george",
+    "bm_lewis",
+];
+
+const VOICES_V1_0: &[&str] = &[
+    "af_alloy",
+    "af_aoede",
+    "af_bella",
+    "af_heart",
+    "af_jessica",
+    "af_kore",
+    "af_nicole",
+    "af_nova",
+    "af_river",
+    "af_sarah",
+    "af_sky",
+    "am_adam",
+    "am_echo",
+    "am_eric",
+    "am_fenrir",
+    "am_liam",
+    "am_michael",
+    "am_onyx",
+    "am_puck",
+    "am_santa",
+    "bf_alice",
+    "bf_emma",
+    "bf_isabella",
+    "bf_lily",
+    "bm_daniel",
+    "bm_fable",
+    "bm_george",
+    "bm_lewis",
+    "ef_dora",
+    "em_alex",
+    "ff_siwis",
+    "hf_alpha",
+    "hf_beta",
+    "hm_omega",
+    "hm_psi",
+    "if_sara",
+    "im_nicola",
+    "jf_alpha",
+    "jf_gongitsune",
+    "jf_nezumi",
+    "jf_tebukuro",
+    "jm_kumo",
+    "pf_dora",
+    "pm_alex",
+    "pm_santa",
+    "zf_xiaobei",
+    "zf_xiaoni",
+    "zf_xiaoxiao",
+    "zf_xiaoyi",
+    "zm_yunjian",
+    "zm_yunxi",
+    "zm_yunxia",
+    "zm_yunyang",
+];
+
+fn voice_language(name: &str) -> Option<&'static str> {
+    let language = match name.chars().next()? {
+        'a' => "en-US",
+        'b' => "en-GB",
+        'e' => "es",
+        'f' => "fr",
+        'h' => "hi",
+        'i' => "it",
+        'j' => "ja",
+        'p' => "pt-BR",
+        'z' => "zh",
+        _ => return None,
+    };
+    Some(language)
 }
 
 unsafe impl Send for KokoroTts {}
 unsafe impl Sync for KokoroTts {}
 
-impl Drop for KokoroTts {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineTts(self.tts);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    fn new_err(config: KokoroTtsConfig) -> Error {
+        match KokoroTts::new(config) {
+            Ok(_) => panic!("created kokoro from a broken config"),
+            Err(err) => err,
         }
     }
+
+    #[test]
+    fn new_rejects_empty_voices_path() {
+        let dir = ScratchDir::new("kokoro-empty");
+        let err = new_err(KokoroTtsConfig {
+            model: dir.touch("model.onnx"),
+            tokens: dir.touch("tokens.txt"),
+            ..Default::default()
+        });
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn new_reports_missing_data_dir() {
+        let dir = ScratchDir::new("kokoro-missing");
+        let err = new_err(KokoroTtsConfig {
+            model: dir.touch("model.onnx"),
+            voices: dir.touch("voices.bin"),
+            tokens: dir.touch("tokens.txt"),
+            data_dir: dir.join("espeak-ng-data"),
+            ..Default::default()
+        });
+        assert!(
+            matches!(&err, Error::ModelNotFound { component, .. } if component == "kokoro data_dir"),
+            "{:?}",
+            err
+        );
+    }
 }
@@ -1,23 +1,51 @@
-use std::{mem, ptr::null};
+use std::{mem, path::Path, ptr::null, time::Duration};
 
-use crate::{utils::cstring_from_str, OnnxConfig};
-use eyre::Result;
+use crate::{
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::ProviderSelection,
+    trace::timed,
+    utils::{cstring_from_str, time_warm_up},
+    CancellationToken, Error, OnnxConfig, Provider, Result,
+};
 use sherpa_rs_sys;
 
-use super::{CommonTtsConfig, TtsAudio};
+use super::{
+    CommonTtsConfig, Segmenter, TtsAudio, TtsGeneration, TtsHandle, TtsProgress, WARM_UP_TEXT,
+};
 
 pub struct MatchaTts {
-    tts: *const sherpa_rs_sys::SherpaOnnxOfflineTts,
+    tts: TtsHandle,
+    segmenter: Segmenter,
+    config: MatchaTtsConfig,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
 }
 
-#[derive(Default)]
+const DEFAULT_NOISE_SCALE: f32 = 0.667;
+const DEFAULT_LENGTH_SCALE: f32 = 1.0;
+
+/// `noise_scale` and `length_scale` of 0.0 mean the upstream defaults (0.667 and 1.0).
+/// The vocoder runs on the same provider as the acoustic model, the C API only takes one.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct MatchaTtsConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::path_list")
+    )]
     pub lexicon: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub dict_dir: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub data_dir: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub acoustic_model: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub vocoder: String,
     pub length_scale: f32,
     pub noise_scale: f32,
@@ -28,65 +56,289 @@ pub struct MatchaTtsConfig {
     pub onnx_config: OnnxConfig,
 }
 
+impl MatchaTtsConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, path) in [
+            ("acoustic_model", &self.acoustic_model),
+            ("vocoder", &self.vocoder),
+            ("tokens", &self.tokens),
+        ] {
+            if path.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "matcha {} path is empty",
+                    name
+                )));
+            }
+            if !Path::new(path).is_file() {
+                return Err(Error::model_not_found(format!("matcha {}", name), path));
+            }
+        }
+        super::check_lexicon_and_dirs("matcha", &self.lexicon, &self.data_dir, &self.dict_dir)?;
+        self.common_config.to_raw()?;
+        Ok(())
+    }
+
+    fn effective_noise_scale(&self) -> f32 {
+        if self.noise_scale == 0.0 {
+            DEFAULT_NOISE_SCALE
+        } else {
+            self.noise_scale
+        }
+    }
+
+    fn effective_length_scale(&self) -> f32 {
+        if self.length_scale == 0.0 {
+            DEFAULT_LENGTH_SCALE
+        } else {
+            self.length_scale
+        }
+    }
+}
+
 impl MatchaTts {
-    pub fn new(config: MatchaTtsConfig) -> Self {
-        let tts = unsafe {
-            let tokens = cstring_from_str(&config.tokens);
-            let data_dir = cstring_from_str(&config.data_dir);
-            let lexicon = cstring_from_str(&config.lexicon);
-            let dict_dir = cstring_from_str(&config.dict_dir);
-
-            let vocoder = cstring_from_str(&config.vocoder);
-            let acoustic_model = cstring_from_str(&config.acoustic_model);
-
-            let provider = cstring_from_str(&config.onnx_config.provider);
-
-            let tts_config = config.common_config.to_raw();
-
-            let model_config = sherpa_rs_sys::SherpaOnnxOfflineTtsModelConfig {
-                num_threads: config.onnx_config.num_threads,
-                vits: mem::zeroed::<_>(),
-                debug: config.onnx_config.debug.into(),
-                provider: provider.as_ptr(),
-                matcha: sherpa_rs_sys::SherpaOnnxOfflineTtsMatchaModelConfig {
-                    acoustic_model: acoustic_model.as_ptr(),
-                    vocoder: vocoder.as_ptr(),
-                    lexicon: lexicon.as_ptr(),
-                    tokens: tokens.as_ptr(),
-                    data_dir: data_dir.as_ptr(),
-                    noise_scale: config.noise_scale,
-                    length_scale: config.length_scale,
-                    dict_dir: dict_dir.as_ptr(),
-                },
-                kokoro: mem::zeroed::<_>(),
-                kitten: mem::zeroed::<_>(),
-                zipvoice: mem::zeroed::<_>(),
-            };
-            let config = sherpa_rs_sys::SherpaOnnxOfflineTtsConfig {
-                max_num_sentences: config.common_config.max_num_sentences,
-                model: model_config,
-                rule_fars: tts_config.rule_fars.map(|v| v.as_ptr()).unwrap_or(null()),
-                rule_fsts: tts_config.rule_fsts.map(|v| v.as_ptr()).unwrap_or(null()),
-                silence_scale: config.silence_scale,
-            };
-            sherpa_rs_sys::SherpaOnnxCreateOfflineTts(&config)
+    /// Like [`Self::new`], with the acoustic model, tokens, lexicon and espeak-ng data
+    /// found in `dir`, an unpacked sherpa-onnx release. sherpa-onnx releases vocoders
+    /// separately, so one is only found if it was put in `dir`, `config.vocoder` is used
+    /// otherwise.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: MatchaTtsConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Matcha,
+            precision,
+            &config.onnx_config.provider,
+        )?;
+        Self::new(MatchaTtsConfig {
+            acoustic_model: dir.file(Role::Model),
+            tokens: dir.file(Role::Tokens),
+            vocoder: dir.file_or(Role::Vocoder, config.vocoder),
+            data_dir: dir.file_or(Role::DataDir, config.data_dir),
+            dict_dir: dir.file_or(Role::DictDir, config.dict_dir),
+            lexicon: dir.file_or(Role::Lexicon, config.lexicon),
+            ..config
+        })
+    }
+
+    pub fn new(config: MatchaTtsConfig) -> Result<Self> {
+        config.validate()?;
+        let (tts, provider) = Self::create_with_fallback(&config)?;
+        Ok(Self {
+            tts,
+            segmenter: Segmenter::new(&config.common_config),
+            precision: ModelPrecision::of_file(&config.acoustic_model),
+            config,
+            provider,
+        })
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded acoustic model. The vocoder is always fp32.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Synthesize a short phrase and discard it, so the first real [`Self::create`]
+    /// doesn't pay for ONNX Runtime's one-time setup. Returns how long it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("tts.warm_up");
+        time_warm_up(|| self.create(WARM_UP_TEXT, 0, 1.0))
+    }
+
+    /// Always fails with [`Error::Unsupported`](crate::Error::Unsupported). The model
+    /// draws noise scaled by `noise_scale` from a generator sherpa-onnx has no seed for,
+    /// and a `noise_scale` of 0.0 means the upstream default rather than no noise.
+    pub fn create_deterministic(
+        &mut self,
+        _text: &str,
+        _sid: i32,
+        _speed: f32,
+    ) -> Result<TtsAudio> {
+        Err(super::unseeded_noise("matcha"))
+    }
+
+    fn create_with_fallback(config: &MatchaTtsConfig) -> Result<(TtsHandle, ProviderSelection)> {
+        super::create_tts_with_fallback("matcha tts", &config.onnx_config, |onnx_config| unsafe {
+            Self::create_tts(config, onnx_config)
+        })
+    }
+
+    unsafe fn create_tts(
+        config: &MatchaTtsConfig,
+        onnx_config: &OnnxConfig,
+    ) -> *const sherpa_rs_sys::SherpaOnnxOfflineTts {
+        let tokens = cstring_from_str(&config.tokens);
+        let data_dir = cstring_from_str(&config.data_dir);
+        let lexicon = cstring_from_str(&config.lexicon);
+        let dict_dir = cstring_from_str(&config.dict_dir);
+
+        let vocoder = cstring_from_str(&config.vocoder);
+        let acoustic_model = cstring_from_str(&config.acoustic_model);
+
+        let provider = match onnx_config.provider.to_cstring() {
+            Ok(provider) => provider,
+            Err(err) => {
+                tracing::error!("Invalid matcha tts provider: {}", err);
+                return null();
+            }
+        };
+
+        let tts_config = match config.common_config.to_raw() {
+            Ok(raw) => raw,
+            Err(err) => {
+                tracing::error!("Invalid matcha tts config: {}", err);
+                return null();
+            }
         };
 
-        Self { tts }
+        let model_config = sherpa_rs_sys::SherpaOnnxOfflineTtsModelConfig {
+            num_threads: onnx_config.num_threads.to_raw(),
+            vits: mem::zeroed::<_>(),
+            debug: onnx_config.debug.into(),
+            provider: provider.as_ptr(),
+            matcha: sherpa_rs_sys::SherpaOnnxOfflineTtsMatchaModelConfig {
+                acoustic_model: acoustic_model.as_ptr(),
+                vocoder: vocoder.as_ptr(),
+                lexicon: lexicon.as_ptr(),
+                tokens: tokens.as_ptr(),
+                data_dir: data_dir.as_ptr(),
+                noise_scale: config.effective_noise_scale(),
+                length_scale: config.effective_length_scale(),
+                dict_dir: dict_dir.as_ptr(),
+            },
+            kokoro: mem::zeroed::<_>(),
+            kitten: mem::zeroed::<_>(),
+            zipvoice: mem::zeroed::<_>(),
+        };
+        let config = sherpa_rs_sys::SherpaOnnxOfflineTtsConfig {
+            max_num_sentences: config.common_config.max_num_sentences,
+            model: model_config,
+            rule_fars: tts_config
+                .rule_fars
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(null()),
+            rule_fsts: tts_config
+                .rule_fsts
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(null()),
+            silence_scale: config.silence_scale,
+        };
+        sherpa_rs_sys::SherpaOnnxCreateOfflineTts(&config)
+    }
+
+    /// Current noise scale, with 0.0 resolved to the default.
+    pub fn noise_scale(&self) -> f32 {
+        self.config.effective_noise_scale()
+    }
+
+    /// Current length scale, with 0.0 resolved to the default.
+    pub fn length_scale(&self) -> f32 {
+        self.config.effective_length_scale()
+    }
+
+    /// Change the noise scale. The C API fixes it at creation, so this reloads the
+    /// model; on failure the previous engine is kept.
+    pub fn set_noise_scale(&mut self, noise_scale: f32) -> Result<()> {
+        let mut config = self.config.clone();
+        config.noise_scale = noise_scale;
+        self.reconfigure(config)
+    }
+
+    /// Change the length scale, reloading the model like [`set_noise_scale`](Self::set_noise_scale).
+    pub fn set_length_scale(&mut self, length_scale: f32) -> Result<()> {
+        let mut config = self.config.clone();
+        config.length_scale = length_scale;
+        self.reconfigure(config)
+    }
+
+    fn reconfigure(&mut self, config: MatchaTtsConfig) -> Result<()> {
+        let (tts, provider) = Self::create_with_fallback(&config)?;
+        self.tts = tts;
+        self.config = config;
+        self.provider = provider;
+        Ok(())
     }
 
     pub fn create(&mut self, text: &str, sid: i32, speed: f32) -> Result<TtsAudio> {
-        unsafe { super::create(self.tts, text, sid, speed) }
+        super::create_with_segmenter(&self.tts, &self.segmenter, text, sid, speed)
+    }
+
+    /// Synthesize each segment of `text` separately, split by the configured
+    /// `sentence_splitter` or on sentence punctuation.
+    pub fn synthesize_segmented(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+    ) -> Result<Vec<TtsAudio>> {
+        super::create_segmented(&self.tts, &self.segmenter, text, sid, speed)
+    }
+
+    /// Generate with `callback` called after each sentence. Returning `false` from it
+    /// stops generation; the audio generated so far is returned marked as truncated.
+    pub fn create_with_progress<F>(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+        mut callback: F,
+    ) -> Result<TtsGeneration>
+    where
+        F: FnMut(TtsProgress) -> bool,
+    {
+        super::create_with_progress(&self.tts, text, sid, speed, &mut callback)
+    }
+
+    /// Like [`Self::create_with_progress`], but cancelling `token` stops generation at the
+    /// next sentence with [`Error::Cancelled`](crate::Error::Cancelled).
+    pub fn create_with_cancel(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+        token: &CancellationToken,
+    ) -> Result<TtsAudio> {
+        super::create_with_cancel(&self.tts, text, sid, speed, token)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        super::sample_rate(&self.tts)
+    }
+
+    pub fn num_speakers(&self) -> u32 {
+        super::num_speakers(&self.tts)
     }
 }
 
 unsafe impl Send for MatchaTts {}
 unsafe impl Sync for MatchaTts {}
 
-impl Drop for MatchaTts {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineTts(self.tts);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    #[test]
+    fn new_requires_vocoder() {
+        let dir = ScratchDir::new("matcha-vocoder");
+        let result = MatchaTts::new(MatchaTtsConfig {
+            acoustic_model: dir.touch("model.onnx"),
+            tokens: dir.touch("tokens.txt"),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(Error::InvalidInput { .. })));
     }
 }
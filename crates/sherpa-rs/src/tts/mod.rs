@@ -1,34 +1,351 @@
+mod engine;
 mod kitten;
 mod kokoro;
 mod matcha;
+mod pool;
 mod vits;
 mod zipvoice;
 
-use std::ffi::CString;
-
-use eyre::{bail, Result};
+use std::{
+    ffi::{c_void, CString},
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+pub use engine::{from_config, TtsEngine, TtsEngineConfig, TtsRequest, ZipVoiceRequest};
 pub use kitten::{KittenTts, KittenTtsConfig};
-pub use kokoro::{KokoroTts, KokoroTtsConfig};
+pub use kokoro::{KokoroTts, KokoroTtsConfig, KokoroVoice};
 pub use matcha::{MatchaTts, MatchaTtsConfig};
-pub use vits::{VitsTts, VitsTtsConfig};
-pub use zipvoice::{ZipVoiceTts, ZipVoiceTtsConfig};
+pub use pool::TtsPool;
+pub use vits::{VitsSynthesisParams, VitsTts, VitsTtsConfig};
+pub use zipvoice::{ZipVoicePrompt, ZipVoiceTts, ZipVoiceTtsConfig, ZipVoiceTtsConfigBuilder};
+
+use crate::{
+    handle::NativeHandle,
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{cstring_from_str, f32_to_pcm16, join_rule_paths, FfiCallback},
+    CancellationToken, Error, OnnxConfig, Result,
+};
+
+/// Audio kept on each side of the audible part by [`TtsAudio::trim_silence`].
+pub const TRIM_MARGIN_MS: u32 = 10;
+
+/// Largest gain [`TtsAudio::normalize_peak`] and [`TtsAudio::normalize_rms`] apply.
+pub const MAX_NORMALIZE_GAIN_DB: f32 = 30.0;
 
-use crate::utils::cstring_from_str;
+/// Synthesized by `warm_up`. Short, but long enough to run every model of an engine.
+const WARM_UP_TEXT: &str = "Hello world.";
 
+/// Mono audio produced by a TTS engine.
 #[derive(Debug)]
 pub struct TtsAudio {
     pub samples: Vec<f32>,
     pub sample_rate: u32,
-    pub duration: i32,
+    /// Length in seconds.
+    pub duration: f32,
+    /// Where each token lands in `samples`. The linked sherpa-onnx C API doesn't report
+    /// alignments for any engine yet, so this is currently always `None`.
+    pub alignments: Option<Vec<TokenSpan>>,
+}
+
+/// A token and the sample range it was synthesized into.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenSpan {
+    pub token: String,
+    pub start: usize,
+    /// Exclusive.
+    pub end: usize,
+    pub sample_rate: u32,
 }
 
-#[derive(Default)]
+impl TokenSpan {
+    pub fn start_secs(&self) -> f32 {
+        self.start as f32 / self.sample_rate.max(1) as f32
+    }
+
+    pub fn end_secs(&self) -> f32 {
+        self.end as f32 / self.sample_rate.max(1) as f32
+    }
+}
+
+impl TtsAudio {
+    pub(crate) fn new(samples: Vec<f32>, sample_rate: u32) -> Self {
+        let mut audio = Self {
+            samples,
+            sample_rate,
+            duration: 0.0,
+            alignments: None,
+        };
+        audio.update_duration();
+        audio
+    }
+
+    fn update_duration(&mut self) {
+        self.duration = if self.sample_rate == 0 {
+            0.0
+        } else {
+            self.samples.len() as f32 / self.sample_rate as f32
+        };
+    }
+
+    /// Remove leading and trailing samples quieter than `threshold_db` dBFS, keeping
+    /// [`TRIM_MARGIN_MS`] around the audible part so soft onsets aren't clipped.
+    pub fn trim_silence(&mut self, threshold_db: f32) {
+        let threshold = 10f32.powf(threshold_db / 20.0);
+        let Some(first) = self.samples.iter().position(|s| s.abs() >= threshold) else {
+            self.samples.clear();
+            self.update_duration();
+            return;
+        };
+        let last = self
+            .samples
+            .iter()
+            .rposition(|s| s.abs() >= threshold)
+            .unwrap_or(first);
+
+        let margin = (self.sample_rate as u64 * TRIM_MARGIN_MS as u64 / 1000) as usize;
+        let start = first.saturating_sub(margin);
+        let end = (last + 1 + margin).min(self.samples.len());
+        self.samples.truncate(end);
+        self.samples.drain(..start);
+        if let Some(spans) = &mut self.alignments {
+            spans.retain(|span| span.end > start && span.start < end);
+            for span in spans {
+                span.start = span.start.clamp(start, end) - start;
+                span.end = span.end.clamp(start, end) - start;
+            }
+        }
+        self.update_duration();
+    }
+
+    /// Scale so the peak sits at `target_db` dBFS. Returns the applied gain in dB, which
+    /// is capped at [`MAX_NORMALIZE_GAIN_DB`] so near-silent clips aren't blown up.
+    pub fn normalize_peak(&mut self, target_db: f32) -> f32 {
+        let peak = self
+            .samples
+            .iter()
+            .fold(0.0f32, |peak, s| peak.max(s.abs()));
+        self.apply_level(peak, target_db)
+    }
+
+    /// Scale so the RMS level sits at `target_db` dBFS. The result isn't limited, so a
+    /// high target can push peaks past full scale. Returns the applied gain in dB.
+    pub fn normalize_rms(&mut self, target_db: f32) -> f32 {
+        let rms = if self.samples.is_empty() {
+            0.0
+        } else {
+            let sum: f64 = self.samples.iter().map(|&s| s as f64 * s as f64).sum();
+            (sum / self.samples.len() as f64).sqrt() as f32
+        };
+        self.apply_level(rms, target_db)
+    }
+
+    fn apply_level(&mut self, level: f32, target_db: f32) -> f32 {
+        if level <= 0.0 {
+            return 0.0;
+        }
+        let gain_db = (target_db - 20.0 * level.log10()).min(MAX_NORMALIZE_GAIN_DB);
+        let gain = 10f32.powf(gain_db / 20.0);
+        self.samples.iter_mut().for_each(|s| *s *= gain);
+        gain_db
+    }
+
+    /// Join clips end to end. All clips must share a sample rate.
+    pub fn concat(clips: &[TtsAudio]) -> Result<TtsAudio> {
+        Self::concat_with_crossfade(clips, 0)
+    }
+
+    /// Join clips with an equal-power crossfade of `fade_ms` at each join, shortened
+    /// where a clip is too short. Each join shortens the output by the fade length.
+    pub fn concat_with_crossfade(clips: &[TtsAudio], fade_ms: u32) -> Result<TtsAudio> {
+        let Some(first) = clips.first() else {
+            return Err(Error::invalid_input("No clips to concatenate"));
+        };
+        let sample_rate = first.sample_rate;
+        if let Some(other) = clips.iter().find(|c| c.sample_rate != sample_rate) {
+            return Err(Error::invalid_input(format!(
+                "Cannot concatenate clips with different sample rates ({} and {})",
+                sample_rate, other.sample_rate
+            )));
+        }
+
+        let fade = (sample_rate as u64 * fade_ms as u64 / 1000) as usize;
+        let mut samples: Vec<f32> = Vec::with_capacity(clips.iter().map(|c| c.samples.len()).sum());
+        let mut alignments = clips.iter().all(|c| c.alignments.is_some()).then(Vec::new);
+        for clip in clips {
+            let n = fade.min(samples.len()).min(clip.samples.len());
+            let offset = samples.len() - n;
+            for i in 0..n {
+                let t = (i as f32 + 0.5) / n as f32 * std::f32::consts::FRAC_PI_2;
+                samples[offset + i] = samples[offset + i] * t.cos() + clip.samples[i] * t.sin();
+            }
+            samples.extend_from_slice(&clip.samples[n..]);
+
+            if let (Some(all), Some(spans)) = (&mut alignments, &clip.alignments) {
+                all.extend(spans.iter().map(|span| TokenSpan {
+                    start: span.start + offset,
+                    end: span.end + offset,
+                    ..span.clone()
+                }));
+            }
+        }
+        let mut audio = TtsAudio::new(samples, sample_rate);
+        audio.alignments = alignments;
+        Ok(audio)
+    }
+
+    /// Add `ms` of silence at the end, e.g. as a gap between sentences.
+    pub fn append_silence(&mut self, ms: u32) {
+        self.pad(0, ms);
+    }
+
+    /// Add exact digital silence before and after the audio.
+    pub fn pad(&mut self, leading_ms: u32, trailing_ms: u32) {
+        let to_samples = |ms: u32| (self.sample_rate as u64 * ms as u64 / 1000) as usize;
+        let leading = to_samples(leading_ms);
+        let trailing = to_samples(trailing_ms);
+
+        let mut samples = Vec::with_capacity(leading + self.samples.len() + trailing);
+        samples.resize(leading, 0.0);
+        samples.extend_from_slice(&self.samples);
+        samples.resize(samples.len() + trailing, 0.0);
+        self.samples = samples;
+        if let Some(spans) = &mut self.alignments {
+            for span in spans {
+                span.start += leading;
+                span.end += leading;
+            }
+        }
+        self.update_duration();
+    }
+
+    /// Write the audio as a 32-bit float WAV.
+    pub fn write_wav<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for &sample in &self.samples {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    /// Write the audio as a 16-bit PCM WAV, clipping out of range samples.
+    pub fn write_wav_i16<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for sample in self.to_interleaved_i16() {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    /// 16-bit PCM samples. TTS output is mono, so this is one sample per frame.
+    pub fn to_interleaved_i16(&self) -> Vec<i16> {
+        f32_to_pcm16(&self.samples, false)
+    }
+}
+
+#[cfg(feature = "playback")]
+impl TtsAudio {
+    /// Play on the default output device and return once it's done.
+    pub fn play_blocking(&self) -> Result<()> {
+        crate::playback::play(&self.samples, self.sample_rate, 1, None, None)
+    }
+
+    /// Like [`Self::play_blocking`], failing with [`Error::Cancelled`] once `token` is
+    /// cancelled.
+    pub fn play_with_cancel(&self, token: &CancellationToken) -> Result<()> {
+        crate::playback::play(&self.samples, self.sample_rate, 1, None, Some(token))
+    }
+
+    /// Play on the output device named `device_name`, one of
+    /// [`output_devices`](crate::playback::output_devices).
+    pub fn play_with_device(
+        &self,
+        device_name: &str,
+        token: Option<&CancellationToken>,
+    ) -> Result<()> {
+        crate::playback::play(&self.samples, self.sample_rate, 1, Some(device_name), token)
+    }
+}
+
+/// Passed to the callback of `create_with_progress` after each sentence.
+///
+/// If the callback panics, generation stops and [`Error::CallbackPanicked`] is returned.
+#[derive(Debug)]
+pub struct TtsProgress<'a> {
+    pub sentence_index: usize,
+    /// Estimated from the native progress fraction.
+    pub total_sentences: Option<usize>,
+    /// Fraction of the text done, in `[0.0, 1.0]`.
+    pub progress: f32,
+    /// Samples of the sentence just generated.
+    pub chunk: &'a [f32],
+    /// All samples generated so far, including `chunk`.
+    pub samples: &'a [f32],
+}
+
+/// Audio returned by `create_with_progress`.
+#[derive(Debug)]
+pub struct TtsGeneration {
+    pub audio: TtsAudio,
+    /// The callback stopped generation before the end of the text.
+    pub truncated: bool,
+}
+
+/// Splits text into the segments synthesized one at a time.
+pub type SentenceSplitter = Arc<dyn Fn(&str) -> Vec<String> + Send + Sync>;
+
+/// Pause inserted between segments by a Rust-side splitter, scaled by `silence_scale`.
+pub const SEGMENT_GAP_MS: u32 = 200;
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct CommonTtsConfig {
-    pub rule_fars: String,
-    pub rule_fsts: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::path_bufs")
+    )]
+    pub rule_fars: Vec<PathBuf>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::path_bufs")
+    )]
+    pub rule_fsts: Vec<PathBuf>,
     pub max_num_sentences: i32,
     pub silence_scale: f32,
+    /// When set, text is split here instead of by sherpa-onnx, each segment is
+    /// synthesized separately and the results are joined with [`SEGMENT_GAP_MS`] of
+    /// silence times `silence_scale` (1.0 if unset).
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub sentence_splitter: Option<SentenceSplitter>,
+}
+
+impl fmt::Debug for CommonTtsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommonTtsConfig")
+            .field("rule_fars", &self.rule_fars)
+            .field("rule_fsts", &self.rule_fsts)
+            .field("max_num_sentences", &self.max_num_sentences)
+            .field("silence_scale", &self.silence_scale)
+            .field("sentence_splitter", &self.sentence_splitter.is_some())
+            .finish()
+    }
 }
 
 pub struct CommonTtsRaw {
@@ -38,25 +355,153 @@ pub struct CommonTtsRaw {
 }
 
 impl CommonTtsConfig {
-    pub fn to_raw(&self) -> CommonTtsRaw {
-        let rule_fars = if self.rule_fars.is_empty() {
-            None
-        } else {
-            Some(cstring_from_str(&self.rule_fars))
+    /// Build a config from the comma separated form sherpa-onnx uses.
+    pub fn from_joined_str(rule_fsts: &str, rule_fars: &str) -> Self {
+        let split = |joined: &str| {
+            joined
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from)
+                .collect()
         };
+        Self {
+            rule_fsts: split(rule_fsts),
+            rule_fars: split(rule_fars),
+            ..Default::default()
+        }
+    }
 
-        let rule_fsts = if self.rule_fsts.is_empty() {
-            None
+    /// Join the rule paths for the C API, failing if any of them don't exist.
+    pub fn to_raw(&self) -> Result<CommonTtsRaw> {
+        Ok(CommonTtsRaw {
+            rule_fars: join_rule_paths("rule_fars", &self.rule_fars)?,
+            rule_fsts: join_rule_paths("rule_fsts", &self.rule_fsts)?,
+            max_num_sentences: self.max_num_sentences,
+        })
+    }
+}
+
+/// Fail with [`Error::ModelNotFound`] if `lexicon` lists a missing file, or `data_dir`
+/// or `dict_dir` is a missing directory. Empty paths are skipped.
+fn check_lexicon_and_dirs(
+    engine: &str,
+    lexicon: &str,
+    data_dir: &str,
+    dict_dir: &str,
+) -> Result<()> {
+    for path in lexicon.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        if !Path::new(path).is_file() {
+            return Err(Error::model_not_found(format!("{} lexicon", engine), path));
+        }
+    }
+    for (name, dir) in [("data_dir", data_dir), ("dict_dir", dict_dir)] {
+        if !dir.is_empty() && !Path::new(dir).is_dir() {
+            return Err(Error::model_not_found(format!("{} {}", engine, name), dir));
+        }
+    }
+    Ok(())
+}
+
+/// The Rust-side splitting settings of a [`CommonTtsConfig`].
+#[derive(Clone, Default)]
+pub(crate) struct Segmenter {
+    splitter: Option<SentenceSplitter>,
+    gap_ms: u32,
+}
+
+impl Segmenter {
+    pub(crate) fn new(config: &CommonTtsConfig) -> Self {
+        let scale = if config.silence_scale > 0.0 {
+            config.silence_scale
         } else {
-            Some(cstring_from_str(&self.rule_fsts))
+            1.0
         };
+        Self {
+            splitter: config.sentence_splitter.clone(),
+            gap_ms: (SEGMENT_GAP_MS as f32 * scale).round() as u32,
+        }
+    }
 
-        CommonTtsRaw {
-            rule_fars,
-            rule_fsts,
-            max_num_sentences: self.max_num_sentences,
+    /// Segments to synthesize, using the built-in splitter if none was configured.
+    pub(crate) fn split(&self, text: &str) -> Vec<String> {
+        match &self.splitter {
+            Some(splitter) => splitter(text),
+            None => split_sentences(text)
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+/// Native engine owned by each TTS wrapper.
+pub(crate) type TtsHandle = NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineTts>;
+
+pub(crate) fn create_segmented(
+    tts: &TtsHandle,
+    segmenter: &Segmenter,
+    text: &str,
+    sid: i32,
+    speed: f32,
+) -> Result<Vec<TtsAudio>> {
+    segmenter
+        .split(text)
+        .iter()
+        .filter(|segment| !segment.trim().is_empty())
+        .map(|segment| unsafe { create(tts.as_ptr(), segment, sid, speed) })
+        .collect()
+}
+
+/// [`create`], going through the configured splitter if there is one.
+pub(crate) fn create_with_segmenter(
+    tts: &TtsHandle,
+    segmenter: &Segmenter,
+    text: &str,
+    sid: i32,
+    speed: f32,
+) -> Result<TtsAudio> {
+    if segmenter.splitter.is_none() {
+        return unsafe { create(tts.as_ptr(), text, sid, speed) };
+    }
+    let mut clips = create_segmented(tts, segmenter, text, sid, speed)?;
+    if clips.is_empty() {
+        return Ok(TtsAudio::new(Vec::new(), sample_rate(tts)));
+    }
+    let last = clips.len() - 1;
+    for clip in &mut clips[..last] {
+        clip.append_silence(segmenter.gap_ms);
+    }
+    TtsAudio::concat(&clips)
+}
+
+/// Split text after sentence-ending punctuation, keeping the punctuation.
+pub(crate) fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        let end = i + c.len_utf8();
+        let boundary = match c {
+            // Don't split decimals or abbreviations glued to the next word
+            '.' | '!' | '?' | ';' => {
+                end == text.len() || text[end..].starts_with(char::is_whitespace)
+            }
+            '。' | '！' | '？' | '；' | '\n' => true,
+            _ => false,
+        };
+        if boundary {
+            let sentence = text[start..end].trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence);
+            }
+            start = end;
         }
     }
+    let rest = text[start..].trim();
+    if !rest.is_empty() {
+        sentences.push(rest);
+    }
+    sentences
 }
 
 /// # Safety
@@ -68,31 +513,390 @@ pub unsafe fn create(
     sid: i32,
     speed: f32,
 ) -> Result<TtsAudio> {
+    let _timed = timed!("tts.generate", sid, speed, chars = text.chars().count());
     let text = cstring_from_str(text);
     let audio_ptr = sherpa_rs_sys::SherpaOnnxOfflineTtsGenerate(tts, text.as_ptr(), sid, speed);
+    take_generated_audio(audio_ptr)
+}
+
+struct ProgressState<'a> {
+    callback: &'a mut dyn FnMut(TtsProgress) -> bool,
+    samples: Vec<f32>,
+    sentence_index: usize,
+    cancelled: bool,
+    guard: FfiCallback,
+}
+
+pub(crate) fn create_with_progress(
+    tts: &TtsHandle,
+    text: &str,
+    sid: i32,
+    speed: f32,
+    callback: &mut dyn FnMut(TtsProgress) -> bool,
+) -> Result<TtsGeneration> {
+    let _timed = timed!("tts.generate", sid, speed, chars = text.chars().count());
+    let text = cstring_from_str(text);
+    let mut state = ProgressState {
+        callback,
+        samples: Vec::new(),
+        sentence_index: 0,
+        cancelled: false,
+        guard: FfiCallback::new("tts progress"),
+    };
+
+    let audio_ptr = unsafe {
+        sherpa_rs_sys::SherpaOnnxOfflineTtsGenerateWithProgressCallbackWithArg(
+            tts.as_ptr(),
+            text.as_ptr(),
+            sid,
+            speed,
+            Some(progress_callback_wrapper),
+            &mut state as *mut ProgressState as *mut c_void,
+        )
+    };
+
+    if state.guard.panicked() {
+        if !audio_ptr.is_null() {
+            unsafe { sherpa_rs_sys::SherpaOnnxDestroyOfflineTtsGeneratedAudio(audio_ptr) };
+        }
+        state.guard.finish()?;
+    }
+
+    let mut audio = unsafe { take_generated_audio(audio_ptr)? };
+    if state.cancelled {
+        // Only keep what the callback has seen
+        audio = TtsAudio::new(state.samples, audio.sample_rate);
+    }
+    Ok(TtsGeneration {
+        audio,
+        truncated: state.cancelled,
+    })
+}
+
+/// [`create_with_progress`] stopping at the next sentence once `token` is cancelled.
+pub(crate) fn create_with_cancel(
+    tts: &TtsHandle,
+    text: &str,
+    sid: i32,
+    speed: f32,
+    token: &CancellationToken,
+) -> Result<TtsAudio> {
+    token.check("tts")?;
+    let generation = create_with_progress(tts, text, sid, speed, &mut |_| !token.is_cancelled())?;
+    if generation.truncated {
+        return Err(Error::cancelled("tts"));
+    }
+    Ok(generation.audio)
+}
+
+unsafe extern "C" fn progress_callback_wrapper(
+    samples: *const f32,
+    n: i32,
+    progress: f32,
+    arg: *mut c_void,
+) -> i32 {
+    let ProgressState {
+        callback,
+        samples: all,
+        sentence_index,
+        cancelled,
+        guard,
+    } = &mut *(arg as *mut ProgressState);
+
+    let chunk = if samples.is_null() || n <= 0 {
+        &[][..]
+    } else {
+        std::slice::from_raw_parts(samples, n as usize)
+    };
+    let start = all.len();
+    all.extend_from_slice(chunk);
+    let index = *sentence_index;
+    *sentence_index += 1;
+    debug_event!(
+        sentence = index,
+        progress,
+        samples = chunk.len(),
+        "tts chunk"
+    );
+
+    let info = TtsProgress {
+        sentence_index: index,
+        total_sentences: (progress > 0.0).then(|| ((index + 1) as f32 / progress).round() as usize),
+        progress,
+        chunk: &all[start..],
+        samples: all,
+    };
+    match guard.call(|| callback(info)) {
+        Some(true) => 1,
+        Some(false) => {
+            *cancelled = true;
+            0
+        }
+        None => 0,
+    }
+}
+
+/// Create a native engine with `create`, retrying with CPU if `onnx_config.fallback`
+/// allows it. `name` describes the engine in errors.
+pub(crate) fn create_tts_with_fallback<F>(
+    name: &str,
+    onnx_config: &OnnxConfig,
+    mut create: F,
+) -> Result<(TtsHandle, ProviderSelection)>
+where
+    F: FnMut(&OnnxConfig) -> *const sherpa_rs_sys::SherpaOnnxOfflineTts,
+{
+    let _timed = timed!("tts.create", engine = name);
+    debug_event!(?onnx_config, "creating tts engine");
+    create_with_fallback(
+        Some(onnx_config.provider.clone()),
+        onnx_config.fallback,
+        |provider| {
+            let tts = create(&OnnxConfig {
+                provider,
+                ..onnx_config.clone()
+            });
+            unsafe { NativeHandle::new(tts, sherpa_rs_sys::SherpaOnnxDestroyOfflineTts) }
+                .ok_or_else(|| Error::native_creation_failed(name))
+        },
+    )
+}
+
+/// Error for engines whose model can draw noise from a generator the C API can't seed.
+fn unseeded_noise(engine: &str) -> Error {
+    Error::unsupported(format!(
+        "deterministic {} synthesis (the model can draw noise and sherpa-onnx takes no seed)",
+        engine
+    ))
+}
+
+pub(crate) fn sample_rate(tts: &TtsHandle) -> u32 {
+    unsafe { sherpa_rs_sys::SherpaOnnxOfflineTtsSampleRate(tts.as_ptr()) }.max(0) as u32
+}
 
+pub(crate) fn num_speakers(tts: &TtsHandle) -> u32 {
+    unsafe { sherpa_rs_sys::SherpaOnnxOfflineTtsNumSpeakers(tts.as_ptr()) }.max(0) as u32
+}
+
+/// Copy the generated audio out and free it.
+///
+/// # Safety
+///
+/// `audio_ptr` must be null or a pointer returned by one of the generate functions,
+/// and must not be used afterwards.
+pub(crate) unsafe fn take_generated_audio(
+    audio_ptr: *const sherpa_rs_sys::SherpaOnnxGeneratedAudio,
+) -> Result<TtsAudio> {
     if audio_ptr.is_null() {
-        bail!("audio is null");
+        return Err(Error::processing_failed("tts", "no audio returned"));
     }
     let audio = audio_ptr.read();
 
     if audio.n.is_negative() {
-        bail!("no samples found");
+        sherpa_rs_sys::SherpaOnnxDestroyOfflineTtsGeneratedAudio(audio_ptr);
+        return Err(Error::processing_failed("tts", "no samples found"));
     }
     if audio.samples.is_null() {
-        bail!("audio samples are null");
+        sherpa_rs_sys::SherpaOnnxDestroyOfflineTtsGeneratedAudio(audio_ptr);
+        return Err(Error::processing_failed("tts", "audio samples are null"));
     }
     let samples: &[f32] = std::slice::from_raw_parts(audio.samples, audio.n as usize);
     let samples = samples.to_vec();
     let sample_rate = audio.sample_rate;
-    let duration = (samples.len() as i32) / sample_rate;
 
     // Free
     sherpa_rs_sys::SherpaOnnxDestroyOfflineTtsGeneratedAudio(audio_ptr);
 
-    Ok(TtsAudio {
-        samples,
-        sample_rate: sample_rate as u32,
-        duration,
-    })
+    Ok(TtsAudio::new(samples, sample_rate as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize) -> TokenSpan {
+        TokenSpan {
+            token: "a".into(),
+            start,
+            end,
+            sample_rate: 1000,
+        }
+    }
+
+    #[test]
+    fn trim_silence_keeps_a_margin() {
+        // 10 ms margin is 10 samples at 1 kHz
+        let mut samples = vec![0.0; 50];
+        samples.extend([0.5; 20]);
+        samples.extend([0.0; 50]);
+        let mut audio = TtsAudio::new(samples, 1000);
+        audio.alignments = Some(vec![span(0, 30), span(30, 60), span(60, 120)]);
+
+        audio.trim_silence(-40.0);
+        assert_eq!(audio.samples.len(), 40);
+        assert_eq!(audio.samples[..10], [0.0; 10]);
+        assert_eq!(audio.samples[10..30], [0.5; 20]);
+        assert_eq!(audio.samples[30..], [0.0; 10]);
+        assert_eq!(audio.duration, 0.04);
+        assert_eq!(audio.alignments, Some(vec![span(0, 20), span(20, 40)]));
+    }
+
+    #[test]
+    fn trim_silence_margin_stops_at_the_edges() {
+        let mut samples = vec![0.0; 5];
+        samples.extend([-0.5; 10]);
+        let mut audio = TtsAudio::new(samples.clone(), 1000);
+        audio.trim_silence(-40.0);
+        assert_eq!(audio.samples, samples);
+    }
+
+    #[test]
+    fn trim_silence_of_silence_is_empty() {
+        // 0.001 is -60 dBFS
+        let mut audio = TtsAudio::new(vec![0.001; 100], 1000);
+        audio.trim_silence(-40.0);
+        assert!(audio.samples.is_empty());
+        assert_eq!(audio.duration, 0.0);
+    }
+
+    #[test]
+    fn pad_adds_silence() {
+        let mut audio = TtsAudio::new(vec![0.5; 100], 16000);
+        audio.alignments = Some(vec![span(0, 100)]);
+        audio.pad(10, 25);
+        assert_eq!(audio.samples.len(), 160 + 100 + 400);
+        assert_eq!(audio.samples[..160], [0.0; 160]);
+        assert_eq!(audio.samples[160..260], [0.5; 100]);
+        assert!(audio.samples[260..].iter().all(|&s| s == 0.0));
+        assert_eq!(audio.duration, 660.0 / 16000.0);
+        assert_eq!(audio.alignments, Some(vec![span(160, 260)]));
+
+        audio.append_silence(5);
+        assert_eq!(audio.samples.len(), 660 + 80);
+    }
+
+    #[test]
+    fn concat_needs_clips() {
+        let err = TtsAudio::concat(&[]).unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn concat_one_clip() {
+        let audio = TtsAudio::concat(&[TtsAudio::new(vec![0.25; 100], 1000)]).unwrap();
+        assert_eq!(audio.samples, [0.25; 100]);
+        assert_eq!(audio.sample_rate, 1000);
+        assert_eq!(audio.duration, 0.1);
+    }
+
+    #[test]
+    fn concat_three_clips() {
+        let clips = [
+            TtsAudio::new(vec![0.1; 100], 1000),
+            TtsAudio::new(vec![0.2; 200], 1000),
+            TtsAudio::new(vec![0.3; 300], 1000),
+        ];
+        let audio = TtsAudio::concat(&clips).unwrap();
+        assert_eq!(audio.samples.len(), 600);
+        assert_eq!(audio.samples[99..101], [0.1, 0.2]);
+        assert_eq!(audio.samples[299..301], [0.2, 0.3]);
+        assert_eq!(audio.duration, 0.6);
+
+        // Each of the two joins overlaps 10 ms, 10 samples
+        let audio = TtsAudio::concat_with_crossfade(&clips, 10).unwrap();
+        assert_eq!(audio.samples.len(), 580);
+        assert_eq!(audio.duration, 0.58);
+        assert_eq!(audio.samples[..90], [0.1; 90]);
+        assert!(audio.samples[90..100].iter().all(|&s| s > 0.1 && s < 0.3));
+        assert_eq!(audio.samples[290..], [0.3; 290]);
+    }
+
+    #[test]
+    fn concat_shifts_alignments() {
+        let mut first = TtsAudio::new(vec![0.1; 100], 1000);
+        first.alignments = Some(vec![span(0, 100)]);
+        let mut second = TtsAudio::new(vec![0.2; 100], 1000);
+        second.alignments = Some(vec![span(0, 50)]);
+        let audio = TtsAudio::concat(&[first, second]).unwrap();
+        assert_eq!(audio.alignments, Some(vec![span(0, 100), span(100, 150)]));
+    }
+
+    #[test]
+    fn concat_rejects_mixed_sample_rates() {
+        let clips = [
+            TtsAudio::new(vec![0.0; 10], 16000),
+            TtsAudio::new(vec![0.0; 10], 22050),
+        ];
+        let err = TtsAudio::concat_with_crossfade(&clips, 5).unwrap_err();
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+    }
+
+    fn peak_db(audio: &TtsAudio) -> f32 {
+        let peak = audio
+            .samples
+            .iter()
+            .fold(0.0f32, |peak, s| peak.max(s.abs()));
+        20.0 * peak.log10()
+    }
+
+    fn rms_db(audio: &TtsAudio) -> f32 {
+        let sum: f32 = audio.samples.iter().map(|s| s * s).sum();
+        10.0 * (sum / audio.samples.len() as f32).log10()
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn normalize_peak_reaches_target() {
+        let samples = (0..1000).map(|i| 0.25 * (i as f32 * 0.1).sin()).collect();
+        let mut audio = TtsAudio::new(samples, 1000);
+        let before = peak_db(&audio);
+        let gain_db = audio.normalize_peak(-1.0);
+        assert_close(peak_db(&audio), -1.0);
+        assert_close(gain_db, -1.0 - before);
+
+        // Turning down works too
+        let gain_db = audio.normalize_peak(-20.0);
+        assert_close(peak_db(&audio), -20.0);
+        assert_close(gain_db, -19.0);
+    }
+
+    #[test]
+    fn normalize_rms_reaches_target() {
+        // DC at 0.1 is -20 dBFS
+        let mut audio = TtsAudio::new(vec![0.1; 1000], 1000);
+        let gain_db = audio.normalize_rms(-12.0);
+        assert_close(rms_db(&audio), -12.0);
+        assert_close(gain_db, 8.0);
+    }
+
+    #[test]
+    fn normalize_gain_is_capped() {
+        // -80 dBFS would need 80 dB of gain
+        let mut audio = TtsAudio::new(vec![1e-4; 100], 1000);
+        let gain_db = audio.normalize_peak(0.0);
+        assert_eq!(gain_db, MAX_NORMALIZE_GAIN_DB);
+        assert_close(peak_db(&audio), -80.0 + MAX_NORMALIZE_GAIN_DB);
+
+        let mut audio = TtsAudio::new(vec![1e-4; 100], 1000);
+        assert_eq!(audio.normalize_rms(0.0), MAX_NORMALIZE_GAIN_DB);
+    }
+
+    #[test]
+    fn normalize_leaves_silence_alone() {
+        let mut audio = TtsAudio::new(vec![0.0; 100], 1000);
+        assert_eq!(audio.normalize_peak(-1.0), 0.0);
+        assert_eq!(audio.normalize_rms(-20.0), 0.0);
+        assert_eq!(audio.samples, [0.0; 100]);
+
+        let mut audio = TtsAudio::new(Vec::new(), 1000);
+        assert_eq!(audio.normalize_rms(-20.0), 0.0);
+    }
 }
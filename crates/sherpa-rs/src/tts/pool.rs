@@ -0,0 +1,236 @@
+use std::{
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    sync::{Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{Error, Result};
+
+use super::{TtsAudio, TtsEngine, TtsRequest};
+
+type Factory<E> = Box<dyn Fn() -> Result<E> + Send + Sync>;
+
+/// A fixed number of engines shared between threads.
+///
+/// Each call checks out an idle engine in round-robin order, blocking until one is free.
+/// An engine that panics is dropped, since its native state can't be trusted anymore,
+/// and the next checkout builds a replacement from the factory.
+pub struct TtsPool<E: TtsEngine> {
+    state: Mutex<PoolState<E>>,
+    available: Condvar,
+    factory: Factory<E>,
+    size: usize,
+    /// Held by [`TtsPool::warm_up_all`], so two of them don't each wait for engines the
+    /// other checked out.
+    warming: Mutex<()>,
+}
+
+struct PoolState<E> {
+    idle: VecDeque<E>,
+    /// Engines dropped after a panic, rebuilt by the next checkout.
+    missing: usize,
+}
+
+impl<E: TtsEngine> TtsPool<E> {
+    pub fn new<F>(factory: F, size: usize) -> Result<Self>
+    where
+        F: Fn() -> Result<E> + Send + Sync + 'static,
+    {
+        if size == 0 {
+            return Err(Error::invalid_input("TtsPool needs at least one engine"));
+        }
+        let idle = (0..size)
+            .map(|_| factory())
+            .collect::<Result<VecDeque<_>>>()?;
+        Ok(Self {
+            state: Mutex::new(PoolState { idle, missing: 0 }),
+            available: Condvar::new(),
+            factory: Box::new(factory),
+            size,
+            warming: Mutex::new(()),
+        })
+    }
+
+    pub fn synthesize(&self, text: &str, sid: i32, speed: f32) -> Result<TtsAudio> {
+        self.synthesize_request(&TtsRequest {
+            sid,
+            speed,
+            ..TtsRequest::new(text)
+        })
+    }
+
+    pub fn synthesize_request(&self, req: &TtsRequest) -> Result<TtsAudio> {
+        self.with_engine(|engine| engine.synthesize(req))
+    }
+
+    /// Like [`Self::synthesize_request`], with [`TtsEngine::synthesize_deterministic`].
+    pub fn synthesize_deterministic(&self, req: &TtsRequest) -> Result<TtsAudio> {
+        self.with_engine(|engine| engine.synthesize_deterministic(req))
+    }
+
+    /// Run `f` on an idle engine, rebuilding the engine if it panics.
+    fn with_engine<F>(&self, f: F) -> Result<TtsAudio>
+    where
+        F: FnOnce(&mut E) -> Result<TtsAudio>,
+    {
+        let mut engine = self.checkout()?;
+
+        match panic::catch_unwind(AssertUnwindSafe(|| f(&mut engine))) {
+            Ok(result) => {
+                self.give_back(Some(engine));
+                result
+            }
+            Err(_) => {
+                drop(engine);
+                self.give_back(None);
+                Err(Error::processing_failed(
+                    "tts pool",
+                    "engine panicked, it will be rebuilt",
+                ))
+            }
+        }
+    }
+
+    /// Run [`TtsEngine::warm_up`] on every engine concurrently, waiting for the ones in
+    /// use to come back first. Returns how long each took.
+    pub fn warm_up_all(&self) -> Result<Vec<Duration>> {
+        let _warming = self.warming.lock().unwrap_or_else(|e| e.into_inner());
+        let mut engines = Vec::with_capacity(self.size);
+        for _ in 0..self.size {
+            match self.checkout() {
+                Ok(engine) => engines.push(engine),
+                Err(err) => {
+                    for engine in engines {
+                        self.give_back(Some(engine));
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let results: Vec<thread::Result<Result<Duration>>> = thread::scope(|scope| {
+            let handles: Vec<_> = engines
+                .iter_mut()
+                .map(|engine| scope.spawn(move || engine.warm_up()))
+                .collect();
+            handles.into_iter().map(|handle| handle.join()).collect()
+        });
+
+        let mut elapsed = Vec::with_capacity(self.size);
+        let mut first_err = None;
+        for (engine, result) in engines.into_iter().zip(results) {
+            match result {
+                Ok(result) => {
+                    self.give_back(Some(engine));
+                    match result {
+                        Ok(duration) => elapsed.push(duration),
+                        Err(err) => {
+                            first_err.get_or_insert(err);
+                        }
+                    }
+                }
+                Err(_) => {
+                    drop(engine);
+                    self.give_back(None);
+                    first_err.get_or_insert(Error::processing_failed(
+                        "tts pool",
+                        "engine panicked, it will be rebuilt",
+                    ));
+                }
+            }
+        }
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(elapsed),
+        }
+    }
+
+    fn checkout(&self) -> Result<E> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(engine) = state.idle.pop_front() {
+                return Ok(engine);
+            }
+            if state.missing > 0 {
+                state.missing -= 1;
+                drop(state);
+                return (self.factory)().inspect_err(|_| self.give_back(None));
+            }
+            state = self
+                .available
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn give_back(&self, engine: Option<E>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match engine {
+            Some(engine) => state.idle.push_back(engine),
+            None => state.missing += 1,
+        }
+        self.available.notify_one();
+    }
+}
+
+#[cfg(feature = "async")]
+impl<E: TtsEngine + 'static> TtsPool<E> {
+    /// Run [`Self::synthesize`] on tokio's blocking thread pool.
+    pub fn synthesize_async(
+        self: &std::sync::Arc<Self>,
+        text: String,
+        sid: i32,
+        speed: f32,
+    ) -> impl std::future::Future<Output = Result<TtsAudio>> {
+        let pool = std::sync::Arc::clone(self);
+        async move {
+            tokio::task::spawn_blocking(move || pool.synthesize(&text, sid, speed))
+                .await
+                .map_err(|err| Error::processing_failed("tts pool", err.to_string()))?
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing,
+        tts::{VitsTts, VitsTtsConfig},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    #[ignore = "needs vits-ljs"]
+    fn pool_serves_concurrent_requests() {
+        let pool = Arc::new(
+            TtsPool::new(
+                || {
+                    VitsTts::new(VitsTtsConfig {
+                        model: testing::model("vits-ljs/vits-ljs.onnx"),
+                        lexicon: testing::model("vits-ljs/lexicon.txt"),
+                        tokens: testing::model("vits-ljs/tokens.txt"),
+                        ..Default::default()
+                    })
+                },
+                4,
+            )
+            .unwrap(),
+        );
+
+        let handles: Vec<_> = (0..32)
+            .map(|i| {
+                let pool = Arc::clone(&pool);
+                thread::spawn(move || pool.synthesize(&format!("Request number {i}."), 0, 1.0))
+            })
+            .collect();
+        for handle in handles {
+            let audio = handle.join().unwrap().unwrap();
+            assert!(!audio.samples.is_empty());
+            assert!(audio.sample_rate > 0);
+        }
+        assert_eq!(pool.state.lock().unwrap().idle.len(), 4);
+    }
+}
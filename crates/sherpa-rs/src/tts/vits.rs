@@ -1,21 +1,45 @@
-use std::{mem, ptr::null};
+use std::{mem, path::Path, ptr::null, time::Duration};
 
-use crate::{utils::cstring_from_str, OnnxConfig};
-use eyre::Result;
+use crate::{
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::ProviderSelection,
+    trace::timed,
+    utils::{cstring_from_str, time_warm_up},
+    CancellationToken, Error, OnnxConfig, Provider, Result,
+};
 use sherpa_rs_sys;
 
-use super::{CommonTtsConfig, TtsAudio};
+use super::{
+    CommonTtsConfig, Segmenter, TtsAudio, TtsGeneration, TtsHandle, TtsProgress, WARM_UP_TEXT,
+};
 
+/// VITS voices are picked by speaker id. sherpa-onnx has no way to pass a speaker
+/// embedding per call, so to clone a voice from a WAV use
+/// [`ZipVoiceTts::create_with_prompt_file`](super::ZipVoiceTts::create_with_prompt_file).
 pub struct VitsTts {
-    tts: *const sherpa_rs_sys::SherpaOnnxOfflineTts,
+    tts: TtsHandle,
+    segmenter: Segmenter,
+    config: VitsTtsConfig,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
 }
 
-#[derive(Default)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct VitsTtsConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub model: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::path_list")
+    )]
     pub lexicon: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub dict_dir: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub data_dir: String,
     pub length_scale: f32,
     pub noise_scale: f32,
@@ -26,63 +50,319 @@ pub struct VitsTtsConfig {
     pub tts_config: CommonTtsConfig,
 }
 
+impl VitsTtsConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, path) in [("model", &self.model), ("tokens", &self.tokens)] {
+            if path.is_empty() {
+                return Err(Error::invalid_input(format!("vits {} path is empty", name)));
+            }
+            if !Path::new(path).is_file() {
+                return Err(Error::model_not_found(format!("vits {}", name), path));
+            }
+        }
+        super::check_lexicon_and_dirs("vits", &self.lexicon, &self.data_dir, &self.dict_dir)?;
+        self.tts_config.to_raw()?;
+        Ok(())
+    }
+}
+
+/// The VITS knobs that trade determinism for expressiveness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VitsSynthesisParams {
+    pub noise_scale: f32,
+    pub noise_scale_w: f32,
+    pub length_scale: f32,
+}
+
 impl VitsTts {
-    pub fn new(config: VitsTtsConfig) -> Self {
-        let tts = unsafe {
-            let model = cstring_from_str(&config.model);
-            let tokens = cstring_from_str(&config.tokens);
-            let data_dir = cstring_from_str(&config.data_dir);
-            let lexicon = cstring_from_str(&config.lexicon);
-            let dict_dir = cstring_from_str(&config.dict_dir);
-
-            let provider = cstring_from_str(&config.onnx_config.provider);
-
-            let tts_config = config.tts_config.to_raw();
-
-            let model_config = sherpa_rs_sys::SherpaOnnxOfflineTtsModelConfig {
-                num_threads: config.onnx_config.num_threads,
-                vits: sherpa_rs_sys::SherpaOnnxOfflineTtsVitsModelConfig {
-                    model: model.as_ptr(),
-                    lexicon: lexicon.as_ptr(),
-                    tokens: tokens.as_ptr(),
-                    data_dir: data_dir.as_ptr(),
-                    noise_scale: config.noise_scale,
-                    noise_scale_w: config.noise_scale_w,
-                    length_scale: config.length_scale,
-                    dict_dir: dict_dir.as_ptr(),
-                },
-                debug: config.onnx_config.debug.into(),
-                provider: provider.as_ptr(),
-                matcha: mem::zeroed::<_>(),
-                kokoro: mem::zeroed::<_>(),
-                kitten: mem::zeroed::<_>(),
-                zipvoice: mem::zeroed::<_>(),
-            };
-            let config = sherpa_rs_sys::SherpaOnnxOfflineTtsConfig {
-                max_num_sentences: config.tts_config.max_num_sentences,
-                model: model_config,
-                rule_fars: tts_config.rule_fars.map(|v| v.as_ptr()).unwrap_or(null()),
-                rule_fsts: tts_config.rule_fsts.map(|v| v.as_ptr()).unwrap_or(null()),
-                silence_scale: config.silence_scale,
-            };
-            sherpa_rs_sys::SherpaOnnxCreateOfflineTts(&config)
+    /// Like [`Self::new`], with the model, tokens and the lexicon or espeak-ng data found
+    /// in `dir`, an unpacked sherpa-onnx release. Fails if the files aren't there.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: VitsTtsConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Vits,
+            precision,
+            &config.onnx_config.provider,
+        )?;
+        Self::new(VitsTtsConfig {
+            model: dir.file(Role::Model),
+            tokens: dir.file(Role::Tokens),
+            data_dir: dir.file_or(Role::DataDir, config.data_dir),
+            dict_dir: dir.file_or(Role::DictDir, config.dict_dir),
+            lexicon: dir.file_or(Role::Lexicon, config.lexicon),
+            ..config
+        })
+    }
+
+    pub fn new(config: VitsTtsConfig) -> Result<Self> {
+        config.validate()?;
+        let (tts, provider) = Self::create_with_fallback(&config)?;
+        Ok(Self {
+            tts,
+            segmenter: Segmenter::new(&config.tts_config),
+            precision: ModelPrecision::of_file(&config.model),
+            config,
+            provider,
+        })
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model file.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Synthesize a short phrase and discard it, so the first real [`Self::create`]
+    /// doesn't pay for ONNX Runtime's one-time setup. Returns how long it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("tts.warm_up");
+        time_warm_up(|| self.create(WARM_UP_TEXT, 0, 1.0))
+    }
+
+    /// Always fails with [`Error::Unsupported`](crate::Error::Unsupported). The model
+    /// draws noise scaled by `noise_scale` from a generator sherpa-onnx has no seed for,
+    /// and a `noise_scale` of 0.0 means the upstream default rather than no noise.
+    pub fn create_deterministic(
+        &mut self,
+        _text: &str,
+        _sid: i32,
+        _speed: f32,
+    ) -> Result<TtsAudio> {
+        Err(super::unseeded_noise("vits"))
+    }
+
+    fn create_with_fallback(config: &VitsTtsConfig) -> Result<(TtsHandle, ProviderSelection)> {
+        super::create_tts_with_fallback("vits tts", &config.onnx_config, |onnx_config| unsafe {
+            Self::create_tts(config, onnx_config)
+        })
+    }
+
+    unsafe fn create_tts(
+        config: &VitsTtsConfig,
+        onnx_config: &OnnxConfig,
+    ) -> *const sherpa_rs_sys::SherpaOnnxOfflineTts {
+        let model = cstring_from_str(&config.model);
+        let tokens = cstring_from_str(&config.tokens);
+        let data_dir = cstring_from_str(&config.data_dir);
+        let lexicon = cstring_from_str(&config.lexicon);
+        let dict_dir = cstring_from_str(&config.dict_dir);
+
+        let provider = match onnx_config.provider.to_cstring() {
+            Ok(provider) => provider,
+            Err(err) => {
+                tracing::error!("Invalid vits tts provider: {}", err);
+                return null();
+            }
+        };
+
+        let tts_config = match config.tts_config.to_raw() {
+            Ok(raw) => raw,
+            Err(err) => {
+                tracing::error!("Invalid vits tts config: {}", err);
+                return null();
+            }
         };
 
-        Self { tts }
+        let model_config = sherpa_rs_sys::SherpaOnnxOfflineTtsModelConfig {
+            num_threads: onnx_config.num_threads.to_raw(),
+            vits: sherpa_rs_sys::SherpaOnnxOfflineTtsVitsModelConfig {
+                model: model.as_ptr(),
+                lexicon: lexicon.as_ptr(),
+                tokens: tokens.as_ptr(),
+                data_dir: data_dir.as_ptr(),
+                noise_scale: config.noise_scale,
+                noise_scale_w: config.noise_scale_w,
+                length_scale: config.length_scale,
+                dict_dir: dict_dir.as_ptr(),
+            },
+            debug: onnx_config.debug.into(),
+            provider: provider.as_ptr(),
+            matcha: mem::zeroed::<_>(),
+            kokoro: mem::zeroed::<_>(),
+            kitten: mem::zeroed::<_>(),
+            zipvoice: mem::zeroed::<_>(),
+        };
+        let config = sherpa_rs_sys::SherpaOnnxOfflineTtsConfig {
+            max_num_sentences: config.tts_config.max_num_sentences,
+            model: model_config,
+            rule_fars: tts_config
+                .rule_fars
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(null()),
+            rule_fsts: tts_config
+                .rule_fsts
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(null()),
+            silence_scale: config.silence_scale,
+        };
+        sherpa_rs_sys::SherpaOnnxCreateOfflineTts(&config)
+    }
+
+    /// Parameters the engine was built with.
+    pub fn params(&self) -> VitsSynthesisParams {
+        VitsSynthesisParams {
+            noise_scale: self.config.noise_scale,
+            noise_scale_w: self.config.noise_scale_w,
+            length_scale: self.config.length_scale,
+        }
+    }
+
+    /// Apply new synthesis parameters. The C API fixes them at creation, so this reloads
+    /// the model; on failure the previous engine is kept.
+    pub fn reconfigure(&mut self, params: VitsSynthesisParams) -> Result<()> {
+        let mut config = self.config.clone();
+        config.noise_scale = params.noise_scale;
+        config.noise_scale_w = params.noise_scale_w;
+        config.length_scale = params.length_scale;
+
+        let (tts, provider) = Self::create_with_fallback(&config)?;
+        self.tts = tts;
+        self.config = config;
+        self.provider = provider;
+        Ok(())
     }
 
     pub fn create(&mut self, text: &str, sid: i32, speed: f32) -> Result<TtsAudio> {
-        unsafe { super::create(self.tts, text, sid, speed) }
+        super::create_with_segmenter(&self.tts, &self.segmenter, text, sid, speed)
+    }
+
+    /// Synthesize each segment of `text` separately, split by the configured
+    /// `sentence_splitter` or on sentence punctuation.
+    pub fn synthesize_segmented(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+    ) -> Result<Vec<TtsAudio>> {
+        super::create_segmented(&self.tts, &self.segmenter, text, sid, speed)
+    }
+
+    /// Generate with `callback` called after each sentence. Returning `false` from it
+    /// stops generation; the audio generated so far is returned marked as truncated.
+    pub fn create_with_progress<F>(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+        mut callback: F,
+    ) -> Result<TtsGeneration>
+    where
+        F: FnMut(TtsProgress) -> bool,
+    {
+        super::create_with_progress(&self.tts, text, sid, speed, &mut callback)
+    }
+
+    /// Like [`Self::create_with_progress`], but cancelling `token` stops generation at the
+    /// next sentence with [`Error::Cancelled`](crate::Error::Cancelled).
+    pub fn create_with_cancel(
+        &mut self,
+        text: &str,
+        sid: i32,
+        speed: f32,
+        token: &CancellationToken,
+    ) -> Result<TtsAudio> {
+        super::create_with_cancel(&self.tts, text, sid, speed, token)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        super::sample_rate(&self.tts)
+    }
+
+    pub fn num_speakers(&self) -> u32 {
+        super::num_speakers(&self.tts)
     }
 }
 
 unsafe impl Send for VitsTts {}
 unsafe impl Sync for VitsTts {}
 
-impl Drop for VitsTts {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineTts(self.tts);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    fn new_err(config: VitsTtsConfig) -> Error {
+        match VitsTts::new(config) {
+            Ok(_) => panic!("created vits from a broken config"),
+            Err(err) => err,
         }
     }
+
+    #[test]
+    fn new_rejects_empty_model_path() {
+        let err = new_err(VitsTtsConfig::default());
+        assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn new_reports_missing_files() {
+        let dir = ScratchDir::new("vits-missing");
+        let err = new_err(VitsTtsConfig {
+            model: dir.join("model.onnx"),
+            tokens: dir.touch("tokens.txt"),
+            ..Default::default()
+        });
+        assert!(
+            matches!(&err, Error::ModelNotFound { component, .. } if component == "vits model"),
+            "{:?}",
+            err
+        );
+
+        let err = new_err(VitsTtsConfig {
+            model: dir.touch("model.onnx"),
+            tokens: dir.join("tokens.txt"),
+            lexicon: dir.join("lexicon.txt"),
+            ..Default::default()
+        });
+        assert!(
+            matches!(&err, Error::ModelNotFound { component, .. } if component == "vits tokens"),
+            "{:?}",
+            err
+        );
+
+        let err = new_err(VitsTtsConfig {
+            model: dir.touch("model.onnx"),
+            tokens: dir.touch("tokens.txt"),
+            lexicon: dir.join("lexicon.txt"),
+            ..Default::default()
+        });
+        assert!(
+            matches!(&err, Error::ModelNotFound { component, .. } if component == "vits lexicon"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn new_reports_native_failure() {
+        // sherpa-onnx rejects an espeak-ng data_dir without its phoneme tables before
+        // loading the model, so the empty files are never read
+        let dir = ScratchDir::new("vits-native");
+        let err = new_err(VitsTtsConfig {
+            model: dir.touch("model.onnx"),
+            tokens: dir.touch("tokens.txt"),
+            data_dir: dir.mkdir("espeak-ng-data"),
+            ..Default::default()
+        });
+        assert!(
+            matches!(err, Error::NativeCreationFailed { .. }),
+            "{:?}",
+            err
+        );
+    }
 }
@@ -1,22 +1,44 @@
-use std::{mem, ptr::null};
+use std::{ffi::CString, mem, path::Path, ptr::null, time::Duration};
 
-use crate::{utils::cstring_from_str, OnnxConfig};
-use eyre::Result;
+use crate::{
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::ProviderSelection,
+    trace::timed,
+    utils::{cstring_from_str, read_wav, resample, time_warm_up, ChannelMix, FfiCallback},
+    Error, OnnxConfig, Provider, Result,
+};
 use sherpa_rs_sys;
 
-use super::{CommonTtsConfig, TtsAudio};
+use super::{CommonTtsConfig, Segmenter, TtsAudio, TtsHandle, WARM_UP_TEXT};
+
+/// Shortest reference audio accepted as a voice prompt, in seconds.
+const MIN_PROMPT_SECS: f32 = 1.0;
 
 pub struct ZipVoiceTts {
-    tts: *const sherpa_rs_sys::SherpaOnnxOfflineTts,
+    tts: TtsHandle,
+    segmenter: Segmenter,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
 }
 
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ZipVoiceTtsConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub encoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub decoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub vocoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub data_dir: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::path_list")
+    )]
     pub lexicon: String,
     pub feat_scale: f32,
     pub t_shift: f32,
@@ -26,52 +48,299 @@ pub struct ZipVoiceTtsConfig {
     pub common_config: CommonTtsConfig,
 }
 
+impl ZipVoiceTtsConfig {
+    /// Start a config with upstream's recommended defaults.
+    pub fn builder() -> ZipVoiceTtsConfigBuilder {
+        ZipVoiceTtsConfigBuilder {
+            config: Self {
+                feat_scale: 0.1,
+                t_shift: 0.5,
+                target_rms: 0.1,
+                guidance_scale: 1.0,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (name, path) in [
+            ("tokens", &self.tokens),
+            ("encoder", &self.encoder),
+            ("decoder", &self.decoder),
+            ("vocoder", &self.vocoder),
+        ] {
+            if path.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "zipvoice {} path is empty",
+                    name
+                )));
+            }
+            if !Path::new(path).is_file() {
+                return Err(Error::model_not_found(format!("zipvoice {}", name), path));
+            }
+        }
+        self.common_config.to_raw()?;
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        [
+            ("tokens", &self.tokens),
+            ("encoder", &self.encoder),
+            ("decoder", &self.decoder),
+            ("vocoder", &self.vocoder),
+            ("data_dir", &self.data_dir),
+            ("lexicon", &self.lexicon),
+        ]
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+pub struct ZipVoiceTtsConfigBuilder {
+    config: ZipVoiceTtsConfig,
+}
+
+fn path_to_string<P: AsRef<Path>>(path: P) -> String {
+    path.as_ref().to_string_lossy().into_owned()
+}
+
+impl ZipVoiceTtsConfigBuilder {
+    pub fn tokens<P: AsRef<Path>>(mut self, tokens: P) -> Self {
+        self.config.tokens = path_to_string(tokens);
+        self
+    }
+
+    pub fn encoder<P: AsRef<Path>>(mut self, encoder: P) -> Self {
+        self.config.encoder = path_to_string(encoder);
+        self
+    }
+
+    pub fn decoder<P: AsRef<Path>>(mut self, decoder: P) -> Self {
+        self.config.decoder = path_to_string(decoder);
+        self
+    }
+
+    pub fn vocoder<P: AsRef<Path>>(mut self, vocoder: P) -> Self {
+        self.config.vocoder = path_to_string(vocoder);
+        self
+    }
+
+    pub fn data_dir<P: AsRef<Path>>(mut self, data_dir: P) -> Self {
+        self.config.data_dir = path_to_string(data_dir);
+        self
+    }
+
+    pub fn lexicon<P: AsRef<Path>>(mut self, lexicon: P) -> Self {
+        self.config.lexicon = path_to_string(lexicon);
+        self
+    }
+
+    pub fn feat_scale(mut self, feat_scale: f32) -> Self {
+        self.config.feat_scale = feat_scale;
+        self
+    }
+
+    pub fn t_shift(mut self, t_shift: f32) -> Self {
+        self.config.t_shift = t_shift;
+        self
+    }
+
+    pub fn target_rms(mut self, target_rms: f32) -> Self {
+        self.config.target_rms = target_rms;
+        self
+    }
+
+    pub fn guidance_scale(mut self, guidance_scale: f32) -> Self {
+        self.config.guidance_scale = guidance_scale;
+        self
+    }
+
+    pub fn onnx_config(mut self, onnx_config: OnnxConfig) -> Self {
+        self.config.onnx_config = onnx_config;
+        self
+    }
+
+    pub fn common_config(mut self, common_config: CommonTtsConfig) -> Self {
+        self.config.common_config = common_config;
+        self
+    }
+
+    pub fn build(self) -> Result<ZipVoiceTtsConfig> {
+        for (name, path) in [
+            ("tokens", &self.config.tokens),
+            ("encoder", &self.config.encoder),
+            ("decoder", &self.config.decoder),
+            ("vocoder", &self.config.vocoder),
+        ] {
+            if path.is_empty() {
+                return Err(Error::invalid_input(format!(
+                    "zipvoice {} path is required",
+                    name
+                )));
+            }
+        }
+        Ok(self.config)
+    }
+}
+
 impl ZipVoiceTts {
-    pub fn new(config: ZipVoiceTtsConfig) -> Self {
-        let tts = unsafe {
-            let tokens = cstring_from_str(&config.tokens);
-            let encoder = cstring_from_str(&config.encoder);
-            let decoder = cstring_from_str(&config.decoder);
-            let vocoder = cstring_from_str(&config.vocoder);
-            let data_dir = cstring_from_str(&config.data_dir);
-            let lexicon = cstring_from_str(&config.lexicon);
-
-            let provider = cstring_from_str(&config.onnx_config.provider);
-
-            let tts_config = config.common_config.to_raw();
-
-            let model_config = sherpa_rs_sys::SherpaOnnxOfflineTtsModelConfig {
-                vits: mem::zeroed::<_>(),
-                num_threads: config.onnx_config.num_threads,
-                debug: config.onnx_config.debug.into(),
-                provider: provider.as_ptr(),
-                matcha: mem::zeroed::<_>(),
-                kokoro: mem::zeroed(),
-                kitten: mem::zeroed::<_>(),
-                zipvoice: sherpa_rs_sys::SherpaOnnxOfflineTtsZipvoiceModelConfig {
-                    tokens: tokens.as_ptr(),
-                    encoder: encoder.as_ptr(),
-                    decoder: decoder.as_ptr(),
-                    vocoder: vocoder.as_ptr(),
-                    data_dir: data_dir.as_ptr(),
-                    lexicon: lexicon.as_ptr(),
-                    feat_scale: config.feat_scale,
-                    t_shift: config.t_shift,
-                    target_rms: config.target_rms,
-                    guidance_scale: config.guidance_scale,
-                },
-            };
-            let config = sherpa_rs_sys::SherpaOnnxOfflineTtsConfig {
-                max_num_sentences: config.common_config.max_num_sentences,
-                model: model_config,
-                rule_fars: tts_config.rule_fars.map(|v| v.as_ptr()).unwrap_or(null()),
-                rule_fsts: tts_config.rule_fsts.map(|v| v.as_ptr()).unwrap_or(null()),
-                silence_scale: config.common_config.silence_scale,
-            };
-            sherpa_rs_sys::SherpaOnnxCreateOfflineTts(&config)
+    /// Like [`Self::new`], with the text encoder, flow-matching decoder, vocoder, tokens,
+    /// lexicon and espeak-ng data found in `dir`, an unpacked sherpa-onnx release. The
+    /// scales in `config` are used as they are, see [`ZipVoiceTtsConfig::builder`] for
+    /// upstream's defaults.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: ZipVoiceTtsConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::ZipVoice,
+            precision,
+            &config.onnx_config.provider,
+        )?;
+        Self::new(ZipVoiceTtsConfig {
+            encoder: dir.file(Role::TextEncoder),
+            decoder: dir.file(Role::FmDecoder),
+            vocoder: dir.file(Role::Vocoder),
+            tokens: dir.file(Role::Tokens),
+            data_dir: dir.file_or(Role::DataDir, config.data_dir),
+            lexicon: dir.file_or(Role::Lexicon, config.lexicon),
+            ..config
+        })
+    }
+
+    pub fn new(config: ZipVoiceTtsConfig) -> Result<Self> {
+        config.validate()?;
+        let (tts, provider) = Self::create_with_fallback(&config)?;
+        Ok(Self {
+            tts,
+            segmenter: Segmenter::new(&config.common_config),
+            provider,
+            precision: ModelPrecision::of_file(&config.encoder),
+        })
+    }
+
+    /// # Panics
+    ///
+    /// If the config is invalid or the model can't be created.
+    #[deprecated(note = "use `ZipVoiceTts::new`, which reports invalid configs as errors")]
+    pub fn new_unchecked(config: ZipVoiceTtsConfig) -> Self {
+        Self::new(config).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded text encoder. The vocoder is always fp32.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Synthesize a short phrase in the voice of `prompt` with a single flow-matching step
+    /// and discard it, so the first real [`Self::create_with_prompt`] doesn't pay for ONNX
+    /// Runtime's one-time setup. Returns how long it took.
+    pub fn warm_up(&mut self, prompt: &ZipVoicePrompt) -> Result<Duration> {
+        let _timed = timed!("tts.warm_up");
+        time_warm_up(|| self.create_with_prompt(WARM_UP_TEXT, prompt, 1.0, 1))
+    }
+
+    /// Always fails with [`Error::Unsupported`](crate::Error::Unsupported): flow matching
+    /// starts from random noise that sherpa-onnx doesn't let callers seed.
+    pub fn create_deterministic(
+        &mut self,
+        _text: &str,
+        _prompt: &ZipVoicePrompt,
+        _speed: f32,
+        _num_steps: i32,
+    ) -> Result<TtsAudio> {
+        Err(super::unseeded_noise("zipvoice"))
+    }
+
+    fn create_with_fallback(config: &ZipVoiceTtsConfig) -> Result<(TtsHandle, ProviderSelection)> {
+        let name = format!("zipvoice tts ({})", config.describe());
+        super::create_tts_with_fallback(&name, &config.onnx_config, |onnx_config| unsafe {
+            Self::create_tts(config, onnx_config)
+        })
+    }
+
+    unsafe fn create_tts(
+        config: &ZipVoiceTtsConfig,
+        onnx_config: &OnnxConfig,
+    ) -> *const sherpa_rs_sys::SherpaOnnxOfflineTts {
+        let tokens = cstring_from_str(&config.tokens);
+        let encoder = cstring_from_str(&config.encoder);
+        let decoder = cstring_from_str(&config.decoder);
+        let vocoder = cstring_from_str(&config.vocoder);
+        let data_dir = cstring_from_str(&config.data_dir);
+        let lexicon = cstring_from_str(&config.lexicon);
+
+        let provider = match onnx_config.provider.to_cstring() {
+            Ok(provider) => provider,
+            Err(_) => return null(),
+        };
+
+        let tts_config = match config.common_config.to_raw() {
+            Ok(raw) => raw,
+            Err(_) => return null(),
+        };
+
+        let model_config = sherpa_rs_sys::SherpaOnnxOfflineTtsModelConfig {
+            vits: mem::zeroed::<_>(),
+            num_threads: onnx_config.num_threads.to_raw(),
+            debug: onnx_config.debug.into(),
+            provider: provider.as_ptr(),
+            matcha: mem::zeroed::<_>(),
+            kokoro: mem::zeroed(),
+            kitten: mem::zeroed::<_>(),
+            zipvoice: sherpa_rs_sys::SherpaOnnxOfflineTtsZipvoiceModelConfig {
+                tokens: tokens.as_ptr(),
+                encoder: encoder.as_ptr(),
+                decoder: decoder.as_ptr(),
+                vocoder: vocoder.as_ptr(),
+                data_dir: data_dir.as_ptr(),
+                lexicon: lexicon.as_ptr(),
+                feat_scale: config.feat_scale,
+                t_shift: config.t_shift,
+                target_rms: config.target_rms,
+                guidance_scale: config.guidance_scale,
+            },
         };
+        let config = sherpa_rs_sys::SherpaOnnxOfflineTtsConfig {
+            max_num_sentences: config.common_config.max_num_sentences,
+            model: model_config,
+            rule_fars: tts_config
+                .rule_fars
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(null()),
+            rule_fsts: tts_config
+                .rule_fsts
+                .as_ref()
+                .map(|v| v.as_ptr())
+                .unwrap_or(null()),
+            silence_scale: config.common_config.silence_scale,
+        };
+        sherpa_rs_sys::SherpaOnnxCreateOfflineTts(&config)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        super::sample_rate(&self.tts)
+    }
 
-        Self { tts }
+    pub fn num_speakers(&self) -> u32 {
+        super::num_speakers(&self.tts)
     }
 
     pub fn create(
@@ -83,12 +352,18 @@ impl ZipVoiceTts {
         speed: f32,
         num_steps: i32,
     ) -> Result<TtsAudio> {
+        let _timed = timed!(
+            "tts.generate",
+            speed,
+            num_steps,
+            chars = text.chars().count()
+        );
         unsafe {
             let text_cstr = cstring_from_str(text);
             let prompt_text_cstr = cstring_from_str(prompt_text);
 
             let audio_ptr = sherpa_rs_sys::SherpaOnnxOfflineTtsGenerateWithZipvoice(
-                self.tts,
+                self.tts.as_ptr(),
                 text_cstr.as_ptr(),
                 prompt_text_cstr.as_ptr(),
                 prompt_samples.as_ptr(),
@@ -98,41 +373,230 @@ impl ZipVoiceTts {
                 num_steps,
             );
 
-            if audio_ptr.is_null() {
-                eyre::bail!("audio is null");
-            }
-            let audio = audio_ptr.read();
+            super::take_generated_audio(audio_ptr)
+        }
+    }
 
-            if audio.n.is_negative() {
-                eyre::bail!("no samples found");
-            }
-            if audio.samples.is_null() {
-                eyre::bail!("audio samples are null");
+    /// Generate sentence by sentence, passing each sentence's samples to `on_samples` as
+    /// soon as they are ready. Returning `false` stops after the current sentence.
+    ///
+    /// The C API has no callback variant for zipvoice, so the text is split here instead,
+    /// with the configured `sentence_splitter` if any. The returned audio holds everything
+    /// generated so far. If `on_samples` panics, [`Error::CallbackPanicked`] is returned.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_streaming<F>(
+        &mut self,
+        text: &str,
+        prompt_text: &str,
+        prompt_samples: &[f32],
+        prompt_sr: i32,
+        speed: f32,
+        num_steps: i32,
+        mut on_samples: F,
+    ) -> Result<TtsAudio>
+    where
+        F: FnMut(&[f32]) -> bool,
+    {
+        let mut sample_rate = self.sample_rate();
+        let mut samples = Vec::new();
+        let mut guard = FfiCallback::new("zipvoice tts samples");
+
+        for sentence in self.segmenter.split(text) {
+            let chunk = self.create(
+                &sentence,
+                prompt_text,
+                prompt_samples,
+                prompt_sr,
+                speed,
+                num_steps,
+            )?;
+            sample_rate = chunk.sample_rate;
+            let start = samples.len();
+            samples.extend_from_slice(&chunk.samples);
+
+            if guard.call(|| on_samples(&samples[start..])) != Some(true) {
+                break;
             }
-            let samples: &[f32] = std::slice::from_raw_parts(audio.samples, audio.n as usize);
-            let samples = samples.to_vec();
-            let sample_rate = audio.sample_rate;
-            let duration = (samples.len() as i32) / sample_rate;
-
-            // Free
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineTtsGeneratedAudio(audio_ptr);
-
-            Ok(TtsAudio {
-                samples,
-                sample_rate: sample_rate as u32,
-                duration,
-            })
+        }
+        guard.finish()?;
+
+        Ok(TtsAudio::new(samples, sample_rate))
+    }
+
+    /// Clone the voice from a WAV file. 16-bit and float PCM are supported, multi-channel
+    /// prompts are downmixed to mono and resampled to the model rate.
+    pub fn create_with_prompt_file<P: AsRef<Path>>(
+        &mut self,
+        text: &str,
+        prompt_text: &str,
+        prompt_wav: P,
+        speed: f32,
+        num_steps: i32,
+    ) -> Result<TtsAudio> {
+        let (samples, sample_rate) = read_prompt_wav(prompt_wav.as_ref())?;
+        let prompt = self.prepare_prompt(prompt_text, &samples, sample_rate)?;
+        self.create_with_prompt(text, &prompt, speed, num_steps)
+    }
+
+    /// Validate a voice prompt and resample it to the model rate once, so it can be
+    /// reused with [`create_with_prompt`](Self::create_with_prompt).
+    ///
+    /// The C API re-encodes the reference audio on every call, so this only saves the
+    /// Rust-side validation, resampling and copies.
+    pub fn prepare_prompt(
+        &self,
+        prompt_text: &str,
+        prompt_samples: &[f32],
+        prompt_sr: i32,
+    ) -> Result<ZipVoicePrompt> {
+        check_prompt_len(prompt_samples, prompt_sr)?;
+
+        let model_rate = self.sample_rate() as i32;
+        let (samples, sample_rate) = if model_rate > 0 && model_rate != prompt_sr {
+            let samples = resample(prompt_samples, prompt_sr as u32, model_rate as u32);
+            (samples, model_rate)
+        } else {
+            (prompt_samples.to_vec(), prompt_sr)
+        };
+
+        Ok(ZipVoicePrompt {
+            text: cstring_from_str(prompt_text),
+            samples,
+            sample_rate,
+        })
+    }
+
+    pub fn create_with_prompt(
+        &mut self,
+        text: &str,
+        prompt: &ZipVoicePrompt,
+        speed: f32,
+        num_steps: i32,
+    ) -> Result<TtsAudio> {
+        let _timed = timed!(
+            "tts.generate",
+            speed,
+            num_steps,
+            chars = text.chars().count()
+        );
+        unsafe {
+            let text_cstr = cstring_from_str(text);
+
+            let audio_ptr = sherpa_rs_sys::SherpaOnnxOfflineTtsGenerateWithZipvoice(
+                self.tts.as_ptr(),
+                text_cstr.as_ptr(),
+                prompt.text.as_ptr(),
+                prompt.samples.as_ptr(),
+                prompt.samples.len() as i32,
+                prompt.sample_rate,
+                speed,
+                num_steps,
+            );
+
+            super::take_generated_audio(audio_ptr)
         }
     }
 }
 
+/// A validated voice prompt, see [`ZipVoiceTts::prepare_prompt`].
+#[derive(Debug, Clone)]
+pub struct ZipVoicePrompt {
+    text: CString,
+    samples: Vec<f32>,
+    sample_rate: i32,
+}
+
+impl ZipVoicePrompt {
+    pub fn text(&self) -> &str {
+        self.text.to_str().unwrap_or_default()
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    pub fn sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
+}
+
+fn check_prompt_len(samples: &[f32], sample_rate: i32) -> Result<()> {
+    if sample_rate <= 0 {
+        return Err(Error::invalid_input(format!(
+            "Invalid prompt sample rate {}",
+            sample_rate
+        )));
+    }
+    let duration = samples.len() as f32 / sample_rate as f32;
+    if duration < MIN_PROMPT_SECS {
+        return Err(Error::invalid_input(format!(
+            "Prompt is too short ({:.2}s), zipvoice needs at least {}s of reference audio",
+            duration, MIN_PROMPT_SECS
+        )));
+    }
+    Ok(())
+}
+
+/// Read a prompt, reporting a file that can't be opened as [`Error::Io`] and one that
+/// isn't a WAV as [`Error::Wav`]. [`read_wav`] makes both `InvalidInput`, which is kept
+/// for prompts that are read but rejected.
+fn read_prompt_wav(path: &Path) -> Result<(Vec<f32>, i32)> {
+    hound::WavReader::open(path).map_err(|err| match err {
+        hound::Error::IoError(err) => Error::Io(err),
+        err => Error::Wav(err),
+    })?;
+    let wav = read_wav(path)?;
+    Ok((wav.to_mono(ChannelMix::Average), wav.sample_rate as i32))
+}
+
 unsafe impl Send for ZipVoiceTts {}
 unsafe impl Sync for ZipVoiceTts {}
 
-impl Drop for ZipVoiceTts {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineTts(self.tts);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::ScratchDir, write_audio_file};
+
+    #[test]
+    fn missing_prompt_is_an_io_error() {
+        let dir = ScratchDir::new("zipvoice-missing-prompt");
+        let err = read_prompt_wav(Path::new(&dir.join("prompt.wav"))).unwrap_err();
+        assert!(
+            matches!(&err, Error::Io(err) if err.kind() == std::io::ErrorKind::NotFound),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn malformed_prompt_is_a_wav_error() {
+        let dir = ScratchDir::new("zipvoice-malformed-prompt");
+        let path = dir.join("prompt.wav");
+        std::fs::write(&path, b"not a wav file").unwrap();
+        let err = read_prompt_wav(Path::new(&path)).unwrap_err();
+        assert!(matches!(err, Error::Wav(_)), "{:?}", err);
+
+        let err = read_prompt_wav(Path::new(&dir.mkdir("dir.wav"))).unwrap_err();
+        assert!(matches!(err, Error::Io(_) | Error::Wav(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn prompt_is_read_as_mono() {
+        let dir = ScratchDir::new("zipvoice-prompt");
+        let path = dir.join("prompt.wav");
+        write_audio_file(&path, &[0.5; 16000], 16000).unwrap();
+        let (samples, sample_rate) = read_prompt_wav(Path::new(&path)).unwrap();
+        assert_eq!(sample_rate, 16000);
+        assert_eq!(samples.len(), 16000);
+        assert!(check_prompt_len(&samples, sample_rate).is_ok());
+    }
+
+    #[test]
+    fn short_prompt_is_invalid_input() {
+        for (len, sample_rate) in [(15999, 16000), (0, 16000), (16000, 0), (16000, -1)] {
+            let err = check_prompt_len(&vec![0.0; len], sample_rate).unwrap_err();
+            assert!(matches!(err, Error::InvalidInput { .. }), "{:?}", err);
         }
+        assert!(check_prompt_len(&[0.0; 24000], 24000).is_ok());
     }
 }
@@ -0,0 +1,705 @@
+use std::{collections::VecDeque, mem, time::Duration};
+
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    providers::{create_with_fallback, ProviderSelection},
+    silero_vad::SileroVadConfig,
+    ten_vad::TenVadConfig,
+    trace::{debug_event, timed},
+    utils::{cstring_from_str, time_warm_up, warm_up_silence, Resampler},
+    CancellationToken, Error, Provider, Result,
+};
+
+/// Voice activity detection model for [`Vad`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum VadConfig {
+    Silero(SileroVadConfig),
+    Ten(TenVadConfig),
+}
+
+/// Audio kept by the detector at minimum, whatever the speech duration limits.
+const MIN_BUFFER_SECS: f32 = 30.0;
+
+#[derive(Debug, Clone)]
+pub struct SpeechSegment {
+    /// Index of the first sample, counted from the first sample fed to the detector.
+    pub start_sample: usize,
+    pub samples: Vec<f32>,
+    pub start_secs: f32,
+    pub end_secs: f32,
+}
+
+/// Voice activity detector backed by either Silero or TEN VAD.
+#[derive(Debug)]
+pub struct Vad {
+    vad: NativeHandle<sherpa_rs_sys::SherpaOnnxVoiceActivityDetector>,
+    sample_rate: u32,
+    window_size: usize,
+    config: VadConfig,
+    provider: ProviderSelection,
+}
+
+impl Vad {
+    pub fn new(config: VadConfig) -> Result<Self> {
+        let _timed = timed!("vad.create");
+        debug_event!(?config, "creating vad");
+        let (requested, fallback) = match &config {
+            VadConfig::Silero(c) => (c.provider.clone(), c.fallback),
+            VadConfig::Ten(c) => (c.provider.clone(), c.fallback),
+        };
+        let (mut vad, provider) = create_with_fallback(requested, fallback, |provider| {
+            let mut config = config.clone();
+            match &mut config {
+                VadConfig::Silero(c) => c.provider = Some(provider),
+                VadConfig::Ten(c) => c.provider = Some(provider),
+            }
+            Self::create(config)
+        })?;
+        vad.provider = provider;
+        Ok(vad)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    fn create(config: VadConfig) -> Result<Self> {
+        let (family, model, sample_rate, window_size, max_speech, provider, num_threads, debug) =
+            match &config {
+                VadConfig::Silero(c) => (
+                    "silero",
+                    &c.model,
+                    c.sample_rate,
+                    c.window_size,
+                    c.max_speech_duration,
+                    &c.provider,
+                    c.num_threads,
+                    c.debug,
+                ),
+                VadConfig::Ten(c) => (
+                    "ten",
+                    &c.model,
+                    c.sample_rate,
+                    c.window_size,
+                    c.max_speech_duration,
+                    &c.provider,
+                    c.num_threads,
+                    c.debug,
+                ),
+            };
+
+        // Both models are trained on fixed frame sizes
+        let expected = match (&config, sample_rate) {
+            (VadConfig::Silero(_), 16000) => 512,
+            (VadConfig::Silero(_), 8000) => 256,
+            (VadConfig::Ten(_), 16000) => 256,
+            _ => {
+                return Err(Error::unsupported(format!(
+                    "{} vad at a sample rate of {}",
+                    family, sample_rate
+                )))
+            }
+        };
+        if window_size != expected {
+            return Err(Error::invalid_input(format!(
+                "{} vad needs window_size {} at {} Hz, got {}",
+                family, expected, sample_rate, window_size
+            )));
+        }
+        if model.is_empty() {
+            return Err(Error::invalid_input(format!(
+                "{} vad model path is empty",
+                family
+            )));
+        }
+
+        let model_ptr = cstring_from_str(model);
+        let provider = provider
+            .clone()
+            .unwrap_or_else(get_default_provider)
+            .to_cstring()?;
+        let mut vad_config = unsafe {
+            sherpa_rs_sys::SherpaOnnxVadModelConfig {
+                debug: debug.into(),
+                provider: provider.as_ptr(),
+                num_threads: num_threads.to_raw(),
+                sample_rate: sample_rate as i32,
+                silero_vad: mem::zeroed::<_>(),
+                ten_vad: mem::zeroed::<_>(),
+            }
+        };
+        match &config {
+            VadConfig::Silero(c) => {
+                vad_config.silero_vad = sherpa_rs_sys::SherpaOnnxSileroVadModelConfig {
+                    model: model_ptr.as_ptr(),
+                    threshold: c.threshold,
+                    min_silence_duration: c.min_silence_duration,
+                    min_speech_duration: c.min_speech_duration,
+                    window_size: c.window_size,
+                    max_speech_duration: c.max_speech_duration,
+                }
+            }
+            VadConfig::Ten(c) => {
+                vad_config.ten_vad = sherpa_rs_sys::SherpaOnnxTenVadModelConfig {
+                    model: model_ptr.as_ptr(),
+                    threshold: c.threshold,
+                    min_silence_duration: c.min_silence_duration,
+                    min_speech_duration: c.min_speech_duration,
+                    window_size: c.window_size,
+                    max_speech_duration: c.max_speech_duration,
+                }
+            }
+        }
+
+        // Room for a couple of maximum-length segments that haven't been popped yet
+        let buffer_size_in_seconds = (max_speech * 2.0).max(MIN_BUFFER_SECS);
+        let vad = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateVoiceActivityDetector(
+                    &vad_config,
+                    buffer_size_in_seconds,
+                ),
+                sherpa_rs_sys::SherpaOnnxDestroyVoiceActivityDetector,
+            )
+        }
+        .ok_or_else(|| {
+            Error::native_creation_failed(format!("{} voice activity detector", family))
+        })?;
+        Ok(Self {
+            vad,
+            sample_rate,
+            window_size: expected as usize,
+            config,
+            provider: ProviderSelection::default(),
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Samples per model frame.
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    pub fn config(&self) -> &VadConfig {
+        &self.config
+    }
+
+    /// Feed mono audio at the configured sample rate, in chunks of any length.
+    pub fn accept_waveform(&mut self, samples: &[f32]) {
+        unsafe {
+            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorAcceptWaveform(
+                self.vad.as_ptr(),
+                samples.as_ptr(),
+                samples.len().try_into().unwrap(),
+            );
+        }
+    }
+
+    /// Whether the most recent frame was speech.
+    pub fn is_speech_detected(&self) -> bool {
+        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorDetected(self.vad.as_ptr()) == 1 }
+    }
+
+    /// Take the oldest completed speech segment.
+    pub fn pop(&mut self) -> Option<SpeechSegment> {
+        unsafe {
+            if sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorEmpty(self.vad.as_ptr()) == 1 {
+                return None;
+            }
+            let segment_ptr =
+                sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorFront(self.vad.as_ptr());
+            let segment = if segment_ptr.is_null() {
+                None
+            } else {
+                let raw = segment_ptr.read();
+                let samples = if raw.samples.is_null() || raw.n <= 0 {
+                    Vec::new()
+                } else {
+                    std::slice::from_raw_parts(raw.samples, raw.n as usize).to_vec()
+                };
+                sherpa_rs_sys::SherpaOnnxDestroySpeechSegment(segment_ptr);
+                let start_sample = raw.start.max(0) as usize;
+                let sr = self.sample_rate as f32;
+                Some(SpeechSegment {
+                    start_sample,
+                    start_secs: start_sample as f32 / sr,
+                    end_secs: (start_sample + samples.len()) as f32 / sr,
+                    samples,
+                })
+            };
+            sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorPop(self.vad.as_ptr());
+            segment
+        }
+    }
+
+    /// End the current segment at the end of the fed audio, so `pop` can return it.
+    pub fn flush(&mut self) {
+        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorFlush(self.vad.as_ptr()) }
+    }
+
+    /// Drop all buffered audio and segments.
+    pub fn clear(&mut self) {
+        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorClear(self.vad.as_ptr()) }
+    }
+
+    /// Clear everything and restart sample indices at zero.
+    pub fn reset(&mut self) {
+        unsafe { sherpa_rs_sys::SherpaOnnxVoiceActivityDetectorReset(self.vad.as_ptr()) }
+    }
+
+    /// Run the model over half a second of silence and [`Self::reset`], so the first real
+    /// audio doesn't pay for ONNX Runtime's one-time setup. Returns how long it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("vad.warm_up");
+        let elapsed = time_warm_up(|| {
+            self.accept_waveform(&warm_up_silence(self.sample_rate));
+            self.flush();
+            Ok(())
+        });
+        self.reset();
+        elapsed
+    }
+
+    /// Speech segments of `samples`, detected lazily as the iterator advances.
+    ///
+    /// The detector is reset first, so segment times are relative to the start of `samples`.
+    /// Input at another sample rate is resampled in one-second blocks.
+    pub fn segments<'a>(
+        &'a mut self,
+        samples: &'a [f32],
+        sample_rate: u32,
+    ) -> impl Iterator<Item = SpeechSegment> + 'a {
+        self.segment_iter(samples, sample_rate, None)
+            .map_while(Result::ok)
+    }
+
+    /// Like [`Vad::segments`], yielding [`Error::Cancelled`] once and ending when `token`
+    /// is cancelled. It is checked before each block of input is fed.
+    pub fn segments_with_cancel<'a>(
+        &'a mut self,
+        samples: &'a [f32],
+        sample_rate: u32,
+        token: &CancellationToken,
+    ) -> impl Iterator<Item = Result<SpeechSegment>> + 'a {
+        self.segment_iter(samples, sample_rate, Some(token.clone()))
+    }
+
+    fn segment_iter<'a>(
+        &'a mut self,
+        samples: &'a [f32],
+        sample_rate: u32,
+        cancel: Option<CancellationToken>,
+    ) -> Segments<'a> {
+        self.reset();
+        let block = if sample_rate == self.sample_rate {
+            self.window_size
+        } else {
+            sample_rate.max(1) as usize
+        };
+        let resampler = (sample_rate != self.sample_rate)
+            .then(|| Resampler::new(sample_rate, self.sample_rate, 1).ok())
+            .flatten();
+        Segments {
+            vad: self,
+            blocks: samples.chunks(block),
+            resampler,
+            cancel,
+            flushed: false,
+        }
+    }
+}
+
+struct Segments<'a> {
+    vad: &'a mut Vad,
+    blocks: std::slice::Chunks<'a, f32>,
+    resampler: Option<Resampler>,
+    cancel: Option<CancellationToken>,
+    flushed: bool,
+}
+
+impl Iterator for Segments<'_> {
+    type Item = Result<SpeechSegment>;
+
+    fn next(&mut self) -> Option<Result<SpeechSegment>> {
+        loop {
+            if let Some(segment) = self.vad.pop() {
+                return Some(Ok(segment));
+            }
+            if self
+                .cancel
+                .as_ref()
+                .is_some_and(CancellationToken::is_cancelled)
+            {
+                // Nothing is yielded after the error
+                let empty: &[f32] = &[];
+                self.blocks = empty.chunks(1);
+                self.cancel = None;
+                self.flushed = true;
+                self.vad.clear();
+                return Some(Err(Error::cancelled("vad")));
+            }
+            if let Some(block) = self.blocks.next() {
+                match &mut self.resampler {
+                    Some(resampler) => self.vad.accept_waveform(&resampler.process(block)),
+                    None => self.vad.accept_waveform(block),
+                }
+            } else if !self.flushed {
+                if let Some(resampler) = &mut self.resampler {
+                    self.vad.accept_waveform(&resampler.flush());
+                }
+                self.vad.flush();
+                self.flushed = true;
+            } else {
+                return None;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SpeechCollectorConfig {
+    /// Audio kept from before the speech onset.
+    pub pre_roll_ms: u32,
+    /// Silence after speech that ends an utterance, kept at its end.
+    pub hangover_ms: u32,
+    /// Utterances are split when they reach this length.
+    pub max_utterance_secs: f32,
+}
+
+impl Default for SpeechCollectorConfig {
+    fn default() -> Self {
+        Self {
+            pre_roll_ms: 300,
+            hangover_ms: 500,
+            max_utterance_secs: 30.0,
+        }
+    }
+}
+
+/// An utterance from [`SpeechCollector`], at the VAD sample rate.
+#[derive(Debug, Clone)]
+pub struct CollectedSpeech {
+    pub start_sample: usize,
+    pub samples: Vec<f32>,
+    pub start_secs: f32,
+    pub end_secs: f32,
+    /// Cut at `max_utterance_secs` rather than at a pause.
+    pub forced_split: bool,
+}
+
+type SpeechCallback = Box<dyn FnMut(CollectedSpeech) + Send>;
+
+/// Keeps only the speech of a live stream, passing each utterance to a callback.
+///
+/// To receive utterances on another thread, send them through a bounded channel
+/// from the callback.
+pub struct SpeechCollector {
+    vad: Vad,
+    on_speech: SpeechCallback,
+    utterances: Utterances,
+}
+
+impl SpeechCollector {
+    pub fn new<F>(vad: Vad, config: SpeechCollectorConfig, on_speech: F) -> Self
+    where
+        F: FnMut(CollectedSpeech) + Send + 'static,
+    {
+        Self {
+            on_speech: Box::new(on_speech),
+            utterances: Utterances::new(&config, vad.sample_rate, vad.window_size),
+            vad,
+        }
+    }
+
+    /// Feed mono audio at the VAD sample rate, in chunks of any length.
+    pub fn push(&mut self, samples: &[f32]) {
+        let vad = &mut self.vad;
+        let is_speech = |window: &[f32]| {
+            vad.accept_waveform(window);
+            let speech = vad.is_speech_detected();
+            // Segments aren't used, don't let them pile up
+            vad.clear();
+            speech
+        };
+        self.utterances
+            .push(samples, is_speech, &mut *self.on_speech);
+    }
+
+    /// Emit the utterance in progress, if any, including samples short of a window.
+    pub fn flush(&mut self) {
+        self.utterances.flush(&mut *self.on_speech);
+        self.vad.reset();
+    }
+}
+
+/// The part of [`SpeechCollector`] that doesn't need the model: splits the stream
+/// into windows and turns per-window speech decisions into utterances.
+struct Utterances {
+    sample_rate: u32,
+    window_size: usize,
+    pre_roll: usize,
+    hangover: usize,
+    max_len: usize,
+    /// Samples waiting for a full window.
+    pending: Vec<f32>,
+    /// Most recent non-speech audio, up to `pre_roll` samples.
+    history: VecDeque<f32>,
+    /// Current utterance and where it started, if speaking.
+    current: Option<(usize, Vec<f32>)>,
+    silence_run: usize,
+    /// Samples processed so far.
+    position: usize,
+}
+
+impl Utterances {
+    fn new(config: &SpeechCollectorConfig, sample_rate: u32, window_size: usize) -> Self {
+        let per_ms = sample_rate as usize / 1000;
+        let pre_roll = config.pre_roll_ms as usize * per_ms;
+        let max_len = (config.max_utterance_secs.max(0.0) * sample_rate as f32) as usize;
+        Self {
+            sample_rate,
+            window_size,
+            pre_roll,
+            hangover: config.hangover_ms as usize * per_ms,
+            max_len: max_len.max(window_size),
+            pending: Vec::with_capacity(window_size),
+            history: VecDeque::with_capacity(pre_roll + window_size),
+            current: None,
+            silence_run: 0,
+            position: 0,
+        }
+    }
+
+    /// Feed samples, asking `is_speech` about each full window.
+    fn push(
+        &mut self,
+        mut samples: &[f32],
+        mut is_speech: impl FnMut(&[f32]) -> bool,
+        on_speech: &mut dyn FnMut(CollectedSpeech),
+    ) {
+        while !samples.is_empty() {
+            let take = (self.window_size - self.pending.len()).min(samples.len());
+            self.pending.extend_from_slice(&samples[..take]);
+            samples = &samples[take..];
+            if self.pending.len() == self.window_size {
+                let window = mem::take(&mut self.pending);
+                let speech = is_speech(&window);
+                self.process(&window, speech, on_speech);
+                self.pending = window;
+                self.pending.clear();
+            }
+        }
+    }
+
+    fn flush(&mut self, on_speech: &mut dyn FnMut(CollectedSpeech)) {
+        if let Some((_, buf)) = &mut self.current {
+            buf.extend_from_slice(&self.pending);
+        }
+        self.position += self.pending.len();
+        self.pending.clear();
+        self.emit(false, on_speech);
+        self.silence_run = 0;
+        self.history.clear();
+    }
+
+    fn process(
+        &mut self,
+        window: &[f32],
+        speech: bool,
+        on_speech: &mut dyn FnMut(CollectedSpeech),
+    ) {
+        self.position += window.len();
+        match &mut self.current {
+            None if speech => {
+                let mut buf = Vec::with_capacity(self.history.len() + window.len());
+                buf.extend(self.history.drain(..));
+                buf.extend_from_slice(window);
+                self.current = Some((self.position - buf.len(), buf));
+                self.silence_run = 0;
+            }
+            None => {
+                self.history.extend(window);
+                let excess = self.history.len().saturating_sub(self.pre_roll);
+                self.history.drain(..excess);
+            }
+            Some((_, buf)) => {
+                buf.extend_from_slice(window);
+                if speech {
+                    self.silence_run = 0;
+                } else {
+                    self.silence_run += window.len();
+                }
+                if self.silence_run >= self.hangover && !speech {
+                    self.emit(false, on_speech);
+                } else if buf.len() >= self.max_len {
+                    self.emit(true, on_speech);
+                    self.current = Some((self.position, Vec::new()));
+                }
+            }
+        }
+    }
+
+    fn emit(&mut self, forced_split: bool, on_speech: &mut dyn FnMut(CollectedSpeech)) {
+        let Some((start_sample, samples)) = self.current.take() else {
+            return;
+        };
+        if samples.is_empty() {
+            return;
+        }
+        let sr = self.sample_rate as f32;
+        on_speech(CollectedSpeech {
+            start_sample,
+            start_secs: start_sample as f32 / sr,
+            end_secs: (start_sample + samples.len()) as f32 / sr,
+            samples,
+            forced_split,
+        });
+    }
+}
+
+unsafe impl Send for Vad {}
+unsafe impl Sync for Vad {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+    const WINDOW: usize = 4;
+    /// Speech samples are offset by this, so the fake detector can tell them apart.
+    const SPEECH: f32 = 1000.0;
+
+    fn config(max_utterance_secs: f32) -> SpeechCollectorConfig {
+        SpeechCollectorConfig {
+            pre_roll_ms: 8,
+            hangover_ms: 8,
+            max_utterance_secs,
+        }
+    }
+
+    /// One window per character of `script`, `S` for speech and `.` for silence. Each
+    /// sample holds its own index.
+    fn audio(script: &str) -> Vec<f32> {
+        script
+            .chars()
+            .flat_map(|c| [c; WINDOW])
+            .enumerate()
+            .map(|(i, c)| i as f32 + if c == 'S' { SPEECH } else { 0.0 })
+            .collect()
+    }
+
+    /// `(start_sample, len, forced_split)` of each utterance, fed `chunk` samples at a
+    /// time.
+    fn collect(
+        config: &SpeechCollectorConfig,
+        audio: &[f32],
+        chunk: usize,
+    ) -> Vec<(usize, usize, bool)> {
+        let mut utterances = Utterances::new(config, SAMPLE_RATE, WINDOW);
+        let mut collected = Vec::new();
+        for samples in audio.chunks(chunk) {
+            utterances.push(samples, |window| window[0] >= SPEECH, &mut |speech| {
+                collected.push(speech)
+            });
+        }
+        utterances.flush(&mut |speech| collected.push(speech));
+
+        for speech in &collected {
+            let expected = &audio[speech.start_sample..][..speech.samples.len()];
+            assert_eq!(speech.samples, expected);
+            let end_sample = speech.start_sample + speech.samples.len();
+            assert_eq!(speech.end_secs, end_sample as f32 / SAMPLE_RATE as f32);
+        }
+        collected
+            .iter()
+            .map(|speech| {
+                (
+                    speech.start_sample,
+                    speech.samples.len(),
+                    speech.forced_split,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn silence_yields_nothing() {
+        assert!(collect(&config(1.0), &audio("........"), WINDOW).is_empty());
+    }
+
+    #[test]
+    fn keeps_pre_roll_and_hangover() {
+        // Speech at 16..24, two windows of pre-roll before and of hangover after
+        let utterances = collect(&config(1.0), &audio("....SS......"), WINDOW);
+        assert_eq!(utterances, [(8, 24, false)]);
+    }
+
+    #[test]
+    fn pre_roll_is_limited_to_the_start_of_the_stream() {
+        assert_eq!(
+            collect(&config(1.0), &audio(".SS..."), WINDOW),
+            [(0, 20, false)]
+        );
+    }
+
+    #[test]
+    fn hangover_ends_the_utterance() {
+        // A one window pause is shorter than the hangover, a two window one isn't
+        assert_eq!(
+            collect(&config(1.0), &audio("SS.S....."), WINDOW),
+            [(0, 24, false)]
+        );
+        assert_eq!(
+            collect(&config(1.0), &audio("SS..S..."), WINDOW),
+            [(0, 16, false), (16, 12, false)]
+        );
+    }
+
+    #[test]
+    fn splits_at_max_utterance_secs() {
+        let utterances = collect(&config(0.012), &audio("SSSSSSS"), WINDOW);
+        assert_eq!(utterances, [(0, 12, true), (12, 12, true), (24, 4, false)]);
+    }
+
+    #[test]
+    fn flush_keeps_samples_short_of_a_window() {
+        let mut samples = audio("SS");
+        samples.extend([SPEECH; 2]);
+        assert_eq!(collect(&config(1.0), &samples, WINDOW), [(0, 10, false)]);
+    }
+
+    #[test]
+    fn chunk_size_does_not_matter() {
+        let samples = audio("...SS.S......SSSSSSSS...S");
+        let config = config(0.02);
+        let whole = collect(&config, &samples, samples.len());
+        assert_eq!(
+            whole,
+            [
+                (4, 20, true),
+                (24, 12, false),
+                (44, 20, true),
+                (64, 20, true),
+                (84, 8, false),
+                (92, 8, false)
+            ]
+        );
+        for chunk in [1, 3, WINDOW, 7, 64] {
+            assert_eq!(collect(&config, &samples, chunk), whole, "chunk {}", chunk);
+        }
+    }
+}
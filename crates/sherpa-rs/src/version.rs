@@ -0,0 +1,76 @@
+use std::fmt;
+
+/// What this build of sherpa-rs is made of, for bug reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// Version of sherpa-rs.
+    pub crate_version: &'static str,
+    /// Version of sherpa-rs-sys.
+    pub sys_version: &'static str,
+    /// Version of the linked sherpa-onnx, `None` if it's too old to report it.
+    pub native_version: Option<String>,
+    /// Git commit of the linked sherpa-onnx.
+    pub native_git_sha1: Option<String>,
+    /// Git commit date of the linked sherpa-onnx.
+    pub native_git_date: Option<String>,
+    /// `"static"` or `"dynamic"`, `None` if nothing was linked.
+    pub linking: Option<&'static str>,
+    /// Enabled features among `cuda`, `directml`, `coreml` and `tts`. `coreml` is
+    /// available on all Apple targets.
+    pub features: Vec<&'static str>,
+}
+
+/// Versions and features of this build.
+pub fn version() -> VersionInfo {
+    let native = sherpa_rs_sys::native_version();
+    let native_str = |field: fn(&sherpa_rs_sys::NativeVersion) -> &'static std::ffi::CStr| {
+        native
+            .as_ref()
+            .map(|native| field(native).to_string_lossy().into_owned())
+            .filter(|value| !value.is_empty())
+    };
+
+    let features = [
+        ("cuda", cfg!(feature = "cuda")),
+        ("directml", cfg!(feature = "directml")),
+        ("coreml", cfg!(target_vendor = "apple")),
+        ("tts", cfg!(feature = "tts")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect();
+
+    VersionInfo {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        sys_version: sherpa_rs_sys::CRATE_VERSION,
+        native_version: native_str(|native| native.version),
+        native_git_sha1: native_str(|native| native.git_sha1),
+        native_git_date: native_str(|native| native.git_date),
+        linking: sherpa_rs_sys::LINKING,
+        features,
+    }
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sherpa-rs {} (sherpa-rs-sys {}), sherpa-onnx {}",
+            self.crate_version,
+            self.sys_version,
+            self.native_version.as_deref().unwrap_or("unknown")
+        )?;
+        if let Some(sha1) = &self.native_git_sha1 {
+            write!(f, " ({}", sha1)?;
+            if let Some(date) = &self.native_git_date {
+                write!(f, ", {}", date)?;
+            }
+            write!(f, ")")?;
+        }
+        write!(f, ", {} linking", self.linking.unwrap_or("unknown"))?;
+        if !self.features.is_empty() {
+            write!(f, ", features: {}", self.features.join(", "))?;
+        }
+        Ok(())
+    }
+}
@@ -1,26 +1,217 @@
-use crate::{get_default_provider, utils::cstring_from_str};
-use eyre::{bail, Result};
-use std::mem;
+use crate::{
+    get_default_provider,
+    handle::NativeHandle,
+    itn::ItnConfig,
+    models::{ModelDir, ModelKind, ModelPrecision, Role},
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{cstring_from_str, edit_distance, time_warm_up, warm_up_silence, WARM_UP_SAMPLE_RATE},
+    Error, FallbackPolicy, Provider, Result, Threads,
+};
+use std::{mem, path::Path, time::Duration};
 
 #[derive(Debug)]
 pub struct WhisperRecognizer {
-    recognizer: *const sherpa_rs_sys::SherpaOnnxOfflineRecognizer,
+    recognizer: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineRecognizer>,
+    provider: ProviderSelection,
+    precision: ModelPrecision,
 }
 
 pub type WhisperRecognizerResult = super::OfflineRecognizerResult;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct WhisperConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub decoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub encoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
-    pub language: String,
+    /// Language code to decode as, e.g. `"de"`. `None` detects it, English-only models
+    /// ignore it.
+    pub language: Option<String>,
+    pub task: WhisperTask,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::opt_path"))]
     pub bpe_vocab: Option<String>,
     pub tail_paddings: Option<i32>,
 
-    pub provider: Option<String>,
-    pub num_threads: Option<i32>,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
+    pub num_threads: Threads,
     pub debug: bool,
+    /// Inverse text normalization and homophone replacement applied to the text.
+    pub itn: ItnConfig,
+}
+
+/// What Whisper does with the speech.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum WhisperTask {
+    /// Text in the spoken language.
+    #[default]
+    Transcribe,
+    /// English text, whatever the spoken language. Needs a multilingual model.
+    Translate,
+}
+
+impl WhisperTask {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WhisperTask::Transcribe => "transcribe",
+            WhisperTask::Translate => "translate",
+        }
+    }
+}
+
+/// Languages Whisper was trained on, as `(code, name)`.
+pub const WHISPER_LANGUAGES: &[(&str, &str)] = &[
+    ("en", "english"),
+    ("zh", "chinese"),
+    ("de", "german"),
+    ("es", "spanish"),
+    ("ru", "russian"),
+    ("ko", "korean"),
+    ("fr", "french"),
+    ("ja", "japanese"),
+    ("pt", "portuguese"),
+    ("tr", "turkish"),
+    ("pl", "polish"),
+    ("ca", "catalan"),
+    ("nl", "dutch"),
+    ("ar", "arabic"),
+    ("sv", "swedish"),
+    ("it", "italian"),
+    ("id", "indonesian"),
+    ("hi", "hindi"),
+    ("fi", "finnish"),
+    ("vi", "vietnamese"),
+    ("he", "hebrew"),
+    ("uk", "ukrainian"),
+    ("el", "greek"),
+    ("ms", "malay"),
+    ("cs", "czech"),
+    ("ro", "romanian"),
+    ("da", "danish"),
+    ("hu", "hungarian"),
+    ("ta", "tamil"),
+    ("no", "norwegian"),
+    ("th", "thai"),
+    ("ur", "urdu"),
+    ("hr", "croatian"),
+    ("bg", "bulgarian"),
+    ("lt", "lithuanian"),
+    ("la", "latin"),
+    ("mi", "maori"),
+    ("ml", "malayalam"),
+    ("cy", "welsh"),
+    ("sk", "slovak"),
+    ("te", "telugu"),
+    ("fa", "persian"),
+    ("lv", "latvian"),
+    ("bn", "bengali"),
+    ("sr", "serbian"),
+    ("az", "azerbaijani"),
+    ("sl", "slovenian"),
+    ("kn", "kannada"),
+    ("et", "estonian"),
+    ("mk", "macedonian"),
+    ("br", "breton"),
+    ("eu", "basque"),
+    ("is", "icelandic"),
+    ("hy", "armenian"),
+    ("ne", "nepali"),
+    ("mn", "mongolian"),
+    ("bs", "bosnian"),
+    ("kk", "kazakh"),
+    ("sq", "albanian"),
+    ("sw", "swahili"),
+    ("gl", "galician"),
+    ("mr", "marathi"),
+    ("pa", "punjabi"),
+    ("si", "sinhala"),
+    ("km", "khmer"),
+    ("sn", "shona"),
+    ("yo", "yoruba"),
+    ("so", "somali"),
+    ("af", "afrikaans"),
+    ("oc", "occitan"),
+    ("ka", "georgian"),
+    ("be", "belarusian"),
+    ("tg", "tajik"),
+    ("sd", "sindhi"),
+    ("gu", "gujarati"),
+    ("am", "amharic"),
+    ("yi", "yiddish"),
+    ("lo", "lao"),
+    ("uz", "uzbek"),
+    ("fo", "faroese"),
+    ("ht", "haitian creole"),
+    ("ps", "pashto"),
+    ("tk", "turkmen"),
+    ("nn", "nynorsk"),
+    ("mt", "maltese"),
+    ("sa", "sanskrit"),
+    ("lb", "luxembourgish"),
+    ("my", "myanmar"),
+    ("bo", "tibetan"),
+    ("tl", "tagalog"),
+    ("mg", "malagasy"),
+    ("as", "assamese"),
+    ("tt", "tatar"),
+    ("haw", "hawaiian"),
+    ("ln", "lingala"),
+    ("ha", "hausa"),
+    ("ba", "bashkir"),
+    ("jw", "javanese"),
+    ("su", "sundanese"),
+    ("yue", "cantonese"),
+];
+
+impl WhisperConfig {
+    fn validate(&self) -> Result<()> {
+        let Some(language) = &self.language else {
+            return Ok(());
+        };
+        if WHISPER_LANGUAGES
+            .iter()
+            .any(|(code, _)| *code == language.as_str())
+        {
+            return Ok(());
+        }
+
+        // Suggest the language itself if it's a name or differently cased code, else
+        // codes one edit away, or names two edits away
+        let wanted = language.trim().to_lowercase();
+        let suggest = |matches: &dyn Fn(&str, &str) -> bool| -> Vec<String> {
+            WHISPER_LANGUAGES
+                .iter()
+                .filter(|(code, name)| matches(code, name))
+                .map(|(code, name)| format!("{} ({})", code, name))
+                .collect()
+        };
+        let mut near = suggest(&|code, name| code == wanted || name == wanted);
+        if near.is_empty() {
+            near = suggest(&|code, name| {
+                if wanted.chars().count() <= 3 {
+                    edit_distance(code, &wanted) <= 1
+                } else {
+                    name.contains(wanted.as_str()) || edit_distance(name, &wanted) <= 2
+                }
+            });
+        }
+        let hint = if near.is_empty() {
+            String::from("use a code such as \"en\" or None to detect it")
+        } else {
+            format!("did you mean {}", near.join(", "))
+        };
+        Err(Error::invalid_input(format!(
+            "unknown whisper language {:?}, {}",
+            language, hint
+        )))
+    }
 }
 
 impl Default for WhisperConfig {
@@ -29,32 +220,99 @@ impl Default for WhisperConfig {
             decoder: String::new(),
             encoder: String::new(),
             tokens: String::new(),
-            language: String::from("en"),
+            language: None,
+            task: WhisperTask::Transcribe,
             bpe_vocab: None,
             tail_paddings: None,
             debug: false,
+            itn: ItnConfig::default(),
             provider: None,
-            num_threads: Some(1),
+            fallback: FallbackPolicy::default(),
+            num_threads: Threads::ONE,
         }
     }
 }
 
 impl WhisperRecognizer {
+    /// Like [`Self::new`], with the encoder, decoder and tokens of the sherpa-onnx
+    /// release unpacked in `dir`.
+    pub fn new_dir<P: AsRef<Path>>(
+        dir: P,
+        precision: ModelPrecision,
+        config: WhisperConfig,
+    ) -> Result<Self> {
+        let dir = ModelDir::open_as(
+            dir,
+            ModelKind::Whisper,
+            precision,
+            &config.provider.clone().unwrap_or_else(get_default_provider),
+        )?;
+        Self::new(WhisperConfig {
+            encoder: dir.file(Role::Encoder),
+            decoder: dir.file(Role::Decoder),
+            tokens: dir.file(Role::Tokens),
+            ..config
+        })
+    }
+
     pub fn new(config: WhisperConfig) -> Result<Self> {
+        let _timed = timed!("whisper.create");
+        debug_event!(?config, "creating whisper recognizer");
+        let (mut recognizer, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(WhisperConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        recognizer.provider = provider;
+        Ok(recognizer)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Precision of the loaded model files.
+    pub fn model_precision(&self) -> ModelPrecision {
+        self.precision
+    }
+
+    /// Transcribe half a second of silence and discard it, so the first real
+    /// [`Self::transcribe`] doesn't pay for ONNX Runtime's one-time setup. Returns how long
+    /// it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("whisper.warm_up");
+        time_warm_up(|| {
+            self.transcribe(WARM_UP_SAMPLE_RATE, &warm_up_silence(WARM_UP_SAMPLE_RATE));
+            Ok(())
+        })
+    }
+
+    fn create(config: WhisperConfig) -> Result<Self> {
+        let precision = ModelPrecision::of_file(&config.encoder);
+        config.validate()?;
         let debug = config.debug.into();
-        let provider = config.provider.unwrap_or(get_default_provider());
+        let itn = config.itn.to_raw()?;
+        let provider = config.provider.unwrap_or_else(get_default_provider);
 
         // Onnx
-        let provider_ptr = cstring_from_str(&provider);
-        let num_threads = config.num_threads.unwrap_or(2);
+        let provider_ptr = provider.to_cstring()?;
+        let num_threads = config.num_threads.to_raw();
 
         // Whisper
         let bpe_vocab_ptr = cstring_from_str(&config.bpe_vocab.unwrap_or("".into()));
         let tail_paddings = config.tail_paddings.unwrap_or(0);
         let decoder_ptr = cstring_from_str(&config.decoder);
         let encoder_ptr = cstring_from_str(&config.encoder);
-        let language_ptr = cstring_from_str(&config.language);
-        let task_ptr = cstring_from_str("transcribe");
+        let language_ptr = cstring_from_str(config.language.as_deref().unwrap_or(""));
+        let task_ptr = cstring_from_str(config.task.as_str());
         let tokens_ptr = cstring_from_str(&config.tokens);
         let decoding_method_ptr = cstring_from_str("greedy_search");
 
@@ -106,31 +364,38 @@ impl WhisperRecognizer {
                 hotwords_score: mem::zeroed::<_>(),
                 lm_config: mem::zeroed::<_>(),
                 max_active_paths: mem::zeroed::<_>(),
-                rule_fars: mem::zeroed::<_>(),
-                rule_fsts: mem::zeroed::<_>(),
+                rule_fars: itn.rule_fars(),
+                rule_fsts: itn.rule_fsts(),
                 blank_penalty: mem::zeroed::<_>(),
-                hr: mem::zeroed::<_>(),
+                hr: itn.homophone_replacer(),
             }
         };
-        let recognizer = unsafe { sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&config) };
-
-        if recognizer.is_null() {
-            bail!("Failed to create recognizer");
+        let recognizer = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer,
+            )
         }
+        .ok_or_else(|| Error::native_creation_failed("whisper recognizer"))?;
 
-        Ok(Self { recognizer })
+        Ok(Self {
+            recognizer,
+            provider: ProviderSelection::default(),
+            precision,
+        })
     }
 
     pub fn transcribe(&mut self, sample_rate: u32, samples: &[f32]) -> WhisperRecognizerResult {
+        let _timed = timed!("whisper.transcribe", sample_rate, samples = samples.len());
         unsafe {
-            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer);
+            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer.as_ptr());
             sherpa_rs_sys::SherpaOnnxAcceptWaveformOffline(
                 stream,
                 sample_rate as i32,
                 samples.as_ptr(),
                 samples.len().try_into().unwrap(),
             );
-            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer, stream);
+            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer.as_ptr(), stream);
             let result_ptr = sherpa_rs_sys::SherpaOnnxGetOfflineStreamResult(stream);
             let raw_result = result_ptr.read();
             let result = WhisperRecognizerResult::new(&raw_result);
@@ -140,15 +405,17 @@ impl WhisperRecognizer {
             result
         }
     }
+
+    /// Decode several clips of `(samples, sample_rate)` in one native batch call.
+    ///
+    /// Results are in input order, an empty batch returns an empty Vec.
+    pub fn transcribe_batch(
+        &mut self,
+        inputs: &[(&[f32], u32)],
+    ) -> Vec<Result<WhisperRecognizerResult>> {
+        crate::decode_offline_batch(self.recognizer.as_ptr(), inputs)
+    }
 }
 
 unsafe impl Send for WhisperRecognizer {}
 unsafe impl Sync for WhisperRecognizer {}
-
-impl Drop for WhisperRecognizer {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer(self.recognizer);
-        }
-    }
-}
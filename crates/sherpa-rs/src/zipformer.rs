@@ -1,35 +1,115 @@
 use crate::{
+    decoding::{warn_unused_beam_options, DEFAULT_MAX_ACTIVE_PATHS},
     get_default_provider,
-    utils::{cstr_to_string, cstring_from_str},
+    handle::NativeHandle,
+    itn::ItnConfig,
+    providers::{create_with_fallback, ProviderSelection},
+    trace::{debug_event, timed},
+    utils::{cstr_to_string, cstring_from_str, time_warm_up, warm_up_silence, WARM_UP_SAMPLE_RATE},
+    DecodingMethod, Error, FallbackPolicy, Provider, Result, Threads,
 };
-use eyre::{bail, Result};
-use std::mem;
+use std::{mem, time::Duration};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ZipFormerConfig {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub decoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub encoder: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub joiner: String,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::config::path"))]
     pub tokens: String,
+    pub decoding_method: DecodingMethod,
+    /// Hypotheses kept by modified beam search.
+    pub max_active_paths: i32,
+    /// Subtracted from the blank score before search, see
+    /// [`TransducerConfig::blank_penalty`](crate::transducer::TransducerConfig::blank_penalty).
+    pub blank_penalty: f32,
 
-    pub num_threads: Option<i32>,
-    pub provider: Option<String>,
+    pub num_threads: Threads,
+    pub provider: Option<Provider>,
+    pub fallback: FallbackPolicy,
     pub debug: bool,
+    /// Inverse text normalization and homophone replacement applied to the text.
+    pub itn: ItnConfig,
+}
+
+impl Default for ZipFormerConfig {
+    fn default() -> Self {
+        Self {
+            decoder: String::new(),
+            encoder: String::new(),
+            joiner: String::new(),
+            tokens: String::new(),
+            decoding_method: DecodingMethod::GreedySearch,
+            max_active_paths: DEFAULT_MAX_ACTIVE_PATHS,
+            blank_penalty: 0.0,
+            num_threads: Threads::ONE,
+            provider: None,
+            fallback: FallbackPolicy::default(),
+            debug: false,
+            itn: ItnConfig::default(),
+        }
+    }
 }
 
 pub struct ZipFormer {
-    recognizer: *const sherpa_rs_sys::SherpaOnnxOfflineRecognizer,
+    recognizer: NativeHandle<sherpa_rs_sys::SherpaOnnxOfflineRecognizer>,
+    provider: ProviderSelection,
 }
 
 impl ZipFormer {
     pub fn new(config: ZipFormerConfig) -> Result<Self> {
+        let _timed = timed!("zipformer.create");
+        debug_event!(?config, "creating zipformer recognizer");
+        let (mut recognizer, provider) =
+            create_with_fallback(config.provider.clone(), config.fallback, |provider| {
+                Self::create(ZipFormerConfig {
+                    provider: Some(provider),
+                    ..config.clone()
+                })
+            })?;
+        recognizer.provider = provider;
+        Ok(recognizer)
+    }
+
+    /// Provider the model was created with, CPU if it fell back.
+    pub fn effective_provider(&self) -> &Provider {
+        &self.provider.provider
+    }
+
+    /// Why the requested provider wasn't used, if the model fell back to CPU.
+    pub fn provider_warning(&self) -> Option<&str> {
+        self.provider.warning.as_deref()
+    }
+
+    /// Decode half a second of silence and discard it, so the first real
+    /// [`Self::decode`] doesn't pay for ONNX Runtime's one-time setup. Returns how long
+    /// it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let _timed = timed!("zipformer.warm_up");
+        time_warm_up(|| {
+            self.decode(WARM_UP_SAMPLE_RATE, warm_up_silence(WARM_UP_SAMPLE_RATE));
+            Ok(())
+        })
+    }
+
+    fn create(config: ZipFormerConfig) -> Result<Self> {
+        let itn = config.itn.to_raw()?;
+        warn_unused_beam_options("zipformer", config.decoding_method, config.max_active_paths);
         // Zipformer config
         let decoder_ptr = cstring_from_str(&config.decoder);
         let encoder_ptr = cstring_from_str(&config.encoder);
         let joiner_ptr = cstring_from_str(&config.joiner);
-        let provider_ptr = cstring_from_str(&config.provider.unwrap_or(get_default_provider()));
+        let provider_ptr = config
+            .provider
+            .unwrap_or_else(get_default_provider)
+            .to_cstring()?;
         let tokens_ptr = cstring_from_str(&config.tokens);
-        let decoding_method_ptr = cstring_from_str("greedy_search");
+        let decoding_method_ptr = config.decoding_method.to_cstring();
 
         let transcuder_config = sherpa_rs_sys::SherpaOnnxOfflineTransducerModelConfig {
             decoder: decoder_ptr.as_ptr(),
@@ -39,7 +119,7 @@ impl ZipFormer {
         // Offline model config
         let model_config = unsafe {
             sherpa_rs_sys::SherpaOnnxOfflineModelConfig {
-                num_threads: config.num_threads.unwrap_or(1),
+                num_threads: config.num_threads.to_raw(),
                 debug: config.debug.into(),
                 provider: provider_ptr.as_ptr(),
                 transducer: transcuder_config,
@@ -68,38 +148,43 @@ impl ZipFormer {
             sherpa_rs_sys::SherpaOnnxOfflineRecognizerConfig {
                 model_config,
                 decoding_method: decoding_method_ptr.as_ptr(),
+                max_active_paths: config.max_active_paths,
+                blank_penalty: config.blank_penalty,
+                rule_fars: itn.rule_fars(),
+                rule_fsts: itn.rule_fsts(),
+                hr: itn.homophone_replacer(),
                 // NULLs
-                blank_penalty: mem::zeroed::<_>(),
                 feat_config: mem::zeroed::<_>(),
                 hotwords_file: mem::zeroed::<_>(),
                 hotwords_score: mem::zeroed::<_>(),
                 lm_config: mem::zeroed::<_>(),
-                max_active_paths: mem::zeroed::<_>(),
-                rule_fars: mem::zeroed::<_>(),
-                rule_fsts: mem::zeroed::<_>(),
-                hr: mem::zeroed::<_>(),
             }
         };
 
-        let recognizer =
-            unsafe { sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&recognizer_config) };
-
-        if recognizer.is_null() {
-            bail!("Failed to create recognizer");
+        let recognizer = unsafe {
+            NativeHandle::new(
+                sherpa_rs_sys::SherpaOnnxCreateOfflineRecognizer(&recognizer_config),
+                sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer,
+            )
         }
-        Ok(Self { recognizer })
+        .ok_or_else(|| Error::native_creation_failed("zipformer recognizer"))?;
+        Ok(Self {
+            recognizer,
+            provider: ProviderSelection::default(),
+        })
     }
 
     pub fn decode(&mut self, sample_rate: u32, samples: Vec<f32>) -> String {
+        let _timed = timed!("zipformer.transcribe", sample_rate, samples = samples.len());
         unsafe {
-            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer);
+            let stream = sherpa_rs_sys::SherpaOnnxCreateOfflineStream(self.recognizer.as_ptr());
             sherpa_rs_sys::SherpaOnnxAcceptWaveformOffline(
                 stream,
                 sample_rate as i32,
                 samples.as_ptr(),
                 samples.len().try_into().unwrap(),
             );
-            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer, stream);
+            sherpa_rs_sys::SherpaOnnxDecodeOfflineStream(self.recognizer.as_ptr(), stream);
             let result_ptr = sherpa_rs_sys::SherpaOnnxGetOfflineStreamResult(stream);
             let raw_result = result_ptr.read();
             let text = cstr_to_string(raw_result.text as _);
@@ -114,11 +199,3 @@ impl ZipFormer {
 
 unsafe impl Send for ZipFormer {}
 unsafe impl Sync for ZipFormer {}
-
-impl Drop for ZipFormer {
-    fn drop(&mut self) {
-        unsafe {
-            sherpa_rs_sys::SherpaOnnxDestroyOfflineRecognizer(self.recognizer);
-        }
-    }
-}
@@ -6,15 +6,18 @@ tar xvf sherpa-onnx-zipformer-audio-tagging-2024-04-09.tar.bz2
 rm sherpa-onnx-zipformer-audio-tagging-2024-04-09.tar.bz2
 
 cargo run --example audio_tag
+cargo run --example audio_tag dog_bark.wav
 */
 
 fn main() {
     let model = "./sherpa-onnx-zipformer-audio-tagging-2024-04-09/model.int8.onnx";
     let labels_path = "./sherpa-onnx-zipformer-audio-tagging-2024-04-09/class_labels_indices.csv";
-    let wav_path = "./sherpa-onnx-zipformer-audio-tagging-2024-04-09/test_wavs/1.wav";
+    let wav_path = std::env::args()
+        .nth(1)
+        .unwrap_or("./sherpa-onnx-zipformer-audio-tagging-2024-04-09/test_wavs/1.wav".into());
     let top_k = 5;
 
-    let (samples, sample_rate) = sherpa_rs::read_audio_file(wav_path).unwrap();
+    let (samples, sample_rate) = sherpa_rs::read_audio_file(&wav_path).unwrap();
 
     let config = sherpa_rs::audio_tag::AudioTagConfig {
         model: model.into(),
@@ -23,6 +26,11 @@ fn main() {
         ..Default::default()
     };
     let mut audio_tag = sherpa_rs::audio_tag::AudioTag::new(config).unwrap();
-    let events = audio_tag.compute(samples, sample_rate);
-    println!("✅ Events ({}): {}", events.len(), events.join(", "));
+    let tags = audio_tag
+        .compute_tags(&samples, sample_rate, top_k)
+        .unwrap();
+    println!("✅ Events ({}):", tags.len());
+    for tag in tags {
+        println!("{:>5.1}% {}", tag.prob * 100.0, tag.name);
+    }
 }
@@ -0,0 +1,30 @@
+/*
+Remove background noise from speech with GTCRN.
+
+wget https://github.com/k2-fsa/sherpa-onnx/releases/download/speech-enhancement-models/gtcrn_simple.onnx
+wget https://github.com/k2-fsa/sherpa-onnx/releases/download/speech-enhancement-models/inp_16k.wav
+
+cargo run --example denoise inp_16k.wav enhanced.wav
+*/
+
+fn main() {
+    let input = std::env::args().nth(1).expect("Missing input wav path");
+    let output = std::env::args().nth(2).unwrap_or("enhanced.wav".into());
+
+    let config = sherpa_rs::denoise::SpeechDenoiserConfig {
+        model: "gtcrn_simple.onnx".into(),
+        auto_resample: true,
+        ..Default::default()
+    };
+    let mut denoiser = sherpa_rs::denoise::SpeechDenoiser::new(config).unwrap();
+
+    let start = std::time::Instant::now();
+    let audio = denoiser.run_file(&input).unwrap();
+    println!(
+        "Denoised {:.2}s of audio in {:.2}s",
+        audio.samples.len() as f32 / audio.sample_rate as f32,
+        start.elapsed().as_secs_f32()
+    );
+    audio.write_wav(&output).unwrap();
+    println!("Saved to {}", output);
+}
@@ -0,0 +1,37 @@
+/*
+Transcribe wav file using FireRedASR
+wget https://github.com/k2-fsa/sherpa-onnx/releases/download/asr-models/sherpa-onnx-fire-red-asr-large-zh_en-2025-02-16.tar.bz2
+tar xvf sherpa-onnx-fire-red-asr-large-zh_en-2025-02-16.tar.bz2
+rm sherpa-onnx-fire-red-asr-large-zh_en-2025-02-16.tar.bz2
+wget https://github.com/thewh1teagle/sherpa-rs/releases/download/v0.1.0/motivation.wav -O motivation.wav
+cargo run --example fire_red_asr motivation.wav
+*/
+
+use sherpa_rs::{
+    fire_red_asr::{FireRedAsrConfig, FireRedAsrRecognizer},
+    read_audio_file, Provider,
+};
+
+fn main() {
+    let path = std::env::args().nth(1).expect("Missing file path argument");
+    let provider = std::env::args()
+        .nth(2)
+        .map(|p| p.parse().expect("Invalid provider"))
+        .unwrap_or(Provider::Cpu);
+    let (samples, sample_rate) = read_audio_file(&path).unwrap();
+    assert_eq!(sample_rate, 16000, "The sample rate must be 16000.");
+
+    let config = FireRedAsrConfig {
+        encoder: "./sherpa-onnx-fire-red-asr-large-zh_en-2025-02-16/encoder.int8.onnx".into(),
+        decoder: "./sherpa-onnx-fire-red-asr-large-zh_en-2025-02-16/decoder.int8.onnx".into(),
+        tokens: "./sherpa-onnx-fire-red-asr-large-zh_en-2025-02-16/tokens.txt".into(),
+        provider: Some(provider),
+        ..Default::default() // fill in any missing fields with defaults
+    };
+    let mut recognizer = FireRedAsrRecognizer::new(config).unwrap();
+
+    let start_t = std::time::Instant::now();
+    let result = recognizer.transcribe(sample_rate, &samples);
+    println!("✅ Text: {}", result.text);
+    println!("⏱️ Time taken for transcription: {:?}", start_t.elapsed());
+}
@@ -19,7 +19,7 @@ fn main() {
         decoder: "sherpa-onnx-whisper-tiny/tiny-decoder.onnx".into(),
         ..Default::default()
     };
-    let mut extractor = sherpa_rs::language_id::SpokenLanguageId::new(config);
+    let mut extractor = sherpa_rs::language_id::SpokenLanguageId::new(config).unwrap();
 
     let language = extractor.compute(samples, sample_rate).unwrap();
     println!("Spoken language: {}", language);
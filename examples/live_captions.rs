@@ -0,0 +1,65 @@
+/*
+Caption the microphone live, printing partial results in place
+
+wget https://github.com/k2-fsa/sherpa-onnx/releases/download/asr-models/sherpa-onnx-streaming-zipformer-en-2023-06-26.tar.bz2
+tar xvf sherpa-onnx-streaming-zipformer-en-2023-06-26.tar.bz2
+cargo run --example live_captions --features capture
+cargo run --example live_captions --features capture -- "<input device name>"
+*/
+
+use std::io::Write;
+
+use sherpa_rs::{
+    capture::{input_devices, MicConfig, MicSource},
+    online::{OnlineModel, OnlineRecognizer, OnlineRecognizerConfig, ResultState},
+};
+
+fn main() {
+    let device_name = std::env::args().nth(1);
+    println!("Input devices: {:?}", input_devices().unwrap());
+
+    let dir = "sherpa-onnx-streaming-zipformer-en-2023-06-26";
+    let config = OnlineRecognizerConfig {
+        model: OnlineModel::Transducer {
+            encoder: format!("{dir}/encoder-epoch-99-avg-1-chunk-16-left-128.onnx"),
+            decoder: format!("{dir}/decoder-epoch-99-avg-1-chunk-16-left-128.onnx"),
+            joiner: format!("{dir}/joiner-epoch-99-avg-1-chunk-16-left-128.onnx"),
+        },
+        tokens: format!("{dir}/tokens.txt"),
+        ..Default::default()
+    };
+    let recognizer = OnlineRecognizer::new(config).unwrap();
+    let stream = recognizer.create_stream().unwrap();
+
+    let mic = MicSource::open(MicConfig {
+        device_name,
+        ..Default::default()
+    })
+    .unwrap();
+    let sample_rate = mic.sample_rate();
+    println!("Listening, press Ctrl+C to stop");
+
+    let mut last = String::new();
+    for chunk in mic {
+        stream.accept_waveform(sample_rate, &chunk);
+        recognizer.decode_ready(&stream);
+
+        let result = recognizer.result(&stream);
+        let text = result.text.trim();
+        match result.state {
+            ResultState::Partial if text != last => {
+                print!("\r\x1b[K{}", text);
+                std::io::stdout().flush().unwrap();
+                last = text.to_string();
+            }
+            ResultState::Final => {
+                if !text.is_empty() {
+                    println!("\r\x1b[K{}", text);
+                }
+                last.clear();
+                recognizer.reset(&stream);
+            }
+            _ => {}
+        }
+    }
+}
@@ -10,12 +10,15 @@ cargo run --example moonshine motivation.wav
 
 use sherpa_rs::{
     moonshine::{MoonshineConfig, MoonshineRecognizer},
-    read_audio_file,
+    read_audio_file, Provider, Threads,
 };
 
 fn main() {
     let path = std::env::args().nth(1).expect("Missing file path argument");
-    let provider = std::env::args().nth(2).unwrap_or("cpu".into());
+    let provider = std::env::args()
+        .nth(2)
+        .map(|p| p.parse().expect("Invalid provider"))
+        .unwrap_or(Provider::Cpu);
     let (samples, sample_rate) = read_audio_file(&path).unwrap();
     assert_eq!(sample_rate, 16000, "The sample rate must be 16000.");
 
@@ -26,7 +29,7 @@ fn main() {
         cached_decoder: "./sherpa-onnx-moonshine-tiny-en-int8/cached_decode.int8.onnx".into(),
         tokens: "./sherpa-onnx-moonshine-tiny-en-int8/tokens.txt".into(),
         provider: Some(provider),
-        num_threads: None,
+        num_threads: Threads::ONE,
         ..Default::default() // fill in any missing fields with defaults
     };
     let mut recognizer = MoonshineRecognizer::new(config).unwrap();
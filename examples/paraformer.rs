@@ -9,12 +9,15 @@ cargo run --example paraformer motivation.wav
 
 use sherpa_rs::{
     paraformer::{ParaformerConfig, ParaformerRecognizer},
-    read_audio_file,
+    read_audio_file, Provider,
 };
 
 fn main() {
     let path = std::env::args().nth(1).expect("Missing file path argument");
-    let provider = std::env::args().nth(2).unwrap_or("cpu".into());
+    let provider = std::env::args()
+        .nth(2)
+        .map(|p| p.parse().expect("Invalid provider"))
+        .unwrap_or(Provider::Cpu);
     let (samples, sample_rate) = read_audio_file(&path).unwrap();
     assert_eq!(sample_rate, 16000, "The sample rate must be 16000.");
 
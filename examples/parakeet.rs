@@ -8,6 +8,7 @@ cargo run --example parakeet motivation.wav
 
 use sherpa_rs::read_audio_file;
 use sherpa_rs::transducer::{TransducerConfig, TransducerRecognizer};
+use sherpa_rs::Threads;
 use std::time::Instant;
 
 pub fn main() {
@@ -24,7 +25,7 @@ pub fn main() {
         encoder: "./sherpa-onnx-nemo-parakeet-tdt-0.6b-v2-int8/encoder.int8.onnx".to_string(),
         joiner: "./sherpa-onnx-nemo-parakeet-tdt-0.6b-v2-int8/joiner.int8.onnx".to_string(),
         tokens: "./sherpa-onnx-nemo-parakeet-tdt-0.6b-v2-int8/tokens.txt".to_string(),
-        num_threads: 1,
+        num_threads: Threads::ONE,
         sample_rate: 16_000,
         feature_dim: 80,
         debug: true,
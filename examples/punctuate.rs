@@ -26,7 +26,7 @@ fn main() {
 
     println!("--------------------");
     for sentence in sentences {
-        let punctuated = punctuate.add_punctuation(sentence);
+        let punctuated = punctuate.add_punctuation(sentence).unwrap();
         println!("Input text: {}", sentence);
         println!("Output text: {}", punctuated);
         println!("--------------------");
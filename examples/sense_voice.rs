@@ -10,11 +10,15 @@ cargo run --example sense_voice motivation.wav
 use sherpa_rs::{
     read_audio_file,
     sense_voice::{SenseVoiceConfig, SenseVoiceRecognizer},
+    Provider,
 };
 
 fn main() {
     let path = std::env::args().nth(1).expect("Missing file path argument");
-    let provider = std::env::args().nth(2).unwrap_or("cpu".into());
+    let provider = std::env::args()
+        .nth(2)
+        .map(|p| p.parse().expect("Invalid provider"))
+        .unwrap_or(Provider::Cpu);
     let (samples, sample_rate) = read_audio_file(&path).unwrap();
     assert_eq!(sample_rate, 16000, "The sample rate must be 16000.");
 
@@ -32,7 +32,8 @@ fn main() {
 
     // Create the embedding manager
     let mut embedding_manager =
-        embedding_manager::EmbeddingManager::new(extractor.embedding_size.try_into().unwrap()); // Assuming dimension 512 for embeddings
+        embedding_manager::EmbeddingManager::new(extractor.embedding_size.try_into().unwrap())
+            .unwrap();
 
     // Map to store speakers and their corresponding files
     let mut speaker_map: HashMap<String, Vec<String>> = HashMap::new();
@@ -51,7 +52,7 @@ fn main() {
             embedding_manager
                 .add(
                     format!("speaker {}", speaker_counter),
-                    &mut embedding.clone(),
+                    embedding,
                 )
                 .unwrap();
             speaker_map
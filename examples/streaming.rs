@@ -0,0 +1,49 @@
+/*
+Split a wav file into utterances with a streaming zipformer and endpoint detection
+
+wget https://github.com/k2-fsa/sherpa-onnx/releases/download/asr-models/sherpa-onnx-streaming-zipformer-en-2023-06-26.tar.bz2
+tar xvf sherpa-onnx-streaming-zipformer-en-2023-06-26.tar.bz2
+wget https://github.com/thewh1teagle/sherpa-rs/releases/download/v0.1.0/sam_altman.wav -O sam_altman.wav
+cargo run --example streaming sam_altman.wav
+*/
+
+use sherpa_rs::{
+    online::{OnlineModel, OnlineRecognizer, OnlineRecognizerConfig, UtteranceSegmenter},
+    read_audio_file,
+};
+
+fn main() {
+    let path = std::env::args().nth(1).expect("Missing file path argument");
+    let (samples, sample_rate) = read_audio_file(&path).unwrap();
+
+    let dir = "sherpa-onnx-streaming-zipformer-en-2023-06-26";
+    let config = OnlineRecognizerConfig {
+        model: OnlineModel::Transducer {
+            encoder: format!("{dir}/encoder-epoch-99-avg-1-chunk-16-left-128.onnx"),
+            decoder: format!("{dir}/decoder-epoch-99-avg-1-chunk-16-left-128.onnx"),
+            joiner: format!("{dir}/joiner-epoch-99-avg-1-chunk-16-left-128.onnx"),
+        },
+        tokens: format!("{dir}/tokens.txt"),
+        rule2_min_trailing_silence: 0.8,
+        ..Default::default()
+    };
+    let recognizer = OnlineRecognizer::new(config).unwrap();
+    let mut segmenter = UtteranceSegmenter::new(&recognizer).unwrap();
+
+    // Feed 100ms at a time, as a microphone would
+    let chunk = sample_rate as usize / 10;
+    for samples in samples.chunks(chunk) {
+        for utterance in segmenter.feed(sample_rate, samples) {
+            println!(
+                "{:.2}s - {:.2}s: {}",
+                utterance.start, utterance.end, utterance.text
+            );
+        }
+    }
+    if let Some(utterance) = segmenter.finish().unwrap() {
+        println!(
+            "{:.2}s - {:.2}s: {}",
+            utterance.start, utterance.end, utterance.text
+        );
+    }
+}
@@ -0,0 +1,62 @@
+/*
+Print partial results in place and final results on new lines, like live captions
+
+wget https://github.com/k2-fsa/sherpa-onnx/releases/download/asr-models/sherpa-onnx-streaming-zipformer-en-2023-06-26.tar.bz2
+tar xvf sherpa-onnx-streaming-zipformer-en-2023-06-26.tar.bz2
+wget https://github.com/thewh1teagle/sherpa-rs/releases/download/v0.1.0/sam_altman.wav -O sam_altman.wav
+cargo run --example streaming_partials sam_altman.wav
+*/
+
+use std::io::Write;
+
+use sherpa_rs::{
+    online::{OnlineModel, OnlineRecognizer, OnlineRecognizerConfig, ResultState},
+    read_audio_file,
+};
+
+fn main() {
+    let path = std::env::args().nth(1).expect("Missing file path argument");
+    let (samples, sample_rate) = read_audio_file(&path).unwrap();
+
+    let dir = "sherpa-onnx-streaming-zipformer-en-2023-06-26";
+    let config = OnlineRecognizerConfig {
+        model: OnlineModel::Transducer {
+            encoder: format!("{dir}/encoder-epoch-99-avg-1-chunk-16-left-128.onnx"),
+            decoder: format!("{dir}/decoder-epoch-99-avg-1-chunk-16-left-128.onnx"),
+            joiner: format!("{dir}/joiner-epoch-99-avg-1-chunk-16-left-128.onnx"),
+        },
+        tokens: format!("{dir}/tokens.txt"),
+        ..Default::default()
+    };
+    let recognizer = OnlineRecognizer::new(config).unwrap();
+    let stream = recognizer.create_stream().unwrap();
+
+    let mut last = String::new();
+    let chunks = samples.chunks(sample_rate as usize / 10);
+    let num_chunks = chunks.len();
+    for (i, chunk) in chunks.enumerate() {
+        stream.accept_waveform(sample_rate, chunk);
+        if i + 1 == num_chunks {
+            stream.input_finished();
+        }
+        recognizer.decode_ready(&stream);
+
+        let result = recognizer.result(&stream);
+        let text = result.text.trim();
+        match result.state {
+            ResultState::Partial if text != last => {
+                print!("\r\x1b[K{}", text);
+                std::io::stdout().flush().unwrap();
+                last = text.to_string();
+            }
+            ResultState::Final => {
+                if !text.is_empty() {
+                    println!("\r\x1b[K{}", text);
+                }
+                last.clear();
+                recognizer.reset(&stream);
+            }
+            _ => {}
+        }
+    }
+}
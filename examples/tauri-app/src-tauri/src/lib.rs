@@ -37,7 +37,9 @@ fn punctuate(sentence: &str, app: tauri::AppHandle) -> Result<String, String> {
     let mut punctuater = punctuater
         .lock()
         .map_err(|e| format!("Failed to get punctuator: {:?}", e))?;
-    let punctuated = punctuater.add_punctuation(sentence);
+    let punctuated = punctuater
+        .add_punctuation(sentence)
+        .map_err(|e| e.to_string())?;
 
     Ok(punctuated)
 }
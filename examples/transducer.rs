@@ -1,5 +1,6 @@
 use sherpa_rs::read_audio_file;
 use sherpa_rs::transducer::{TransducerConfig, TransducerRecognizer};
+use sherpa_rs::Threads;
 use std::time::Instant;
 
 /*
@@ -25,7 +26,7 @@ pub fn main() {
         encoder: "encoder-epoch-90-avg-20.onnx".to_string(),
         joiner: "joiner-epoch-90-avg-20.onnx".to_string(),
         tokens: "tokens.txt".to_string(),
-        num_threads: 1,
+        num_threads: Threads::ONE,
         sample_rate: 16_000,
         feature_dim: 80,
         debug: true,
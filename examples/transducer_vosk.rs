@@ -1,5 +1,7 @@
 use sherpa_rs::read_audio_file;
 use sherpa_rs::transducer::{TransducerConfig, TransducerRecognizer};
+use sherpa_rs::Threads;
+use sherpa_rs::DecodingMethod;
 use std::time::Instant;
 
 /*
@@ -9,7 +11,6 @@ wget https://huggingface.co/alphacep/vosk-model-ru/resolve/main/am-onnx/joiner.o
 wget https://huggingface.co/alphacep/vosk-model-ru/resolve/main/lang/tokens.txt
 wget https://huggingface.co/alphacep/vosk-model-ru/resolve/main/lang/unigram_500.vocab
 wget https://huggingface.co/alphacep/vosk-model-ru/resolve/main/test.wav
-touch hotwords.txt
 cargo run --example transducer_vosk test.wav
 */
 pub fn main() {
@@ -27,13 +28,13 @@ pub fn main() {
         joiner: "joiner.onnx".to_string(),
         tokens: "tokens.txt".to_string(),
         bpe_vocab: "unigram_500.vocab".to_string(),
-        hotwords_file: "hotwords.txt".to_string(),
+        hotwords: vec!["ПРИВЕТ".to_string()],
         hotwords_score: 1.2,
-        num_threads: 1,
+        num_threads: Threads::ONE,
         sample_rate: 16_000,
         feature_dim: 80,
         modeling_unit: "bpe".to_string(),
-        decoding_method: "modified_beam_search".to_string(),
+        decoding_method: DecodingMethod::ModifiedBeamSearch,
         debug: true,
         ..Default::default()
     };
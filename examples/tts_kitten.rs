@@ -16,11 +16,11 @@ fn main() {
         length_scale: 1.0,
         ..Default::default()
     };
-    let mut tts = KittenTts::new(config);
+    let mut tts = KittenTts::new(config).unwrap();
 
     let sid = 2;
     let text = "Hello, this is generated by the Kitten text-to-speech model.";
-    let audio = tts.create(&text, sid, 1.0).unwrap();
+    let audio = tts.create(text, sid, 1.0).unwrap();
     dbg!(audio.sample_rate);
     sherpa_rs::write_audio_file("audio.wav", &audio.samples, audio.sample_rate).unwrap();
     println!("Created audio.wav")
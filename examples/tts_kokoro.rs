@@ -23,7 +23,7 @@ fn main() {
         length_scale: 1.0,
         ..Default::default()
     };
-    let mut tts = KokoroTts::new(config);
+    let mut tts = KokoroTts::new(config).unwrap();
 
     let sid = 0;
     let text = "This is generated by next generation Kaldi using Kokoro without Misaki.";
@@ -16,7 +16,7 @@ fn main() {
         data_dir: "./matcha-icefall-en_US-ljspeech/espeak-ng-data".into(),
         ..Default::default()
     };
-    let mut tts = MatchaTts::new(config);
+    let mut tts = MatchaTts::new(config).unwrap();
     let sid = 0;
     let audio = tts
         .create("Hello! This audio generated by onnx model!", sid, 1.0)
@@ -14,7 +14,7 @@ fn main() {
         length_scale: 1.0,
         ..Default::default()
     };
-    let mut tts = VitsTts::new(config);
+    let mut tts = VitsTts::new(config).unwrap();
     let sid = 0;
     let audio = tts
         .create("Hello! This audio generated by onnx model!", sid, 1.0)
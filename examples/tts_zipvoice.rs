@@ -4,7 +4,6 @@ tar xf sherpa-onnx-zipvoice-distill-zh-en-emilia.tar.bz2
 rm sherpa-onnx-zipvoice-distill-zh-en-emilia.tar.bz2
 cargo run --example tts_zipvoice
 */
-use hound;
 use sherpa_rs::{
     tts::{CommonTtsConfig, ZipVoiceTts, ZipVoiceTtsConfig},
     OnnxConfig,
@@ -47,11 +46,11 @@ fn main() {
 
     let config = ZipVoiceTtsConfig {
         tokens: format!("{}/tokens.txt", model_dir),
-        text_model: format!("{}/text_encoder.onnx", model_dir),
-        flow_matching_model: format!("{}/fm_decoder.onnx", model_dir),
+        encoder: format!("{}/text_encoder.onnx", model_dir),
+        decoder: format!("{}/fm_decoder.onnx", model_dir),
         vocoder: format!("{}/vocos_24khz.onnx", model_dir),
         data_dir: format!("{}/espeak-ng-data", model_dir),
-        pinyin_dict: format!("{}/pinyin.raw", model_dir),
+        lexicon: format!("{}/lexicon.txt", model_dir),
         feat_scale: 0.1,
         t_shift: 0.5, // Match the default
         target_rms: 0.1,
@@ -64,9 +63,8 @@ fn main() {
             debug: true,
             ..Default::default()
         },
-        ..Default::default()
     };
-    let mut tts = ZipVoiceTts::new(config);
+    let mut tts = ZipVoiceTts::new(config).unwrap();
 
     let prompt_text = ""; // Can be empty if no prompt text
     let text = "Hello world."; // Start with simple English text
@@ -77,8 +75,8 @@ fn main() {
 
     let audio = tts
         .create(
-            &text,
-            &prompt_text,
+            text,
+            prompt_text,
             &prompt_samples,
             prompt_sr,
             speed,
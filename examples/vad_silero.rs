@@ -92,7 +92,8 @@ fn main() {
     };
     let mut extractor = speaker_id::EmbeddingExtractor::new(extractor_config).unwrap();
     let mut embedding_manager =
-        embedding_manager::EmbeddingManager::new(extractor.embedding_size.try_into().unwrap()); // Assuming dimension 512 for embeddings
+        embedding_manager::EmbeddingManager::new(extractor.embedding_size.try_into().unwrap())
+            .unwrap();
 
     let mut speaker_counter = 1;
 
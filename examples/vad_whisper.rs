@@ -30,13 +30,14 @@ fn main() {
     };
     let mut extractor = speaker_id::EmbeddingExtractor::new(extractor_config).unwrap();
     let mut embedding_manager =
-        embedding_manager::EmbeddingManager::new(extractor.embedding_size.try_into().unwrap()); // Assuming dimension 512 for embeddings
+        embedding_manager::EmbeddingManager::new(extractor.embedding_size.try_into().unwrap())
+            .unwrap();
 
     let config = WhisperConfig {
         decoder: "sherpa-onnx-whisper-tiny/tiny-decoder.onnx".into(),
         encoder: "sherpa-onnx-whisper-tiny/tiny-encoder.onnx".into(),
         tokens: "sherpa-onnx-whisper-tiny/tiny-tokens.txt".into(),
-        language: "en".into(),
+        language: Some("en".into()),
         ..Default::default() // fill in any missing fields with defaults
     };
 
@@ -64,7 +65,7 @@ fn main() {
                 let transcript = recognizer.transcribe(sample_rate, &segment.samples);
 
                 // Compute the speaker embedding
-                let mut embedding = extractor
+                let embedding = extractor
                     .compute_speaker_embedding(segment.samples, sample_rate)
                     .unwrap();
                 let name = if let Some(speaker_name) = embedding_manager.search(&embedding, 0.4) {
@@ -72,7 +73,7 @@ fn main() {
                 } else {
                     // Register a new speaker and add the embedding
                     let name = format!("speaker {}", speaker_counter);
-                    embedding_manager.add(name.clone(), &mut embedding).unwrap();
+                    embedding_manager.add(name.clone(), &embedding).unwrap();
 
                     speaker_counter += 1;
                     name
@@ -100,7 +101,7 @@ fn main() {
             let transcript = recognizer.transcribe(sample_rate, &segment.samples);
 
             // Compute the speaker embedding
-            let mut embedding = extractor
+            let embedding = extractor
                 .compute_speaker_embedding(segment.samples, sample_rate)
                 .unwrap();
 
@@ -109,7 +110,7 @@ fn main() {
             } else {
                 // Register a new speaker and add the embedding
                 let name = format!("speaker {}", speaker_counter);
-                embedding_manager.add(name.clone(), &mut embedding).unwrap();
+                embedding_manager.add(name.clone(), &embedding).unwrap();
 
                 speaker_counter += 1;
                 name
@@ -0,0 +1,66 @@
+/*
+Listen for a wake word with a per-session keyword list
+
+wget https://github.com/k2-fsa/sherpa-onnx/releases/download/kws-models/sherpa-onnx-kws-zipformer-gigaspeech-3.3M-2024-01-01.tar.bz2
+tar xvf sherpa-onnx-kws-zipformer-gigaspeech-3.3M-2024-01-01.tar.bz2
+rm sherpa-onnx-kws-zipformer-gigaspeech-3.3M-2024-01-01.tar.bz2
+cargo run --example wake_word sherpa-onnx-kws-zipformer-gigaspeech-3.3M-2024-01-01/test_wavs/0.wav
+*/
+
+use sherpa_rs::{
+    keyword_spot::{Keyword, KeywordSpotConfig, KeywordSpotter},
+    read_audio_file,
+};
+
+fn main() {
+    let path = std::env::args().nth(1).expect("Missing file path argument");
+    let (samples, sample_rate) = read_audio_file(&path).unwrap();
+
+    let dir = "sherpa-onnx-kws-zipformer-gigaspeech-3.3M-2024-01-01";
+    let config = KeywordSpotConfig {
+        zipformer_encoder: format!("{dir}/encoder-epoch-12-avg-2-chunk-16-left-64.int8.onnx"),
+        zipformer_decoder: format!("{dir}/decoder-epoch-12-avg-2-chunk-16-left-64.int8.onnx"),
+        zipformer_joiner: format!("{dir}/joiner-epoch-12-avg-2-chunk-16-left-64.int8.onnx"),
+        tokens: format!("{dir}/tokens.txt"),
+        keywords: format!("{dir}/test_wavs/test_keywords.txt"),
+        ..Default::default()
+    };
+    let spotter = KeywordSpotter::new(config).unwrap();
+
+    // Keywords for this session only, tokenized like test_keywords.txt
+    let keywords = [
+        Keyword {
+            phrase: "▁HE LL O ▁WORLD".into(),
+            boost: Some(1.5),
+            threshold: Some(0.25),
+        },
+        Keyword {
+            phrase: "▁FOREVER".into(),
+            ..Default::default()
+        },
+    ];
+    let stream = spotter.create_stream_with_keywords(&keywords).unwrap();
+
+    // Feed 100ms at a time, as a microphone would
+    for chunk in samples.chunks(sample_rate as usize / 10) {
+        stream.accept_waveform(sample_rate, chunk);
+        if let Some(result) = spotter.detect(&stream) {
+            println!(
+                "🔔 {} at {:.2}s",
+                result.keyword,
+                result.absolute_start_time()
+            );
+        }
+    }
+
+    // Trailing silence so the last keyword is decoded
+    stream.accept_waveform(sample_rate, &vec![0.0; sample_rate as usize / 2]);
+    stream.input_finished();
+    if let Some(result) = spotter.detect(&stream) {
+        println!(
+            "🔔 {} at {:.2}s",
+            result.keyword,
+            result.absolute_start_time()
+        );
+    }
+}
@@ -10,11 +10,15 @@ cargo run --example whisper motivation.wav
 use sherpa_rs::{
     read_audio_file,
     whisper::{WhisperConfig, WhisperRecognizer},
+    Provider, Threads,
 };
 
 fn main() {
     let path = std::env::args().nth(1).expect("Missing file path argument");
-    let provider = std::env::args().nth(2).unwrap_or("cpu".into());
+    let provider = std::env::args()
+        .nth(2)
+        .map(|p| p.parse().expect("Invalid provider"))
+        .unwrap_or(Provider::Cpu);
     let (samples, sample_rate) = read_audio_file(&path).unwrap();
     assert_eq!(sample_rate, 16000, "The sample rate must be 16000.");
 
@@ -22,9 +26,9 @@ fn main() {
         decoder: "sherpa-onnx-whisper-tiny/tiny-decoder.onnx".into(),
         encoder: "sherpa-onnx-whisper-tiny/tiny-encoder.onnx".into(),
         tokens: "sherpa-onnx-whisper-tiny/tiny-tokens.txt".into(),
-        language: "en".into(),
+        language: Some("en".into()),
         provider: Some(provider),
-        num_threads: None,
+        num_threads: Threads::ONE,
         bpe_vocab: None,
         ..Default::default() // fill in any missing fields with defaults
     };
@@ -0,0 +1,86 @@
+/*
+Generate SRT subtitles from a wav file using OpenAI Whisper word timestamps
+
+wget https://github.com/k2-fsa/sherpa-onnx/releases/download/asr-models/sherpa-onnx-whisper-tiny.tar.bz2
+tar xvf sherpa-onnx-whisper-tiny.tar.bz2
+wget https://github.com/thewh1teagle/sherpa-rs/releases/download/v0.1.0/motivation.wav -O motivation.wav
+cargo run --example whisper_srt motivation.wav > motivation.srt
+*/
+
+use sherpa_rs::{
+    read_audio_file,
+    whisper::{WhisperConfig, WhisperRecognizer},
+    WordSpan,
+};
+
+const MAX_WORDS_PER_CUE: usize = 8;
+const MAX_CUE_SECS: f32 = 4.0;
+
+fn srt_time(secs: f32) -> String {
+    let ms = (secs.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+fn main() {
+    let path = std::env::args().nth(1).expect("Missing file path argument");
+    let (samples, sample_rate) = read_audio_file(&path).unwrap();
+    assert_eq!(sample_rate, 16000, "The sample rate must be 16000.");
+
+    let config = WhisperConfig {
+        decoder: "sherpa-onnx-whisper-tiny/tiny-decoder.onnx".into(),
+        encoder: "sherpa-onnx-whisper-tiny/tiny-encoder.onnx".into(),
+        tokens: "sherpa-onnx-whisper-tiny/tiny-tokens.txt".into(),
+        language: Some("en".into()),
+        ..Default::default()
+    };
+    let mut recognizer = WhisperRecognizer::new(config).unwrap();
+    let result = recognizer.transcribe(sample_rate, &samples);
+    let duration = samples.len() as f32 / sample_rate as f32;
+
+    let words = result.words();
+    if words.is_empty() {
+        // No timestamps from this model, fall back to a single cue
+        println!(
+            "1\n{} --> {}\n{}\n",
+            srt_time(0.0),
+            srt_time(duration),
+            result.text.trim()
+        );
+        return;
+    }
+
+    let mut cues: Vec<Vec<WordSpan>> = Vec::new();
+    for word in words {
+        match cues.last_mut() {
+            Some(cue)
+                if cue.len() < MAX_WORDS_PER_CUE && word.end - cue[0].start <= MAX_CUE_SECS =>
+            {
+                cue.push(word)
+            }
+            _ => cues.push(vec![word]),
+        }
+    }
+
+    for (i, cue) in cues.iter().enumerate() {
+        let start = cue[0].start;
+        let end = cue[cue.len() - 1].end.max(start + 0.5).min(duration);
+        let text = cue
+            .iter()
+            .map(|w| w.word.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{}\n{} --> {}\n{}\n",
+            i + 1,
+            srt_time(start),
+            srt_time(end),
+            text
+        );
+    }
+}